@@ -19,4 +19,8 @@ fn main() {
     let dest_path = Path::new(&env::var("OUT_DIR").unwrap()).join("git_version");
     let mut file = File::create(&dest_path).unwrap();
     write!(file, "\" ({} {})\"", git_hash, build_date).unwrap();
+
+    let dest_path = Path::new(&env::var("OUT_DIR").unwrap()).join("host_target");
+    let mut file = File::create(&dest_path).unwrap();
+    write!(file, "\"{}\"", env::var("TARGET").unwrap()).unwrap();
 }
@@ -4,58 +4,33 @@ extern crate clap;
 #[macro_use]
 extern crate log;
 extern crate time;
+extern crate semver;
+extern crate rustc_serialize;
 
 
 use std::env;
 use std::fs;
-use std::process::{Command, exit};
-use std::path::PathBuf;
+use std::io::Write;
+use std::process::exit;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use std::collections::BTreeMap;
 
-use cratesfyi::docbuilder::{DocBuilder, DocBuilderError, command_result};
-use cratesfyi::docbuilder::crte::Crate;
-use cratesfyi::{db, web};
-use clap::{Arg, App, SubCommand};
-use log::{LogLevel, LogLevelFilter, LogRecord, LogMetadata};
-use time::now;
+use cratesfyi::docbuilder::{DocBuilder, DocBuilderError, BuildResult, CRATE_TOO_LARGE_LOG_PREFIX};
+use cratesfyi::docbuilder::{queue, blacklist, rebuild, overrides, toolchain};
+use cratesfyi::docbuilder::overrides::BuildOverride;
+use cratesfyi::docbuilder::limits::{BuildLimits, CommandFailure, BUILD_TIMED_OUT_EXIT_CODE};
+use cratesfyi::docbuilder::builds::TestMode;
+use cratesfyi::docbuilder::download::{is_download_error, DOWNLOAD_FAILED_LOG_MARKER};
+use cratesfyi::{db, web, daemon, shutdown};
+use cratesfyi::config::Config;
+use cratesfyi::logging::{self, LogFormat};
+use cratesfyi::lock::{Lock, LOCK_FILE_NAME};
+use clap::{Arg, App, ArgMatches, SubCommand};
+use rustc_serialize::json;
 
 
 
-// I am using really simple Logger in this program
-struct Logger;
-
-impl log::Log for Logger {
-    fn enabled(&self, metadata: &LogMetadata) -> bool {
-        metadata.level() <= LogLevel::Info
-    }
-
-    fn log(&self, record: &LogRecord) {
-        if self.enabled(record.metadata()) {
-            println!("{} {} - {}", now().to_utc().rfc3339(), record.level(), record.args());
-        }
-    }
-}
-
-
-
-fn update_crates_io_index(path: &PathBuf) -> Result<String, String> {
-    info!("Updating crates.io-index");
-
-    if path.exists() {
-        let cwd = env::current_dir().unwrap();
-        env::set_current_dir(path).unwrap();
-        let res = command_result(Command::new("git").arg("pull").output().unwrap());
-        env::set_current_dir(cwd).unwrap();
-        res
-    } else {
-        command_result(Command::new("git")
-            .arg("clone")
-            .arg("https://github.com/rust-lang/crates.io-index.git")
-            .arg(path.to_str().unwrap())
-            .output().unwrap())
-    }
-}
-
-
 // This will remove everything in CWD!!!
 fn clean_build_dir() -> Result<(), DocBuilderError> {
 
@@ -81,15 +56,94 @@ fn clean_build_dir() -> Result<(), DocBuilderError> {
 
 
 
-fn main() {
-    log::set_logger(|max_log_level| {
-        max_log_level.set(LogLevelFilter::Info);
-        Box::new(Logger)
-    }).unwrap();
+// Reads build limit arguments, unset limits are using defaults
+fn build_limits_from_matches(config: &Config, matches: &ArgMatches) -> BuildLimits {
+    let mut limits = config.build_limits();
+
+    if let Some(timeout) = matches.value_of("TIMEOUT").and_then(|t| t.parse().ok()) {
+        limits.timeout = Duration::from_secs(timeout);
+    }
+
+    if let Some(memory) = matches.value_of("MEMORY_LIMIT").and_then(|m| m.parse().ok()) {
+        limits.memory = Some(memory);
+    }
+
+    if let Some(max_output) = matches.value_of("MAX_OUTPUT").and_then(|m| m.parse().ok()) {
+        limits.max_output = max_output;
+    }
+
+    if matches.is_present("FROZEN") {
+        limits.frozen = true;
+    }
+
+    if matches.is_present("DENY_NETWORK") {
+        limits.deny_network = true;
+    }
+
+    limits
+}
+
+
+
+// Writes content into a file, exits on failure
+fn write_file(path: &Path, content: &str) {
+    if let Err(e) = fs::File::create(path).and_then(|mut f| f.write_all(content.as_bytes())) {
+        println!("Failed to write {}: {}", path.display(), e);
+        exit(1);
+    }
+}
+
+
+// Acquires lock of build and daemon commands, exits if another instance
+// is running
+fn acquire_lock(config: &Config, matches: &ArgMatches) -> Lock {
+    let path = match matches.value_of("PREFIX") {
+        Some(prefix) => Path::new(prefix).join(LOCK_FILE_NAME),
+        None => config.lock_path(),
+    };
+    match Lock::acquire(&path, matches.is_present("FORCE")) {
+        Ok(lock) => lock,
+        Err(e) => {
+            println!("{}\nUse --force to ignore lock {}", e, path.display());
+            exit(1);
+        }
+    }
+}
+
+
+// Releases lock, exits with 128 + signal number if command is stopped by a
+// shutdown signal
+fn release_lock(lock: Lock) {
+    drop(lock);
+    if let Some(signal) = shutdown::received_signal() {
+        println!("Stopped by signal {}", signal);
+        exit(128 + signal);
+    }
+}
+
+
+// Converts bytes into megabytes
+fn megabytes(bytes: i64) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0)
+}
+
 
+
+fn main() {
     let matches = App::new("cratesfyi")
                       .version(cratesfyi::BUILD_VERSION)
                       .about("Crates for your info!")
+                      .arg(Arg::with_name("CONFIG")
+                               .short("C")
+                               .long("config")
+                               .help("Sets configuration file path, default is \
+                                      /etc/cratesfyi.toml")
+                               .takes_value(true))
+                      .arg(Arg::with_name("LOG_FORMAT")
+                               .long("log-format")
+                               .help("Sets format of log messages, text by default")
+                               .possible_values(&["text", "json"])
+                               .takes_value(true))
                       .subcommand(SubCommand::with_name("build")
                                       .about("Builds documentation in a chroot environment")
                                       .arg(Arg::with_name("PREFIX")
@@ -111,10 +165,67 @@ fn main() {
                                                .long("chroot-user")
                                                .help("Sets chroot user name")
                                                .takes_value(true))
+                                      .arg(Arg::with_name("BUILD_DIR")
+                                               .long("build-dir")
+                                               .help("Sets directory where crates are \
+                                                      downloaded and built")
+                                               .takes_value(true))
                                       .arg(Arg::with_name("CRATES_IO_INDEX_PATH")
                                                .long("crates-io-index-path")
                                                .help("Sets crates.io-index path")
                                                .takes_value(true))
+                                      .arg(Arg::with_name("DOCKER_IMAGE")
+                                               .long("docker-image")
+                                               .help("Builds documentation in a docker \
+                                                      container instead of chroot")
+                                               .takes_value(true))
+                                      .arg(Arg::with_name("TIMEOUT")
+                                               .long("timeout")
+                                               .help("Sets build timeout in seconds")
+                                               .takes_value(true))
+                                      .arg(Arg::with_name("MEMORY_LIMIT")
+                                               .long("memory-limit")
+                                               .help("Sets memory limit of build in \
+                                                      kilobytes")
+                                               .takes_value(true))
+                                      .arg(Arg::with_name("MAX_OUTPUT")
+                                               .long("max-output")
+                                               .help("Sets maximum size of build output \
+                                                      in bytes")
+                                               .takes_value(true))
+                                      .arg(Arg::with_name("FROZEN")
+                                               .long("frozen")
+                                               .help("Fetches dependencies before build and \
+                                                      builds with cargo --frozen"))
+                                      .arg(Arg::with_name("DENY_NETWORK")
+                                               .long("deny-network")
+                                               .help("Disables network access of builds in \
+                                                      docker sandbox"))
+                                      .arg(Arg::with_name("TARGET")
+                                               .long("target")
+                                               .help("Builds documentation for an additional \
+                                                      target, can be used multiple times")
+                                               .takes_value(true)
+                                               .multiple(true))
+                                      .arg(Arg::with_name("RUSTDOC_ARG")
+                                               .long("rustdoc-arg")
+                                               .help("Passes an argument to rustdoc of every \
+                                                      crate, i.e: --rustdoc-arg=--cfg, can \
+                                                      be used multiple times")
+                                               .takes_value(true)
+                                               .multiple(true))
+                                      .arg(Arg::with_name("NO_ALL_FEATURES")
+                                               .long("no-all-features")
+                                               .help("Builds crates with their default \
+                                                      features instead of all features"))
+                                      .arg(Arg::with_name("DOCUMENT_PRIVATE_ITEMS")
+                                               .long("document-private-items")
+                                               .help("Documents private items of crates"))
+                                      .arg(Arg::with_name("TOOLCHAIN")
+                                               .long("toolchain")
+                                               .help("Builds with a rustup toolchain, i.e: \
+                                                      nightly-2016-06-10")
+                                               .takes_value(true))
                                       .arg(Arg::with_name("LOGS_PATH")
                                                .long("logs-path")
                                                .help("Sets logs path")
@@ -132,8 +243,36 @@ fn main() {
                                                .short("-k")
                                                .long("keep-build-directory")
                                                .help("Keeps build directory after build."))
+                                      .arg(Arg::with_name("FORCE")
+                                               .long("force")
+                                               .help("Runs even if lock file of another \
+                                                      instance exists"))
+                                      .arg(Arg::with_name("ARCHIVE_DOCS")
+                                               .long("archive-docs")
+                                               .help("Packs documentation of releases into \
+                                                      compressed archives"))
+                                      .arg(Arg::with_name("RUN_TESTS")
+                                               .long("run-tests")
+                                               .help("Compiles (build) or runs (run) tests \
+                                                      of crates after building \
+                                                      documentation")
+                                               .possible_values(&["build", "run"])
+                                               .takes_value(true))
                                       .subcommand(SubCommand::with_name("download-sources")
                                                       .about("Downloads sources of all crates"))
+                                      .subcommand(SubCommand::with_name("queue")
+                                                      .about("Builds documentation of crates \
+                                                              in build queue")
+                                                      .arg(Arg::with_name("WORKERS")
+                                                               .long("workers")
+                                                               .help("Number of crates to \
+                                                                      build in parallel")
+                                                               .takes_value(true))
+                                                      .arg(Arg::with_name("MAX_ATTEMPTS")
+                                                               .long("max-attempts")
+                                                               .help("Maximum number of build \
+                                                                      attempts of a crate")
+                                                               .takes_value(true)))
                                       .subcommand(SubCommand::with_name("world")
                                                       .about("Builds documentation of every \
                                                               crate")
@@ -147,8 +286,14 @@ fn main() {
                                                                .long("build-only-latest-versio\
                                                                       n")
                                                                .help("Builds only latest \
-                                                                      version of crate and \
-                                                                      skips oldest versions")))
+                                                                      stable version of \
+                                                                      crates which isn't \
+                                                                      yanked"))
+                                                      .arg(Arg::with_name("RESTART")
+                                                               .long("restart")
+                                                               .help("Starts from first crate \
+                                                                      instead of resuming \
+                                                                      interrupted build")))
                                       .subcommand(SubCommand::with_name("crate")
                                                       .about("Builds documentation for a crate")
                                                       .arg(Arg::with_name("CRATE_NAME")
@@ -157,8 +302,31 @@ fn main() {
                                                                .help("Crate name"))
                                                       .arg(Arg::with_name("CRATE_VERSION")
                                                                .index(2)
+                                                               .help("Version of crate, \
+                                                                      latest version is \
+                                                                      used if it's not \
+                                                                      given")))
+                                      .subcommand(SubCommand::with_name("git")
+                                                      .about("Builds documentation for crate \
+                                                              in a git repository")
+                                                      .arg(Arg::with_name("URL")
+                                                               .index(1)
                                                                .required(true)
-                                                               .help("Version of crate"))))
+                                                               .help("URL of repository"))
+                                                      .arg(Arg::with_name("REV")
+                                                               .long("rev")
+                                                               .help("Branch, tag or commit, \
+                                                                      default branch is used \
+                                                                      if it's not given")
+                                                               .takes_value(true)))
+                                      .subcommand(SubCommand::with_name("local")
+                                                      .about("Builds documentation for a local \
+                                                              crate like a release, without \
+                                                              storing it")
+                                                      .arg(Arg::with_name("PATH")
+                                                               .index(1)
+                                                               .required(true)
+                                                               .help("Path of crate"))))
                       .subcommand(SubCommand::with_name("build-doc")
                                       .about("Builds documentation in CWD")
                                       .arg(Arg::with_name("CRATES_IO_INDEX_PATH")
@@ -166,6 +334,54 @@ fn main() {
                                                .long("crates-io-index-path")
                                                .help("Sets crates.io-index path")
                                                .takes_value(true))
+                                      .arg(Arg::with_name("TIMEOUT")
+                                               .long("timeout")
+                                               .help("Sets build timeout in seconds")
+                                               .takes_value(true))
+                                      .arg(Arg::with_name("MEMORY_LIMIT")
+                                               .long("memory-limit")
+                                               .help("Sets memory limit of build in \
+                                                      kilobytes")
+                                               .takes_value(true))
+                                      .arg(Arg::with_name("MAX_OUTPUT")
+                                               .long("max-output")
+                                               .help("Sets maximum size of build output \
+                                                      in bytes")
+                                               .takes_value(true))
+                                      .arg(Arg::with_name("FROZEN")
+                                               .long("frozen")
+                                               .help("Fetches dependencies before build and \
+                                                      builds with cargo --frozen"))
+                                      .arg(Arg::with_name("TARGET")
+                                               .long("target")
+                                               .help("Builds documentation for an additional \
+                                                      target, can be used multiple times")
+                                               .takes_value(true)
+                                               .multiple(true))
+                                      .arg(Arg::with_name("RUSTDOC_ARG")
+                                               .long("rustdoc-arg")
+                                               .help("Passes an argument to rustdoc of every \
+                                                      crate, i.e: --rustdoc-arg=--cfg, can \
+                                                      be used multiple times")
+                                               .takes_value(true)
+                                               .multiple(true))
+                                      .arg(Arg::with_name("NO_ALL_FEATURES")
+                                               .long("no-all-features")
+                                               .help("Builds crates with their default \
+                                                      features instead of all features"))
+                                      .arg(Arg::with_name("DOCUMENT_PRIVATE_ITEMS")
+                                               .long("document-private-items")
+                                               .help("Documents private items of crates"))
+                                      .arg(Arg::with_name("TOOLCHAIN")
+                                               .long("toolchain")
+                                               .help("Builds with a rustup toolchain, i.e: \
+                                                      nightly-2016-06-10")
+                                               .takes_value(true))
+                                      .arg(Arg::with_name("FEATURES")
+                                               .long("features")
+                                               .help("Builds crate with space separated \
+                                                      list of features")
+                                               .takes_value(true))
                                       .arg(Arg::with_name("CLEAN")
                                                .short("c")
                                                .long("clean")
@@ -178,24 +394,331 @@ fn main() {
                                                .index(2)
                                                .required(true)
                                                .help("Version of crate")))
+                      .subcommand(SubCommand::with_name("test-crate")
+                                      .about("Runs tests of a crate built with build-doc in \
+                                              CWD")
+                                      .arg(Arg::with_name("TIMEOUT")
+                                               .long("timeout")
+                                               .help("Sets build timeout in seconds")
+                                               .takes_value(true))
+                                      .arg(Arg::with_name("MEMORY_LIMIT")
+                                               .long("memory-limit")
+                                               .help("Sets memory limit of build in \
+                                                      kilobytes")
+                                               .takes_value(true))
+                                      .arg(Arg::with_name("MAX_OUTPUT")
+                                               .long("max-output")
+                                               .help("Sets maximum size of build output \
+                                                      in bytes")
+                                               .takes_value(true))
+                                      .arg(Arg::with_name("FROZEN")
+                                               .long("frozen")
+                                               .help("Fetches dependencies before build and \
+                                                      builds with cargo --frozen"))
+                                      .arg(Arg::with_name("RUN")
+                                               .long("run")
+                                               .help("Runs tests instead of only \
+                                                      compiling them"))
+                                      .arg(Arg::with_name("TOOLCHAIN")
+                                               .long("toolchain")
+                                               .help("Builds with a rustup toolchain, i.e: \
+                                                      nightly-2016-06-10")
+                                               .takes_value(true))
+                                      .arg(Arg::with_name("CRATE_NAME")
+                                               .index(1)
+                                               .required(true)
+                                               .help("Crate name"))
+                                      .arg(Arg::with_name("CRATE_VERSION")
+                                               .index(2)
+                                               .required(true)
+                                               .help("Version of crate")))
+                      .subcommand(SubCommand::with_name("daemon")
+                                      .about("Watches crates.io-index and builds new releases")
+                                      .arg(Arg::with_name("PREFIX")
+                                               .short("P")
+                                               .long("prefix")
+                                               .takes_value(true))
+                                      .arg(Arg::with_name("POLL_INTERVAL")
+                                               .long("poll-interval")
+                                               .help("Sets crates.io-index poll interval in \
+                                                      seconds")
+                                               .takes_value(true))
+                                      .arg(Arg::with_name("FORCE")
+                                               .long("force")
+                                               .help("Runs even if lock file of another \
+                                                      instance exists")))
                       .subcommand(SubCommand::with_name("database")
                                       .about("Database operations")
                                       .subcommand(SubCommand::with_name("init")
-                                                      .about("Initialize database. Currently \
-                                                             only creates tables in database.")))
+                                                      .about("Initialize database. Same as \
+                                                             migrate on an empty database."))
+                                      .subcommand(SubCommand::with_name("migrate")
+                                                      .about("Applies pending schema \
+                                                             migrations"))
+                                      .subcommand(SubCommand::with_name("disk-usage")
+                                                      .about("Lists crates using most \
+                                                             storage")
+                                                      .arg(Arg::with_name("LIMIT")
+                                                               .long("limit")
+                                                               .help("Number of crates to \
+                                                                      list, 20 by default")
+                                                               .takes_value(true))))
+                      .subcommand(SubCommand::with_name("queue")
+                                      .about("Build queue operations")
+                                      .subcommand(SubCommand::with_name("add")
+                                                      .about("Adds a crate into build queue")
+                                                      .arg(Arg::with_name("CRATE_NAME")
+                                                               .index(1)
+                                                               .required(true)
+                                                               .help("Crate name"))
+                                                      .arg(Arg::with_name("CRATE_VERSION")
+                                                               .index(2)
+                                                               .help("Version of crate, \
+                                                                      latest version is \
+                                                                      used if it's not \
+                                                                      given"))
+                                                      .arg(Arg::with_name("PRIORITY")
+                                                               .long("priority")
+                                                               .help("Priority of crate, \
+                                                                      crates with higher \
+                                                                      priority are built \
+                                                                      first. Downloads of \
+                                                                      crate are used by \
+                                                                      default")
+                                                               .takes_value(true))
+                                                      .arg(Arg::with_name("CRATES_IO_INDEX_PATH")
+                                                               .long("crates-io-index-path")
+                                                               .help("Sets crates.io-index \
+                                                                      path")
+                                                               .takes_value(true)))
+                                      .subcommand(SubCommand::with_name("bump")
+                                                      .about("Moves a crate to front of build \
+                                                              queue")
+                                                      .arg(Arg::with_name("CRATE_NAME")
+                                                               .index(1)
+                                                               .required(true)
+                                                               .help("Crate name"))
+                                                      .arg(Arg::with_name("CRATE_VERSION")
+                                                               .index(2)
+                                                               .help("Version of crate, every \
+                                                                      queued version is \
+                                                                      bumped if it's not \
+                                                                      given"))))
+                      .subcommand(SubCommand::with_name("blacklist")
+                                      .about("Blacklist operations, blacklisted crates are \
+                                              never built")
+                                      .subcommand(SubCommand::with_name("add")
+                                                      .about("Adds a crate into blacklist")
+                                                      .arg(Arg::with_name("CRATE_NAME")
+                                                               .index(1)
+                                                               .required(true)
+                                                               .help("Crate name"))
+                                                      .arg(Arg::with_name("REASON")
+                                                               .long("reason")
+                                                               .help("Reason of blacklisting")
+                                                               .takes_value(true)))
+                                      .subcommand(SubCommand::with_name("remove")
+                                                      .about("Removes a crate from blacklist")
+                                                      .arg(Arg::with_name("CRATE_NAME")
+                                                               .index(1)
+                                                               .required(true)
+                                                               .help("Crate name")))
+                                      .subcommand(SubCommand::with_name("list")
+                                                      .about("Lists blacklisted crates")))
+                      .subcommand(SubCommand::with_name("override")
+                                      .about("Build override operations, overrides change how \
+                                              a crate is built")
+                                      .subcommand(SubCommand::with_name("set")
+                                                      .about("Sets build override of a crate, \
+                                                              existing override is replaced")
+                                                      .arg(Arg::with_name("CRATE_NAME")
+                                                               .index(1)
+                                                               .required(true)
+                                                               .help("Crate name"))
+                                                      .arg(Arg::with_name("FEATURES")
+                                                               .long("features")
+                                                               .help("Space separated list of \
+                                                                      features")
+                                                               .takes_value(true))
+                                                      .arg(Arg::with_name("DEFAULT_TARGET")
+                                                               .long("default-target")
+                                                               .help("Target documentation \
+                                                                      is built for")
+                                                               .takes_value(true))
+                                                      .arg(Arg::with_name("TIMEOUT")
+                                                               .long("timeout")
+                                                               .help("Build timeout in \
+                                                                      seconds")
+                                                               .takes_value(true))
+                                                      .arg(Arg::with_name("MEMORY_LIMIT")
+                                                               .long("memory-limit")
+                                                               .help("Memory limit of build \
+                                                                      in kilobytes")
+                                                               .takes_value(true))
+                                                      .arg(Arg::with_name("DOCKER_IMAGE")
+                                                               .long("docker-image")
+                                                               .help("Docker image used in \
+                                                                      docker sandbox")
+                                                               .takes_value(true))
+                                                      .arg(Arg::with_name("NOTE")
+                                                               .long("note")
+                                                               .help("Why crate needs an \
+                                                                      override")
+                                                               .takes_value(true)))
+                                      .subcommand(SubCommand::with_name("remove")
+                                                      .about("Removes build override of a \
+                                                              crate")
+                                                      .arg(Arg::with_name("CRATE_NAME")
+                                                               .index(1)
+                                                               .required(true)
+                                                               .help("Crate name")))
+                                      .subcommand(SubCommand::with_name("list")
+                                                      .about("Lists build overrides")))
+                      .subcommand(SubCommand::with_name("toolchain")
+                                      .about("Toolchain operations, toolchain is switched \
+                                              for next builds")
+                                      .subcommand(SubCommand::with_name("set")
+                                                      .about("Sets active rustup toolchain \
+                                                              of builds")
+                                                      .arg(Arg::with_name("TOOLCHAIN")
+                                                               .index(1)
+                                                               .required(true)
+                                                               .help("Toolchain, i.e: \
+                                                                      nightly-2016-06-10")))
+                                      .subcommand(SubCommand::with_name("unset")
+                                                      .about("Uses toolchain of \
+                                                              configuration again"))
+                                      .subcommand(SubCommand::with_name("show")
+                                                      .about("Shows active toolchain")))
+                      .subcommand(SubCommand::with_name("delete-crate")
+                                      .about("Deletes documentation, sources, build logs \
+                                              and database rows of a crate")
+                                      .arg(Arg::with_name("CRATE_NAME")
+                                               .index(1)
+                                               .required(true)
+                                               .help("Crate name")))
+                      .subcommand(SubCommand::with_name("info")
+                                      .about("Downloads a release and prints its metadata \
+                                              without building documentation")
+                                      .arg(Arg::with_name("CRATE_NAME")
+                                               .index(1)
+                                               .required(true)
+                                               .help("Crate name"))
+                                      .arg(Arg::with_name("CRATE_VERSION")
+                                               .index(2)
+                                               .required(true)
+                                               .help("Version of crate"))
+                                      .arg(Arg::with_name("JSON")
+                                               .long("json")
+                                               .help("Prints metadata as JSON")))
+                      .subcommand(SubCommand::with_name("rebuild")
+                                      .about("Adds releases built with an old rustc into \
+                                              build queue")
+                                      .arg(Arg::with_name("RUSTC_OLDER_THAN")
+                                               .long("rustc-older-than")
+                                               .help("Rebuilds releases whose latest build \
+                                                      used a rustc older than this version")
+                                               .takes_value(true))
+                                      .subcommand(SubCommand::with_name("status")
+                                                      .about("Shows progress of rebuilds"))
+                                      .subcommand(SubCommand::with_name("crate")
+                                                      .about("Moves a release to front of \
+                                                              build queue")
+                                                      .arg(Arg::with_name("CRATE_NAME")
+                                                               .index(1)
+                                                               .required(true)
+                                                               .help("Crate name"))
+                                                      .arg(Arg::with_name("CRATE_VERSION")
+                                                               .index(2)
+                                                               .required(true)
+                                                               .help("Version of crate"))
+                                                      .arg(Arg::with_name("REQUESTED_BY")
+                                                               .long("requested-by")
+                                                               .help("Requester of rebuild, \
+                                                                      current user by \
+                                                                      default")
+                                                               .takes_value(true))
+                                                      .arg(Arg::with_name("REASON")
+                                                               .long("reason")
+                                                               .help("Reason of rebuild")
+                                                               .takes_value(true))))
+                      .subcommand(SubCommand::with_name("update-owners")
+                                      .about("Refreshes owners of every crate from crates.io"))
+                      .subcommand(SubCommand::with_name("update-yanked")
+                                      .about("Updates yanked status of releases from \
+                                              crates.io-index"))
+                      .subcommand(SubCommand::with_name("search")
+                                      .about("Searches crates")
+                                      .arg(Arg::with_name("QUERY")
+                                               .index(1)
+                                               .required(true)
+                                               .help("Search query")))
+                      .subcommand(SubCommand::with_name("sitemap")
+                                      .about("Exports sitemap.xml and sitemaps into a directory")
+                                      .arg(Arg::with_name("BASE_URL")
+                                               .long("base-url")
+                                               .required(true)
+                                               .takes_value(true)
+                                               .help("URL of cratesfyi server"))
+                                      .arg(Arg::with_name("OUTPUT")
+                                               .long("output")
+                                               .short("o")
+                                               .required(true)
+                                               .takes_value(true)
+                                               .help("Output directory")))
+                      .subcommand(SubCommand::with_name("badge")
+                                      .about("Prints documentation status badge of a crate")
+                                      .arg(Arg::with_name("CRATE_NAME")
+                                               .index(1)
+                                               .required(true)
+                                               .help("Crate name"))
+                                      .arg(Arg::with_name("CRATE_VERSION")
+                                               .index(2)
+                                               .help("Version of crate, latest release \
+                                                      is used if it's not given")))
                       .subcommand(SubCommand::with_name("web")
                                       .about("Web application")
                                       .subcommand(SubCommand::with_name("cratesfyi")
-                                                      .about("Starts cratesfyi server")))
+                                                      .about("Starts cratesfyi server")
+                                                      .arg(Arg::with_name("DESTINATION")
+                                                               .short("d")
+                                                               .long("destination")
+                                                               .help("Sets documentation \
+                                                                      path")
+                                                               .takes_value(true))))
                       .get_matches();
 
+    let mut config = match Config::load(matches.value_of("CONFIG").map(Path::new)) {
+        Ok(config) => config,
+        Err(e) => {
+            println!("Failed to load configuration: {:?}", e);
+            exit(1);
+        }
+    };
+
+    // --log-format has precedence over configuration file
+    let log_format = matches.value_of("LOG_FORMAT").map(|f| f.to_string())
+        .or(config.log_format.clone())
+        .unwrap_or("text".to_string());
+    match LogFormat::from_str(&log_format) {
+        Some(format) => logging::init(format).unwrap(),
+        None => {
+            println!("Unknown log format: {}", log_format);
+            exit(1);
+        }
+    }
+
     // DocBuilder
     if let Some(matches) = matches.subcommand_matches("build") {
+        let lock = acquire_lock(&config, &matches);
+        // current crate is finished after SIGINT or SIGTERM
+        shutdown::install_signal_handlers();
+
         let mut dbuilder = {
             if let Some(prefix) = matches.value_of("PREFIX") {
                 DocBuilder::from_prefix(PathBuf::from(prefix))
             } else {
-                DocBuilder::default()
+                config.docbuilder()
             }
         };
 
@@ -206,7 +729,7 @@ fn main() {
 
         // set chroot path
         if let Some(chroot_path) = matches.value_of("CHROOT_PATH") {
-            dbuilder.destination(PathBuf::from(chroot_path));
+            dbuilder.chroot_path(PathBuf::from(chroot_path));
         }
 
         // set chroot user name
@@ -214,6 +737,11 @@ fn main() {
             dbuilder.chroot_user(chroot_user.to_string());
         }
 
+        // set build directory, after chroot settings which are resetting it
+        if let Some(build_dir) = matches.value_of("BUILD_DIR") {
+            dbuilder.build_dir(PathBuf::from(build_dir));
+        }
+
         // set crates.io-index path
         if let Some(crates_io_index_path) = matches.value_of("CRATES_IO_INDEX_PATH") {
             dbuilder.crates_io_index_path(PathBuf::from(crates_io_index_path));
@@ -224,9 +752,47 @@ fn main() {
             dbuilder.logs_path(PathBuf::from(logs_path));
         }
 
+        // set docker image
+        if let Some(docker_image) = matches.value_of("DOCKER_IMAGE") {
+            dbuilder.docker_image(docker_image.to_string());
+        }
+
+        dbuilder.build_limits(build_limits_from_matches(&config, &matches));
+
+        // set additional targets
+        if let Some(targets) = matches.values_of("TARGET") {
+            dbuilder.targets(targets.iter().map(|t| t.to_string()).collect());
+        }
+
+        // set rustdoc arguments
+        if let Some(args) = matches.values_of("RUSTDOC_ARG") {
+            dbuilder.rustdoc_args(args.iter().map(|a| a.to_string()).collect());
+        }
+        if matches.is_present("NO_ALL_FEATURES") {
+            dbuilder.all_features(false);
+        }
+        if matches.is_present("DOCUMENT_PRIVATE_ITEMS") {
+            dbuilder.document_private_items(true);
+        }
+        if let Some(toolchain) = matches.value_of("TOOLCHAIN") {
+            dbuilder.toolchain(toolchain.to_string());
+        }
+
         dbuilder.skip_if_exists(matches.is_present("SKIP_IF_EXISTS"));
         dbuilder.skip_if_log_exists(matches.is_present("SKIP_IF_LOG_EXISTS"));
         dbuilder.keep_build_directory(matches.is_present("KEEP_BUILD_DIRECTORY"));
+        if matches.is_present("ARCHIVE_DOCS") {
+            dbuilder.archive_docs(true);
+        }
+        if let Some(mode) = matches.value_of("RUN_TESTS") {
+            dbuilder.run_tests(TestMode::from_str(mode));
+        }
+
+        // clone or update crates.io-index
+        if let Err(e) = dbuilder.update_crates_io_index() {
+            println!("Failed to update crates.io-index: {:?}", e);
+            std::process::exit(1);
+        }
 
         // check paths
         if let Err(e) = dbuilder.check_paths() {
@@ -238,39 +804,89 @@ fn main() {
         if let Some(matches) = matches.subcommand_matches("world") {
             dbuilder.build_only_latest_version(matches.is_present("BUILD_ONLY_LATEST_VERSION"));
             dbuilder.skip_oldest_versions(matches.is_present("SKIP_OLDEST_VERSIONS"));
-            if let Err(e) = dbuilder.build_doc_for_every_crate() {
+            dbuilder.resume_world_build(!matches.is_present("RESTART"));
+            let conn = config.connect_db().unwrap();
+            if let Err(e) = dbuilder.build_doc_for_every_crate(Some(&conn)) {
                 println!("Failed to build world: {:#?}", e);
             }
         } else if let Some(matches) = matches.subcommand_matches("crate") {
             // Safe to call unwrap here
             let crte_name = matches.value_of("CRATE_NAME").unwrap();
-            let version = matches.value_of("CRATE_VERSION").unwrap();
-            let crte = Crate::new(crte_name.to_string(), vec![version.to_string()]);
-
-            if let Err(e) = dbuilder.build_doc_for_crate_version(&crte, 0) {
-                match e {
-                    DocBuilderError::SkipDocumentationExists => {
-                        println!("Skipping {} documentation already exists",
-                                 crte.canonical_name(0))
-                    }
-                    _ => {
-                        println!("Failed to build documentation for {}: {:?}",
-                                 crte.canonical_name(0),
-                                 e)
+            let version = match matches.value_of("CRATE_VERSION") {
+                Some(version) => version.to_string(),
+                // latest stable version, or first version of crate if it
+                // doesn't have a stable version
+                None => match dbuilder.latest_version(crte_name) {
+                    Ok(version) => version,
+                    Err(e) => {
+                        println!("Failed to find {} in crates.io-index: {:?}", crte_name, e);
+                        exit(1);
                     }
+                },
+            };
+
+            let conn = config.connect_db().unwrap();
+            match dbuilder.build(crte_name, &version, Some(&conn)) {
+                Ok(BuildResult { error: None, .. }) => {}
+                Err(DocBuilderError::SkipDocumentationExists) => {
+                    println!("Skipping {}-{} documentation already exists", crte_name, version)
+                }
+                Ok(BuildResult { error: Some(e), .. }) | Err(e) => {
+                    println!("Failed to build documentation for {}-{}: {}", crte_name, version, e)
+                }
+            }
+        } else if let Some(matches) = matches.subcommand_matches("git") {
+            let url = matches.value_of("URL").unwrap();
+            match dbuilder.build_doc_for_git_repository(url, matches.value_of("REV")) {
+                Ok(prefix) => println!("Documentation of {} is stored in {}", url, prefix),
+                Err(e) => {
+                    println!("Failed to build documentation for {}: {}", url, e);
+                    exit(1);
+                }
+            }
+        } else if let Some(matches) = matches.subcommand_matches("local") {
+            let path = Path::new(matches.value_of("PATH").unwrap());
+            match dbuilder.build_doc_for_local_crate(path) {
+                Ok(Ok(output)) => {
+                    println!("{}", output);
+                    println!("Documentation of {} built successfully", path.display());
+                }
+                Ok(Err(failure)) => {
+                    println!("{}", failure.message());
+                    println!("Failed to build documentation for {}", path.display());
+                    exit(1);
+                }
+                Err(e) => {
+                    println!("Failed to build {}: {}", path.display(), e);
+                    exit(1);
                 }
             }
         } else if let Some(_) = matches.subcommand_matches("download-sources") {
             if let Err(e) = dbuilder.download_sources() {
                 println!("{:?}", e);
             }
+        } else if let Some(matches) = matches.subcommand_matches("queue") {
+            if let Some(workers) = matches.value_of("WORKERS").and_then(|w| w.parse().ok()) {
+                dbuilder.workers(workers);
+            }
+            if let Some(max_attempts) = matches.value_of("MAX_ATTEMPTS")
+                .and_then(|m| m.parse().ok()) {
+                dbuilder.max_attempts(max_attempts);
+            }
+            let worker_config = config.clone();
+            match dbuilder.build_packages_queue_parallel(move || worker_config.connect_db()) {
+                Ok(n) => println!("Built {} crates from queue", n),
+                Err(e) => println!("Failed to build queue: {:?}", e),
+            }
         }
+
+        release_lock(lock);
     }
 
 
     // build-doc
     else if let Some(matches) = matches.subcommand_matches("build-doc") {
-        let mut docbuilder = DocBuilder::default();
+        let mut docbuilder = config.docbuilder();
 
         // set crates.io-index path
         let crates_io_index_path = {
@@ -284,11 +900,33 @@ fn main() {
         };
 
         docbuilder.crates_io_index_path(PathBuf::from(&crates_io_index_path));
+        docbuilder.build_limits(build_limits_from_matches(&config, &matches));
+        if let Some(targets) = matches.values_of("TARGET") {
+            docbuilder.targets(targets.iter().map(|t| t.to_string()).collect());
+        }
+        if let Some(args) = matches.values_of("RUSTDOC_ARG") {
+            docbuilder.rustdoc_args(args.iter().map(|a| a.to_string()).collect());
+        }
+        if matches.is_present("NO_ALL_FEATURES") {
+            docbuilder.all_features(false);
+        }
+        if matches.is_present("DOCUMENT_PRIVATE_ITEMS") {
+            docbuilder.document_private_items(true);
+        }
+        if let Some(toolchain) = matches.value_of("TOOLCHAIN") {
+            docbuilder.toolchain(toolchain.to_string());
+        }
+        if let Some(features) = matches.value_of("FEATURES") {
+            let mut crate_features = BTreeMap::new();
+            crate_features.insert(matches.value_of("CRATE_NAME").unwrap().to_string(),
+                                  features.split_whitespace().map(|f| f.to_string()).collect());
+            docbuilder.crate_features(crate_features);
+        }
 
 
         // update crates.io-index path
-        if let Err(e) = update_crates_io_index(&crates_io_index_path) {
-            panic!("{}", e);
+        if let Err(e) = docbuilder.update_crates_io_index() {
+            panic!("{:?}", e);
         }
 
         // crates.io-index required for single crate
@@ -299,33 +937,483 @@ fn main() {
         let crte_name = matches.value_of("CRATE_NAME").unwrap();
         let version = matches.value_of("CRATE_VERSION").unwrap();
 
-        let crte = Crate::new(crte_name.to_string(), vec![version.to_string()]);
-
         if matches.is_present("CLEAN") {
             clean_build_dir().unwrap();
         }
 
-        if let Err(e) = crte.build_crate_doc(0, &docbuilder) {
+        // build-doc builds documentation in current working directory
+        let cwd = env::current_dir().unwrap();
+        if let Err(e) = docbuilder.build_crate_doc_in_dir(crte_name, version, &cwd) {
             error!("Failed to build crate\n{:?}", e);
-            exit(1);
+            // builder outside of chroot classifies build by this marker
+            if is_download_error(&e) {
+                println!("{}", DOWNLOAD_FAILED_LOG_MARKER);
+            }
+            if let DocBuilderError::CrateTooLarge(ref reason) = e {
+                println!("{}{}", CRATE_TOO_LARGE_LOG_PREFIX, reason);
+            }
+            match e {
+                DocBuilderError::BuildTimedOut => exit(BUILD_TIMED_OUT_EXIT_CODE),
+                _ => exit(1),
+            }
         } else {
             info!("Crate successfully built!");
         }
     }
 
 
+    // test-crate, runs in chroot after build-doc
+    else if let Some(matches) = matches.subcommand_matches("test-crate") {
+        let mut docbuilder = config.docbuilder();
+        docbuilder.build_limits(build_limits_from_matches(&config, &matches));
+        if let Some(toolchain) = matches.value_of("TOOLCHAIN") {
+            docbuilder.toolchain(toolchain.to_string());
+        }
+
+        let crte_name = matches.value_of("CRATE_NAME").unwrap();
+        let version = matches.value_of("CRATE_VERSION").unwrap();
+        let mode = if matches.is_present("RUN") {
+            TestMode::Run
+        } else {
+            TestMode::Build
+        };
+
+        let cwd = env::current_dir().unwrap();
+        match docbuilder.test_crate_in_dir(crte_name, version, &cwd, mode) {
+            Ok(output) => println!("{}", output),
+            Err(CommandFailure::TimedOut(output)) => {
+                println!("{}", output);
+                exit(BUILD_TIMED_OUT_EXIT_CODE);
+            }
+            Err(CommandFailure::Failed(output)) => {
+                println!("{}", output);
+                exit(1);
+            }
+        }
+    }
+
+
+    // daemon
+    else if let Some(matches) = matches.subcommand_matches("daemon") {
+        let lock = acquire_lock(&config, &matches);
+
+        let dbuilder = {
+            if let Some(prefix) = matches.value_of("PREFIX") {
+                DocBuilder::from_prefix(PathBuf::from(prefix))
+            } else {
+                config.docbuilder()
+            }
+        };
+
+        let poll_interval = matches.value_of("POLL_INTERVAL")
+            .and_then(|i| i.parse().ok())
+            .unwrap_or(daemon::DEFAULT_POLL_INTERVAL);
+
+        daemon::start_daemon(dbuilder, &config, Duration::from_secs(poll_interval));
+        release_lock(lock);
+    }
+
+
     // database operations
     else if let Some(matches) = matches.subcommand_matches("database") {
-        if let Some(_) = matches.subcommand_matches("init") {
-            let conn = db::connect_db().unwrap();
-            db::create_tables(&conn);
+        if matches.subcommand_matches("init").is_some() ||
+           matches.subcommand_matches("migrate").is_some() {
+            let conn = config.connect_db().unwrap();
+            match db::migrate(&conn) {
+                Ok(n) => println!("Applied {} migration(s), schema version is {}", n,
+                                  db::migrate::current_version(&conn).unwrap()),
+                Err(e) => panic!("Failed to migrate database: {}", e),
+            }
+        } else if let Some(matches) = matches.subcommand_matches("disk-usage") {
+            let conn = config.connect_db().unwrap();
+            let limit = matches.value_of("LIMIT").and_then(|l| l.parse().ok()).unwrap_or(20);
+            match db::disk_usage(&conn, limit) {
+                Ok(crates) => {
+                    println!("{:<40} {:>8} {:>12} {:>12} {:>12}",
+                             "crate", "releases", "docs (MB)", "sources (MB)", "total (MB)");
+                    for c in crates {
+                        println!("{:<40} {:>8} {:>12.1} {:>12.1} {:>12.1}",
+                                 c.name,
+                                 c.releases,
+                                 megabytes(c.doc_size_bytes),
+                                 megabytes(c.source_size_bytes),
+                                 megabytes(c.total_bytes()));
+                    }
+                }
+                Err(e) => {
+                    println!("Failed to get disk usage: {:?}", e);
+                    exit(1);
+                }
+            }
+        }
+    }
+
+    // queue operations
+    else if let Some(matches) = matches.subcommand_matches("queue") {
+        if let Some(matches) = matches.subcommand_matches("add") {
+            let crte_name = matches.value_of("CRATE_NAME").unwrap();
+            let version = match matches.value_of("CRATE_VERSION") {
+                Some(version) => version.to_string(),
+                None => {
+                    let mut dbuilder = config.docbuilder();
+                    if let Some(path) = matches.value_of("CRATES_IO_INDEX_PATH") {
+                        dbuilder.crates_io_index_path(PathBuf::from(path));
+                    }
+                    match dbuilder.latest_version(crte_name) {
+                        Ok(version) => version,
+                        Err(e) => {
+                            println!("Failed to find {} in crates.io-index: {:?}",
+                                     crte_name, e);
+                            exit(1);
+                        }
+                    }
+                }
+            };
+
+            let conn = config.connect_db().unwrap();
+            let res = match matches.value_of("PRIORITY").and_then(|p| p.parse().ok()) {
+                Some(priority) => {
+                    queue::add_crate_to_queue_with_priority(&conn, crte_name, &version, priority)
+                }
+                None => queue::add_crate_to_queue(&conn, crte_name, &version),
+            };
+            if let Err(e) = res {
+                println!("Failed to add {}-{} into queue: {:?}", crte_name, version, e);
+                exit(1);
+            }
+            println!("{}-{} added into build queue", crte_name, version);
+        } else if let Some(matches) = matches.subcommand_matches("bump") {
+            let crte_name = matches.value_of("CRATE_NAME").unwrap();
+            let conn = config.connect_db().unwrap();
+            match queue::bump_crate(&conn, crte_name, matches.value_of("CRATE_VERSION")) {
+                Ok(0) => {
+                    println!("{} is not in build queue", crte_name);
+                    exit(1);
+                }
+                Ok(n) => println!("Moved {} versions of {} to front of build queue", n, crte_name),
+                Err(e) => {
+                    println!("Failed to bump {}: {:?}", crte_name, e);
+                    exit(1);
+                }
+            }
+        }
+    }
+
+    // blacklist operations
+    else if let Some(matches) = matches.subcommand_matches("blacklist") {
+        let conn = config.connect_db().unwrap();
+        if let Some(matches) = matches.subcommand_matches("add") {
+            let crte_name = matches.value_of("CRATE_NAME").unwrap();
+            if let Err(e) = blacklist::add_crate(&conn, crte_name, matches.value_of("REASON")) {
+                println!("Failed to add {} into blacklist: {:?}", crte_name, e);
+                exit(1);
+            }
+            println!("{} added into blacklist", crte_name);
+        } else if let Some(matches) = matches.subcommand_matches("remove") {
+            let crte_name = matches.value_of("CRATE_NAME").unwrap();
+            match blacklist::remove_crate(&conn, crte_name) {
+                Ok(true) => println!("{} removed from blacklist", crte_name),
+                Ok(false) => println!("{} is not in blacklist", crte_name),
+                Err(e) => {
+                    println!("Failed to remove {} from blacklist: {:?}", crte_name, e);
+                    exit(1);
+                }
+            }
+        } else if let Some(_) = matches.subcommand_matches("list") {
+            match blacklist::list_crates(&conn) {
+                Ok(crates) => {
+                    for (name, reason) in crates {
+                        match reason {
+                            Some(reason) => println!("{}: {}", name, reason),
+                            None => println!("{}", name),
+                        }
+                    }
+                }
+                Err(e) => {
+                    println!("Failed to get blacklist: {:?}", e);
+                    exit(1);
+                }
+            }
         }
     }
 
+    // build override operations
+    else if let Some(matches) = matches.subcommand_matches("override") {
+        let conn = config.connect_db().unwrap();
+        if let Some(matches) = matches.subcommand_matches("set") {
+            let crte_name = matches.value_of("CRATE_NAME").unwrap();
+            let build_override = BuildOverride {
+                features: matches.value_of("FEATURES")
+                    .map(|f| f.split_whitespace().map(|f| f.to_string()).collect()),
+                default_target: matches.value_of("DEFAULT_TARGET").map(|t| t.to_string()),
+                timeout: matches.value_of("TIMEOUT").and_then(|t| t.parse().ok()),
+                memory_limit: matches.value_of("MEMORY_LIMIT").and_then(|m| m.parse().ok()),
+                docker_image: matches.value_of("DOCKER_IMAGE").map(|i| i.to_string()),
+                note: matches.value_of("NOTE").map(|n| n.to_string()),
+            };
+            if let Err(e) = overrides::set_override(&conn, crte_name, &build_override) {
+                println!("Failed to set build override of {}: {:?}", crte_name, e);
+                exit(1);
+            }
+            println!("Build override of {} is set", crte_name);
+        } else if let Some(matches) = matches.subcommand_matches("remove") {
+            let crte_name = matches.value_of("CRATE_NAME").unwrap();
+            match overrides::remove_override(&conn, crte_name) {
+                Ok(true) => println!("Build override of {} removed", crte_name),
+                Ok(false) => println!("{} doesn't have a build override", crte_name),
+                Err(e) => {
+                    println!("Failed to remove build override of {}: {:?}", crte_name, e);
+                    exit(1);
+                }
+            }
+        } else if let Some(_) = matches.subcommand_matches("list") {
+            match overrides::list_overrides(&conn) {
+                Ok(list) => {
+                    for (name, build_override) in list {
+                        println!("{}: {:?}", name, build_override);
+                    }
+                }
+                Err(e) => {
+                    println!("Failed to get build overrides: {:?}", e);
+                    exit(1);
+                }
+            }
+        }
+    }
+
+    // toolchain operations
+    else if let Some(matches) = matches.subcommand_matches("toolchain") {
+        let conn = config.connect_db().unwrap();
+        if let Some(matches) = matches.subcommand_matches("set") {
+            let name = matches.value_of("TOOLCHAIN").unwrap();
+            if !toolchain::is_valid_toolchain(name) {
+                println!("Invalid toolchain: {}", name);
+                exit(1);
+            }
+            // a toolchain missing in build environment would fail every build
+            match config.docbuilder().toolchain_version(name) {
+                Ok(rustc_version) => println!("Using {}", rustc_version.trim()),
+                Err(e) => {
+                    println!("Toolchain {} is not available in build environment: {}",
+                             name, e);
+                    exit(1);
+                }
+            }
+            if let Err(e) = toolchain::set_active_toolchain(&conn, Some(name)) {
+                println!("Failed to set active toolchain: {:?}", e);
+                exit(1);
+            }
+            println!("Active toolchain is {}", name);
+        } else if let Some(_) = matches.subcommand_matches("unset") {
+            if let Err(e) = toolchain::set_active_toolchain(&conn, None) {
+                println!("Failed to unset active toolchain: {:?}", e);
+                exit(1);
+            }
+            println!("Toolchain of configuration is used");
+        } else if let Some(_) = matches.subcommand_matches("show") {
+            match toolchain::get_active_toolchain(&conn) {
+                Ok(Some(name)) => println!("{}", name),
+                Ok(None) => println!("{}", config.toolchain.as_ref().map_or("none", |t| &t[..])),
+                Err(e) => {
+                    println!("Failed to get active toolchain: {:?}", e);
+                    exit(1);
+                }
+            }
+        }
+    }
+
+    // delete a crate
+    else if let Some(matches) = matches.subcommand_matches("delete-crate") {
+        let conn = config.connect_db().unwrap();
+        let crte_name = matches.value_of("CRATE_NAME").unwrap();
+        match config.docbuilder().delete_crate(&conn, crte_name) {
+            Ok(true) => println!("{} deleted", crte_name),
+            Ok(false) => println!("{} not found in database", crte_name),
+            Err(e) => {
+                println!("Failed to delete {}: {:?}", crte_name, e);
+                exit(1);
+            }
+        }
+    }
+
+    // release info
+    else if let Some(matches) = matches.subcommand_matches("info") {
+        let name = matches.value_of("CRATE_NAME").unwrap();
+        let version = matches.value_of("CRATE_VERSION").unwrap();
+        let release = match config.docbuilder().release_info(name, version) {
+            Ok(release) => release,
+            Err(e) => {
+                println!("Failed to get info of {}-{}: {}", name, version, e);
+                exit(1);
+            }
+        };
+        if matches.is_present("JSON") {
+            println!("{}", json::as_pretty_json(&release));
+        } else {
+            let info = &release.info;
+            println!("{} {}", info.name, info.version);
+            if let Some(ref description) = info.metadata.description {
+                println!("{}", description);
+            }
+            println!("license: {}", info.metadata.license.as_ref().map_or("none", |l| &l[..]));
+            println!("targets: {}", info.targets.iter()
+                                        .map(|t| format!("{} ({})", t.0, t.1))
+                                        .collect::<Vec<_>>()
+                                        .join(", "));
+            println!("default target: {}", info.build_metadata.target());
+            println!("features: {}", info.features.iter()
+                                         .map(|f| if f.enabled {
+                                             format!("{} (enabled)", f.name)
+                                         } else {
+                                             f.name.clone()
+                                         })
+                                         .collect::<Vec<_>>()
+                                         .join(", "));
+            println!("dependencies: {}", info.dependencies.len());
+            println!("released: {}", release.release_time.as_ref().map_or("unknown", |t| &t[..]));
+            println!("yanked: {}", release.yanked.map_or("unknown".to_string(), |y| y.to_string()));
+        }
+    }
+
+    // rebuilds
+    else if let Some(matches) = matches.subcommand_matches("rebuild") {
+        let conn = config.connect_db().unwrap();
+        if let Some(_) = matches.subcommand_matches("status") {
+            match rebuild::rebuild_progress(&conn) {
+                Ok(rebuilds) => {
+                    for r in rebuilds {
+                        println!("#{} rustc < {} started {}: {}/{} built, {} successful",
+                                 r.id, r.rustc_older_than, time::at(r.created_at).rfc822(),
+                                 r.built, r.total, r.successful);
+                    }
+                }
+                Err(e) => {
+                    println!("Failed to get progress of rebuilds: {:?}", e);
+                    exit(1);
+                }
+            }
+        } else if let Some(matches) = matches.subcommand_matches("crate") {
+            let crte_name = matches.value_of("CRATE_NAME").unwrap();
+            let version = matches.value_of("CRATE_VERSION").unwrap();
+            let requested_by = matches.value_of("REQUESTED_BY")
+                .map(|r| r.to_string())
+                .or(env::var("USER").ok())
+                .unwrap_or("cli".to_string());
+            match rebuild::request_rebuild(&conn, crte_name, version, &requested_by,
+                                           matches.value_of("REASON")) {
+                Ok(true) => println!("{}-{} moved to front of build queue", crte_name, version),
+                Ok(false) => {
+                    println!("{}-{} is not in database", crte_name, version);
+                    exit(1);
+                }
+                Err(e) => {
+                    println!("Failed to request rebuild of {}-{}: {:?}", crte_name, version, e);
+                    exit(1);
+                }
+            }
+        } else if let Some(version) = matches.value_of("RUSTC_OLDER_THAN") {
+            let version = match semver::Version::parse(version) {
+                Ok(version) => version,
+                Err(e) => {
+                    println!("Invalid rustc version {}: {:?}", version, e);
+                    exit(1);
+                }
+            };
+            match rebuild::start_rebuild(&conn, &version) {
+                Ok((id, n)) => println!("Rebuild #{} started, {} releases added into queue", id, n),
+                Err(e) => {
+                    println!("Failed to start rebuild: {:?}", e);
+                    exit(1);
+                }
+            }
+        } else {
+            println!("One of --rustc-older-than, status or crate is required");
+            exit(1);
+        }
+    }
+
+    // owners
+    else if let Some(_) = matches.subcommand_matches("update-owners") {
+        let conn = config.connect_db().unwrap();
+        match config.docbuilder().update_owners(&conn) {
+            Ok(n) => println!("Owners of {} crates updated", n),
+            Err(e) => {
+                println!("Failed to update owners: {}", e);
+                exit(1);
+            }
+        }
+    }
+
+    // yanked
+    else if let Some(_) = matches.subcommand_matches("update-yanked") {
+        let conn = config.connect_db().unwrap();
+        let dbuilder = config.docbuilder();
+        if let Err(e) = dbuilder.update_crates_io_index() {
+            println!("Failed to update crates.io-index: {}", e);
+            exit(1);
+        }
+        match dbuilder.update_yanked(&conn) {
+            Ok(n) => println!("Yanked status of {} releases changed", n),
+            Err(e) => {
+                println!("Failed to update yanked releases: {}", e);
+                exit(1);
+            }
+        }
+    }
+
+    // search
+    else if let Some(matches) = matches.subcommand_matches("search") {
+        let conn = config.connect_db().unwrap();
+        match db::search_crates(&conn, matches.value_of("QUERY").unwrap(), 20, 0) {
+            Ok(results) => {
+                for result in results {
+                    println!("{}-{}: {}", result.name, result.version,
+                             result.description.unwrap_or(String::new()));
+                }
+            }
+            Err(e) => {
+                println!("Failed to search crates: {}", e);
+                exit(1);
+            }
+        }
+    }
+
+    // sitemap
+    else if let Some(matches) = matches.subcommand_matches("sitemap") {
+        let conn = config.connect_db().unwrap();
+        let base_url = matches.value_of("BASE_URL").unwrap().trim_right_matches('/');
+        let output = PathBuf::from(matches.value_of("OUTPUT").unwrap());
+        let count = web::sitemap::sitemap_count(&conn).unwrap();
+
+        let mut urls = Vec::new();
+        for page in 1..count + 1 {
+            let entries = web::sitemap::get_sitemap_entries(&conn, page).unwrap();
+            let file_name = format!("sitemap-{}.xml", page);
+            write_file(&output.join(&file_name),
+                       &web::sitemap::sitemap(&entries, base_url));
+            urls.push(format!("{}/{}", base_url, file_name));
+        }
+
+        write_file(&output.join("sitemap.xml"), &web::sitemap::sitemap_index(&urls));
+        println!("{} sitemaps written into {}", count, output.display());
+    }
+
+    // badge
+    else if let Some(matches) = matches.subcommand_matches("badge") {
+        let conn = config.connect_db().unwrap();
+        let status = web::badge::crate_badge_status(&conn,
+                                                    matches.value_of("CRATE_NAME").unwrap(),
+                                                    matches.value_of("CRATE_VERSION"));
+        println!("{}", web::badge::badge_svg("docs", &status));
+    }
+
     // web operations
     else if let Some(matches) = matches.subcommand_matches("web") {
-        if let Some(_) = matches.subcommand_matches("cratesfyi") {
-            web::start_cratesfyi_server();
+        if let Some(matches) = matches.subcommand_matches("cratesfyi") {
+            if let Some(destination) = matches.value_of("DESTINATION") {
+                config.destination = Some(destination.to_string());
+            }
+            web::start_cratesfyi_server(&config);
         }
     }
 
@@ -0,0 +1,552 @@
+//! cratesfyi configuration
+//!
+//! Configuration is loaded from `/etc/cratesfyi.toml` by default. Every key
+//! is optional and DocBuilder defaults are used for missing keys:
+//!
+//! ```text
+//! prefix = "/home/cratesfyi"
+//! destination = "/home/cratesfyi/public_html/crates"
+//! chroot_path = "/home/cratesfyi/chroot"
+//! chroot_user = "cratesfyi"
+//! build_dir = "/home/cratesfyi/chroot/home/cratesfyi/builds"
+//! crates_io_index_path = "/home/cratesfyi/crates.io-index"
+//! logs_path = "/home/cratesfyi/logs"
+//! sources_path = "/home/cratesfyi/sources"
+//! lock_path = "/home/cratesfyi/cratesfyi.lock"
+//! docker_image = "cratesfyi/build"
+//! targets = [ "x86_64-pc-windows-gnu", "i686-unknown-linux-gnu" ]
+//! rustdoc_args = [ "--cfg", "docsrs" ]
+//! all_features = true
+//! document_private_items = false
+//! toolchain = "nightly-2016-06-10"
+//! workers = 4
+//! max_attempts = 5
+//! remove_yanked_docs_after = 30
+//! run_tests = "build"
+//! frozen = true
+//! deny_network = true
+//! database_url = "postgresql://cratesfyi@localhost"
+//! database_ssl_mode = "prefer"
+//! cratesio_contact = "admin@example.com"
+//! cratesio_rate_limit = 1.0
+//! cratesio_cache_path = "/home/cratesfyi/cache/crates.io"
+//! crate_cache_path = "/home/cratesfyi/cache/crates"
+//! crate_cache_max_size = 10240
+//! download_retries = 3
+//! download_retry_delay = 2
+//! download_urls = [ "https://static.crates.io/crates/{crate}/{crate}-{version}.crate",
+//!                   "https://mirror.example.com/crates/{crate}/{crate}-{version}.crate" ]
+//! max_extracted_size = 512
+//! max_extracted_files = 50000
+//! max_extracted_file_size = 256
+//! s3_endpoint = "https://s3.amazonaws.com"
+//! s3_bucket = "cratesfyi-docs"
+//! s3_region = "us-east-1"
+//! s3_access_key = "ACCESS_KEY"
+//! s3_secret_key = "SECRET_KEY"
+//! archive_docs = true
+//! precompress_docs = true
+//! notify_webhook_url = "https://example.com/hooks/cratesfyi"
+//! notify_email_to = [ "admin@example.com" ]
+//! notify_email_from = "cratesfyi@example.com"
+//! sendmail_path = "/usr/sbin/sendmail"
+//! notify_events = [ "failed", "regressed" ]
+//! web_address = "localhost:3000"
+//! health_max_index_age = 3600
+//! health_max_queue_length = 1000
+//! health_max_build_age = 86400
+//! log_format = "json"
+//!
+//! [admin_tokens]
+//! onur = "TOKEN"
+//!
+//! [default_targets]
+//! winapi = "x86_64-pc-windows-msvc"
+//!
+//! [crate_features]
+//! openssl = [ "v102", "v110" ]
+//!
+//! [registry]
+//! index_url = "https://git.example.com/crates-index.git"
+//! dl = "https://crates.example.com/api/v1/crates"
+//! api = "https://crates.example.com"
+//! ```
+
+use std::io::prelude::*;
+use std::io;
+use std::fs;
+use std::collections::BTreeMap;
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+use toml;
+use postgres::Connection;
+use postgres::error::ConnectError;
+use docbuilder::{DocBuilder, CrateCache, ExtractLimits};
+use docbuilder::builds::TestMode;
+use docbuilder::limits::BuildLimits;
+use docbuilder::download::RetryPolicy;
+use docbuilder::cratesio_api::{CratesIoClient, DEFAULT_CONTACT, DEFAULT_RATE_LIMIT};
+use docbuilder::s3::{self, S3Client};
+use docbuilder::storage::{Storage, FileStorage};
+use docbuilder::archive::ArchiveStorage;
+use docbuilder::notify::{Notifier, BuildEvent};
+use docbuilder::registry::Registry;
+use lock::LOCK_FILE_NAME;
+use web::health::HealthThresholds;
+use db::{self, DbSslMode, DB_CONNECTION_STR, DATABASE_URL_ENV, DATABASE_SSL_MODE_ENV};
+
+
+/// Default path of configuration file
+pub const DEFAULT_CONFIG_PATH: &'static str = "/etc/cratesfyi.toml";
+
+/// Default listen address of web server
+pub const DEFAULT_WEB_ADDRESS: &'static str = "localhost:3000";
+
+
+#[derive(Debug, Default, Clone, RustcDecodable)]
+pub struct Config {
+    pub prefix: Option<String>,
+    pub destination: Option<String>,
+    pub chroot_path: Option<String>,
+    pub chroot_user: Option<String>,
+    /// Directory where crates are downloaded and built, home directory of
+    /// chroot user by default
+    pub build_dir: Option<String>,
+    pub crates_io_index_path: Option<String>,
+    pub logs_path: Option<String>,
+    pub sources_path: Option<String>,
+    /// Lock file of build and daemon commands, cratesfyi.lock in prefix by
+    /// default
+    pub lock_path: Option<String>,
+    pub docker_image: Option<String>,
+    /// Additional targets to build documentation for
+    pub targets: Option<Vec<String>>,
+    /// Arguments passed to rustdoc of every crate
+    pub rustdoc_args: Option<Vec<String>>,
+    /// Builds crates with all features, true by default
+    pub all_features: Option<bool>,
+    /// Documents private items of every crate
+    pub document_private_items: Option<bool>,
+    /// rustup toolchain of builds, cargo on PATH is used if it's not set
+    pub toolchain: Option<String>,
+    /// Number of workers building crates in queue in parallel
+    pub workers: Option<usize>,
+    /// Maximum number of build attempts of a queued crate
+    pub max_attempts: Option<i32>,
+    /// Documentation of releases yanked longer than this many days is
+    /// removed, documentation of yanked releases is kept if it's not set
+    pub remove_yanked_docs_after: Option<u64>,
+    /// Compiles ("build") or runs ("run") tests of crates after build
+    pub run_tests: Option<String>,
+    /// Builds are using pre-fetched dependencies
+    pub frozen: Option<bool>,
+    /// Builds in docker sandbox have no network access
+    pub deny_network: Option<bool>,
+    pub database_url: Option<String>,
+    pub database_ssl_mode: Option<String>,
+    /// Contact address sent to crates.io in User-Agent
+    pub cratesio_contact: Option<String>,
+    /// Maximum number of crates.io API requests per second, 0 disables limit
+    pub cratesio_rate_limit: Option<f64>,
+    /// Directory to cache crates.io API responses
+    pub cratesio_cache_path: Option<String>,
+    /// Directory to cache downloaded crate files
+    pub crate_cache_path: Option<String>,
+    /// Maximum size of crate file cache in megabytes
+    pub crate_cache_max_size: Option<u64>,
+    /// Number of retries of a failed crate download
+    pub download_retries: Option<u32>,
+    /// Delay before first retry of a failed download in seconds, doubles
+    /// after every retry
+    pub download_retry_delay: Option<u64>,
+    /// URL templates of crate downloads, mirrors are tried in order
+    pub download_urls: Option<Vec<String>>,
+    /// Registry crates are documented from, crates.io by default
+    pub registry: Option<Registry>,
+    /// Maximum size of extracted crate in megabytes
+    pub max_extracted_size: Option<u64>,
+    /// Maximum number of files in a crate archive
+    pub max_extracted_files: Option<u64>,
+    /// Maximum size of a single file in a crate archive in megabytes
+    pub max_extracted_file_size: Option<u64>,
+    /// Documentation is stored in S3 bucket if s3_bucket is set
+    pub s3_endpoint: Option<String>,
+    pub s3_bucket: Option<String>,
+    pub s3_region: Option<String>,
+    pub s3_access_key: Option<String>,
+    pub s3_secret_key: Option<String>,
+    /// Packs documentation of releases into compressed archives
+    pub archive_docs: Option<bool>,
+    /// Stores a gzipped copy of static text files of documentation
+    pub precompress_docs: Option<bool>,
+    /// Failed builds are POSTed to this URL as JSON
+    pub notify_webhook_url: Option<String>,
+    /// Recipients of failed build emails
+    pub notify_email_to: Option<Vec<String>>,
+    pub notify_email_from: Option<String>,
+    pub sendmail_path: Option<String>,
+    /// Events notifications are sent for: "failed" and "regressed"
+    pub notify_events: Option<Vec<String>>,
+    pub web_address: Option<String>,
+    /// Maximum age of crates.io-index HEAD commit in seconds before /health
+    /// reports unhealthy
+    pub health_max_index_age: Option<i64>,
+    /// Maximum length of build queue before /health reports unhealthy
+    pub health_max_queue_length: Option<i64>,
+    /// Maximum time since last successful build in seconds before /health
+    /// reports unhealthy
+    pub health_max_build_age: Option<i64>,
+    /// Format of log messages, "text" or "json"
+    pub log_format: Option<String>,
+    /// Tokens of admin API keyed by their owners
+    pub admin_tokens: Option<BTreeMap<String, String>>,
+    /// Default targets of crates keyed by crate name, they are preferred
+    /// over `default-target` of crates
+    pub default_targets: Option<BTreeMap<String, String>>,
+    /// Features of crates keyed by crate name, they are preferred over
+    /// features in metadata of crates
+    pub crate_features: Option<BTreeMap<String, Vec<String>>>,
+}
+
+
+#[derive(Debug)]
+pub enum ConfigError {
+    IoError(io::Error),
+    ParseError,
+}
+
+
+impl Config {
+    /// Loads configuration from path. If path is not given default
+    /// configuration path is used and a missing default file is not an error.
+    pub fn load(path: Option<&Path>) -> Result<Config, ConfigError> {
+        let path = match path {
+            Some(path) => PathBuf::from(path),
+            None => {
+                let path = PathBuf::from(DEFAULT_CONFIG_PATH);
+                if !path.exists() {
+                    return Ok(Config::default());
+                }
+                path
+            }
+        };
+
+        let mut content = String::new();
+        try!(fs::File::open(&path)
+             .and_then(|mut f| f.read_to_string(&mut content))
+             .map_err(ConfigError::IoError));
+
+        Config::from_str(&content)
+    }
+
+
+    /// Parses configuration from a TOML string
+    pub fn from_str(content: &str) -> Result<Config, ConfigError> {
+        toml::decode_str(content).ok_or(ConfigError::ParseError)
+    }
+
+
+    /// Returns a DocBuilder configured with this configuration
+    pub fn docbuilder(&self) -> DocBuilder {
+        let mut docbuilder = match self.prefix {
+            Some(ref prefix) => DocBuilder::from_prefix(PathBuf::from(prefix)),
+            None => DocBuilder::default(),
+        };
+
+        if let Some(ref destination) = self.destination {
+            docbuilder.destination(PathBuf::from(destination));
+        }
+        if let Some(ref chroot_path) = self.chroot_path {
+            docbuilder.chroot_path(PathBuf::from(chroot_path));
+        }
+        if let Some(ref chroot_user) = self.chroot_user {
+            docbuilder.chroot_user(chroot_user.clone());
+        }
+        if let Some(ref build_dir) = self.build_dir {
+            docbuilder.build_dir(PathBuf::from(build_dir));
+        }
+        if let Some(ref crates_io_index_path) = self.crates_io_index_path {
+            docbuilder.crates_io_index_path(PathBuf::from(crates_io_index_path));
+        }
+        if let Some(ref logs_path) = self.logs_path {
+            docbuilder.logs_path(PathBuf::from(logs_path));
+        }
+        if let Some(ref sources_path) = self.sources_path {
+            docbuilder.sources_path(PathBuf::from(sources_path));
+        }
+        if let Some(ref docker_image) = self.docker_image {
+            docbuilder.docker_image(docker_image.clone());
+        }
+        if let Some(ref targets) = self.targets {
+            docbuilder.targets(targets.clone());
+        }
+        if let Some(ref default_targets) = self.default_targets {
+            docbuilder.default_targets(default_targets.clone());
+        }
+        if let Some(all_features) = self.all_features {
+            docbuilder.all_features(all_features);
+        }
+        if let Some(ref crate_features) = self.crate_features {
+            docbuilder.crate_features(crate_features.clone());
+        }
+        if let Some(ref rustdoc_args) = self.rustdoc_args {
+            docbuilder.rustdoc_args(rustdoc_args.clone());
+        }
+        if let Some(document_private_items) = self.document_private_items {
+            docbuilder.document_private_items(document_private_items);
+        }
+        if let Some(ref toolchain) = self.toolchain {
+            docbuilder.toolchain(toolchain.clone());
+        }
+        if let Some(workers) = self.workers {
+            docbuilder.workers(workers);
+        }
+        if let Some(max_attempts) = self.max_attempts {
+            docbuilder.max_attempts(max_attempts);
+        }
+        if let Some(days) = self.remove_yanked_docs_after {
+            docbuilder.remove_yanked_docs(Some(Duration::from_secs(days * 24 * 60 * 60)));
+        }
+        if let Some(ref run_tests) = self.run_tests {
+            docbuilder.run_tests(TestMode::from_str(run_tests));
+        }
+        docbuilder.build_limits(self.build_limits());
+        if let Some(ref registry) = self.registry {
+            docbuilder.registry(registry.clone());
+        }
+        if self.cratesio_contact.is_some() || self.cratesio_rate_limit.is_some() ||
+           self.cratesio_cache_path.is_some() || self.registry.is_some() {
+            let contact = self.cratesio_contact.as_ref().map(|c| &c[..])
+                .unwrap_or(DEFAULT_CONTACT);
+            let rate_limit = self.cratesio_rate_limit.unwrap_or(DEFAULT_RATE_LIMIT);
+            let mut client = CratesIoClient::new(contact, Some(rate_limit));
+            if let Some(ref cache_path) = self.cratesio_cache_path {
+                client.cache_dir(PathBuf::from(cache_path));
+            }
+            if let Some(ref registry) = self.registry {
+                client.api_url(registry.api_url());
+            }
+            docbuilder.cratesio_client(client);
+        }
+        if let Some(ref crate_cache_path) = self.crate_cache_path {
+            let mut cache = CrateCache::new(PathBuf::from(crate_cache_path));
+            cache.max_size(self.crate_cache_max_size.map(|size| size * 1024 * 1024));
+            docbuilder.crate_cache(cache);
+        }
+        if self.download_retries.is_some() || self.download_retry_delay.is_some() {
+            let mut policy = RetryPolicy::default();
+            if let Some(retries) = self.download_retries {
+                policy.retries = retries;
+            }
+            if let Some(delay) = self.download_retry_delay {
+                policy.delay = Duration::from_secs(delay);
+            }
+            docbuilder.download_retry(policy);
+        }
+        let mut extract_limits = ExtractLimits::default();
+        if let Some(size) = self.max_extracted_size {
+            extract_limits.max_size = size * 1024 * 1024;
+        }
+        if let Some(files) = self.max_extracted_files {
+            extract_limits.max_files = files;
+        }
+        if let Some(size) = self.max_extracted_file_size {
+            extract_limits.max_file_size = size * 1024 * 1024;
+        }
+        docbuilder.extract_limits(extract_limits);
+        if let Some(ref urls) = self.download_urls {
+            docbuilder.download_urls(urls.clone());
+        }
+        if let Some(client) = self.s3_client() {
+            docbuilder.storage(Arc::new(client));
+        }
+        if let Some(archive_docs) = self.archive_docs {
+            docbuilder.archive_docs(archive_docs);
+        }
+        if let Some(precompress_docs) = self.precompress_docs {
+            docbuilder.precompress_docs(precompress_docs);
+        }
+        docbuilder.notifier(self.notifier());
+
+        docbuilder
+    }
+
+
+    /// Returns notifier of failed builds
+    pub fn notifier(&self) -> Notifier {
+        let mut notifier = Notifier::default();
+        if let Some(ref url) = self.notify_webhook_url {
+            notifier.webhook_url(url.clone());
+        }
+        if let Some(ref to) = self.notify_email_to {
+            notifier.email_to(to.clone());
+        }
+        if let Some(ref from) = self.notify_email_from {
+            notifier.email_from(from.clone());
+        }
+        if let Some(ref path) = self.sendmail_path {
+            notifier.sendmail_path(PathBuf::from(path));
+        }
+        if let Some(ref events) = self.notify_events {
+            notifier.events(events.iter().filter_map(|e| BuildEvent::from_str(e)).collect());
+        }
+        notifier
+    }
+
+
+    /// Returns default build limits with configured restrictions
+    pub fn build_limits(&self) -> BuildLimits {
+        let mut limits = BuildLimits::default();
+        limits.frozen = self.frozen.unwrap_or(false);
+        limits.deny_network = self.deny_network.unwrap_or(false);
+        limits
+    }
+
+
+    /// Returns S3 client if a bucket is configured
+    pub fn s3_client(&self) -> Option<S3Client> {
+        self.s3_bucket.as_ref().map(|bucket| {
+            S3Client::new(self.s3_endpoint.as_ref().map(|e| &e[..]).unwrap_or(s3::DEFAULT_ENDPOINT),
+                          bucket,
+                          self.s3_region.as_ref().map(|r| &r[..]).unwrap_or(s3::DEFAULT_REGION),
+                          self.s3_access_key.as_ref().map(|k| &k[..]).unwrap_or(""),
+                          self.s3_secret_key.as_ref().map(|k| &k[..]).unwrap_or(""))
+        })
+    }
+
+
+    /// Returns storage of documentation, S3 bucket is used if it's
+    /// configured
+    pub fn storage(&self) -> Arc<Storage> {
+        if let Some(client) = self.s3_client() {
+            return Arc::new(client);
+        }
+        if self.archive_docs.unwrap_or(false) {
+            return Arc::new(ArchiveStorage::new(self.destination_path()));
+        }
+        Arc::new(FileStorage::new(self.destination_path()))
+    }
+
+
+    /// Returns documentation path used by web server
+    pub fn destination_path(&self) -> PathBuf {
+        if let Some(ref destination) = self.destination {
+            return PathBuf::from(destination);
+        }
+        let prefix = match self.prefix {
+            Some(ref prefix) => PathBuf::from(prefix),
+            None => env::current_dir().unwrap(),
+        };
+        prefix.join("public_html/crates")
+    }
+
+
+    /// Returns path of stored sources used by source browser
+    pub fn sources_path(&self) -> PathBuf {
+        if let Some(ref sources_path) = self.sources_path {
+            return PathBuf::from(sources_path);
+        }
+        let prefix = match self.prefix {
+            Some(ref prefix) => PathBuf::from(prefix),
+            None => env::current_dir().unwrap(),
+        };
+        prefix.join("sources")
+    }
+
+
+    /// Returns path of lock file used by build and daemon commands
+    pub fn lock_path(&self) -> PathBuf {
+        if let Some(ref lock_path) = self.lock_path {
+            return PathBuf::from(lock_path);
+        }
+        let prefix = match self.prefix {
+            Some(ref prefix) => PathBuf::from(prefix),
+            None => env::current_dir().unwrap(),
+        };
+        prefix.join(LOCK_FILE_NAME)
+    }
+
+
+    /// Returns database connection string. CRATESFYI_DATABASE_URL environment
+    /// variable has precedence over configuration file.
+    pub fn database_url(&self) -> String {
+        env::var(DATABASE_URL_ENV).ok()
+            .or(self.database_url.clone())
+            .unwrap_or(DB_CONNECTION_STR.to_string())
+    }
+
+
+    /// Returns SSL mode of database connection. CRATESFYI_DATABASE_SSLMODE
+    /// environment variable has precedence over configuration file.
+    pub fn database_ssl_mode(&self) -> DbSslMode {
+        env::var(DATABASE_SSL_MODE_ENV).ok()
+            .or(self.database_ssl_mode.clone())
+            .and_then(|m| DbSslMode::from_str(&m))
+            .unwrap_or(DbSslMode::Disable)
+    }
+
+
+    /// Connects to database using configured connection string and SSL mode
+    pub fn connect_db(&self) -> Result<Connection, ConnectError> {
+        db::connect_db_url(&self.database_url(), self.database_ssl_mode())
+    }
+
+
+    /// Returns listen address of web server
+    pub fn web_address(&self) -> String {
+        self.web_address.clone().unwrap_or(DEFAULT_WEB_ADDRESS.to_string())
+    }
+
+
+    /// Returns thresholds of /health, defaults are used for missing keys
+    pub fn health_thresholds(&self) -> HealthThresholds {
+        let mut thresholds = HealthThresholds::default();
+        if let Some(age) = self.health_max_index_age {
+            thresholds.max_index_age = age;
+        }
+        if let Some(length) = self.health_max_queue_length {
+            thresholds.max_queue_length = length;
+        }
+        if let Some(age) = self.health_max_build_age {
+            thresholds.max_build_age = age;
+        }
+        thresholds
+    }
+
+
+    /// Returns tokens of admin API keyed by their owners
+    pub fn admin_tokens(&self) -> BTreeMap<String, String> {
+        self.admin_tokens.clone().unwrap_or(BTreeMap::new())
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_config_from_str() {
+        let config = Config::from_str("prefix = \"/home/cratesfyi\"\n\
+                                       database_url = \"postgresql://test@db\"").unwrap();
+        assert_eq!(config.prefix, Some("/home/cratesfyi".to_string()));
+        assert_eq!(config.database_url.as_ref().unwrap(), "postgresql://test@db");
+        assert_eq!(config.web_address(), DEFAULT_WEB_ADDRESS);
+        assert!(config.destination.is_none());
+        assert!(config.admin_tokens().is_empty());
+
+        let config = Config::from_str("workers = 2\n[admin_tokens]\nonur = \"secret\"").unwrap();
+        assert_eq!(config.admin_tokens().get("onur").map(|t| &t[..]), Some("secret"));
+
+        let config = Config::from_str("[registry]\n\
+                                       index_url = \"https://git.example.com/index.git\"\n\
+                                       dl = \"https://crates.example.com/dl\"\n\
+                                       api = \"https://crates.example.com\"").unwrap();
+        assert_eq!(config.registry.map(|r| r.api_url()),
+                   Some("https://crates.example.com/api/v1".to_string()));
+    }
+}
@@ -0,0 +1,94 @@
+//! Registry watcher daemon
+//!
+//! Daemon periodically updates crates.io-index, adds newly published
+//! versions into build queue and builds queued crates. Yanked status of
+//! releases is synchronized once in YANKED_UPDATE_INTERVAL and owners of
+//! crates are refreshed once in OWNERS_UPDATE_INTERVAL. It stops after
+//! receiving SIGINT or SIGTERM once current iteration is finished.
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+use docbuilder::DocBuilder;
+use config::Config;
+use shutdown::{install_signal_handlers, shutdown_requested};
+
+
+/// Default poll interval of daemon in seconds
+pub const DEFAULT_POLL_INTERVAL: u64 = 60;
+
+/// Interval of yanked status synchronization in seconds
+pub const YANKED_UPDATE_INTERVAL: u64 = 60 * 60;
+
+/// Interval of owners update in seconds
+pub const OWNERS_UPDATE_INTERVAL: u64 = 24 * 60 * 60;
+
+
+/// Starts registry watcher daemon. This function only returns after
+/// receiving a shutdown signal.
+pub fn start_daemon(docbuilder: DocBuilder, config: &Config, poll_interval: Duration) {
+    install_signal_handlers();
+
+    info!("Starting cratesfyi daemon, poll interval: {} seconds",
+          poll_interval.as_secs());
+
+    let mut last_yanked_update: Option<Instant> = None;
+    let mut last_owners_update: Option<Instant> = None;
+
+    while !shutdown_requested() {
+        // connecting database in every iteration to survive database restarts
+        match config.connect_db() {
+            Ok(conn) => {
+                match docbuilder.add_new_crates_to_queue(&conn) {
+                    Ok(n) => info!("{} new versions added into build queue", n),
+                    Err(e) => error!("Failed to queue new crates: {:?}", e),
+                }
+
+                let worker_config = config.clone();
+                match docbuilder.build_packages_queue_parallel(move || {
+                    worker_config.connect_db()
+                }) {
+                    Ok(n) => info!("{} crates built from queue", n),
+                    Err(e) => error!("Failed to build queue: {:?}", e),
+                }
+
+                if is_due(last_yanked_update, YANKED_UPDATE_INTERVAL) && !shutdown_requested() {
+                    match docbuilder.update_yanked(&conn) {
+                        Ok(n) => info!("Yanked status of {} releases changed", n),
+                        Err(e) => error!("Failed to update yanked releases: {:?}", e),
+                    }
+                    last_yanked_update = Some(Instant::now());
+                }
+
+                if is_due(last_owners_update, OWNERS_UPDATE_INTERVAL) && !shutdown_requested() {
+                    match docbuilder.update_owners(&conn) {
+                        Ok(n) => info!("Owners of {} crates updated", n),
+                        Err(e) => error!("Failed to update owners: {}", e),
+                    }
+                    last_owners_update = Some(Instant::now());
+                }
+            }
+            Err(e) => error!("Failed to connect database: {:?}", e),
+        }
+
+        sleep(poll_interval);
+    }
+
+    info!("cratesfyi daemon stopped");
+}
+
+
+// Returns true if a periodic task never run or interval is passed since last run
+fn is_due(last_run: Option<Instant>, interval: u64) -> bool {
+    last_run.map(|t| t.elapsed() >= Duration::from_secs(interval)).unwrap_or(true)
+}
+
+
+// Sleeps in short periods to react shutdown signals quickly
+fn sleep(duration: Duration) {
+    let mut remaining = duration.as_secs();
+    while remaining > 0 && !shutdown_requested() {
+        thread::sleep(Duration::from_secs(1));
+        remaining -= 1;
+    }
+}
@@ -0,0 +1,491 @@
+//! Database schema migrations
+//!
+//! Every schema change is a numbered migration. Applied migrations are
+//! recorded in schema_versions table and only newer migrations are run by
+//! `cratesfyi database migrate`. New migrations must be appended to the end
+//! of MIGRATIONS with an increasing version number.
+
+use postgres::Connection;
+use postgres::error::Error;
+
+
+/// A schema migration
+pub struct Migration {
+    pub version: i32,
+    pub description: &'static str,
+    pub queries: &'static [&'static str],
+}
+
+
+/// All migrations in order
+pub const MIGRATIONS: &'static [Migration] = &[
+    Migration {
+        version: 1,
+        description: "Initial database schema",
+        queries: &[
+            "CREATE TABLE IF NOT EXISTS crates ( \
+                id SERIAL, \
+                name text UNIQUE NOT NULL, \
+                latest_version_id INT DEFAULT 0, \
+                stars INT DEFAULT 0, \
+                issues JSON, \
+                versions JSON DEFAULT '[]', \
+                downloads_total INT DEFAULT 0, \
+                github_last_update TIMESTAMP \
+            )",
+            "CREATE TABLE IF NOT EXISTS releases ( \
+                id SERIAL, \
+                crate_id INT NOT NULL, \
+                version TEXT, \
+                release_time TIMESTAMP, \
+                dependencies JSON, \
+                yanked BOOL DEFAULT FALSE, \
+                build_status INT DEFAULT 0, \
+                rustdoc_status INT DEFAULT 0, \
+                test_status INT DEFAULT 0, \
+                license TEXT, \
+                repository_url TEXT, \
+                homepage_url TEXT, \
+                description TEXT, \
+                description_long TEXT, \
+                readme TEXT, \
+                authors JSON, \
+                keywords JSON, \
+                have_examples BOOL DEFAULT FALSE, \
+                downloads INT DEFAULT 0, \
+                UNIQUE (crate_id, version) \
+            )",
+            "CREATE TABLE IF NOT EXISTS authors ( \
+                id SERIAL, \
+                name TEXT NOT NULL, \
+                email TEXT, \
+                slug TEXT UNIQUE NOT NULL \
+            )",
+            "CREATE TABLE IF NOT EXISTS author_rels ( \
+                rid INT, \
+                aid INT, \
+                UNIQUE(rid, aid) \
+            )",
+            "CREATE TABLE IF NOT EXISTS keywords ( \
+                id SERIAL, \
+                name TEXT, \
+                slug TEXT NOT NULL UNIQUE \
+            )",
+            "CREATE TABLE IF NOT EXISTS keyword_rels ( \
+                rid INT, \
+                kid INT, \
+                UNIQUE(rid, kid) \
+            )",
+            "CREATE TABLE IF NOT EXISTS owners ( \
+                id SERIAL, \
+                login TEXT NOT NULL UNIQUE, \
+                slug TEXT NOT NULL UNIQUE, \
+                avatar TEXT, \
+                name TEXT, \
+                email TEXT \
+            )",
+            "CREATE TABLE IF NOT EXISTS owner_rels ( \
+                cid INT, \
+                oid INT, \
+                UNIQUE(cid, oid) \
+            )"
+        ],
+    },
+    Migration {
+        version: 2,
+        description: "Store build configuration of releases",
+        queries: &["ALTER TABLE releases ADD COLUMN build_config JSON"],
+    },
+    Migration {
+        version: 3,
+        description: "Add build queue",
+        queries: &[
+            "CREATE TABLE queue ( \
+                id SERIAL, \
+                name TEXT, \
+                version TEXT, \
+                attempt INT DEFAULT 0, \
+                date_added TIMESTAMP DEFAULT CURRENT_TIMESTAMP, \
+                UNIQUE(name, version) \
+            )",
+        ],
+    },
+    Migration {
+        version: 4,
+        description: "Add config table",
+        queries: &[
+            "CREATE TABLE config ( \
+                name VARCHAR(100) NOT NULL PRIMARY KEY, \
+                value JSON NOT NULL \
+            )",
+        ],
+    },
+    Migration {
+        version: 5,
+        description: "Add builds table",
+        queries: &[
+            "CREATE TABLE builds ( \
+                id SERIAL, \
+                rid INT NOT NULL, \
+                rustc_version TEXT NOT NULL, \
+                cratesfyi_version TEXT NOT NULL, \
+                output TEXT, \
+                successful BOOL NOT NULL, \
+                timed_out BOOL NOT NULL DEFAULT FALSE, \
+                build_time TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP \
+            )",
+            "CREATE INDEX builds_rid_idx ON builds (rid)",
+        ],
+    },
+    Migration {
+        version: 6,
+        description: "Add categories",
+        queries: &[
+            "CREATE TABLE categories ( \
+                id SERIAL, \
+                slug TEXT NOT NULL UNIQUE, \
+                name TEXT NOT NULL, \
+                description TEXT \
+            )",
+            "CREATE TABLE category_rels ( \
+                cid INT, \
+                catid INT, \
+                UNIQUE(cid, catid) \
+            )",
+        ],
+    },
+    Migration {
+        version: 7,
+        description: "Add full-text search column to releases",
+        queries: &[
+            "ALTER TABLE releases ADD COLUMN content tsvector",
+            "UPDATE releases SET content = \
+                setweight(to_tsvector(crates.name), 'A') || \
+                setweight(to_tsvector(coalesce(releases.keywords::text, '')), 'B') || \
+                setweight(to_tsvector(coalesce(releases.description, '')), 'B') || \
+                setweight(to_tsvector(coalesce(releases.readme, '')), 'C') \
+             FROM crates WHERE crates.id = releases.crate_id",
+            "CREATE INDEX releases_content_idx ON releases USING gin(content)",
+        ],
+    },
+    Migration {
+        version: 8,
+        description: "Add index on release time",
+        queries: &[
+            "CREATE INDEX releases_release_time_idx ON releases (release_time DESC)",
+        ],
+    },
+    Migration {
+        version: 9,
+        description: "Store documentation target name of releases",
+        queries: &[
+            "ALTER TABLE releases ADD COLUMN target_name TEXT",
+        ],
+    },
+    Migration {
+        version: 10,
+        description: "Store additional documentation targets of releases",
+        queries: &[
+            "ALTER TABLE releases ADD COLUMN doc_targets JSON DEFAULT '[]'",
+        ],
+    },
+    Migration {
+        version: 11,
+        description: "Store rendered README of releases",
+        queries: &[
+            "ALTER TABLE releases ADD COLUMN readme_html TEXT",
+        ],
+    },
+    Migration {
+        version: 12,
+        description: "Store every target of releases",
+        queries: &[
+            "ALTER TABLE releases ADD COLUMN targets JSON DEFAULT '[]'",
+        ],
+    },
+    Migration {
+        version: 13,
+        description: "Store features of releases",
+        queries: &[
+            "ALTER TABLE releases ADD COLUMN features JSON DEFAULT '[]'",
+        ],
+    },
+    Migration {
+        version: 14,
+        description: "Store dependencies of releases as objects",
+        queries: &[
+            // kind of dependency wasn't recorded in [name, req] pairs
+            "UPDATE releases SET dependencies = ( \
+                SELECT COALESCE(json_agg(json_build_object( \
+                    'name', dep->>0, 'req', dep->>1, 'kind', 'normal', \
+                    'optional', false, 'target', NULL)), '[]') \
+                FROM json_array_elements(releases.dependencies) AS dep) \
+             WHERE json_typeof(dependencies->0) = 'array'",
+        ],
+    },
+    Migration {
+        version: 15,
+        description: "Add blacklist of crates",
+        queries: &[
+            "CREATE TABLE blocked_crates ( \
+                name TEXT PRIMARY KEY, \
+                reason TEXT, \
+                added_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP \
+             )",
+        ],
+    },
+    Migration {
+        version: 16,
+        description: "Retry failed builds in queue with a delay",
+        queries: &[
+            "ALTER TABLE queue ADD COLUMN next_attempt_at TIMESTAMP \
+             DEFAULT CURRENT_TIMESTAMP",
+            "ALTER TABLE queue ADD COLUMN failed BOOLEAN DEFAULT FALSE",
+            // crates were given up after 5 attempts
+            "UPDATE queue SET failed = TRUE WHERE attempt >= 5",
+        ],
+    },
+    Migration {
+        version: 17,
+        description: "Add rebuilds of releases built with an old rustc",
+        queries: &[
+            "CREATE TABLE rebuilds ( \
+                id SERIAL PRIMARY KEY, \
+                rustc_older_than TEXT NOT NULL, \
+                created_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP \
+             )",
+            "CREATE TABLE rebuild_releases ( \
+                rebuild_id INT NOT NULL, \
+                rid INT NOT NULL, \
+                UNIQUE(rebuild_id, rid) \
+             )",
+        ],
+    },
+    Migration {
+        version: 18,
+        description: "Store rustc and cratesfyi versions of releases",
+        queries: &[
+            "ALTER TABLE releases ADD COLUMN rustc_version TEXT",
+            "ALTER TABLE releases ADD COLUMN cratesfyi_version TEXT",
+            // versions of latest builds
+            "UPDATE releases \
+             SET rustc_version = latest_builds.rustc_version, \
+                 cratesfyi_version = latest_builds.cratesfyi_version \
+             FROM ( \
+                SELECT DISTINCT ON (rid) rid, rustc_version, cratesfyi_version \
+                FROM builds \
+                ORDER BY rid, build_time DESC, id DESC \
+             ) AS latest_builds \
+             WHERE releases.id = latest_builds.rid",
+        ],
+    },
+    Migration {
+        version: 19,
+        description: "Store build duration and documentation size",
+        queries: &[
+            "ALTER TABLE builds ADD COLUMN build_time_ms BIGINT",
+            "ALTER TABLE releases ADD COLUMN build_time_ms BIGINT",
+            "ALTER TABLE releases ADD COLUMN doc_size_bytes BIGINT",
+        ],
+    },
+    Migration {
+        version: 20,
+        description: "Store documentation coverage of releases",
+        queries: &[
+            "ALTER TABLE releases ADD COLUMN documented_items INT",
+            "ALTER TABLE releases ADD COLUMN total_items INT",
+        ],
+    },
+    Migration {
+        version: 21,
+        description: "Store test results of builds",
+        queries: &[
+            "ALTER TABLE builds ADD COLUMN test_status INT NOT NULL DEFAULT 0",
+        ],
+    },
+    Migration {
+        version: 22,
+        description: "Mark releases without a library target",
+        queries: &[
+            "ALTER TABLE releases ADD COLUMN is_library BOOL NOT NULL DEFAULT TRUE",
+            // targets are stored as [name, kind] pairs
+            "UPDATE releases SET is_library = FALSE \
+             WHERE json_array_length(targets) > 0 AND \
+                   NOT EXISTS (SELECT 1 FROM json_array_elements(targets) AS target \
+                               WHERE target->>1 = 'lib')",
+        ],
+    },
+    Migration {
+        version: 23,
+        description: "Record download failures of builds",
+        queries: &[
+            "ALTER TABLE builds ADD COLUMN download_failed BOOL NOT NULL DEFAULT FALSE",
+        ],
+    },
+    Migration {
+        version: 24,
+        description: "Record download mirror of builds",
+        queries: &[
+            "ALTER TABLE builds ADD COLUMN download_url TEXT",
+        ],
+    },
+    Migration {
+        version: 25,
+        description: "Record yank time of releases",
+        queries: &[
+            "ALTER TABLE releases ADD COLUMN yanked_at TIMESTAMP",
+            // yank time of already yanked releases is unknown, grace period
+            // of their documentation starts now
+            "UPDATE releases SET yanked_at = CURRENT_TIMESTAMP WHERE yanked",
+        ],
+    },
+    Migration {
+        version: 26,
+        description: "Store size of sources of releases",
+        queries: &[
+            "ALTER TABLE releases ADD COLUMN source_size_bytes BIGINT",
+        ],
+    },
+    Migration {
+        version: 27,
+        description: "Prioritize build queue by downloads",
+        queries: &[
+            "ALTER TABLE queue ADD COLUMN priority BIGINT NOT NULL DEFAULT 0",
+            "UPDATE queue SET priority = downloads.total \
+             FROM ( \
+                 SELECT crates.name, SUM(releases.downloads) AS total \
+                 FROM releases \
+                 INNER JOIN crates ON crates.id = releases.crate_id \
+                 GROUP BY crates.name \
+             ) AS downloads \
+             WHERE downloads.name = queue.name AND downloads.total IS NOT NULL",
+        ],
+    },
+    Migration {
+        version: 28,
+        description: "Record rebuild requests",
+        queries: &[
+            "CREATE TABLE rebuild_requests ( \
+                id SERIAL PRIMARY KEY, \
+                name VARCHAR(255) NOT NULL, \
+                version VARCHAR(100) NOT NULL, \
+                requested_by TEXT NOT NULL, \
+                reason TEXT, \
+                requested_at TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP \
+            )",
+        ],
+    },
+    Migration {
+        version: 29,
+        description: "Add trigram index to crate names for suggestions",
+        queries: &[
+            "CREATE EXTENSION IF NOT EXISTS pg_trgm",
+            "CREATE INDEX crates_name_trgm_idx ON crates USING gin (name gin_trgm_ops)",
+        ],
+    },
+    Migration {
+        version: 30,
+        description: "Record default target of releases",
+        queries: &[
+            "ALTER TABLE releases ADD COLUMN default_target VARCHAR(100)",
+        ],
+    },
+    Migration {
+        version: 31,
+        description: "Add build overrides of crates",
+        queries: &[
+            "CREATE TABLE build_overrides ( \
+                name VARCHAR(255) PRIMARY KEY, \
+                features JSON, \
+                default_target VARCHAR(100), \
+                timeout INT, \
+                memory_limit BIGINT, \
+                docker_image VARCHAR(255), \
+                note TEXT \
+            )",
+        ],
+    },
+    Migration {
+        version: 32,
+        description: "Record if private items of releases are documented",
+        queries: &[
+            "ALTER TABLE releases ADD COLUMN document_private_items BOOL DEFAULT FALSE",
+        ],
+    },
+    Migration {
+        version: 33,
+        description: "Record rustup toolchain of builds",
+        queries: &[
+            "ALTER TABLE builds ADD COLUMN toolchain TEXT",
+        ],
+    },
+];
+
+
+fn create_schema_versions_table(conn: &Connection) -> Result<(), Error> {
+    conn.execute("CREATE TABLE IF NOT EXISTS schema_versions ( \
+                    version INT PRIMARY KEY, \
+                    description TEXT, \
+                    applied_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP \
+                  )",
+                 &[])
+        .map(|_| ())
+}
+
+
+/// Returns current schema version, 0 if no migration has been applied yet
+pub fn current_version(conn: &Connection) -> Result<i32, Error> {
+    try!(create_schema_versions_table(conn));
+    let rows = try!(conn.query("SELECT COALESCE(MAX(version), 0) FROM schema_versions", &[]));
+    Ok(rows.get(0).get(0))
+}
+
+
+/// Runs every pending migration and returns the number of applied migrations.
+/// Each migration runs in its own transaction.
+pub fn migrate(conn: &Connection) -> Result<usize, Error> {
+    let current_version = try!(current_version(conn));
+    let mut applied = 0;
+
+    for migration in MIGRATIONS.iter().filter(|m| m.version > current_version) {
+        info!("Applying migration {}: {}", migration.version, migration.description);
+        let trans = try!(conn.transaction());
+        for query in migration.queries {
+            try!(trans.execute(query, &[]));
+        }
+        try!(trans.execute("INSERT INTO schema_versions (version, description) \
+                            VALUES ($1, $2)",
+                           &[&migration.version, &migration.description]));
+        try!(trans.commit());
+        applied += 1;
+    }
+
+    Ok(applied)
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_migrations_are_ordered() {
+        let mut last_version = 0;
+        for migration in MIGRATIONS {
+            assert!(migration.version > last_version);
+            last_version = migration.version;
+        }
+    }
+
+    #[test]
+    fn test_initial_schema_is_baseline() {
+        // databases created before migrations only have baseline tables,
+        // later schema must be added by its own migration
+        for query in MIGRATIONS[0].queries {
+            assert!(!query.contains("build_config"));
+            assert!(!query.contains("queue"));
+            assert!(!query.contains("config ("));
+        }
+    }
+}
@@ -0,0 +1,282 @@
+//! Database operations
+
+pub mod migrate;
+
+use std::env;
+
+use postgres::{Connection, SslMode};
+use postgres::error::{ConnectError, Error};
+use openssl::ssl::{SslContext, SslMethod};
+use rustc_serialize::json::Json;
+
+pub use self::migrate::migrate;
+
+
+/// Default database connection string
+pub const DB_CONNECTION_STR: &'static str = "postgresql://cratesfyi@localhost";
+
+/// Environment variable to override database connection string
+pub const DATABASE_URL_ENV: &'static str = "CRATESFYI_DATABASE_URL";
+
+/// Environment variable to set SSL mode of database connection
+pub const DATABASE_SSL_MODE_ENV: &'static str = "CRATESFYI_DATABASE_SSLMODE";
+
+
+/// SSL mode of database connection
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DbSslMode {
+    /// Never use SSL
+    Disable,
+    /// Use SSL if server supports it
+    Prefer,
+    /// Fail if server doesn't support SSL
+    Require,
+}
+
+
+impl DbSslMode {
+    /// Parses SSL mode from "disable", "prefer" or "require"
+    pub fn from_str(mode: &str) -> Option<DbSslMode> {
+        match mode {
+            "disable" => Some(DbSslMode::Disable),
+            "prefer" => Some(DbSslMode::Prefer),
+            "require" => Some(DbSslMode::Require),
+            _ => None,
+        }
+    }
+}
+
+
+/// Connects to database.
+///
+/// Connection string and SSL mode are read from CRATESFYI_DATABASE_URL and
+/// CRATESFYI_DATABASE_SSLMODE environment variables if they are set.
+pub fn connect_db() -> Result<Connection, ConnectError> {
+    let url = env::var(DATABASE_URL_ENV).unwrap_or(DB_CONNECTION_STR.to_string());
+    let ssl_mode = env::var(DATABASE_SSL_MODE_ENV).ok()
+        .and_then(|m| DbSslMode::from_str(&m))
+        .unwrap_or(DbSslMode::Disable);
+    connect_db_url(&url, ssl_mode)
+}
+
+
+/// Connects to database using a connection string
+pub fn connect_db_url(url: &str, ssl_mode: DbSslMode) -> Result<Connection, ConnectError> {
+    if ssl_mode == DbSslMode::Disable {
+        return Connection::connect(url, SslMode::None);
+    }
+
+    let ssl_context = try!(SslContext::new(SslMethod::Sslv23)
+                           .map_err(|e| ConnectError::Ssl(Box::new(e))));
+
+    match ssl_mode {
+        DbSslMode::Require => Connection::connect(url, SslMode::Require(&ssl_context)),
+        _ => Connection::connect(url, SslMode::Prefer(&ssl_context)),
+    }
+}
+
+
+/// A crate found by search_crates
+#[derive(Debug)]
+pub struct SearchResult {
+    pub name: String,
+    pub version: String,
+    pub description: Option<String>,
+    pub build_status: i32,
+    pub rank: f32,
+}
+
+
+/// Updates full-text search content of a release from its name, keywords,
+/// description and readme
+pub fn update_search_index(conn: &Connection, release_id: i32) -> Result<(), Error> {
+    conn.execute("UPDATE releases SET content = \
+                      setweight(to_tsvector(crates.name), 'A') || \
+                      setweight(to_tsvector(coalesce(releases.keywords::text, '')), 'B') || \
+                      setweight(to_tsvector(coalesce(releases.description, '')), 'B') || \
+                      setweight(to_tsvector(coalesce(releases.readme, '')), 'C') \
+                  FROM crates \
+                  WHERE crates.id = releases.crate_id AND releases.id = $1",
+                 &[&release_id])
+        .map(|_| ())
+}
+
+
+/// Searches crates and returns results ordered by rank. Latest matching
+/// release of every crate is returned.
+pub fn search_crates(conn: &Connection,
+                     query: &str,
+                     limit: i64,
+                     offset: i64) -> Result<Vec<SearchResult>, Error> {
+    let rows = try!(conn.query("SELECT name, version, description, build_status, rank \
+                                FROM ( \
+                                    SELECT DISTINCT ON (crates.id) \
+                                           crates.name, \
+                                           releases.version, \
+                                           releases.description, \
+                                           releases.build_status, \
+                                           ts_rank_cd(releases.content, query) AS rank \
+                                    FROM crates \
+                                    INNER JOIN releases ON releases.crate_id = crates.id, \
+                                         plainto_tsquery($1) query \
+                                    WHERE releases.content @@ query \
+                                    ORDER BY crates.id, releases.release_time DESC \
+                                ) AS results \
+                                ORDER BY rank DESC, name \
+                                LIMIT $2 OFFSET $3",
+                               &[&query, &limit, &offset]));
+
+    Ok(rows.iter().map(|row| {
+        SearchResult {
+            name: row.get(0),
+            version: row.get(1),
+            description: row.get(2),
+            build_status: row.get(3),
+            rank: row.get(4),
+        }
+    }).collect())
+}
+
+
+/// Returns names of crates similar to given name, most similar name comes
+/// first. Names only differing in case, `-` and `_` are listed before
+/// trigram matches.
+pub fn similar_crate_names(conn: &Connection,
+                           name: &str,
+                           limit: i64) -> Result<Vec<String>, Error> {
+    let rows = try!(conn.query("SELECT name \
+                                FROM crates \
+                                WHERE name <> $1 AND \
+                                      (name % $1 OR \
+                                       lower(replace(name, '-', '_')) = \
+                                           lower(replace($1, '-', '_'))) \
+                                ORDER BY lower(replace(name, '-', '_')) = \
+                                             lower(replace($1, '-', '_')) DESC, \
+                                         similarity(name, $1) DESC, \
+                                         name \
+                                LIMIT $2",
+                               &[&name, &limit]));
+    Ok(rows.iter().map(|row| row.get(0)).collect())
+}
+
+
+/// Returns (slug, name) pairs of categories of a crate
+pub fn get_crate_categories(conn: &Connection, name: &str) -> Result<Vec<(String, String)>, Error> {
+    let rows = try!(conn.query("SELECT categories.slug, categories.name \
+                                FROM categories \
+                                INNER JOIN category_rels ON category_rels.catid = categories.id \
+                                INNER JOIN crates ON crates.id = category_rels.cid \
+                                WHERE crates.name = $1 \
+                                ORDER BY categories.name",
+                               &[&name]));
+    Ok(rows.iter().map(|row| (row.get(0), row.get(1))).collect())
+}
+
+
+/// Returns a value from config table
+pub fn get_config(conn: &Connection, name: &str) -> Result<Option<Json>, Error> {
+    let rows = try!(conn.query("SELECT value FROM config WHERE name = $1", &[&name]));
+    Ok(if rows.len() == 0 { None } else { Some(rows.get(0).get(0)) })
+}
+
+
+/// Sets a value in config table
+pub fn set_config(conn: &Connection, name: &str, value: &Json) -> Result<(), Error> {
+    let updated = try!(conn.execute("UPDATE config SET value = $2 WHERE name = $1",
+                                    &[&name, value]));
+    if updated == 0 {
+        try!(conn.execute("INSERT INTO config (name, value) VALUES ($1, $2)",
+                          &[&name, value]));
+    }
+    Ok(())
+}
+
+
+/// Storage used by a crate
+#[derive(Debug)]
+pub struct CrateDiskUsage {
+    pub name: String,
+    pub releases: i64,
+    /// Size of documentation of every release in bytes
+    pub doc_size_bytes: i64,
+    /// Size of sources of every release in bytes
+    pub source_size_bytes: i64,
+}
+
+
+impl CrateDiskUsage {
+    pub fn total_bytes(&self) -> i64 {
+        self.doc_size_bytes + self.source_size_bytes
+    }
+}
+
+
+/// Returns crates using most storage, largest crate comes first
+pub fn disk_usage(conn: &Connection, limit: i64) -> Result<Vec<CrateDiskUsage>, Error> {
+    // SUM of BIGINT is a NUMERIC
+    let rows = try!(conn.query("SELECT crates.name, \
+                                       COUNT(*), \
+                                       COALESCE(SUM(releases.doc_size_bytes), 0)::BIGINT, \
+                                       COALESCE(SUM(releases.source_size_bytes), 0)::BIGINT \
+                                FROM releases \
+                                INNER JOIN crates ON crates.id = releases.crate_id \
+                                GROUP BY crates.name \
+                                ORDER BY COALESCE(SUM(releases.doc_size_bytes), 0) + \
+                                         COALESCE(SUM(releases.source_size_bytes), 0) DESC, \
+                                         crates.name \
+                                LIMIT $1",
+                               &[&limit]));
+    Ok(rows.iter()
+        .map(|row| {
+            CrateDiskUsage {
+                name: row.get(0),
+                releases: row.get(1),
+                doc_size_bytes: row.get(2),
+                source_size_bytes: row.get(3),
+            }
+        })
+        .collect())
+}
+
+
+/// Deletes a crate and every row of its releases, builds and relations in a
+/// single transaction. Crate is also removed from build queue. Returns false
+/// if crate doesn't exist.
+pub fn delete_crate(conn: &Connection, name: &str) -> Result<bool, Error> {
+    let trans = try!(conn.transaction());
+    try!(trans.execute("DELETE FROM queue WHERE name = $1", &[&name]));
+
+    let rows = try!(trans.query("SELECT id FROM crates WHERE name = $1", &[&name]));
+    if rows.len() == 0 {
+        try!(trans.commit());
+        return Ok(false);
+    }
+    let crate_id: i32 = rows.get(0).get(0);
+
+    for query in &["DELETE FROM builds WHERE rid IN \
+                        (SELECT id FROM releases WHERE crate_id = $1)",
+                   "DELETE FROM author_rels WHERE rid IN \
+                        (SELECT id FROM releases WHERE crate_id = $1)",
+                   "DELETE FROM keyword_rels WHERE rid IN \
+                        (SELECT id FROM releases WHERE crate_id = $1)",
+                   "DELETE FROM rebuild_releases WHERE rid IN \
+                        (SELECT id FROM releases WHERE crate_id = $1)",
+                   "DELETE FROM releases WHERE crate_id = $1",
+                   "DELETE FROM owner_rels WHERE cid = $1",
+                   "DELETE FROM category_rels WHERE cid = $1",
+                   "DELETE FROM crates WHERE id = $1"] {
+        try!(trans.execute(query, &[&crate_id]));
+    }
+
+    try!(trans.commit());
+    Ok(true)
+}
+
+
+
+#[test]
+#[ignore]
+fn test_connect_db() {
+    let conn = connect_db();
+    assert!(conn.is_ok());
+}
@@ -0,0 +1,270 @@
+//! Compressed documentation archives
+//!
+//! Documentation of a release can be packed into a single archive file,
+//! `<CRATE>/<VERSION>.archive`, instead of keeping thousands of small files
+//! on disk. Every file is compressed separately and an index is placed at
+//! the end of archive, so a single file can be extracted without reading
+//! whole archive:
+//!
+//! ```text
+//! [file 1] [file 2] ... [index] [offset of index: u64, little endian]
+//! ```
+//!
+//! Files and index are deflate compressed and index is a JSON object of
+//! `path: [offset, length]` pairs.
+
+use std::io;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::collections::BTreeMap;
+
+use flate2::Compression;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
+use rustc_serialize::json;
+
+use super::storage::{Storage, StorageError, FileStorage};
+
+
+/// Extension of archive files
+pub const ARCHIVE_EXTENSION: &'static str = "archive";
+
+
+/// Index of an archive: path => (offset, compressed length)
+type ArchiveIndex = BTreeMap<String, (u64, u64)>;
+
+
+/// Packs every file in dir into an archive. Returns number of packed files.
+pub fn pack_dir(dir: &Path, archive_path: &Path) -> io::Result<usize> {
+    let mut archive = try!(fs::File::create(archive_path));
+    let mut index = ArchiveIndex::new();
+    let mut offset = 0;
+
+    try!(pack_files(dir, "", &mut archive, &mut index, &mut offset));
+
+    let index_json = try!(json::encode(&index)
+                          .map_err(|e| io::Error::new(io::ErrorKind::Other, e)));
+    try!(archive.write_all(&try!(compress(index_json.as_bytes()))));
+    try!(archive.write_all(&u64_to_le(offset)));
+
+    Ok(index.len())
+}
+
+
+fn pack_files(dir: &Path,
+              prefix: &str,
+              archive: &mut fs::File,
+              index: &mut ArchiveIndex,
+              offset: &mut u64) -> io::Result<()> {
+    for file in try!(dir.read_dir()) {
+        let file = try!(file);
+        let path = format!("{}{}", prefix, file.file_name().to_string_lossy());
+
+        if try!(file.metadata()).is_dir() {
+            try!(pack_files(&file.path(), &format!("{}/", path), archive, index, offset));
+            continue;
+        }
+
+        let mut content = Vec::new();
+        try!(fs::File::open(file.path()).and_then(|mut f| f.read_to_end(&mut content)));
+        let compressed = try!(compress(&content));
+        try!(archive.write_all(&compressed));
+
+        index.insert(path, (*offset, compressed.len() as u64));
+        *offset += compressed.len() as u64;
+    }
+
+    Ok(())
+}
+
+
+fn read_index(archive: &mut fs::File) -> io::Result<ArchiveIndex> {
+    let end = try!(archive.seek(SeekFrom::End(-8)));
+    let mut offset = [0; 8];
+    try!(archive.read_exact(&mut offset));
+    let offset = u64_from_le(&offset);
+
+    try!(archive.seek(SeekFrom::Start(offset)));
+    let mut compressed = Vec::new();
+    try!(archive.take(end - offset).read_to_end(&mut compressed));
+
+    let index = try!(String::from_utf8(try!(decompress(&compressed)))
+                     .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)));
+    json::decode(&index).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+
+/// Returns paths of every file in archive
+pub fn list_files(archive_path: &Path) -> io::Result<Vec<String>> {
+    let mut archive = try!(fs::File::open(archive_path));
+    Ok(try!(read_index(&mut archive)).keys().cloned().collect())
+}
+
+
+/// Extracts a single file from archive, returns None if file doesn't exist
+pub fn read_file(archive_path: &Path, path: &str) -> io::Result<Option<Vec<u8>>> {
+    let mut archive = try!(fs::File::open(archive_path));
+    let (offset, length) = match try!(read_index(&mut archive)).get(path) {
+        Some(&entry) => entry,
+        None => return Ok(None),
+    };
+
+    try!(archive.seek(SeekFrom::Start(offset)));
+    let mut compressed = Vec::new();
+    try!(archive.take(length).read_to_end(&mut compressed));
+    decompress(&compressed).map(Some)
+}
+
+
+fn compress(content: &[u8]) -> io::Result<Vec<u8>> {
+    let mut encoder = DeflateEncoder::new(Vec::new(), Compression::Default);
+    try!(encoder.write_all(content));
+    encoder.finish()
+}
+
+
+fn decompress(compressed: &[u8]) -> io::Result<Vec<u8>> {
+    let mut content = Vec::new();
+    try!(DeflateDecoder::new(compressed).read_to_end(&mut content));
+    Ok(content)
+}
+
+
+fn u64_to_le(n: u64) -> [u8; 8] {
+    let mut bytes = [0; 8];
+    for i in 0..8 {
+        bytes[i] = (n >> (i * 8)) as u8;
+    }
+    bytes
+}
+
+
+fn u64_from_le(bytes: &[u8; 8]) -> u64 {
+    bytes.iter().enumerate().fold(0, |n, (i, b)| n | ((*b as u64) << (i * 8)))
+}
+
+
+/// Local storage keeping documentation of releases in archives
+///
+/// Files are written into directories like FileStorage and a release is
+/// moved into an archive with `pack_release`. Paths of a packed release
+/// are read from its archive.
+pub struct ArchiveStorage {
+    root: PathBuf,
+    files: FileStorage,
+}
+
+
+impl ArchiveStorage {
+    pub fn new(root: PathBuf) -> ArchiveStorage {
+        ArchiveStorage {
+            files: FileStorage::new(root.clone()),
+            root: root,
+        }
+    }
+
+
+    /// Returns archive path of a release, i.e: `root/rand/0.3.14.archive`
+    fn release_archive(&self, name: &str, version: &str) -> PathBuf {
+        self.root.join(name).join(format!("{}.{}", version, ARCHIVE_EXTENSION))
+    }
+
+
+    /// Returns archive path and path in archive if path belongs to a
+    /// packed release
+    fn archived_path(&self, path: &str) -> Option<(PathBuf, String)> {
+        let mut components = path.trim_matches('/').splitn(3, '/');
+        match (components.next(), components.next()) {
+            (Some(name), Some(version)) => {
+                let archive = self.release_archive(name, version);
+                if archive.is_file() {
+                    Some((archive, components.next().unwrap_or("").to_string()))
+                } else {
+                    None
+                }
+            }
+            _ => None,
+        }
+    }
+
+
+    /// Packs stored documentation of a release into an archive and removes
+    /// its directory. Returns number of packed files.
+    pub fn pack_release(&self, name: &str, version: &str) -> Result<usize, StorageError> {
+        let dir = self.root.join(name).join(version);
+        let count = try!(pack_dir(&dir, &self.release_archive(name, version)));
+        try!(fs::remove_dir_all(dir));
+        Ok(count)
+    }
+}
+
+
+impl Storage for ArchiveStorage {
+    fn store_file(&self, path: &str, content: &[u8]) -> Result<(), StorageError> {
+        self.files.store_file(path, content)
+    }
+
+
+    fn get_file(&self, path: &str) -> Result<Option<Vec<u8>>, StorageError> {
+        match self.archived_path(path) {
+            Some((archive, path)) => Ok(try!(read_file(&archive, &path))),
+            None => self.files.get_file(path),
+        }
+    }
+
+
+    fn exists(&self, path: &str) -> Result<bool, StorageError> {
+        match self.archived_path(path) {
+            Some((_, ref path)) if path.is_empty() => Ok(true),
+            Some((archive, path)) => {
+                let dir = format!("{}/", path);
+                Ok(try!(list_files(&archive)).iter().any(|f| *f == path || f.starts_with(&dir)))
+            }
+            None => self.files.exists(path),
+        }
+    }
+
+
+    fn delete_prefix(&self, prefix: &str) -> Result<(), StorageError> {
+        if let Some((archive, path)) = self.archived_path(prefix) {
+            if path.is_empty() {
+                try!(fs::remove_file(archive));
+            }
+        }
+        self.files.delete_prefix(prefix)
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs;
+    use super::*;
+    use super::super::storage::Storage;
+
+    #[test]
+    fn test_archive_storage() {
+        let root = env::temp_dir().join("cratesfyi-test-archive-storage");
+        let storage = ArchiveStorage::new(root.clone());
+
+        storage.store_file("rand/0.3.14/rand/index.html", b"<html></html>").unwrap();
+        storage.store_file("rand/0.3.14/src/rand/lib.rs.html", b"source").unwrap();
+        assert_eq!(storage.pack_release("rand", "0.3.14").unwrap(), 2);
+        assert!(!root.join("rand/0.3.14").exists());
+
+        assert_eq!(storage.get_file("rand/0.3.14/rand/index.html").unwrap(),
+                   Some(b"<html></html>".to_vec()));
+        assert!(storage.get_file("rand/0.3.14/missing.html").unwrap().is_none());
+        assert!(storage.exists("rand/0.3.14/rand").unwrap());
+        assert!(!storage.exists("rand/0.3.14/ran").unwrap());
+
+        storage.delete_prefix("rand/0.3.14").unwrap();
+        assert!(!storage.exists("rand/0.3.14").unwrap());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
@@ -0,0 +1,45 @@
+//! Blacklist of crates
+//!
+//! Crates in blocked_crates table are never built. This is used for crates
+//! hanging the builder or spam crates.
+
+use postgres::Connection;
+use postgres::error::Error;
+
+
+/// Returns reason of blacklisting if crate is blacklisted
+pub fn blocked_reason(conn: &Connection, name: &str) -> Result<Option<String>, Error> {
+    let rows = try!(conn.query("SELECT reason FROM blocked_crates WHERE name = $1",
+                               &[&name]));
+    if rows.len() == 0 {
+        return Ok(None);
+    }
+    let reason: Option<String> = rows.get(0).get(0);
+    Ok(Some(reason.unwrap_or(String::new())))
+}
+
+
+/// Adds a crate into blacklist, reason is updated if crate is already
+/// blacklisted
+pub fn add_crate(conn: &Connection, name: &str, reason: Option<&str>) -> Result<(), Error> {
+    let updated = try!(conn.execute("UPDATE blocked_crates SET reason = $2 WHERE name = $1",
+                                    &[&name, &reason]));
+    if updated == 0 {
+        try!(conn.execute("INSERT INTO blocked_crates (name, reason) VALUES ($1, $2)",
+                          &[&name, &reason]));
+    }
+    Ok(())
+}
+
+
+/// Removes a crate from blacklist. Returns false if crate wasn't blacklisted.
+pub fn remove_crate(conn: &Connection, name: &str) -> Result<bool, Error> {
+    conn.execute("DELETE FROM blocked_crates WHERE name = $1", &[&name]).map(|n| n > 0)
+}
+
+
+/// Returns names and reasons of every blacklisted crate
+pub fn list_crates(conn: &Connection) -> Result<Vec<(String, Option<String>)>, Error> {
+    let rows = try!(conn.query("SELECT name, reason FROM blocked_crates ORDER BY name", &[]));
+    Ok(rows.iter().map(|row| (row.get(0), row.get(1))).collect())
+}
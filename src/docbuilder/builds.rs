@@ -0,0 +1,239 @@
+//! Build attempts
+//!
+//! Every build attempt of a release is stored in builds table with its
+//! full output and duration. build_status, versions, build duration,
+//! documentation size and coverage of a release are taken from its latest
+//! build.
+
+use postgres::Connection;
+use postgres::error::Error;
+
+use super::coverage::DocCoverage;
+
+
+/// How tests of crates are run after documentation is built
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TestMode {
+    /// Only compile tests with `cargo test --no-run`
+    Build,
+    /// Run tests with `cargo test`
+    Run,
+}
+
+
+impl TestMode {
+    /// Parses test mode from "build" or "run"
+    pub fn from_str(mode: &str) -> Option<TestMode> {
+        match mode {
+            "build" => Some(TestMode::Build),
+            "run" => Some(TestMode::Run),
+            _ => None,
+        }
+    }
+
+    /// Returns arguments of cargo
+    pub fn cargo_args(&self) -> Vec<String> {
+        let mut args = vec!["test".to_string(), "--verbose".to_string()];
+        if *self == TestMode::Build {
+            args.push("--no-run".to_string());
+        }
+        args
+    }
+}
+
+
+/// Result of a build attempt
+#[derive(Debug)]
+pub struct Build {
+    pub rustc_version: String,
+    /// rustup toolchain of build, None if cargo on PATH is used
+    pub toolchain: Option<String>,
+    pub cratesfyi_version: String,
+    pub output: String,
+    pub successful: bool,
+    pub timed_out: bool,
+    /// Crate couldn't be downloaded, build didn't start
+    pub download_failed: bool,
+    /// URL crate is downloaded from, None if it's served from cache or
+    /// download failed
+    pub download_url: Option<String>,
+    /// Duration of build in milliseconds
+    pub build_time_ms: i64,
+    /// Size of documentation in bytes, None if build failed
+    pub doc_size_bytes: Option<i64>,
+    /// Size of sources in sources_path in bytes, None if sources aren't
+    /// stored
+    pub source_size_bytes: Option<i64>,
+    /// Documentation coverage, None if build failed
+    pub doc_coverage: Option<DocCoverage>,
+    /// Result of tests: 1 passed, -1 failed, -2 timed out and 0 if tests
+    /// are not run
+    pub test_status: i32,
+}
+
+
+impl Build {
+    /// Returns build_status value of this build
+    pub fn build_status(&self) -> i32 {
+        build_status(self.successful, self.timed_out, self.download_failed)
+    }
+}
+
+
+/// Returns test_status value of a test run
+pub fn test_status(successful: bool, timed_out: bool) -> i32 {
+    build_status(successful, timed_out, false)
+}
+
+
+/// Returns 1 if build is successful, -1 if it's failed, -2 if it's timed
+/// out and -3 if crate couldn't be downloaded
+fn build_status(successful: bool, timed_out: bool, download_failed: bool) -> i32 {
+    if successful {
+        1
+    } else if timed_out {
+        -2
+    } else if download_failed {
+        -3
+    } else {
+        -1
+    }
+}
+
+
+/// Adds a build attempt into database and updates build_status and versions
+/// of release. Returns id of build.
+pub fn add_build_into_database(conn: &Connection,
+                               release_id: i32,
+                               build: &Build) -> Result<i32, Error> {
+    let rows = try!(conn.query("INSERT INTO builds (rid, rustc_version, cratesfyi_version, \
+                                                    output, successful, timed_out, \
+                                                    build_time_ms, test_status, \
+                                                    download_failed, download_url, \
+                                                    toolchain) \
+                                VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11) \
+                                RETURNING id",
+                               &[&release_id,
+                                 &build.rustc_version,
+                                 &build.cratesfyi_version,
+                                 &build.output,
+                                 &build.successful,
+                                 &build.timed_out,
+                                 &build.build_time_ms,
+                                 &build.test_status,
+                                 &build.download_failed,
+                                 &build.download_url,
+                                 &build.toolchain]));
+    try!(conn.execute("UPDATE releases \
+                       SET build_status = $2, rustc_version = $3, cratesfyi_version = $4, \
+                           build_time_ms = $5, doc_size_bytes = $6, \
+                           documented_items = $7, total_items = $8, test_status = $9, \
+                           source_size_bytes = $10 \
+                       WHERE id = $1",
+                      &[&release_id,
+                        &build.build_status(),
+                        &build.rustc_version,
+                        &build.cratesfyi_version,
+                        &build.build_time_ms,
+                        &build.doc_size_bytes,
+                        &build.doc_coverage.map(|c| c.documented_items),
+                        &build.doc_coverage.map(|c| c.total_items),
+                        &build.test_status,
+                        &build.source_size_bytes]));
+    Ok(rows.get(0).get(0))
+}
+
+
+/// Returns test_status of a release from its latest build, 0 if release
+/// is never built.
+pub fn release_test_status(conn: &Connection, release_id: i32) -> Result<i32, Error> {
+    let rows = try!(conn.query("SELECT test_status \
+                                FROM builds \
+                                WHERE rid = $1 \
+                                ORDER BY build_time DESC, id DESC \
+                                LIMIT 1",
+                               &[&release_id]));
+    Ok(if rows.len() == 0 { 0 } else { rows.get(0).get(0) })
+}
+
+
+/// Returns build_status of a release from its latest build, 0 if release
+/// is never built.
+pub fn release_build_status(conn: &Connection, release_id: i32) -> Result<i32, Error> {
+    let rows = try!(conn.query("SELECT successful, timed_out, download_failed \
+                                FROM builds \
+                                WHERE rid = $1 \
+                                ORDER BY build_time DESC, id DESC \
+                                LIMIT 1",
+                               &[&release_id]));
+    if rows.len() == 0 {
+        return Ok(0);
+    }
+
+    let row = rows.get(0);
+    Ok(build_status(row.get(0), row.get(1), row.get(2)))
+}
+
+
+
+/// Returns build_status of latest build of a crate, 0 if crate is never
+/// built.
+pub fn crate_build_status(conn: &Connection, name: &str) -> Result<i32, Error> {
+    let rows = try!(conn.query("SELECT builds.successful, builds.timed_out, \
+                                       builds.download_failed \
+                                FROM builds \
+                                INNER JOIN releases ON releases.id = builds.rid \
+                                INNER JOIN crates ON crates.id = releases.crate_id \
+                                WHERE crates.name = $1 \
+                                ORDER BY builds.build_time DESC, builds.id DESC \
+                                LIMIT 1",
+                               &[&name]));
+    if rows.len() == 0 {
+        return Ok(0);
+    }
+
+    let row = rows.get(0);
+    Ok(build_status(row.get(0), row.get(1), row.get(2)))
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_build_status() {
+        let mut build = Build {
+            rustc_version: "rustc 1.9.0".to_string(),
+            toolchain: None,
+            cratesfyi_version: "cratesfyi 0.1.0".to_string(),
+            output: String::new(),
+            successful: true,
+            timed_out: false,
+            download_failed: false,
+            download_url: None,
+            build_time_ms: 0,
+            doc_size_bytes: None,
+            source_size_bytes: None,
+            doc_coverage: None,
+            test_status: 0,
+        };
+        assert_eq!(build.build_status(), 1);
+        build.successful = false;
+        assert_eq!(build.build_status(), -1);
+        build.download_failed = true;
+        assert_eq!(build.build_status(), -3);
+        build.timed_out = true;
+        assert_eq!(build.build_status(), -2);
+    }
+
+    #[test]
+    fn test_test_mode() {
+        assert_eq!(TestMode::from_str("build"), Some(TestMode::Build));
+        assert_eq!(TestMode::from_str("run"), Some(TestMode::Run));
+        assert_eq!(TestMode::from_str("bench"), None);
+        assert!(TestMode::Build.cargo_args().contains(&"--no-run".to_string()));
+        assert!(!TestMode::Run.cargo_args().contains(&"--no-run".to_string()));
+    }
+}
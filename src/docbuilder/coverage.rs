@@ -0,0 +1,133 @@
+//! Documentation coverage
+//!
+//! Coverage is calculated from rustdoc output. Every item (struct, enum,
+//! trait, function etc.) has its own page and an item is documented if
+//! its declaration is followed by a docblock.
+
+use std::io;
+use std::io::prelude::*;
+use std::fs;
+use std::path::Path;
+
+use regex::Regex;
+
+
+/// File names of item pages, i.e: `struct.Rng.html`
+const ITEM_PAGE_REGEX: &'static str =
+    r"^(struct|enum|trait|fn|macro|type|constant|static|union)\.[^.]+\.html$";
+
+
+/// Number of documented items and all items of a crate
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DocCoverage {
+    pub documented_items: i32,
+    pub total_items: i32,
+}
+
+
+impl DocCoverage {
+    /// Returns percentage of documented items, None if crate doesn't have
+    /// any item
+    pub fn percentage(&self) -> Option<f32> {
+        if self.total_items == 0 {
+            None
+        } else {
+            Some(self.documented_items as f32 * 100.0 / self.total_items as f32)
+        }
+    }
+}
+
+
+/// Calculates documentation coverage of a crate from its rustdoc output,
+/// i.e: `target/doc/rand`
+pub fn doc_coverage(crate_doc_path: &Path) -> io::Result<DocCoverage> {
+    let mut coverage = DocCoverage {
+        documented_items: 0,
+        total_items: 0,
+    };
+    let item_page = Regex::new(ITEM_PAGE_REGEX).unwrap();
+    try!(count_items(crate_doc_path, &item_page, &mut coverage));
+    Ok(coverage)
+}
+
+
+fn count_items(dir: &Path, item_page: &Regex, coverage: &mut DocCoverage) -> io::Result<()> {
+    for file in try!(dir.read_dir()) {
+        let file = try!(file);
+        if try!(file.metadata()).is_dir() {
+            try!(count_items(&file.path(), item_page, coverage));
+            continue;
+        }
+
+        if !item_page.is_match(&file.file_name().to_string_lossy()) {
+            continue;
+        }
+
+        let mut content = String::new();
+        try!(fs::File::open(file.path()).and_then(|mut f| f.read_to_string(&mut content)));
+        if let Some(documented) = is_documented(&content) {
+            coverage.total_items += 1;
+            if documented {
+                coverage.documented_items += 1;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+
+/// Returns true if item in page has documentation. Returns None if page is
+/// a redirect or doesn't contain an item.
+fn is_documented(page: &str) -> Option<bool> {
+    // re-exported items have redirect pages
+    if page.contains("http-equiv=\"refresh\"") {
+        return None;
+    }
+
+    // item declaration comes after its title and docs come after declaration
+    let title = match page.find("class='fqn'").or(page.find("class=\"fqn\"")) {
+        Some(title) => title,
+        None => return None,
+    };
+    let declaration_end = match page[title..].find("</pre>") {
+        Some(end) => title + end + "</pre>".len(),
+        None => return None,
+    };
+    let after_declaration = page[declaration_end..].trim_left();
+
+    Some(after_declaration.starts_with("<div class='docblock'>") ||
+         after_declaration.starts_with("<div class=\"docblock\">"))
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_is_documented() {
+        assert_eq!(is_documented("<h1 class='fqn'>Struct <a>Rng</a></h1>\
+                                  <pre class='rust struct'>pub struct Rng</pre>\n\
+                                  <div class='docblock'><p>A random generator</p></div>"),
+                   Some(true));
+        assert_eq!(is_documented("<h1 class='fqn'>Function <a>random</a></h1>\
+                                  <pre class='rust fn'>pub fn random()</pre>\
+                                  <h2 id='methods'>Methods</h2>\
+                                  <div class='docblock'><p>Method docs</p></div>"),
+                   Some(false));
+        assert_eq!(is_documented("<meta http-equiv=\"refresh\" content=\"0;URL=Rng.html\">"),
+                   None);
+    }
+
+    #[test]
+    fn test_coverage_percentage() {
+        let coverage = DocCoverage {
+            documented_items: 3,
+            total_items: 4,
+        };
+        assert_eq!(coverage.percentage(), Some(75.0));
+        assert_eq!(DocCoverage { documented_items: 0, total_items: 0 }.percentage(), None);
+    }
+}
@@ -0,0 +1,187 @@
+//! Local cache of downloaded crate files
+//!
+//! Crate files are stored as `{name}-{version}-{sha256}.crate` in cache
+//! directory. A crate with a known checksum is only served from cache if
+//! content of cached file has the same checksum, crates without a checksum
+//! (i.e: crates not loaded from crates.io-index) are served from any cached
+//! file of same version.
+//!
+//! Cache is pruned after every insertion if it has a maximum size, oldest
+//! files are removed first until cache fits into maximum size.
+
+use std::io;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::env;
+
+use super::download::sha256_file;
+
+
+/// Directory name of cache in temporary directory, used by
+/// `Crate::download_crate`
+const TEMP_CACHE_DIR: &'static str = "cratesfyi-crate-cache";
+
+
+#[derive(Debug, Clone)]
+pub struct CrateCache {
+    dir: PathBuf,
+    /// Maximum size of cache in bytes, cache is not pruned if it's None
+    max_size: Option<u64>,
+}
+
+
+impl CrateCache {
+    /// Returns a new cache stored in dir
+    pub fn new(dir: PathBuf) -> CrateCache {
+        CrateCache {
+            dir: dir,
+            max_size: None,
+        }
+    }
+
+
+    /// Returns a cache stored in temporary directory
+    pub fn in_temp_dir() -> CrateCache {
+        CrateCache::new(env::temp_dir().join(TEMP_CACHE_DIR))
+    }
+
+
+    /// Set maximum size of cache in bytes
+    pub fn max_size(&mut self, max_size: Option<u64>) {
+        self.max_size = max_size;
+    }
+
+
+    /// Returns path of cached crate file, i.e: `rand-0.3.14-{sha256}.crate`
+    fn cache_path(&self, canonical_name: &str, checksum: &str) -> PathBuf {
+        self.dir.join(format!("{}-{}.crate", canonical_name, checksum))
+    }
+
+
+    /// Returns path of a cached file of crate. Any cached file of crate is
+    /// used if checksum is not known.
+    fn lookup(&self, canonical_name: &str, checksum: Option<&str>) -> Option<PathBuf> {
+        if let Some(checksum) = checksum {
+            let path = self.cache_path(canonical_name, checksum);
+            return if path.exists() { Some(path) } else { None };
+        }
+
+        let prefix = format!("{}-", canonical_name);
+        let files = match self.dir.read_dir() {
+            Ok(files) => files,
+            Err(_) => return None,
+        };
+        files.filter_map(|f| f.ok())
+            .find(|f| {
+                let file_name = f.file_name().to_string_lossy().into_owned();
+                // prefix of another version can't match, i.e: foo-0.1.0-beta
+                file_name.starts_with(&prefix) &&
+                is_checksum_file_name(&file_name[prefix.len()..])
+            })
+            .map(|f| f.path())
+    }
+
+
+    /// Copies cached crate file into destination. Returns false if crate is
+    /// not in cache.
+    pub fn get(&self, canonical_name: &str, checksum: Option<&str>, destination: &Path) -> bool {
+        match self.lookup(canonical_name, checksum) {
+            Some(path) => fs::copy(&path, destination).is_ok(),
+            None => false,
+        }
+    }
+
+
+    /// Stores a downloaded crate file into cache and prunes cache
+    pub fn insert(&self, canonical_name: &str, file: &Path) -> io::Result<()> {
+        try!(fs::create_dir_all(&self.dir));
+
+        let checksum = try!(sha256_file(file));
+        let path = self.cache_path(canonical_name, &checksum);
+        if !path.exists() {
+            // cached files are never partially written, parallel builds
+            // might be reading them
+            let tmp_path = self.dir.join(format!(".{}-{}.tmp", canonical_name, checksum));
+            try!(fs::copy(file, &tmp_path));
+            try!(fs::rename(&tmp_path, &path));
+        }
+
+        if let Some(max_size) = self.max_size {
+            try!(self.prune(max_size));
+        }
+
+        Ok(())
+    }
+
+
+    /// Removes oldest cached files until total size of cache is less than
+    /// max_size. Returns number of removed files.
+    pub fn prune(&self, max_size: u64) -> io::Result<usize> {
+        let mut files = Vec::new();
+        let mut total_size = 0;
+        for file in try!(self.dir.read_dir()) {
+            let file = try!(file);
+            let metadata = try!(file.metadata());
+            if !metadata.is_file() ||
+               !file.file_name().to_string_lossy().ends_with(".crate") {
+                continue;
+            }
+            total_size += metadata.len();
+            files.push((try!(metadata.modified()), metadata.len(), file.path()));
+        }
+
+        files.sort();
+
+        let mut removed = 0;
+        for (_, size, path) in files {
+            if total_size <= max_size {
+                break;
+            }
+            try!(fs::remove_file(&path));
+            total_size -= size;
+            removed += 1;
+        }
+
+        Ok(removed)
+    }
+}
+
+
+/// Returns true if file name is `{sha256}.crate`
+fn is_checksum_file_name(file_name: &str) -> bool {
+    file_name.len() == 64 + ".crate".len() && file_name.ends_with(".crate") &&
+    file_name[..64].chars().all(|c| c.is_digit(16))
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::prelude::*;
+    use std::fs;
+    use std::env;
+
+    #[test]
+    fn test_crate_cache() {
+        let dir = env::temp_dir().join("cratesfyi-crate-cache-test");
+        let _ = fs::remove_dir_all(&dir);
+        let cache = CrateCache::new(dir.join("cache"));
+
+        let file = dir.join("rand-0.3.14.crate");
+        fs::File::create(&file).and_then(|mut f| f.write_all(b"crate")).unwrap();
+        let destination = dir.join("copy.crate");
+
+        assert!(!cache.get("rand-0.3.14", None, &destination));
+        cache.insert("rand-0.3.14", &file).unwrap();
+        assert!(cache.get("rand-0.3.14", None, &destination));
+        assert!(!cache.get("rand-0.3.14", Some("0000"), &destination));
+        assert!(!cache.get("rand-0.3", None, &destination));
+
+        assert_eq!(cache.prune(1024).unwrap(), 0);
+        assert_eq!(cache.prune(0).unwrap(), 1);
+        assert!(!cache.get("rand-0.3.14", None, &destination));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,442 @@
+//! crates.io API client
+//!
+//! Requests are retried with exponential backoff if they fail because of
+//! network errors or server errors. Client errors (4xx) and malformed
+//! responses are not retried.
+//!
+//! Every request is sent with a `User-Agent` containing a contact address as
+//! required by crates.io crawler policy, and requests made through the same
+//! client are rate limited.
+//!
+//! Responses can be cached on disk. Cached responses are revalidated with
+//! `If-None-Match` and `If-Modified-Since` headers and reused if crates.io
+//! responds with 304 Not Modified.
+
+use std::io;
+use std::io::prelude::*;
+use std::fs;
+use std::path::PathBuf;
+use std::error;
+use std::fmt;
+use std::thread;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use hyper;
+use hyper::client::Client;
+use hyper::header::{Headers, UserAgent};
+use hyper::status::{StatusCode, StatusClass};
+use rustc_serialize::Decodable;
+use rustc_serialize::json::{self, Json, DecoderError, ParserError};
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+
+
+/// Base URL of crates.io API
+pub const CRATESIO_API_URL: &'static str = "https://crates.io/api/v1";
+
+/// Timeout of a single request
+pub const REQUEST_TIMEOUT: u64 = 30;
+
+/// Maximum number of retries of a failed request
+pub const MAX_RETRIES: u32 = 3;
+
+/// Wait time before first retry, doubled after every retry
+pub const INITIAL_BACKOFF_MS: u64 = 500;
+
+/// Contact address sent in User-Agent if it's not configured
+pub const DEFAULT_CONTACT: &'static str = "https://github.com/onur/cratesfyi";
+
+/// Default maximum number of requests per second
+pub const DEFAULT_RATE_LIMIT: f64 = 1.0;
+
+
+/// A published version of a crate
+#[derive(Debug, RustcDecodable)]
+pub struct Version {
+    pub num: String,
+    pub created_at: String,
+    pub yanked: bool,
+    pub downloads: i64,
+}
+
+
+/// An owner of a crate
+#[derive(Debug, RustcDecodable)]
+pub struct Owner {
+    pub login: String,
+    pub name: Option<String>,
+    pub email: Option<String>,
+    pub avatar: Option<String>,
+}
+
+
+/// A category of a crate
+#[derive(Debug, RustcDecodable)]
+pub struct Category {
+    pub slug: String,
+    pub category: String,
+    pub description: Option<String>,
+}
+
+
+/// A response stored in cache directory
+#[derive(RustcEncodable, RustcDecodable)]
+struct CachedResponse {
+    etag: Option<String>,
+    last_modified: Option<String>,
+    body: String,
+}
+
+
+#[derive(RustcDecodable)]
+struct VersionsResponse {
+    versions: Vec<Version>,
+}
+
+
+#[derive(RustcDecodable)]
+struct OwnersResponse {
+    users: Vec<Owner>,
+}
+
+
+#[derive(RustcDecodable)]
+struct CrateResponse {
+    categories: Option<Vec<Category>>,
+}
+
+
+#[derive(Debug)]
+pub enum ApiError {
+    HttpError(hyper::Error),
+    StatusError(StatusCode),
+    IoError(io::Error),
+    ParseError(ParserError),
+    DecodeError(DecoderError),
+}
+
+
+impl ApiError {
+    /// Returns true if request can be tried again
+    fn is_retryable(&self) -> bool {
+        match *self {
+            ApiError::HttpError(_) | ApiError::IoError(_) => true,
+            ApiError::StatusError(status) => status.class() == StatusClass::ServerError,
+            _ => false,
+        }
+    }
+}
+
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ApiError::HttpError(ref e) => write!(f, "HTTP error: {}", e),
+            ApiError::StatusError(ref s) => write!(f, "Unexpected response status: {}", s),
+            ApiError::IoError(ref e) => write!(f, "Failed to read response: {}", e),
+            ApiError::ParseError(ref e) => write!(f, "Failed to parse response: {}", e),
+            ApiError::DecodeError(ref e) => write!(f, "Unexpected response: {}", e),
+        }
+    }
+}
+
+
+impl error::Error for ApiError {
+    fn description(&self) -> &str {
+        "crates.io API error"
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            ApiError::HttpError(ref e) => Some(e),
+            ApiError::IoError(ref e) => Some(e),
+            ApiError::ParseError(ref e) => Some(e),
+            ApiError::DecodeError(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+
+/// crates.io API client shared by every API call of DocBuilder
+pub struct CratesIoClient {
+    /// Base URL of API, crates.io by default
+    api_url: String,
+    user_agent: String,
+    min_interval: Option<Duration>,
+    last_request: Mutex<Option<Instant>>,
+    cache_dir: Option<PathBuf>,
+}
+
+
+impl Default for CratesIoClient {
+    fn default() -> CratesIoClient {
+        CratesIoClient::new(DEFAULT_CONTACT, Some(DEFAULT_RATE_LIMIT))
+    }
+}
+
+
+impl fmt::Debug for CratesIoClient {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "CratesIoClient {{ api_url: {:?}, user_agent: {:?}, min_interval: {:?}, \
+                cache_dir: {:?} }}",
+               self.api_url,
+               self.user_agent,
+               self.min_interval,
+               self.cache_dir)
+    }
+}
+
+
+impl CratesIoClient {
+    /// Returns a new client. rate_limit is maximum number of requests per
+    /// second, requests are not limited if it's None.
+    pub fn new(contact: &str, rate_limit: Option<f64>) -> CratesIoClient {
+        CratesIoClient {
+            api_url: CRATESIO_API_URL.to_string(),
+            user_agent: format!("cratesfyi/{} ({})", env!("CARGO_PKG_VERSION"), contact),
+            min_interval: rate_limit.and_then(|r| {
+                if r > 0.0 { Some(Duration::from_millis((1000.0 / r) as u64)) } else { None }
+            }),
+            last_request: Mutex::new(None),
+            cache_dir: None,
+        }
+    }
+
+
+    /// Set directory used to cache responses. Responses are not cached if
+    /// it's not set.
+    pub fn cache_dir(&mut self, path: PathBuf) {
+        self.cache_dir = Some(path);
+    }
+
+
+    /// Set base URL of a crates.io compatible API of another registry,
+    /// i.e: `https://crates.example.com/api/v1`
+    pub fn api_url(&mut self, url: String) {
+        self.api_url = url;
+    }
+
+
+    /// Returns every published version of a crate
+    pub fn get_versions(&self, name: &str) -> Result<Vec<Version>, ApiError> {
+        let url = format!("{}/crates/{}/versions", self.api_url, name);
+        self.get::<VersionsResponse>(&url).map(|r| r.versions)
+    }
+
+
+    /// Returns owners of a crate
+    pub fn get_owners(&self, name: &str) -> Result<Vec<Owner>, ApiError> {
+        let url = format!("{}/crates/{}/owners", self.api_url, name);
+        self.get::<OwnersResponse>(&url).map(|r| r.users)
+    }
+
+
+    /// Returns categories of a crate
+    pub fn get_categories(&self, name: &str) -> Result<Vec<Category>, ApiError> {
+        let url = format!("{}/crates/{}", self.api_url, name);
+        self.get::<CrateResponse>(&url).map(|r| r.categories.unwrap_or(Vec::new()))
+    }
+
+
+    /// Sends a GET request and decodes response, retrying failed requests
+    fn get<T: Decodable>(&self, url: &str) -> Result<T, ApiError> {
+        let mut backoff = INITIAL_BACKOFF_MS;
+        let mut retries = 0;
+
+        loop {
+            match self.get_json(url) {
+                Ok(json) => {
+                    let mut decoder = json::Decoder::new(json);
+                    return T::decode(&mut decoder).map_err(ApiError::DecodeError);
+                }
+                Err(e) => {
+                    if retries >= MAX_RETRIES || !e.is_retryable() {
+                        return Err(e);
+                    }
+                    warn!("Request to {} failed, retrying in {}ms: {}", url, backoff, e);
+                    thread::sleep(Duration::from_millis(backoff));
+                    backoff *= 2;
+                    retries += 1;
+                }
+            }
+        }
+    }
+
+
+    /// Blocks until next request is allowed by rate limit
+    fn wait_for_rate_limit(&self) {
+        let min_interval = match self.min_interval {
+            Some(min_interval) => min_interval,
+            None => return,
+        };
+
+        let mut last_request = self.last_request.lock().unwrap();
+        if let Some(last) = *last_request {
+            let elapsed = last.elapsed();
+            if elapsed < min_interval {
+                thread::sleep(min_interval - elapsed);
+            }
+        }
+        *last_request = Some(Instant::now());
+    }
+
+
+    fn get_json(&self, url: &str) -> Result<Json, ApiError> {
+        let cached = self.read_cache(url);
+
+        self.wait_for_rate_limit();
+
+        let mut client = Client::new();
+        client.set_read_timeout(Some(Duration::from_secs(REQUEST_TIMEOUT)));
+        client.set_write_timeout(Some(Duration::from_secs(REQUEST_TIMEOUT)));
+
+        let mut headers = Headers::new();
+        headers.set(UserAgent(self.user_agent.clone()));
+        if let Some(ref cached) = cached {
+            if let Some(ref etag) = cached.etag {
+                headers.set_raw("If-None-Match", vec![etag.clone().into_bytes()]);
+            }
+            if let Some(ref last_modified) = cached.last_modified {
+                headers.set_raw("If-Modified-Since", vec![last_modified.clone().into_bytes()]);
+            }
+        }
+
+        let mut res = try!(client.get(url)
+                           .headers(headers)
+                           .send()
+                           .map_err(ApiError::HttpError));
+
+        if res.status == StatusCode::NotModified {
+            if let Some(cached) = cached {
+                debug!("Using cached response of {}", url);
+                return Json::from_str(&cached.body).map_err(ApiError::ParseError);
+            }
+        }
+
+        if res.status != StatusCode::Ok {
+            return Err(ApiError::StatusError(res.status));
+        }
+
+        let mut body = String::new();
+        try!(res.read_to_string(&mut body).map_err(ApiError::IoError));
+        let json = try!(Json::from_str(&body).map_err(ApiError::ParseError));
+
+        self.write_cache(url, CachedResponse {
+            etag: raw_header(&res.headers, "ETag"),
+            last_modified: raw_header(&res.headers, "Last-Modified"),
+            body: body,
+        });
+
+        Ok(json)
+    }
+
+
+    /// Returns cache file path of an URL
+    fn cache_path(&self, url: &str) -> Option<PathBuf> {
+        self.cache_dir.as_ref().map(|dir| {
+            let mut hasher = Sha256::new();
+            hasher.input_str(url);
+            dir.join(format!("{}.json", hasher.result_str()))
+        })
+    }
+
+
+    fn read_cache(&self, url: &str) -> Option<CachedResponse> {
+        let path = match self.cache_path(url) {
+            Some(path) => path,
+            None => return None,
+        };
+
+        let mut content = String::new();
+        if fs::File::open(&path).and_then(|mut f| f.read_to_string(&mut content)).is_err() {
+            return None;
+        }
+
+        json::decode(&content).ok()
+    }
+
+
+    /// Writes a response into cache. Cache is only an optimization, errors
+    /// are logged and ignored.
+    fn write_cache(&self, url: &str, response: CachedResponse) {
+        let path = match self.cache_path(url) {
+            Some(path) => path,
+            None => return,
+        };
+
+        // there is no point to cache a response which can't be revalidated
+        if response.etag.is_none() && response.last_modified.is_none() {
+            return;
+        }
+
+        let res = json::encode(&response)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+            .and_then(|content| {
+                if let Some(parent) = path.parent() {
+                    try!(fs::create_dir_all(parent));
+                }
+                fs::File::create(&path).and_then(|mut f| f.write_all(content.as_bytes()))
+            });
+
+        if let Err(e) = res {
+            warn!("Failed to write cache file {}: {}", path.display(), e);
+        }
+    }
+}
+
+
+fn raw_header(headers: &Headers, name: &str) -> Option<String> {
+    headers.get_raw(name)
+        .and_then(|values| values.first())
+        .map(|value| String::from_utf8_lossy(value).into_owned())
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::PathBuf;
+    use std::time::Duration;
+
+    #[test]
+    fn test_user_agent() {
+        let client = CratesIoClient::new("admin@example.com", Some(2.0));
+        assert!(client.user_agent.starts_with("cratesfyi/"));
+        assert!(client.user_agent.ends_with("(admin@example.com)"));
+        assert_eq!(client.min_interval, Some(Duration::from_millis(500)));
+        assert_eq!(CratesIoClient::new("admin@example.com", None).min_interval, None);
+    }
+
+
+    #[test]
+    fn test_cache_path() {
+        let mut client = CratesIoClient::default();
+        assert!(client.cache_path("https://crates.io/api/v1/crates/rand/owners").is_none());
+
+        client.cache_dir(PathBuf::from("/tmp/cratesfyi-cache"));
+        let owners = client.cache_path("https://crates.io/api/v1/crates/rand/owners").unwrap();
+        let versions = client.cache_path("https://crates.io/api/v1/crates/rand/versions").unwrap();
+        assert!(owners.starts_with("/tmp/cratesfyi-cache"));
+        assert!(owners != versions);
+    }
+
+
+    #[test]
+    #[ignore]
+    fn test_get_versions() {
+        let versions = CratesIoClient::default().get_versions("rand").unwrap();
+        assert!(versions.iter().any(|v| v.num == "0.3.14"));
+    }
+
+
+    #[test]
+    #[ignore]
+    fn test_get_owners() {
+        let owners = CratesIoClient::default().get_owners("rand").unwrap();
+        assert!(!owners.is_empty());
+    }
+}
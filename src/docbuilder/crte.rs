@@ -3,22 +3,38 @@
 use std::io::prelude::*;
 use std::io::BufReader;
 use std::io::Error;
+use std::error;
+use std::fmt;
 use std::fs;
 use std::path::{Path, PathBuf};
-use std::process::Command;
 use std::collections;
-use std::env;
 
-use cargo;
 use toml;
 use rustc_serialize::json::{encode, Json, ParserError, EncoderError, ToJson};
 use postgres;
-use hyper::client::Client;
 use time;
 use regex::Regex;
 use slug::slugify;
-
-use super::{DocBuilder, DocBuilderError, copy_files, command_result};
+use semver::{Version, VersionReq};
+use db;
+
+use super::{DocBuilder, DocBuilderError, copy_files};
+use super::index::crate_index_path;
+use super::download::{download_file, download_url, sha256_file, DownloadOptions,
+                      DOWNLOADED_FROM_LOG_PREFIX};
+use super::crate_cache::CrateCache;
+use super::extract::{extract_crate_file, ExtractLimits};
+use super::limits::{CommandFailure, BUILD_SCRIPT_BLOCKED_LOG_MARKER,
+                    ALL_FEATURES_FAILED_LOG_MARKER};
+use super::builds::{release_build_status, release_test_status, TestMode};
+use super::cratesio_api::ApiError;
+use super::owners::update_crate_owners;
+use super::metadata::Metadata;
+use super::storage::StorageError;
+use super::manifest::{read_manifest, Manifest, ManifestMetadata, ManifestError, Target,
+                      Dependency};
+use super::markdown::render_markdown;
+use super::workspace::{isolate_workspace, is_virtual_manifest};
 
 
 /// Really simple crate model
@@ -28,6 +44,12 @@ pub struct Crate {
     pub name: String,
     /// Versions of crate
     pub versions: Vec<String>,
+    /// SHA-256 checksums of versions, empty if crate is not loaded from
+    /// crates.io-index
+    pub checksums: Vec<String>,
+    /// Yanked status of versions, empty if crate is not loaded from
+    /// crates.io-index
+    pub yanked: Vec<bool>,
 }
 
 
@@ -37,25 +59,66 @@ pub enum CrateOpenError {
     EncoderError(EncoderError),
     ParseError(ParserError),
     IoError(Error),
-    ManifestError(Box<cargo::util::errors::CargoError>),
+    ManifestError(ManifestError),
     NotObject,
     NameNotFound,
     VersNotFound,
     DbError(postgres::error::Error),
     CommandError(String),
     DocBuilderError(DocBuilderError),
+    ApiError(ApiError),
+    TimeParseError(time::ParseError),
+    StorageError(StorageError),
 }
 
 
-#[derive(Debug)]
+#[derive(Debug, RustcEncodable)]
 pub struct CrateInfo {
     pub name: String,
+    /// Name of library target, name of first target if crate doesn't
+    /// have a library
     pub target_name: String,
+    /// False if crate doesn't have a library target, i.e: binary crates
+    pub is_library: bool,
+    /// Name and kind of every target in manifest, i.e: `("rand", "lib")`
+    pub targets: Vec<(String, String)>,
     pub version: String,
-    pub dependencies: Vec<(String, String)>,
+    pub dependencies: Vec<Dependency>,
     pub rustdoc: Option<String>,
     pub readme: Option<String>,
-    pub metadata: cargo::core::manifest::ManifestMetadata,
+    /// README rendered into sanitized HTML
+    pub readme_html: Option<String>,
+    pub metadata: ManifestMetadata,
+    /// Build options from [package.metadata.docs.rs]
+    pub build_metadata: Metadata,
+    /// Features and optional dependencies of crate
+    pub features: Vec<Feature>,
+}
+
+
+/// A feature or an optional dependency of a crate
+#[derive(Debug, Clone, PartialEq, RustcEncodable)]
+pub struct Feature {
+    pub name: String,
+    /// Features and dependencies enabled by this feature
+    pub subfeatures: Vec<String>,
+    pub optional_dependency: bool,
+    /// True if feature is enabled when building documentation
+    pub enabled: bool,
+}
+
+
+/// CrateInfo of a release with data added into releases table
+#[derive(Debug, RustcEncodable)]
+pub struct ReleaseInfo {
+    /// Crate info, build metadata has build overrides of operator applied
+    pub info: CrateInfo,
+    pub have_examples: bool,
+    /// Release time in RFC 3339 format, None if release is not found in
+    /// crates.io
+    pub release_time: Option<String>,
+    pub yanked: Option<bool>,
+    pub downloads: Option<i32>,
 }
 
 
@@ -66,6 +129,8 @@ impl Crate {
         Crate {
             name: name,
             versions: versions,
+            checksums: Vec::new(),
+            yanked: Vec::new(),
         }
     }
 
@@ -76,19 +141,27 @@ impl Crate {
 
         let mut name = String::new();
         let mut versions = Vec::new();
+        let mut checksums = Vec::new();
+        let mut yanked = Vec::new();
 
         for line in reader.lines() {
             let line = try!(line);
-            let (cname, vers) = try!(Crate::parse_cargo_index_line(&line));
+            let (cname, vers, cksum, is_yanked) = try!(Crate::parse_cargo_index_line(&line));
             name = cname;
             versions.push(vers);
+            checksums.push(cksum);
+            yanked.push(is_yanked);
         }
 
         versions.reverse();
+        checksums.reverse();
+        yanked.reverse();
 
         Ok(Crate {
             name: name,
-            versions: versions
+            versions: versions,
+            checksums: checksums,
+            yanked: yanked,
         })
     }
 
@@ -101,33 +174,21 @@ impl Crate {
             return Err(CrateOpenError::FileNotFound);
         }
 
-        for file in try!(path.read_dir()) {
-
-            let file = try!(file);
-
-            let path = file.path();
-
-            // skip files under .git and config.json
-            if path.to_str().unwrap().contains(".git") ||
-                path.file_name().unwrap() == "config.json" {
-                    continue;
-                }
-
-            if path.is_dir() {
-                if let Ok(c) = Crate::from_cargo_index_path(&name, &path) {
-                    return Ok(c);
-                }
-            } else if file.file_name().into_string().unwrap() == name {
-                return Crate::from_cargo_index_file(path);
-            }
+        let crate_path = match crate_index_path(name) {
+            Some(crate_path) => path.join(crate_path),
+            None => return Err(CrateOpenError::FileNotFound),
+        };
 
+        if !crate_path.is_file() {
+            return Err(CrateOpenError::FileNotFound);
         }
 
-        Err(CrateOpenError::FileNotFound)
+        Crate::from_cargo_index_file(crate_path)
     }
 
 
-    fn parse_cargo_index_line(line: &String) -> Result<(String, String), CrateOpenError> {
+    fn parse_cargo_index_line(line: &String)
+                              -> Result<(String, String, String, bool), CrateOpenError> {
         let data = try!(Json::from_str(line.trim()).map_err(CrateOpenError::ParseError));
         let obj = try!(data.as_object().ok_or(CrateOpenError::NotObject));
 
@@ -139,7 +200,11 @@ impl Crate {
                         .and_then(|n| n.as_string())
                         .ok_or(CrateOpenError::VersNotFound));
 
-        Ok((String::from(crate_name), String::from(vers)))
+        // old index entries may not have a checksum
+        let cksum = obj.get("cksum").and_then(|n| n.as_string()).unwrap_or("");
+        let yanked = obj.get("yanked").and_then(|n| n.as_boolean()).unwrap_or(false);
+
+        Ok((String::from(crate_name), String::from(vers), String::from(cksum), yanked))
     }
 
 
@@ -155,6 +220,7 @@ impl Crate {
 
 
     /// Returns index of version if it starts with it
+    #[cfg(test)]
     pub fn version_starts_with(&self, version: &str) -> Option<usize> {
         // if version is "*" return latest version index which is 0
         if version == "*" {
@@ -169,40 +235,207 @@ impl Crate {
     }
 
 
+    /// Returns index of highest version matching semver requirement
+    ///
+    /// Pre-release versions are ignored unless requirement contains a
+    /// pre-release tag.
+    pub fn resolve_version(&self, req: &str) -> Option<usize> {
+        let version_req = match VersionReq::parse(req) {
+            Ok(r) => r,
+            Err(_) => return None,
+        };
+        let allow_prerelease = req.contains('-');
+
+        let mut resolved: Option<(usize, Version)> = None;
+
+        for (i, version) in self.versions.iter().enumerate() {
+            let version = match Version::parse(version) {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+
+            if version.is_prerelease() && !allow_prerelease {
+                continue;
+            }
+
+            if !version_req.matches(&version) {
+                continue;
+            }
+
+            let is_higher = match resolved {
+                Some((_, ref highest)) => version > *highest,
+                None => true,
+            };
+
+            if is_higher {
+                resolved = Some((i, version));
+            }
+        }
+
+        resolved.map(|(i, _)| i)
+    }
+
+
+    /// Returns index of latest version by semver ordering. Yanked and
+    /// pre-release versions are ignored.
+    pub fn latest_version(&self) -> Option<usize> {
+        let mut latest: Option<(usize, Version)> = None;
+
+        for (i, version) in self.versions.iter().enumerate() {
+            if self.is_yanked(i) {
+                continue;
+            }
+
+            let version = match Version::parse(version) {
+                Ok(v) => v,
+                Err(_) => continue,
+            };
+
+            if version.is_prerelease() {
+                continue;
+            }
+
+            let is_higher = match latest {
+                Some((_, ref highest)) => version > *highest,
+                None => true,
+            };
+
+            if is_higher {
+                latest = Some((i, version));
+            }
+        }
+
+        latest.map(|(i, _)| i)
+    }
+
+
+    /// Returns true if version is yanked from crates.io-index
+    pub fn is_yanked(&self, version_index: usize) -> bool {
+        self.yanked.get(version_index).cloned().unwrap_or(false)
+    }
+
+
+    /// Returns SHA-256 checksum of version if it's known
+    pub fn checksum(&self, version_index: usize) -> Option<&str> {
+        self.checksums.get(version_index)
+            .map(|c| &c[..])
+            .and_then(|c| if c.is_empty() { None } else { Some(c) })
+    }
+
+
     /// Returns canonical name of crate, i.e: "rand-0.1.13"
     pub fn canonical_name(&self, version_index: usize) -> String {
         format!("{}-{}", self.name, self.versions[version_index])
     }
 
 
-    /// Extracts crate into CWD
-    pub fn extract_crate(&self, version_index: usize) -> Result<String, String> {
-        let crate_name = format!("{}.crate", self.canonical_name(version_index));
-        command_result(Command::new("tar")
-                       .arg("-xzvf")
-                       .arg(crate_name)
-                       .output()
-                       .unwrap())
+    /// Returns path of crate file in dir, i.e: "dir/rand-0.1.13.crate"
+    pub fn crate_file(&self, version_index: usize, dir: &Path) -> PathBuf {
+        dir.join(format!("{}.crate", self.canonical_name(version_index)))
     }
 
 
-    /// Downloads crate into CWD
-    pub fn download_crate(&self, version_index: usize) -> Result<String, String> {
-        // By default crates.io is using:
-        // https://crates.io/api/v1/crates/$crate/$version/download
-        // But I believe this url is increasing download count and this program is
-        // downloading alot during development. I am using redirected url.
-        let url = format!("https://crates-io.s3-us-west-1.amazonaws.com/crates/{}/{}-{}.crate",
-                          self.name,
-                          self.name,
-                          self.versions[version_index]);
-        // Use wget for now
-        command_result(Command::new("wget")
-                       .arg("-c")
-                       .arg("--content-disposition")
-                       .arg(url)
-                       .output()
-                       .unwrap())
+    /// Extracts crate file in dir into dir and returns path of extracted
+    /// crate root
+    pub fn extract_crate(&self,
+                         version_index: usize,
+                         dir: &Path,
+                         limits: &ExtractLimits)
+                         -> Result<PathBuf, DocBuilderError> {
+        extract_crate_file(&self.crate_file(version_index, dir), dir, limits)
+    }
+
+
+    /// Downloads crate into dir, crate files are cached in temporary
+    /// directory
+    #[cfg(test)]
+    pub fn download_crate(&self, version_index: usize, dir: &Path) -> Result<(), DocBuilderError> {
+        self.download_crate_cached(version_index,
+                                   dir,
+                                   &DownloadOptions::default(),
+                                   Some(&CrateCache::in_temp_dir()))
+            .map(|_| ())
+    }
+
+
+    /// Downloads crate into dir. Crate file is copied from cache if it's
+    /// available and downloaded crate is stored into cache. Returns URL of
+    /// downloaded crate, None if crate file is copied from cache.
+    pub fn download_crate_cached(&self,
+                                 version_index: usize,
+                                 dir: &Path,
+                                 options: &DownloadOptions,
+                                 cache: Option<&CrateCache>)
+                                 -> Result<Option<String>, DocBuilderError> {
+        let canonical_name = self.canonical_name(version_index);
+        let destination = self.crate_file(version_index, dir);
+
+        if let Some(cache) = cache {
+            if cache.get(&canonical_name, self.checksum(version_index), &destination) {
+                debug!("Using cached crate file of {}", canonical_name);
+                return Ok(None);
+            }
+        }
+
+        let url = try!(self.download_crate_from_mirrors(version_index, dir, options));
+
+        if let Some(cache) = cache {
+            // cache is only an optimization, build can continue
+            if let Err(e) = cache.insert(&canonical_name, &destination) {
+                warn!("Failed to cache crate file of {}: {}", canonical_name, e);
+            }
+        }
+
+        Ok(Some(url))
+    }
+
+
+    /// Downloads crate into dir from first mirror serving a valid crate
+    /// file and returns URL of downloaded crate. Error of last mirror is
+    /// returned if every mirror fails.
+    pub fn download_crate_from_mirrors(&self,
+                                       version_index: usize,
+                                       dir: &Path,
+                                       options: &DownloadOptions)
+                                       -> Result<String, DocBuilderError> {
+        let destination = self.crate_file(version_index, dir);
+        let mut last_error = DocBuilderError::CrateDownloadFailed;
+
+        for template in &options.urls {
+            let url = download_url(template, &self.name, &self.versions[version_index]);
+            match self.download_from_url(version_index, &url, &destination, options) {
+                Ok(()) => return Ok(url),
+                Err(e) => {
+                    warn!("Failed to download {}: {}", url, e);
+                    last_error = e;
+                }
+            }
+        }
+
+        Err(last_error)
+    }
+
+
+    /// Downloads crate from url into destination and verifies its checksum
+    fn download_from_url(&self,
+                         version_index: usize,
+                         url: &str,
+                         destination: &Path,
+                         options: &DownloadOptions)
+                         -> Result<(), DocBuilderError> {
+        try!(download_file(url, destination, options.timeout, &options.retry_policy));
+
+        // verify downloaded file if checksum is available
+        if let Some(expected) = self.checksum(version_index) {
+            let found = try!(sha256_file(destination)
+                             .map_err(DocBuilderError::DownloadCrateIoError));
+            if expected != found {
+                let _ = fs::remove_file(destination);
+                return Err(DocBuilderError::ChecksumMismatch(expected.to_string(), found));
+            }
+        }
+
+        Ok(())
     }
 
 
@@ -212,7 +445,10 @@ impl Crate {
     /// Some packages have local dependencies defined in Cargo.toml
     ///
     /// This function is intentionall written verbose
-    fn download_dependencies(&self, root_dir: &PathBuf, docbuilder: &DocBuilder) -> Result<(), DocBuilderError> {
+    fn download_dependencies(&self,
+                             root_dir: &PathBuf,
+                             dir: &Path,
+                             docbuilder: &DocBuilder) -> Result<(), DocBuilderError> {
 
         let mut cargo_toml_path = PathBuf::from(&root_dir);
         cargo_toml_path.push("Cargo.toml");
@@ -227,7 +463,10 @@ impl Crate {
             .and_then(|cargo_toml| cargo_toml.get("dependencies"))
             .and_then(|dependencies| dependencies.as_table())
             .and_then(|dependencies_table| self.get_local_dependencies(dependencies_table, docbuilder))
-            .map(|local_dependencies| self.handle_local_dependencies(local_dependencies, &root_dir))
+            .map(|local_dependencies| self.handle_local_dependencies(local_dependencies,
+                                                                     &root_dir,
+                                                                     dir,
+                                                                     docbuilder))
             .unwrap_or(Ok(()))
     }
 
@@ -245,20 +484,23 @@ impl Crate {
                 .and_then(|key_val| key_val.as_table())
                 .map(|key_table| {
                     key_table.get("path").and_then(|p| p.as_str()).map(|path| {
-                        key_table.get("version").and_then(|p| p.as_str())
-                            .map(|version| {
-                                // TODO: This kinda became a mess
-                                //       I wonder if can use more and_then's...
-                                if let Ok(dep_crate) = Crate::from_cargo_index_path(&key,
-                                                            &docbuilder.crates_io_index_path) {
-                                    if let Some(version_index) =
-                                        dep_crate.version_starts_with(version) {
-                                        local_dependencies.push((dep_crate,
-                                                                 version_index,
-                                                                 path.to_string()));
-                                    }
-                                }
-                            });
+                        let version = key_table.get("version").and_then(|p| p.as_str());
+                        // TODO: This kinda became a mess
+                        //       I wonder if can use more and_then's...
+                        if let Ok(dep_crate) = Crate::from_cargo_index_path(&key,
+                                                    &docbuilder.crates_io_index_path) {
+                            // workspace members can depend on each other without
+                            // a version, latest version is used for them
+                            let version_index = match version {
+                                Some(version) => dep_crate.resolve_version(version),
+                                None => dep_crate.latest_version(),
+                            };
+                            if let Some(version_index) = version_index {
+                                local_dependencies.push((dep_crate,
+                                                         version_index,
+                                                         path.to_string()));
+                            }
+                        }
                     });
                 });
 
@@ -267,10 +509,13 @@ impl Crate {
     }
 
 
-    /// Handles local dependencies
+    /// Handles local dependencies, dependencies are downloaded into dir and
+    /// copied into crate root
     fn handle_local_dependencies(&self,
                                  local_dependencies: Vec<(Crate, usize, String)>,
-                                 root_dir: &PathBuf) -> Result<(), DocBuilderError> {
+                                 root_dir: &PathBuf,
+                                 dir: &Path,
+                                 docbuilder: &DocBuilder) -> Result<(), DocBuilderError> {
         for local_dependency in local_dependencies {
             let crte = local_dependency.0;
             let version_index = local_dependency.1;
@@ -283,21 +528,19 @@ impl Crate {
                 try!(fs::create_dir_all(&path).map_err(DocBuilderError::LocalDependencyIoError));
             }
 
-            try!(crte.download_crate(version_index)
-                 .map_err(DocBuilderError::LocalDependencyDownloadError));
-            try!(crte.extract_crate(version_index)
-                 .map_err(DocBuilderError::LocalDependencyExtractCrateError));
+            try!(crte.download_crate_cached(version_index,
+                                            dir,
+                                            &docbuilder.download_options(),
+                                            docbuilder.crate_cache.as_ref()));
+            try!(crte.extract_crate(version_index, dir, &docbuilder.extract_limits));
 
-            let crte_download_dir = PathBuf::from(format!("{}-{}",
-                                                          crte.name,
-                                                          crte.versions[version_index]));
+            let crte_download_dir = dir.join(crte.canonical_name(version_index));
 
             if !crte_download_dir.exists() {
                 return Err(DocBuilderError::LocalDependencyDownloadDirNotExist);
             }
 
 
-            // self.extract_crate will extract crate into build_dir
             // Copy files to proper location
             try!(copy_files(&crte_download_dir, &path));
 
@@ -305,7 +548,7 @@ impl Crate {
             try!(fs::remove_dir_all(&crte_download_dir)
                  .map_err(DocBuilderError::LocalDependencyIoError));
 
-            try!(crte.remove_crate_file(version_index));
+            try!(crte.remove_crate_file(version_index, dir));
         }
 
         Ok(())
@@ -313,8 +556,9 @@ impl Crate {
 
 
     fn remove_build_dir_for_crate(&self,
-                                  version_index: usize) -> Result<(), DocBuilderError> {
-        let path = PathBuf::from(self.canonical_name(version_index));
+                                  version_index: usize,
+                                  dir: &Path) -> Result<(), DocBuilderError> {
+        let path = dir.join(self.canonical_name(version_index));
 
         if path.exists() && path.is_dir() {
             try!(fs::remove_dir_all(&path).map_err(DocBuilderError::RemoveBuildDir));
@@ -324,69 +568,278 @@ impl Crate {
     }
 
 
-    /// Builds crate documentation
-    pub fn build_crate_doc(&self,
-                           version_index: usize,
-                           docbuilder: &DocBuilder) -> Result<(), DocBuilderError> {
-
-
-        let package_root = PathBuf::from(self.canonical_name(version_index));
-
-        info!("Building documentation for {}-{}", self.name, self.versions[version_index]);
+    /// Downloads and extracts crate into dir, isolates it from its workspace
+    /// and handles local dependencies. Returns crate root and URL of
+    /// downloaded crate, URL is None if crate file is copied from cache.
+    pub fn prepare_crate(&self,
+                         version_index: usize,
+                         dir: &Path,
+                         docbuilder: &DocBuilder)
+                         -> Result<(PathBuf, Option<String>), DocBuilderError> {
 
         // removing old build directory
-        try!(self.remove_build_dir_for_crate(version_index));
+        try!(self.remove_build_dir_for_crate(version_index, dir));
 
         // Download crate
-        // FIXME: Need to capture failed command outputs
-        info!("Downloading crate\n{}",
-              try!(self.download_crate(version_index)
-                   .map_err(DocBuilderError::DownloadCrateError)));
+        info!("Downloading crate");
+        let url = try!(self.download_crate_cached(version_index,
+                                                  dir,
+                                                  &docbuilder.download_options(),
+                                                  docbuilder.crate_cache.as_ref()));
+        if let Some(ref url) = url {
+            info!("{}{}", DOWNLOADED_FROM_LOG_PREFIX, url);
+        }
 
         // Extract crate
-        info!("Extracting crate\n{}",
-              try!(self.extract_crate(version_index)
-                   .map_err(DocBuilderError::ExtractCrateError)));
+        let package_root = try!(self.extract_crate(version_index,
+                                                   dir,
+                                                   &docbuilder.extract_limits));
+        info!("Extracted crate into {}", package_root.display());
+
+        // crates published from a workspace must be built standalone
+        if try!(isolate_workspace(&package_root).map_err(DocBuilderError::WorkspaceIoError)) {
+            info!("Replaced workspace settings of Cargo.toml");
+        }
 
         info!("Checking local dependencies");
-        try!(self.download_dependencies(&package_root, &docbuilder));
+        try!(self.download_dependencies(&package_root, dir, &docbuilder));
+
+        Ok((package_root, url))
+    }
+
+
+    /// Builds crate documentation in dir
+    pub fn build_crate_doc(&self,
+                           version_index: usize,
+                           dir: &Path,
+                           docbuilder: &DocBuilder) -> Result<(), DocBuilderError> {
+
+        info!("Building documentation for {}-{}", self.name, self.versions[version_index]);
+
+        try!(self.prepare_crate(version_index, dir, docbuilder));
 
         // build docs
         info!("Building documentation");
-        let (status, message) = match self.build_doc(version_index) {
-            Ok(m) => (true, m),
-            Err(m) => (false, m),
+        let res = self.build_doc(version_index, dir, docbuilder);
+        info!("cargo doc --no-deps --verbose\n{}", match res {
+            Ok(ref m) => &m[..],
+            Err(ref f) => f.message(),
+        });
+
+        match res {
+            Ok(_) => Ok(()),
+            Err(CommandFailure::TimedOut(_)) => Err(DocBuilderError::BuildTimedOut),
+            Err(CommandFailure::Failed(_)) => Err(DocBuilderError::FailedToBuildCrate),
+        }
+    }
+
+
+    /// Runs cargo doc in crate root in dir, inside sandbox if DocBuilder has
+    /// one
+    ///
+    /// Documentation is also built for additional targets of DocBuilder
+    /// after default target. Failure of an additional target doesn't fail
+    /// the build, its documentation is just not available.
+    ///
+    /// Build limits of DocBuilder are enforced in both cases.
+    pub fn build_doc(&self,
+                     version_index: usize,
+                     dir: &Path,
+                     docbuilder: &DocBuilder) -> Result<String, CommandFailure> {
+        let package_root = dir.join(self.canonical_name(version_index));
+
+        // apply options from [package.metadata.docs.rs]
+        let metadata = self.docs_metadata(&package_root, docbuilder);
+
+        // crates are built with all features unless they choose their
+        // features, default features are used if it fails
+        let (mut output, metadata) = if docbuilder.all_features &&
+                                        metadata.uses_default_features() {
+            let mut all_features_metadata = metadata.clone();
+            all_features_metadata.all_features = true;
+            match self.run_cargo_doc(version_index,
+                                     &package_root,
+                                     docbuilder,
+                                     &all_features_metadata) {
+                Ok(m) => (m, all_features_metadata),
+                Err(CommandFailure::Failed(m)) => {
+                    let failure = format!("{}\n{}\n", m, ALL_FEATURES_FAILED_LOG_MARKER);
+                    match self.run_cargo_doc(version_index, &package_root, docbuilder, &metadata) {
+                        Ok(m) => (failure + &m, metadata),
+                        Err(CommandFailure::Failed(m)) => {
+                            return Err(CommandFailure::Failed(failure + &m))
+                        }
+                        Err(CommandFailure::TimedOut(m)) => {
+                            return Err(CommandFailure::TimedOut(failure + &m))
+                        }
+                    }
+                }
+                // timed out build is not built again
+                Err(f) => return Err(f),
+            }
+        } else {
+            (try!(self.run_cargo_doc(version_index, &package_root, docbuilder, &metadata)),
+             metadata)
+        };
+
+        for doc_target in &docbuilder.targets {
+            if Some(doc_target) == metadata.default_target.as_ref() {
+                continue;
+            }
+
+            let mut target_metadata = metadata.clone();
+            target_metadata.default_target = Some(doc_target.clone());
+            match self.run_cargo_doc(version_index, &package_root, docbuilder, &target_metadata) {
+                Ok(m) => output.push_str(&m),
+                Err(f) => {
+                    output.push_str(&format!("Failed to build documentation for {}:\n{}",
+                                             doc_target, f.message()))
+                }
+            }
+        }
+
+        Ok(output)
+    }
+
+
+    fn run_cargo_doc(&self,
+                     version_index: usize,
+                     package_root: &Path,
+                     docbuilder: &DocBuilder,
+                     metadata: &Metadata) -> Result<String, CommandFailure> {
+        let mut args = vec!["doc".to_string(), "--no-deps".to_string(), "--verbose".to_string()];
+        args.extend(self.package_args(package_root));
+        args.extend(metadata.cargo_args());
+
+        let mut output = String::new();
+        if docbuilder.build_limits.is_restricted() {
+            output = try!(self.fetch_dependencies(version_index, package_root, docbuilder));
+            args.push("--frozen".to_string());
+        }
+
+        let mut env_vars = metadata.build_env();
+        if let Some(rustdoc_flags) = metadata.rustdoc_flags(&docbuilder.rustdoc_args) {
+            env_vars.push(("RUSTDOCFLAGS".to_string(), rustdoc_flags));
+        }
+
+        let mut args = docbuilder.cargo_args(&args);
+        let res = if let Some(ref sandbox) = docbuilder.sandbox {
+            let container_name = format!("cratesfyi-{}", self.canonical_name(version_index));
+            args.insert(0, "cargo".to_string());
+            sandbox.run(&container_name,
+                        package_root,
+                        &args,
+                        &env_vars,
+                        &docbuilder.build_limits)
+        } else {
+            docbuilder.build_limits.run("cargo", &args, &env_vars, package_root)
         };
-        info!("cargo doc --no-deps --verbose\n{}", message);
 
-        if status {
-            Ok(())
+        match res {
+            Ok(m) => Ok(output + &m),
+            Err(CommandFailure::Failed(m)) => {
+                output.push_str(&m);
+                // explain failures caused by restrictions
+                if docbuilder.build_limits.is_restricted() &&
+                   m.contains("failed to run custom build command") {
+                    output.push_str(&format!("\n{}\n", BUILD_SCRIPT_BLOCKED_LOG_MARKER));
+                }
+                Err(CommandFailure::Failed(output))
+            }
+            Err(CommandFailure::TimedOut(m)) => Err(CommandFailure::TimedOut(output + &m)),
+        }
+    }
+
+
+    /// Returns arguments to select member package of crate if crate root
+    /// has a virtual manifest
+    fn package_args(&self, package_root: &Path) -> Vec<String> {
+        if is_virtual_manifest(package_root) {
+            vec!["-p".to_string(), self.name.clone()]
         } else {
-            Err(DocBuilderError::FailedToBuildCrate)
+            Vec::new()
         }
     }
 
 
-    fn build_doc(&self, version_index: usize) -> Result<String, String> {
-        let cwd = env::current_dir().unwrap();
-        let mut target = PathBuf::from(&cwd);
-        target.push(self.canonical_name(version_index));
-        env::set_current_dir(target).unwrap();
-        let res = command_result(Command::new("cargo")
-                                 .arg("doc")
-                                 .arg("--no-deps")
-                                 .arg("--verbose")
-                                 .output()
-                                 .unwrap());
-        env::set_current_dir(cwd).unwrap();
-        res
+    /// Reads `[package.metadata.docs.rs]` of crate, Cargo.toml of member
+    /// package is used if crate root has a virtual manifest. Default target
+    /// set by operator for crate is preferred over metadata.
+    pub fn docs_metadata(&self, package_root: &Path, docbuilder: &DocBuilder) -> Metadata {
+        let mut metadata = if !is_virtual_manifest(package_root) {
+            Metadata::from_crate_root(package_root)
+        } else {
+            read_manifest(package_root, &self.name)
+                .ok()
+                .and_then(|m| m.manifest_path.parent().map(Metadata::from_crate_root))
+                .unwrap_or(Metadata::default())
+        };
+        docbuilder.override_metadata(&self.name, &mut metadata);
+        metadata
+    }
+
+
+    /// Fetches dependencies of crate with network access before a
+    /// restricted build
+    fn fetch_dependencies(&self,
+                          version_index: usize,
+                          package_root: &Path,
+                          docbuilder: &DocBuilder) -> Result<String, CommandFailure> {
+        let mut limits = docbuilder.build_limits.clone();
+        limits.deny_network = false;
+        let mut args = docbuilder.cargo_args(&["fetch".to_string()]);
+
+        if let Some(ref sandbox) = docbuilder.sandbox {
+            let container_name = format!("cratesfyi-fetch-{}",
+                                         self.canonical_name(version_index));
+            // keeps CARGO_HOME of sandbox in package_root
+            limits.frozen = true;
+            args.insert(0, "cargo".to_string());
+            return sandbox.run(&container_name, package_root, &args, &[], &limits);
+        }
+
+        limits.run("cargo", &args, &[], package_root)
+    }
+
+
+    /// Runs tests of crate in dir, inside sandbox if DocBuilder has one.
+    /// Build limits of DocBuilder are enforced in both cases.
+    pub fn run_tests(&self,
+                     version_index: usize,
+                     dir: &Path,
+                     docbuilder: &DocBuilder,
+                     mode: TestMode) -> Result<String, CommandFailure> {
+        let package_root = dir.join(self.canonical_name(version_index));
+        let metadata = self.docs_metadata(&package_root, docbuilder);
+
+        let mut args = mode.cargo_args();
+        args.extend(self.package_args(&package_root));
+        args.extend(metadata.cargo_args());
+        if docbuilder.build_limits.is_restricted() {
+            // dependencies are already fetched by build
+            args.push("--frozen".to_string());
+        }
+
+        let mut args = docbuilder.cargo_args(&args);
+        if let Some(ref sandbox) = docbuilder.sandbox {
+            let container_name = format!("cratesfyi-test-{}", self.canonical_name(version_index));
+            args.insert(0, "cargo".to_string());
+            return sandbox.run(&container_name,
+                               &package_root,
+                               &args,
+                               &[],
+                               &docbuilder.build_limits);
+        }
+
+        docbuilder.build_limits.run("cargo", &args, &[], &package_root)
     }
 
 
-    /// Removes crate file if it's exists in CWD
+    /// Removes crate file if it's exists in dir
     pub fn remove_crate_file(&self,
-                             version_index: usize) -> Result<(), DocBuilderError>{
-        let path = PathBuf::from(format!("{}.crate", self.canonical_name(version_index)));
+                             version_index: usize,
+                             dir: &Path) -> Result<(), DocBuilderError>{
+        let path = self.crate_file(version_index, dir);
 
         if path.exists() && path.is_file() {
             try!(fs::remove_file(path).map_err(DocBuilderError::RemoveCrateFile));
@@ -396,34 +849,111 @@ impl Crate {
     }
 
 
-    /// Get manifest of a crate. This function assumes crate downloaded and exracted.
-    pub fn manifest(&self,
-                    version_index: usize)
-    -> Result<cargo::core::manifest::Manifest, CrateOpenError> {
-        let cwd = env::current_dir().unwrap();
-        let mut package_root = PathBuf::from(&cwd);
-        package_root.push(self.canonical_name(version_index));
-        let (manifest, _) = try!(path_to_manifest(package_root.as_path()).
-                                 map_err(CrateOpenError::ManifestError));
+    /// Get manifest of a crate. This function assumes crate downloaded and
+    /// extracted into dir.
+    pub fn manifest(&self, version_index: usize, dir: &Path) -> Result<Manifest, CrateOpenError> {
+        let package_root = dir.join(self.canonical_name(version_index));
+        read_manifest(&package_root, &self.name).map_err(CrateOpenError::ManifestError)
+    }
+
+
+    /// Gets CrateInfo. This function assumes crate downloaded and extracted
+    /// into dir.
+    #[cfg(test)]
+    pub fn info(&self, version_index: usize, dir: &Path) -> Result<CrateInfo, CrateOpenError> {
+        info_from_path(&dir.join(self.canonical_name(version_index)), &self.name)
+    }
+
+
+    /// Gets ReleaseInfo of a version without building it, crate is
+    /// downloaded unless its sources are stored in sources_path
+    pub fn release_info(&self,
+                        version_index: usize,
+                        docbuilder: &DocBuilder)
+                        -> Result<ReleaseInfo, CrateOpenError> {
+        let (mut info, have_examples) = try!(self.read_info(version_index, docbuilder));
+        docbuilder.override_metadata(&self.name, &mut info.build_metadata);
+        let (release_time, yanked, downloads) = try!(self.cratesio_release(version_index,
+                                                                            docbuilder));
+        Ok(ReleaseInfo {
+            info: info,
+            have_examples: have_examples,
+            release_time: release_time.map(|t| time::at_utc(t).rfc3339().to_string()),
+            yanked: yanked,
+            downloads: downloads,
+        })
+    }
+
+
+    /// Reads CrateInfo of a version and returns it with existence of
+    /// examples. Stored sources are used if they exist, otherwise crate is
+    /// downloaded into build directory.
+    fn read_info(&self,
+                 version_index: usize,
+                 docbuilder: &DocBuilder)
+                 -> Result<(CrateInfo, bool), CrateOpenError> {
+        fn have_examples(path: &PathBuf) -> bool {
+            let path = PathBuf::from(path).join("examples");
+            path.exists() && path.is_dir()
+        }
+
+        // check source directory
+        let mut path = PathBuf::from(&docbuilder.sources_path);
+        path.push(&self.name);
+        path.push(&self.versions[version_index]);
+        if path.exists() {
+            return Ok((try!(info_from_path(&path, &self.name)), have_examples(&path)));
+        }
 
-        Ok(manifest)
+        // download crate into build directory
+        let dir = docbuilder.build_dir.clone();
+        try!(self.download_crate_cached(version_index,
+                                        &dir,
+                                        &docbuilder.download_options(),
+                                        docbuilder.crate_cache.as_ref())
+             .map_err(CrateOpenError::DocBuilderError));
+        let path = try!(self.extract_crate(version_index, &dir, &docbuilder.extract_limits)
+                        .map_err(CrateOpenError::DocBuilderError));
+        try!(isolate_workspace(&path).map_err(CrateOpenError::IoError));
+        let info = (try!(info_from_path(&path, &self.name)), have_examples(&path));
+        try!(self.remove_crate_file(version_index, &dir)
+             .map_err(CrateOpenError::DocBuilderError));
+        try!(self.remove_build_dir_for_crate(version_index, &dir)
+             .map_err(CrateOpenError::DocBuilderError));
+        Ok(info)
     }
 
 
-    /// Gets CrateInfo. This function assumes crate downloaded and exracted.
-    pub fn info(&self, version_index: usize) -> Result<CrateInfo, CrateOpenError> {
-        let cwd = env::current_dir().unwrap();
-        let mut package_root = PathBuf::from(&cwd);
-        package_root.push(self.canonical_name(version_index));
-        info_from_path(package_root.as_path())
+    /// Returns release time, yanked status and downloads of a version in
+    /// crates.io, they are None if crates.io doesn't have version
+    fn cratesio_release(&self,
+                        version_index: usize,
+                        docbuilder: &DocBuilder)
+                        -> Result<(Option<time::Timespec>, Option<bool>, Option<i32>),
+                                  CrateOpenError> {
+        let versions = try!(docbuilder.cratesio.get_versions(&self.name));
+
+        let (mut release_time, mut yanked, mut downloads) = (None, None, None);
+
+        if let Some(version) = versions.iter()
+            .find(|v| v.num == self.versions[version_index]) {
+            release_time = Some(try!(time::strptime(&version.created_at,
+                                                    "%Y-%m-%dT%H:%M:%S")
+                                     .map_err(CrateOpenError::TimeParseError))
+                                .to_timespec());
+            yanked = Some(version.yanked);
+            downloads = Some(version.downloads as i32);
+        }
+
+        Ok((release_time, yanked, downloads))
     }
 
 
-    /// Adds crate into database
+    /// Adds crate into database and returns id of release
     pub fn add_crate_into_database(&self,
                                    version_index: usize,
                                    conn: &postgres::Connection,
-                                   docbuilder: &DocBuilder) -> Result<(), CrateOpenError> {
+                                   docbuilder: &DocBuilder) -> Result<i32, CrateOpenError> {
 
         let crate_id: i32 = {
             let mut rows = try!(conn.query("SELECT id FROM crates WHERE name = $1",
@@ -436,118 +966,59 @@ impl Crate {
             rows.get(0).get(0)
         };
 
-        let (crate_info, have_examples) = {
+        let (mut crate_info, have_examples) = try!(self.read_info(version_index, docbuilder));
 
-            fn have_examples(path: &PathBuf) -> bool {
-                let path = PathBuf::from(path).join("examples");
-                path.exists() && path.is_dir()
-            }
-
-            // check source directory
-            let mut path = PathBuf::from(&docbuilder.sources_path);
-            path.push(&self.name);
-            path.push(&self.versions[version_index]);
-            if path.exists() {
-                (try!(info_from_path(&path)), have_examples(&path))
-            } else {
-                try!(self.download_crate(version_index).map_err(CrateOpenError::CommandError));
-                try!(self.extract_crate(version_index).map_err(CrateOpenError::CommandError));
-                let mut path = PathBuf::from(env::current_dir().unwrap());
-                path.push(self.canonical_name(version_index));
-                let info = try!(info_from_path(&path));
-                try!(self.remove_crate_file(version_index)
-                     .map_err(CrateOpenError::DocBuilderError));
-                try!(self.remove_build_dir_for_crate(version_index)
-                     .map_err(CrateOpenError::DocBuilderError));
-                (info, have_examples(&path))
-            }
-        };
 
+        docbuilder.override_metadata(&self.name, &mut crate_info.build_metadata);
+        let default_target = crate_info.build_metadata.target().to_string();
+        let document_private_items = crate_info.build_metadata.document_private_items;
 
         let dependencies = try!(encode(&crate_info.dependencies)
                                 .map_err(CrateOpenError::EncoderError));
 
-        let (release_time, yanked, downloads) = {
-            let url = format!("https://crates.io/api/v1/crates/{}/versions", self.name);
-            // FIXME: There is probably better way to do this
-            //        and so many unwraps...
-            let client = Client::new();
-            let mut res = client.get(&url[..]).send().unwrap();
-            let mut body = String::new();
-            res.read_to_string(&mut body).unwrap();
-            let json = Json::from_str(&body[..]).unwrap();
-            let versions = try!(json.as_object()
-                .and_then(|o| o.get("versions"))
-                .and_then(|v| v.as_array())
-                .ok_or(CrateOpenError::NotObject));
-
-            let (mut release_time, mut yanked, mut downloads) = (None, None, None);
-
-            for version in versions {
-                let version = try!(version.as_object().ok_or(CrateOpenError::NotObject));
-                let version_num = try!(version.get("num").and_then(|v| v.as_string())
-                    .ok_or(CrateOpenError::NotObject));
-
-                if version_num == self.versions[version_index] {
-                    let release_time_raw = try!(version.get("created_at")
-                                                .and_then(|c| c.as_string())
-                                                .ok_or(CrateOpenError::NotObject));
-                    release_time = Some(time::strptime(release_time_raw,
-                                                       "%Y-%m-%dT%H:%M:%S").unwrap()
-                                        .to_timespec());
-
-                    yanked = Some(try!(version.get("yanked").and_then(|c| c.as_boolean())
-                        .ok_or(CrateOpenError::NotObject)));
-
-                    downloads = Some(try!(version.get("downloads").and_then(|c| c.as_i64())
-                        .ok_or(CrateOpenError::NotObject)) as i32);
-
-                    break;
-                }
-            }
-
-            (release_time, yanked, downloads)
-        };
-
+        let dependencies = try!(Json::from_str(&dependencies[..]));
+        let targets = try!(Json::from_str(&try!(encode(&crate_info.targets))));
+        let features = try!(Json::from_str(&try!(encode(&crate_info.features))));
+        let authors = try!(Json::from_str(&try!(encode(&crate_info.metadata.authors))));
+        let keywords = try!(Json::from_str(&try!(encode(&crate_info.metadata.keywords))));
 
-        let (build_status, rustdoc_status) = {
-            let mut build_log_path = PathBuf::from(&docbuilder.logs_path);
-            build_log_path.push(format!("{}/{}-{}.log",
-                                        self.name,
-                                        self.name,
-                                        self.versions[version_index]));
+        let build_config = try!(encode(&crate_info.build_metadata)
+                                .map_err(CrateOpenError::EncoderError)
+                                .and_then(|c| Json::from_str(&c[..])
+                                          .map_err(CrateOpenError::ParseError)));
 
-            let mut crate_doc_path = PathBuf::from(&docbuilder.destination);
-            crate_doc_path.push(&self.name);
-            crate_doc_path.push(&self.versions[version_index]);
+        let (release_time, yanked, downloads) = try!(self.cratesio_release(version_index,
+                                                                            docbuilder));
 
-            // Build is _most likely successfully_ if build log
-            // and crate doc directory exists
-            let build_status = if build_log_path.exists() && crate_doc_path.exists() {
-                1
-            }
-            // Build is most likely failed if crate build log is exists
-            // but documentation directory isn't
-            else if build_log_path.exists() && !crate_doc_path.exists() {
-                -1
-            }
-            // cratesfyi not tried to build this crate if build log is not exists
-            else {
-                0
-            };
 
+        let storage = docbuilder.doc_storage();
+        let release_doc_path = format!("{}/{}", self.name, self.versions[version_index]);
 
-            // check existence of first target in manifest
-            // in destination directory to find out rustdoc status
-            crate_doc_path.push(crate_info.target_name);
-            let rustdoc_status = if crate_doc_path.exists() { 1 } else { 0 };
-
-            (build_status, rustdoc_status)
+        let rustdoc_status = if !crate_info.is_library {
+            // binaries don't have library documentation
+            0
+        } else {
+            // check existence of library target in documentation storage
+            // to find out rustdoc status
+            let crate_doc_path = format!("{}/{}", release_doc_path, crate_info.target_name);
+            if try!(storage.exists(&crate_doc_path)) { 1 } else { 0 }
         };
 
+        // additional targets having documentation in storage
+        let doc_targets = {
+            let mut doc_targets = Vec::new();
+            for target in &docbuilder.targets {
+                if crate_info.is_library &&
+                   try!(storage.exists(&format!("{}/{}/{}",
+                                                release_doc_path,
+                                                target,
+                                                crate_info.target_name))) {
+                    doc_targets.push(target);
+                }
+            }
+            try!(Json::from_str(&try!(encode(&doc_targets))))
+        };
 
-        // TODO: Add test status
-        let test_status = 0;
 
         let release_id: i32 = {
             let rows = try!(conn.query("SELECT id FROM releases \
@@ -556,23 +1027,31 @@ impl Crate {
 
             // Add release into database if it's not exists
             if rows.len() == 0 {
+                // release is never built if it's not in database
+                let build_status = 0;
+                let test_status = 0;
                 let rows = try!(conn.query("INSERT INTO releases ( \
                                                crate_id,         version,        release_time, \
                                                dependencies,     yanked,         build_status, \
                                                rustdoc_status,   test_status,    license, \
                                                repository_url,   homepage_url,   description, \
                                                description_long, readme,         authors, \
-                                               keywords,         have_examples,  downloads \
+                                               keywords,         have_examples,  downloads, \
+                                               build_config,     target_name,    doc_targets, \
+                                               readme_html,      targets,        features, \
+                                               is_library,       default_target, \
+                                               document_private_items \
                                            ) \
                                            VALUES ( \
                                                $1,  $2,  $3,  $4,  $5,  $6,  $7, $8, $9, $10, \
-                                               $11, $12, $13, $14, $15, $16, $17, $18 \
+                                               $11, $12, $13, $14, $15, $16, $17, $18, $19, \
+                                               $20, $21, $22, $23, $24, $25, $26, $27 \
                                            ) RETURNING id",
                                            &[
                                                &crate_id,
                                                &crate_info.version,
                                                &release_time,
-                                               &Json::from_str(&dependencies[..]).unwrap(),
+                                               &dependencies,
                                                &yanked,
                                                &build_status,
                                                &rustdoc_status,
@@ -583,18 +1062,28 @@ impl Crate {
                                                &crate_info.metadata.description,
                                                &crate_info.rustdoc,
                                                &crate_info.readme,
-                                               &Json::from_str(
-                                                   &encode(&crate_info.metadata.authors).unwrap())
-                                                   .unwrap(),
-                                               &Json::from_str(
-                                                   &encode(&crate_info.metadata.keywords).unwrap())
-                                                   .unwrap(),
+                                               &authors,
+                                               &keywords,
                                                &have_examples,
                                                &downloads,
+                                               &build_config,
+                                               &crate_info.target_name,
+                                               &doc_targets,
+                                               &crate_info.readme_html,
+                                               &targets,
+                                               &features,
+                                               &crate_info.is_library,
+                                               &default_target,
+                                               &document_private_items,
                                            ]));
                 // return id
                 rows.get(0).get(0)
             } else {
+                let release_id: i32 = rows.get(0).get(0);
+                // build_status and test_status are derived from latest build of
+                // release
+                let build_status = try!(release_build_status(conn, release_id));
+                let test_status = try!(release_test_status(conn, release_id));
                 try!(conn.query("UPDATE releases \
                                  SET release_time = $3, \
                                      dependencies = $4,      yanked = $5, \
@@ -604,13 +1093,17 @@ impl Crate {
                                      description = $12,      description_long = $13, \
                                      readme = $14,           authors = $15, \
                                      keywords = $16,         have_examples = $17, \
-                                     downloads = $18 \
+                                     downloads = $18,        build_config = $19, \
+                                     target_name = $20,      doc_targets = $21, \
+                                     readme_html = $22,      targets = $23, \
+                                     features = $24,         is_library = $25, \
+                                     default_target = $26,   document_private_items = $27 \
                                  WHERE crate_id = $1 AND version = $2",
                                  &[
                                      &crate_id,
                                      &crate_info.version,
                                      &release_time,
-                                     &Json::from_str(&dependencies[..]).unwrap(),
+                                     &dependencies,
                                      &yanked,
                                      &build_status,
                                      &rustdoc_status,
@@ -621,21 +1114,29 @@ impl Crate {
                                      &crate_info.metadata.description,
                                      &crate_info.rustdoc,
                                      &crate_info.readme,
-                                     &Json::from_str(
-                                         &encode(&crate_info.metadata.authors).unwrap())
-                                         .unwrap(),
-                                     &Json::from_str(
-                                         &encode(&crate_info.metadata.keywords).unwrap())
-                                         .unwrap(),
+                                     &authors,
+                                     &keywords,
                                      &have_examples,
                                      &downloads,
+                                     &build_config,
+                                     &crate_info.target_name,
+                                     &doc_targets,
+                                     &crate_info.readme_html,
+                                     &targets,
+                                     &features,
+                                     &crate_info.is_library,
+                                     &default_target,
+                                     &document_private_items,
                                  ]));
-                rows.get(0).get(0)
+                release_id
             }
         };
 
 
 
+        try!(db::update_search_index(conn, release_id));
+
+
         // Add keywords into database
         for keyword in crate_info.metadata.keywords {
             let slug = slugify(&keyword);
@@ -685,50 +1186,35 @@ impl Crate {
         // Add owners into database
         // owners available in: https://crates.io/api/v1/crates/rand/owners
         {
-            let owners_url = format!("https://crates.io/api/v1/crates/{}/owners", self.name);
-            let client = Client::new();
-            let mut res = client.get(&owners_url[..]).send().unwrap();
-            // FIXME: There is probably better way to do this
-            //        and so many unwraps...
-            let mut body = String::new();
-            res.read_to_string(&mut body).unwrap();
-            let json = try!(Json::from_str(&body[..]).map_err(CrateOpenError::ParseError));
-
-            if let Some(owners) = json.as_object().and_then(|j| j.get("users"))
-                                                  .and_then(|j| j.as_array()) {
-                for owner in owners {
-                    let avatar = owner.as_object().and_then(|o| o.get("avatar"))
-                                      .and_then(|o| o.as_string()).unwrap_or("");
-                    let email = owner.as_object().and_then(|o| o.get("email"))
-                                     .and_then(|o| o.as_string()).unwrap_or("");
-                    let login = owner.as_object().and_then(|o| o.get("login"))
-                                     .and_then(|o| o.as_string()).unwrap_or("");
-                    let name = owner.as_object().and_then(|o| o.get("name"))
-                                    .and_then(|o| o.as_string()).unwrap_or("");
-                    let slug = slugify(&name);
-
-                    if login.is_empty() {
-                        continue;
-                    }
+            let owners = try!(docbuilder.cratesio.get_owners(&self.name));
+            try!(update_crate_owners(conn, crate_id, &owners));
+        }
 
-                    let owner_id: i32 = {
-                        let rows = try!(conn.query("SELECT id FROM owners WHERE login = $1",
-                                                   &[&login]));
-                        if rows.len() > 0 {
-                            rows.get(0).get(0)
-                        } else {
-                            try!(conn.query("INSERT INTO owners (login, slug, avatar, name, email) \
-                                            VALUES ($1, $2, $3, $4, $5) RETURNING id",
-                                            &[&login, &slug, &avatar, &name, &email]))
-                                .get(0).get(0)
-                        }
-                    };
 
-                    // add relationship
-                    let _ = conn.query("INSERT INTO owner_rels (cid, oid) VALUES ($1, $2)",
-                                       &[&crate_id, &owner_id]);
-                }
+        // Add categories into database
+        // categories of a crate can change, old relationships are removed
+        {
+            let categories = try!(docbuilder.cratesio.get_categories(&self.name));
+            try!(conn.execute("DELETE FROM category_rels WHERE cid = $1", &[&crate_id]));
 
+            for category in categories {
+                let category_id: i32 = {
+                    let rows = try!(conn.query("SELECT id FROM categories WHERE slug = $1",
+                                               &[&category.slug]));
+                    if rows.len() > 0 {
+                        rows.get(0).get(0)
+                    } else {
+                        try!(conn.query("INSERT INTO categories (slug, name, description) \
+                                        VALUES ($1, $2, $3) RETURNING id",
+                                        &[&category.slug,
+                                          &category.category,
+                                          &category.description]))
+                            .get(0).get(0)
+                    }
+                };
+
+                let _ = conn.query("INSERT INTO category_rels (cid, catid) VALUES ($1, $2)",
+                                   &[&crate_id, &category_id]);
             }
         }
 
@@ -741,7 +1227,7 @@ impl Crate {
             if let Some(versions_array) = versions.as_array_mut() {
                 let mut found = false;
                 for version in versions_array.clone() {
-                    if version.as_string().unwrap() == self.versions[version_index] {
+                    if version.as_string() == Some(&self.versions[version_index][..]) {
                         found = true;
                     }
                 }
@@ -755,52 +1241,45 @@ impl Crate {
                                &[&versions, &crate_id]);
         }
 
-        Ok(())
+        Ok(release_id)
     }
 
 }
 
 
 
-/// Generates cargo::core::manifest::Manifest from a crate path
-pub fn path_to_manifest(root_dir: &Path) ->
-cargo::util::errors::CargoResult<(cargo::core::manifest::Manifest, Vec<PathBuf>)> {
-    let cargo_config = try!(cargo::util::config::Config::default());
-    let source_id = try!(cargo::core::source::SourceId::for_path(&root_dir));
-
-    // read Cargo.toml
-    let mut cargo_toml_path = PathBuf::from(&root_dir);
-    cargo_toml_path.push("Cargo.toml");
-
-    let mut cargo_toml_fh = try!(fs::File::open(cargo_toml_path));
-    let mut cargo_toml_content = Vec::new();
-    try!(cargo_toml_fh.read_to_end(&mut cargo_toml_content));
-
-    let layout = cargo::util::toml::project_layout(root_dir);
-
-    cargo::util::toml::to_manifest(&cargo_toml_content[..], &source_id, layout, &cargo_config)
-}
-
-
-
-/// Gets crate info from path
-pub fn info_from_path(path: &Path) -> Result<CrateInfo, CrateOpenError> {
+/// Gets crate info from path, package named package_name is used if path
+/// has a virtual manifest
+pub fn info_from_path(path: &Path, package_name: &str) -> Result<CrateInfo, CrateOpenError> {
     debug!("Getting info from path: {:?}", path);
-    let (manifest, _) = try!(path_to_manifest(path).
-                             map_err(CrateOpenError::ManifestError));
-
-    let rustdoc = if manifest.targets()[0].src_path().is_absolute() {
-        try!(read_rust_doc(manifest.targets()[0].src_path()))
+    let manifest = try!(read_manifest(path, package_name)
+                        .map_err(CrateOpenError::ManifestError));
+    // sources and readme are relative to package directory
+    let path = manifest.manifest_path.parent().unwrap_or(path).to_path_buf();
+
+    // documentation is generated for library target
+    let doc_target = manifest.targets
+        .iter()
+        .find(|t| t.is_lib())
+        .unwrap_or(&manifest.targets[0]);
+
+    let rustdoc = if doc_target.src_path.is_absolute() {
+        try!(read_rust_doc(&doc_target.src_path))
     } else {
         let mut path = PathBuf::from(&path);
-        path.push(manifest.targets()[0].src_path());
+        path.push(&doc_target.src_path);
         try!(read_rust_doc(path.as_path()))
     };
 
+    let targets = manifest.targets
+        .iter()
+        .map(|t| (t.name.clone(), target_kind(t).to_string()))
+        .collect();
+
     let readme = {
-        if manifest.metadata().readme.is_some() {
-            let mut readme_path = PathBuf::from(path);
-            readme_path.push(manifest.metadata().readme.clone().unwrap());
+        if manifest.metadata.readme.is_some() {
+            let mut readme_path = PathBuf::from(&path);
+            readme_path.push(manifest.metadata.readme.clone().unwrap());
 
             let mut reader = try!(fs::File::open(readme_path).map(|f| BufReader::new(f)));
             let mut readme = String::new();
@@ -811,51 +1290,286 @@ pub fn info_from_path(path: &Path) -> Result<CrateInfo, CrateOpenError> {
         }
     };
 
-    let mut dependencies: Vec<(String, String)> = Vec::new();
+    let readme_html = readme.as_ref().map(|readme| render_markdown(readme));
 
-    for dependency in manifest.dependencies() {
-        let name = dependency.name().to_string();
-        let version = format!("{}", dependency.version_req());
-        dependencies.push((name, version));
-    }
+    let build_metadata = Metadata::from_crate_root(&path);
+    let optional_dependencies: Vec<String> = manifest.dependencies
+        .iter()
+        .filter(|d| d.optional)
+        .map(|d| d.name.clone())
+        .collect();
+    let features = crate_features(&manifest.features, &optional_dependencies, &build_metadata);
 
     Ok(CrateInfo {
-        name: manifest.name().to_string(),
-        target_name: manifest.targets()[0].name().to_string(),
-        version: format!("{}", manifest.summary().version()),
-        dependencies: dependencies,
+        name: manifest.name,
+        target_name: doc_target.name.clone(),
+        is_library: doc_target.is_lib(),
+        targets: targets,
+        version: manifest.version,
+        dependencies: manifest.dependencies,
         rustdoc: rustdoc,
         readme: readme,
-        metadata: manifest.metadata().clone()
+        readme_html: readme_html,
+        metadata: manifest.metadata,
+        build_metadata: build_metadata,
+        features: features,
     })
 }
 
 
+/// Returns features and optional dependencies of a crate and marks features
+/// enabled by build metadata
+fn crate_features(features: &collections::HashMap<String, Vec<String>>,
+                  optional_dependencies: &[String],
+                  metadata: &Metadata)
+                  -> Vec<Feature> {
+    // find out enabled features, features can enable other features and
+    // optional dependencies
+    let mut enabled = collections::HashSet::new();
+    let mut pending = metadata.features.clone().unwrap_or(Vec::new());
+    if metadata.default_features && features.contains_key("default") {
+        pending.push("default".to_string());
+    }
+    if metadata.all_features {
+        pending.extend(features.keys().cloned());
+        pending.extend(optional_dependencies.iter().cloned());
+    }
+    while let Some(feature) = pending.pop() {
+        // dependency/feature enables the dependency
+        let name = feature.split('/').next().unwrap_or("").to_string();
+        if enabled.contains(&name) {
+            continue;
+        }
+        if let Some(subfeatures) = features.get(&name) {
+            pending.extend(subfeatures.iter().cloned());
+        }
+        enabled.insert(name);
+    }
+
+    let mut crate_features: Vec<Feature> = features.iter()
+        .map(|(name, subfeatures)| {
+            Feature {
+                name: name.clone(),
+                subfeatures: subfeatures.clone(),
+                optional_dependency: false,
+                enabled: enabled.contains(name),
+            }
+        })
+        .collect();
+    crate_features.sort_by(|a, b| a.name.cmp(&b.name));
+
+    for dependency in optional_dependencies {
+        if !features.contains_key(dependency) {
+            crate_features.push(Feature {
+                name: dependency.clone(),
+                subfeatures: Vec::new(),
+                optional_dependency: true,
+                enabled: enabled.contains(dependency),
+            });
+        }
+    }
+
+    crate_features
+}
+
+
+/// Returns kind of a manifest target
+fn target_kind(target: &Target) -> &str {
+    if target.is_lib() {
+        "lib"
+    } else {
+        target.kind.first().map(|k| &k[..]).unwrap_or("unknown")
+    }
+}
+
+
 /// Gets rustdoc from file
 fn read_rust_doc(file_path: &Path) -> Result<Option<String>, CrateOpenError> {
     debug!("Reading rustdoc from: {:?}", file_path);
-    let reader = try!(fs::File::open(file_path).map(|f| BufReader::new(f)));
+    let mut content = String::new();
+    try!(fs::File::open(file_path).and_then(|mut f| f.read_to_string(&mut content)));
+    Ok(parse_rust_doc(&content))
+}
+
+
+/// Parses inner documentation of a crate root. `//!` lines, `/*! ... */`
+/// blocks and `#![doc = "..."]` attributes are supported.
+fn parse_rust_doc(content: &str) -> Option<String> {
+    let doc_attr_re = Regex::new(r#"^#!\[doc\s*=\s*"((?:[^"\\]|\\.)*)"\s*\]"#).unwrap();
     let mut rustdoc = String::new();
+    // lines of a /*! block which isn't closed yet
+    let mut block: Option<Vec<&str>> = None;
+
+    for line in content.lines() {
+        let trimmed = line.trim_left();
 
-    for line in reader.lines() {
-        let line = try!(line);
-        if line.starts_with("//!") {
-            if line.len() > 3 {
-                rustdoc.push_str(line.split_at(4).1);
+        if let Some(mut lines) = block.take() {
+            match line.find("*/") {
+                Some(end) => {
+                    lines.push(&line[..end]);
+                    push_block_doc(&mut rustdoc, &lines);
+                }
+                None => {
+                    lines.push(line);
+                    block = Some(lines);
+                }
             }
+        } else if trimmed.starts_with("//!") {
+            let doc = &trimmed[3..];
+            rustdoc.push_str(if doc.starts_with(' ') { &doc[1..] } else { doc });
+            rustdoc.push('\n');
+        } else if trimmed.starts_with("/*!") {
+            let doc = &trimmed[3..];
+            match doc.find("*/") {
+                Some(end) => push_block_doc(&mut rustdoc, &[&doc[..end]]),
+                None => block = Some(vec![doc]),
+            }
+        } else if let Some(captures) = doc_attr_re.captures(trimmed) {
+            rustdoc.push_str(&unescape_str(captures.at(1).unwrap()));
             rustdoc.push('\n');
         }
     }
 
     if rustdoc.is_empty() {
-        Ok(None)
+        None
     } else {
-        Ok(Some(rustdoc))
+        Some(rustdoc)
+    }
+}
+
+
+/// Appends lines of a `/*! ... */` block to rustdoc. Leading `*` of lines
+/// is removed if every line has it, otherwise common indentation is removed.
+fn push_block_doc(rustdoc: &mut String, lines: &[&str]) {
+    let mut lines: Vec<&str> = lines.to_vec();
+    // first line is the rest of /*! line and last line is the part before */
+    if lines.first().map_or(false, |l| l.trim().is_empty()) {
+        lines.remove(0);
+    }
+    if lines.last().map_or(false, |l| l.trim().is_empty()) {
+        lines.pop();
+    }
+
+    let starred = lines.iter()
+        .filter(|l| !l.trim().is_empty())
+        .all(|l| l.trim_left().starts_with('*'));
+    let indent = lines.iter()
+        .filter(|l| !l.trim().is_empty())
+        .map(|l| l.len() - l.trim_left().len())
+        .min()
+        .unwrap_or(0);
+
+    for line in lines {
+        let line = if line.trim().is_empty() {
+            ""
+        } else if starred {
+            let line = &line.trim_left()[1..];
+            if line.starts_with(' ') { &line[1..] } else { line }
+        } else if line.is_char_boundary(indent) {
+            &line[indent..]
+        } else {
+            line.trim_left()
+        };
+        rustdoc.push_str(line.trim_right());
+        rustdoc.push('\n');
+    }
+}
+
+
+/// Unescapes content of a string literal
+fn unescape_str(s: &str) -> String {
+    let mut unescaped = String::new();
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => unescaped.push('\n'),
+            Some('t') => unescaped.push('\t'),
+            Some(c) => unescaped.push(c),
+            None => {}
+        }
+    }
+    unescaped
+}
+
+
+
+impl fmt::Display for CrateOpenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CrateOpenError::FileNotFound => write!(f, "File not found"),
+            CrateOpenError::EncoderError(ref e) => write!(f, "JSON encoding error: {}", e),
+            CrateOpenError::ParseError(ref e) => write!(f, "JSON parse error: {}", e),
+            CrateOpenError::IoError(ref e) => write!(f, "IO error: {}", e),
+            CrateOpenError::ManifestError(ref e) => write!(f, "Manifest error: {}", e),
+            CrateOpenError::NotObject => write!(f, "Unexpected JSON structure"),
+            CrateOpenError::NameNotFound => write!(f, "Crate name not found"),
+            CrateOpenError::VersNotFound => write!(f, "Crate version not found"),
+            CrateOpenError::DbError(ref e) => write!(f, "Database error: {}", e),
+            CrateOpenError::CommandError(ref e) => write!(f, "Command failed: {}", e),
+            CrateOpenError::DocBuilderError(ref e) => write!(f, "{}", e),
+            CrateOpenError::ApiError(ref e) => write!(f, "crates.io API error: {}", e),
+            CrateOpenError::TimeParseError(ref e) => write!(f, "Time parse error: {}", e),
+            CrateOpenError::StorageError(ref e) => write!(f, "Storage error: {}", e),
+        }
+    }
+}
+
+
+impl error::Error for CrateOpenError {
+    fn description(&self) -> &str {
+        "failed to open crate"
     }
 
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            CrateOpenError::EncoderError(ref e) => Some(e),
+            CrateOpenError::ParseError(ref e) => Some(e),
+            CrateOpenError::IoError(ref e) => Some(e),
+            CrateOpenError::ManifestError(ref e) => Some(e),
+            CrateOpenError::DbError(ref e) => Some(e),
+            CrateOpenError::DocBuilderError(ref e) => Some(e),
+            CrateOpenError::ApiError(ref e) => Some(e),
+            CrateOpenError::TimeParseError(ref e) => Some(e),
+            CrateOpenError::StorageError(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+
+impl From<StorageError> for CrateOpenError {
+    fn from(err: StorageError) -> CrateOpenError {
+        CrateOpenError::StorageError(err)
+    }
 }
 
+impl From<ApiError> for CrateOpenError {
+    fn from(err: ApiError) -> CrateOpenError {
+        CrateOpenError::ApiError(err)
+    }
+}
 
+impl From<DocBuilderError> for CrateOpenError {
+    fn from(err: DocBuilderError) -> CrateOpenError {
+        CrateOpenError::DocBuilderError(err)
+    }
+}
+
+impl From<EncoderError> for CrateOpenError {
+    fn from(err: EncoderError) -> CrateOpenError {
+        CrateOpenError::EncoderError(err)
+    }
+}
+
+impl From<ParserError> for CrateOpenError {
+    fn from(err: ParserError) -> CrateOpenError {
+        CrateOpenError::ParseError(err)
+    }
+}
 
 impl From<postgres::error::Error> for CrateOpenError {
     fn from(err: postgres::error::Error) -> CrateOpenError {
@@ -888,9 +1602,38 @@ mod test {
     }
 
 
+    #[test]
+    fn test_resolve_version() {
+        let crte = Crate::new("cratesfyi".to_string(),
+                              vec!["0.10.3".to_string(), "0.2.0-beta.1".to_string(),
+                                   "0.1.9".to_string(), "0.1.2".to_string()]);
+        assert_eq!(crte.resolve_version("0.1"), Some(2));
+        assert_eq!(crte.resolve_version("=0.1.2"), Some(3));
+        assert_eq!(crte.resolve_version("*"), Some(0));
+        assert_eq!(crte.resolve_version("^0.2.0-beta"), Some(1));
+        assert_eq!(crte.resolve_version("^0.2"), None);
+        assert_eq!(crte.resolve_version("not a version"), None);
+    }
+
+
     // Rest of the tests only works if crates.io-index is exists in:
     // ../cratesfyi-prefix/crates.io-index
 
+    #[test]
+    fn test_latest_version() {
+        let mut crte = Crate::new("cratesfyi".to_string(),
+                                  vec!["0.3.0-beta.1".to_string(), "0.2.1".to_string(),
+                                       "0.10.0".to_string(), "0.9.0".to_string()]);
+        assert_eq!(crte.latest_version(), Some(2));
+
+        crte.yanked = vec![false, false, true, false];
+        assert_eq!(crte.latest_version(), Some(3));
+
+        let crte = Crate::new("cratesfyi".to_string(), vec!["0.1.0-alpha".to_string()]);
+        assert_eq!(crte.latest_version(), None);
+    }
+
+
     #[test]
     #[ignore]
     fn test_from_cargo_index_path() {
@@ -924,41 +1667,82 @@ mod test {
     }
 
 
+    #[test]
+    fn test_parse_rust_doc() {
+        assert_eq!(parse_rust_doc("//! Line comments\n//!\n//! second\nfn main() {}"),
+                   Some("Line comments\n\nsecond\n".to_string()));
+        assert_eq!(parse_rust_doc("/*!\n * Block\n *\n * * item\n */\nfn main() {}"),
+                   Some("Block\n\n* item\n".to_string()));
+        assert_eq!(parse_rust_doc("/*!\n    Indented\n      code\n*/"),
+                   Some("Indented\n  code\n".to_string()));
+        assert_eq!(parse_rust_doc("/*! One line */"), Some("One line\n".to_string()));
+        assert_eq!(parse_rust_doc("#![doc = \"Attribute \\\"doc\\\"\"]\n#![deny(missing_docs)]"),
+                   Some("Attribute \"doc\"\n".to_string()));
+        assert_eq!(parse_rust_doc("// comment\n/// outer doc\nfn main() {}"), None);
+    }
+
+    #[test]
+    fn test_crate_features() {
+        let mut features = collections::HashMap::new();
+        features.insert("default".to_string(), vec!["std".to_string()]);
+        features.insert("std".to_string(), vec!["rand/std".to_string()]);
+        features.insert("nightly".to_string(), Vec::new());
+        let optional_dependencies = vec!["rand".to_string(), "serde".to_string()];
+
+        let features = crate_features(&features, &optional_dependencies, &Metadata::default());
+        let names: Vec<&str> = features.iter().map(|f| &f.name[..]).collect();
+        assert_eq!(names, vec!["default", "nightly", "std", "rand", "serde"]);
+        let enabled: Vec<&str> = features.iter()
+            .filter(|f| f.enabled)
+            .map(|f| &f.name[..])
+            .collect();
+        assert_eq!(enabled, vec!["default", "std", "rand"]);
+        assert!(features[3].optional_dependency);
+    }
+
+    #[test]
+    fn test_encode_crate_info() {
+        use rustc_serialize::json::{encode, Json};
+        use std::path::Path;
+        let info = info_from_path(Path::new(env!("CARGO_MANIFEST_DIR")), "cratesfyi").unwrap();
+        let json = Json::from_str(&encode(&info).unwrap()).unwrap();
+        assert_eq!(json.find("name").and_then(|n| n.as_string()), Some("cratesfyi"));
+        assert!(json.find_path(&["build_metadata", "all_features"]).is_some());
+    }
+
     #[test]
     fn test_download_extract_remove_crate() {
         let crte = Crate::new("rand".to_string(),
                               vec!["0.3.13".to_string()]);
-        assert!(crte.download_crate(0).is_ok());
-        assert!(crte.extract_crate(0).is_ok());
+        let dir = env::temp_dir();
+        assert!(crte.download_crate(0, &dir).is_ok());
+        assert!(crte.extract_crate(0, &dir, &ExtractLimits::default()).is_ok());
 
-        let path = PathBuf::from(crte.canonical_name(0));
+        let path = dir.join(crte.canonical_name(0));
         assert!(path.exists());
 
-        assert!(crte.remove_crate_file(0).is_ok());
-        assert!(crte.remove_build_dir_for_crate(0).is_ok());
+        assert!(crte.remove_crate_file(0, &dir).is_ok());
+        assert!(crte.remove_build_dir_for_crate(0, &dir).is_ok());
     }
 
 
     #[test]
-    fn test_path_to_manifest() {
+    fn test_read_manifest() {
         let _ = env_logger::init();
         let crte = Crate::new("calculator".to_string(), vec!["0.0.1".to_string()]);
 
-        assert!(crte.download_crate(0).is_ok());
-        assert!(crte.extract_crate(0).is_ok());
+        let dir = env::temp_dir();
+        assert!(crte.download_crate(0, &dir).is_ok());
+        assert!(crte.extract_crate(0, &dir, &ExtractLimits::default()).is_ok());
 
-        let cwd = env::current_dir().unwrap();
-        let mut package_root = PathBuf::from(&cwd);
-        package_root.push(crte.canonical_name(0));
-
-        let res = path_to_manifest(package_root.as_path());
+        let res = crte.manifest(0, &dir);
 
         info!("MANIFEST:\n{:#?}", res);
         assert!(res.is_ok());
 
         // remove downloaded stuff
-        assert!(crte.remove_crate_file(0).is_ok());
-        assert!(crte.remove_build_dir_for_crate(0).is_ok());
+        assert!(crte.remove_crate_file(0, &dir).is_ok());
+        assert!(crte.remove_build_dir_for_crate(0, &dir).is_ok());
     }
 
 
@@ -968,9 +1752,10 @@ mod test {
         let _ = env_logger::init();
         let crte = Crate::new("rand".to_string(), vec!["0.3.9".to_string()]);
 
-        crte.download_crate(0).unwrap();
-        crte.extract_crate(0).unwrap();
-        let info = crte.info(0);
+        let dir = env::temp_dir();
+        crte.download_crate(0, &dir).unwrap();
+        crte.extract_crate(0, &dir, &ExtractLimits::default()).unwrap();
+        let info = crte.info(0, &dir);
 
         info!("CRATE INFO: {:#?}", info);
 
@@ -984,9 +1769,13 @@ mod test {
         assert!(info.readme.is_some());
         assert!(!info.readme.unwrap().is_empty());
 
+        assert_eq!(info.target_name, "rand");
+        assert!(info.targets.contains(&("rand".to_string(), "lib".to_string())));
+        assert!(info.dependencies.iter().any(|d| d.name == "libc" && d.kind == "normal"));
+
         // remove downloaded stuff
-        assert!(crte.remove_crate_file(0).is_ok());
-        assert!(crte.remove_build_dir_for_crate(0).is_ok());
+        assert!(crte.remove_crate_file(0, &dir).is_ok());
+        assert!(crte.remove_build_dir_for_crate(0, &dir).is_ok());
     }
 
 
@@ -0,0 +1,308 @@
+//! Native crate downloader
+
+use std::io;
+use std::io::prelude::*;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+use std::thread;
+use std::cmp;
+
+use hyper::client::{Client, RedirectPolicy};
+use hyper::header::{Headers, ContentLength};
+use hyper::status::StatusCode;
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+
+use super::DocBuilderError;
+
+
+/// Default timeout used for download requests
+pub const DEFAULT_DOWNLOAD_TIMEOUT: u64 = 60;
+
+/// Default number of retries of a failed download
+pub const DEFAULT_DOWNLOAD_RETRIES: u32 = 3;
+
+/// Default delay before first retry of a failed download in seconds
+pub const DEFAULT_DOWNLOAD_RETRY_DELAY: u64 = 2;
+
+/// This line is written into build log if crate couldn't be downloaded
+pub const DOWNLOAD_FAILED_LOG_MARKER: &'static str = "cratesfyi: failed to download crate";
+
+/// Prefix of line written into build log with URL of downloaded crate
+pub const DOWNLOADED_FROM_LOG_PREFIX: &'static str = "cratesfyi: downloaded crate from ";
+
+/// Default URL templates of crate downloads, mirrors are tried in order.
+/// `{crate}` and `{version}` are replaced with name and version of crate.
+/// DocBuilder is only using them if config.json of index can't be read.
+///
+/// By default crates.io is using:
+/// https://crates.io/api/v1/crates/$crate/$version/download
+/// But I believe this url is increasing download count and this program is
+/// downloading alot during development. Redirected urls are used instead.
+pub const DEFAULT_DOWNLOAD_URLS: &'static [&'static str] =
+    &["https://crates-io.s3-us-west-1.amazonaws.com/crates/{crate}/{crate}-{version}.crate",
+      "https://static.crates.io/crates/{crate}/{crate}-{version}.crate"];
+
+
+/// Retries of failed downloads
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    /// Number of retries after first attempt
+    pub retries: u32,
+    /// Delay before first retry, delay doubles after every retry
+    pub delay: Duration,
+}
+
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            retries: DEFAULT_DOWNLOAD_RETRIES,
+            delay: Duration::from_secs(DEFAULT_DOWNLOAD_RETRY_DELAY),
+        }
+    }
+}
+
+
+impl RetryPolicy {
+    /// Returns delay before a retry, first retry is 0
+    pub fn delay(&self, retry: u32) -> Duration {
+        self.delay * 2u32.pow(cmp::min(retry, 16))
+    }
+}
+
+
+/// Options of crate downloads
+#[derive(Debug, Clone)]
+pub struct DownloadOptions {
+    /// Timeout of requests, None disables timeout
+    pub timeout: Option<Duration>,
+    pub retry_policy: RetryPolicy,
+    /// URL templates of mirrors, next mirror is tried if a download fails
+    pub urls: Vec<String>,
+}
+
+
+impl Default for DownloadOptions {
+    fn default() -> DownloadOptions {
+        DownloadOptions {
+            timeout: Some(Duration::from_secs(DEFAULT_DOWNLOAD_TIMEOUT)),
+            retry_policy: RetryPolicy::default(),
+            urls: default_download_urls(),
+        }
+    }
+}
+
+
+/// Downloads url into destination file.
+///
+/// Redirects are followed and response body is streamed into file. A failed
+/// download is retried according to retry policy and partially downloaded
+/// file is resumed with a range request. Partially downloaded file is
+/// removed if all attempts fail.
+pub fn download_file(url: &str,
+                     destination: &Path,
+                     timeout: Option<Duration>,
+                     retry_policy: &RetryPolicy) -> Result<(), DocBuilderError> {
+    debug!("Downloading {} into {}", url, destination.display());
+
+    // file left from an old download can't be resumed
+    let _ = fs::remove_file(destination);
+
+    let mut retry = 0;
+    loop {
+        match download_range(url, destination, timeout) {
+            Ok(()) => return Ok(()),
+            Err(e) => {
+                if retry >= retry_policy.retries || !is_retryable(&e) {
+                    let _ = fs::remove_file(destination);
+                    return Err(e);
+                }
+                let delay = retry_policy.delay(retry);
+                warn!("Failed to download {}, retrying in {} seconds: {}",
+                      url, delay.as_secs(), e);
+                thread::sleep(delay);
+                retry += 1;
+            }
+        }
+    }
+}
+
+
+/// Downloads rest of url into destination, file is resumed if it's
+/// partially downloaded
+fn download_range(url: &str,
+                  destination: &Path,
+                  timeout: Option<Duration>) -> Result<(), DocBuilderError> {
+    let downloaded = fs::metadata(destination).map(|m| m.len()).unwrap_or(0);
+
+    let mut client = Client::new();
+    client.set_redirect_policy(RedirectPolicy::FollowAll);
+    client.set_read_timeout(timeout);
+    client.set_write_timeout(timeout);
+
+    let mut headers = Headers::new();
+    if downloaded > 0 {
+        debug!("Resuming download of {} from byte {}", url, downloaded);
+        headers.set_raw("Range", vec![format!("bytes={}-", downloaded).into_bytes()]);
+    }
+
+    let mut res = try!(client.get(url)
+                       .headers(headers)
+                       .send()
+                       .map_err(DocBuilderError::DownloadCrateError));
+
+    // server can ignore range and send whole file
+    let append = match res.status {
+        StatusCode::Ok => false,
+        StatusCode::PartialContent if downloaded > 0 => true,
+        // file is already complete
+        StatusCode::RangeNotSatisfiable if downloaded > 0 => return Ok(()),
+        status => return Err(DocBuilderError::DownloadCrateStatusError(status)),
+    };
+
+    let mut file = try!(fs::OpenOptions::new()
+                        .create(true)
+                        .write(true)
+                        .append(append)
+                        .truncate(!append)
+                        .open(destination)
+                        .map_err(DocBuilderError::DownloadCrateIoError));
+
+    let size = try!(io::copy(&mut res, &mut file).map_err(DocBuilderError::DownloadCrateIoError));
+
+    // connection can be closed before whole body is received
+    if let Some(&ContentLength(expected)) = res.headers.get::<ContentLength>() {
+        if size < expected {
+            return Err(DocBuilderError::DownloadCrateIoError(
+                io::Error::new(io::ErrorKind::UnexpectedEof,
+                               format!("received {} of {} bytes", size, expected))));
+        }
+    }
+
+    Ok(())
+}
+
+
+/// Returns default download URL templates
+pub fn default_download_urls() -> Vec<String> {
+    DEFAULT_DOWNLOAD_URLS.iter().map(|u| u.to_string()).collect()
+}
+
+
+/// Returns URL template of dl in config.json of a registry index. dl can
+/// be a template with `{crate}` and `{version}` markers, otherwise
+/// `/{crate}/{version}/download` is appended to it like cargo does.
+pub fn dl_template(dl: &str) -> String {
+    if dl.contains("{crate}") || dl.contains("{version}") {
+        dl.to_string()
+    } else {
+        format!("{}/{{crate}}/{{version}}/download", dl.trim_right_matches('/'))
+    }
+}
+
+
+/// Returns download URL of a crate from a URL template
+pub fn download_url(template: &str, name: &str, version: &str) -> String {
+    template.replace("{crate}", name).replace("{version}", version)
+}
+
+
+/// Returns URL of downloaded crate from a build log
+pub fn downloaded_from(output: &str) -> Option<&str> {
+    // log lines of chroot builds are starting with log level
+    output.lines()
+        .filter_map(|line| {
+            line.find(DOWNLOADED_FROM_LOG_PREFIX)
+                .map(|pos| line[pos + DOWNLOADED_FROM_LOG_PREFIX.len()..].trim())
+        })
+        .next()
+}
+
+
+/// Returns true if a download error is temporary. Network errors and
+/// server errors are retried, other responses are not going to change.
+fn is_retryable(err: &DocBuilderError) -> bool {
+    match *err {
+        DocBuilderError::DownloadCrateError(_) |
+        DocBuilderError::DownloadCrateIoError(_) => true,
+        DocBuilderError::DownloadCrateStatusError(ref status) => status.is_server_error(),
+        _ => false,
+    }
+}
+
+
+/// Returns true if error is a download failure of a crate
+pub fn is_download_error(err: &DocBuilderError) -> bool {
+    match *err {
+        DocBuilderError::DownloadCrateError(_) |
+        DocBuilderError::DownloadCrateStatusError(_) |
+        DocBuilderError::DownloadCrateIoError(_) |
+        DocBuilderError::ChecksumMismatch(_, _) |
+        DocBuilderError::CrateDownloadFailed => true,
+        _ => false,
+    }
+}
+
+
+/// Returns hex encoded SHA-256 checksum of a file
+pub fn sha256_file(path: &Path) -> io::Result<String> {
+    let mut file = try!(fs::File::open(path));
+    let mut hasher = Sha256::new();
+    let mut buf = [0; 8192];
+
+    loop {
+        let n = try!(file.read(&mut buf));
+        if n == 0 {
+            break;
+        }
+        hasher.input(&buf[..n]);
+    }
+
+    Ok(hasher.result_str())
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+    use hyper::status::StatusCode;
+    use docbuilder::DocBuilderError;
+
+    #[test]
+    fn test_retry_delay() {
+        let policy = RetryPolicy {
+            retries: 3,
+            delay: Duration::from_secs(2),
+        };
+        assert_eq!(policy.delay(0), Duration::from_secs(2));
+        assert_eq!(policy.delay(2), Duration::from_secs(8));
+    }
+
+    #[test]
+    fn test_download_url() {
+        assert_eq!(download_url(DEFAULT_DOWNLOAD_URLS[1], "rand", "0.3.14"),
+                   "https://static.crates.io/crates/rand/rand-0.3.14.crate");
+        assert_eq!(download_url(&dl_template("https://crates.io/api/v1/crates"),
+                                "rand",
+                                "0.3.14"),
+                   "https://crates.io/api/v1/crates/rand/0.3.14/download");
+        assert_eq!(downloaded_from("INFO:cratesfyi: cratesfyi: downloaded crate from \
+                                    https://mirror/rand.crate\nDocumenting rand"),
+                   Some("https://mirror/rand.crate"));
+        assert!(downloaded_from("Documenting rand").is_none());
+    }
+
+    #[test]
+    fn test_is_retryable() {
+        assert!(is_retryable(&DocBuilderError::DownloadCrateStatusError(
+            StatusCode::ServiceUnavailable)));
+        assert!(!is_retryable(&DocBuilderError::DownloadCrateStatusError(StatusCode::NotFound)));
+        assert!(is_download_error(&DocBuilderError::DownloadCrateStatusError(
+            StatusCode::NotFound)));
+        assert!(!is_download_error(&DocBuilderError::FailedToBuildCrate));
+    }
+}
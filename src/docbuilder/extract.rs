@@ -0,0 +1,388 @@
+//! Native .crate archive extraction
+//!
+//! Crate files are untrusted input. Every entry path must stay inside
+//! destination directory, device nodes and fifos are skipped. Symlinks are
+//! created after every other entry with a normalized relative target and
+//! they are removed if they are pointing outside of destination directory.
+//!
+//! Size of extracted files and number of entries are limited with
+//! `ExtractLimits`, a decompression bomb can't fill the disk.
+
+use std::io;
+use std::fs;
+use std::os::unix::fs::symlink;
+use std::path::{Component, Path, PathBuf};
+
+use tar::Archive;
+use flate2::read::GzDecoder;
+
+use super::DocBuilderError;
+
+
+/// Default maximum size of all extracted files in bytes
+pub const DEFAULT_MAX_EXTRACTED_SIZE: u64 = 512 * 1024 * 1024;
+
+/// Default maximum number of entries in a crate archive
+pub const DEFAULT_MAX_EXTRACTED_FILES: u64 = 50_000;
+
+/// Default maximum size of a single extracted file in bytes
+pub const DEFAULT_MAX_EXTRACTED_FILE_SIZE: u64 = 256 * 1024 * 1024;
+
+/// Prefix of line written into build log if crate exceeded ExtractLimits
+pub const CRATE_TOO_LARGE_LOG_PREFIX: &'static str = "cratesfyi: crate is too large: ";
+
+
+#[derive(Debug, Clone)]
+pub struct ExtractLimits {
+    /// Maximum size of all extracted files in bytes
+    pub max_size: u64,
+    /// Maximum number of entries, directories are included
+    pub max_files: u64,
+    /// Maximum size of a single file in bytes
+    pub max_file_size: u64,
+}
+
+
+impl Default for ExtractLimits {
+    fn default() -> ExtractLimits {
+        ExtractLimits {
+            max_size: DEFAULT_MAX_EXTRACTED_SIZE,
+            max_files: DEFAULT_MAX_EXTRACTED_FILES,
+            max_file_size: DEFAULT_MAX_EXTRACTED_FILE_SIZE,
+        }
+    }
+}
+
+
+/// Counts extracted entries against ExtractLimits
+struct ExtractCounter<'a> {
+    limits: &'a ExtractLimits,
+    files: u64,
+    size: u64,
+}
+
+
+impl<'a> ExtractCounter<'a> {
+    fn new(limits: &'a ExtractLimits) -> ExtractCounter<'a> {
+        ExtractCounter {
+            limits: limits,
+            files: 0,
+            size: 0,
+        }
+    }
+
+
+    /// Adds an entry, returns reason if a limit is exceeded
+    fn add(&mut self, path: &Path, size: u64) -> Result<(), String> {
+        self.files += 1;
+        if self.files > self.limits.max_files {
+            return Err(format!("archive has more than {} files", self.limits.max_files));
+        }
+        self.add_size(path, size)
+    }
+
+
+    /// Adds size of a file which isn't read from archive, i.e: a copy of
+    /// hard link target
+    fn add_size(&mut self, path: &Path, size: u64) -> Result<(), String> {
+        if size > self.limits.max_file_size {
+            return Err(format!("{} is larger than {} bytes",
+                               path.display(),
+                               self.limits.max_file_size));
+        }
+        self.size += size;
+        if self.size > self.limits.max_size {
+            return Err(format!("extracted files are larger than {} bytes",
+                               self.limits.max_size));
+        }
+        Ok(())
+    }
+}
+
+
+/// Extracts a gzipped .crate archive into destination directory.
+///
+/// Returns path of extracted crate root which is the first path component
+/// of archive entries, i.e: `destination/rand-0.3.13`.
+pub fn extract_crate_file(crate_file: &Path,
+                          destination: &Path,
+                          limits: &ExtractLimits)
+                          -> Result<PathBuf, DocBuilderError> {
+    debug!("Extracting {} into {}", crate_file.display(), destination.display());
+
+    let file = try!(fs::File::open(crate_file).map_err(DocBuilderError::ExtractCrateError));
+    let decoder = try!(GzDecoder::new(file).map_err(DocBuilderError::ExtractCrateError));
+    let mut archive = Archive::new(decoder);
+
+    let mut root: Option<PathBuf> = None;
+    let mut counter = ExtractCounter::new(limits);
+    // (path of link, target of link)
+    let mut symlinks: Vec<(PathBuf, PathBuf)> = Vec::new();
+
+    for entry in try!(archive.entries().map_err(DocBuilderError::ExtractCrateError)) {
+        let mut entry = try!(entry.map_err(DocBuilderError::ExtractCrateError));
+        let raw_path = try!(entry.path().map_err(DocBuilderError::ExtractCrateError))
+            .into_owned();
+        let entry_path = try!(safe_path(&raw_path)
+            .ok_or(DocBuilderError::ExtractCrateUnsafePath(raw_path.clone())));
+
+        if root.is_none() {
+            root = entry_path.components().next()
+                .map(|c| destination.join(c.as_os_str()));
+        }
+
+        // size in header is exactly what is going to be read from archive
+        let size = try!(entry.header().size().map_err(DocBuilderError::ExtractCrateError));
+        try!(counter.add(&entry_path, size).map_err(DocBuilderError::CrateTooLarge));
+
+        let entry_type = entry.header().entry_type();
+        if entry_type.is_character_special() || entry_type.is_block_special() ||
+           entry_type.is_fifo() {
+            warn!("Skipping special file in crate archive: {}", entry_path.display());
+            continue;
+        }
+
+        let full_path = destination.join(&entry_path);
+        if let Some(parent) = full_path.parent() {
+            try!(fs::create_dir_all(parent).map_err(DocBuilderError::ExtractCrateError));
+        }
+
+        if entry_type.is_symlink() || entry_type.is_hard_link() {
+            let link_name = match try!(entry.link_name()
+                                           .map_err(DocBuilderError::ExtractCrateError)) {
+                Some(link_name) => link_name.into_owned(),
+                None => continue,
+            };
+
+            if entry_type.is_hard_link() {
+                // hard links are relative to archive root
+                let source = try!(safe_path(&link_name)
+                    .ok_or(DocBuilderError::ExtractCrateUnsafePath(link_name.clone())));
+                // size of hard link entry is 0, copied file is counted
+                let source_size = try!(fs::metadata(destination.join(&source))
+                                       .map_err(DocBuilderError::ExtractCrateError))
+                    .len();
+                try!(counter.add_size(&entry_path, source_size)
+                     .map_err(DocBuilderError::CrateTooLarge));
+                try!(fs::copy(destination.join(source), &full_path)
+                     .map_err(DocBuilderError::ExtractCrateError));
+            } else {
+                match symlink_target(&entry_path, &link_name) {
+                    Some(target) => symlinks.push((full_path, target)),
+                    None => {
+                        warn!("Skipping symlink escaping crate: {} -> {}",
+                              entry_path.display(),
+                              link_name.display());
+                    }
+                }
+            }
+            continue;
+        }
+
+        try!(entry.unpack(&full_path).map_err(DocBuilderError::ExtractCrateError));
+    }
+
+    // symlinks are created last, no other entry can be written through them
+    for &(ref path, ref target) in &symlinks {
+        try!(symlink(target, path).map_err(DocBuilderError::ExtractCrateError));
+    }
+    try!(remove_escaping_symlinks(destination, &symlinks)
+         .map_err(DocBuilderError::ExtractCrateError));
+
+    root.ok_or(DocBuilderError::ExtractCrateEmptyArchive)
+}
+
+
+/// Returns reason of CrateTooLarge error from build output
+pub fn crate_too_large_reason(output: &str) -> Option<&str> {
+    output.lines()
+        .filter_map(|line| {
+            line.find(CRATE_TOO_LARGE_LOG_PREFIX)
+                .map(|pos| line[pos + CRATE_TOO_LARGE_LOG_PREFIX.len()..].trim())
+        })
+        .next()
+}
+
+
+/// Returns normalized relative path of an archive entry, None if it's an
+/// absolute path or if it's leaving archive root
+fn safe_path(path: &Path) -> Option<PathBuf> {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(c) => normalized.push(c),
+            Component::CurDir => {}
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+    if normalized.as_os_str().is_empty() {
+        None
+    } else {
+        Some(normalized)
+    }
+}
+
+
+/// Returns normalized relative target of a symlink at entry_path, None if
+/// target is absolute or it's leaving archive root
+fn symlink_target(entry_path: &Path, link_name: &Path) -> Option<PathBuf> {
+    let parent: Vec<_> = entry_path.parent()
+        .map(|p| p.components().collect())
+        .unwrap_or(Vec::new());
+
+    // resolve target relative to archive root
+    let mut resolved = parent.clone();
+    for component in link_name.components() {
+        match component {
+            Component::Normal(_) => resolved.push(component),
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if resolved.pop().is_none() {
+                    return None;
+                }
+            }
+            Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+
+    // make it relative to directory of link again
+    let common = parent.iter().zip(resolved.iter()).take_while(|&(a, b)| a == b).count();
+    let mut target = PathBuf::new();
+    for _ in common..parent.len() {
+        target.push("..");
+    }
+    for component in &resolved[common..] {
+        target.push(component.as_os_str());
+    }
+    if target.as_os_str().is_empty() {
+        target.push(".");
+    }
+    Some(target)
+}
+
+
+/// Removes symlinks which are dangling or resolving outside of destination.
+/// A symlink can still point outside through another symlink, links are
+/// checked until none of them is removed.
+fn remove_escaping_symlinks(destination: &Path,
+                            symlinks: &[(PathBuf, PathBuf)]) -> io::Result<()> {
+    let destination = try!(destination.canonicalize());
+    let mut remaining: Vec<&PathBuf> = symlinks.iter().map(|&(ref path, _)| path).collect();
+    loop {
+        let (escaping, safe): (Vec<&PathBuf>, Vec<&PathBuf>) = remaining.into_iter()
+            .partition(|path| {
+                path.canonicalize()
+                    .map(|resolved| !resolved.starts_with(&destination))
+                    .unwrap_or(true)
+            });
+        if escaping.is_empty() {
+            return Ok(());
+        }
+        for path in escaping {
+            warn!("Removing symlink resolving outside of crate: {}", path.display());
+            try!(fs::remove_file(path));
+        }
+        remaining = safe;
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::{safe_path, symlink_target, extract_crate_file, ExtractCounter, ExtractLimits};
+    use std::env;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use tar::{Builder, EntryType, Header};
+    use docbuilder::DocBuilderError;
+
+    #[test]
+    fn test_safe_path() {
+        assert_eq!(safe_path(Path::new("rand-0.3.14/./src/lib.rs")),
+                   Some(PathBuf::from("rand-0.3.14/src/lib.rs")));
+        assert!(safe_path(Path::new("rand-0.3.14/../../etc/passwd")).is_none());
+        assert!(safe_path(Path::new("/etc/passwd")).is_none());
+        assert!(safe_path(Path::new(".")).is_none());
+    }
+
+
+    #[test]
+    fn test_symlink_target() {
+        let link = Path::new("rand-0.3.14/src/README.md");
+        assert_eq!(symlink_target(link, Path::new("../README.md")),
+                   Some(PathBuf::from("../README.md")));
+        assert_eq!(symlink_target(link, Path::new("./../src/./lib.rs")),
+                   Some(PathBuf::from("lib.rs")));
+        assert_eq!(symlink_target(link, Path::new("..")), Some(PathBuf::from("..")));
+        assert_eq!(symlink_target(link, Path::new("../../rand-0.3.14/src")),
+                   Some(PathBuf::from(".")));
+        assert!(symlink_target(link, Path::new("../../../etc/passwd")).is_none());
+        assert!(symlink_target(link, Path::new("/etc/passwd")).is_none());
+    }
+
+
+    #[test]
+    fn test_extract_counter() {
+        let limits = ExtractLimits {
+            max_size: 100,
+            max_files: 3,
+            max_file_size: 60,
+        };
+        let mut counter = ExtractCounter::new(&limits);
+        assert!(counter.add(Path::new("rand/src"), 0).is_ok());
+        assert!(counter.add(Path::new("rand/src/lib.rs"), 70).is_err());
+
+        let mut counter = ExtractCounter::new(&limits);
+        assert!(counter.add(Path::new("rand/src/lib.rs"), 60).is_ok());
+        assert!(counter.add(Path::new("rand/src/os.rs"), 50).is_err());
+
+        let mut counter = ExtractCounter::new(&limits);
+        for _ in 0..3 {
+            assert!(counter.add(Path::new("rand"), 0).is_ok());
+        }
+        assert!(counter.add(Path::new("rand"), 0).is_err());
+    }
+
+
+    #[test]
+    fn test_extract_hard_links() {
+        let crate_file = env::temp_dir().join("cratesfyi-hard-links.crate");
+        {
+            let file = fs::File::create(&crate_file).unwrap();
+            let mut builder = Builder::new(GzEncoder::new(file, Compression::Default));
+            let mut header = Header::new_gnu();
+            header.set_path("bomb-0.1.0/data").unwrap();
+            header.set_size(50);
+            header.set_cksum();
+            builder.append(&header, &[0u8; 50][..]).unwrap();
+            // hard links are copied, every copy is counted
+            for i in 0..3 {
+                let mut header = Header::new_gnu();
+                header.set_path(&format!("bomb-0.1.0/link{}", i)).unwrap();
+                header.set_entry_type(EntryType::Link);
+                header.set_link_name("bomb-0.1.0/data").unwrap();
+                header.set_size(0);
+                header.set_cksum();
+                builder.append(&header, &[][..]).unwrap();
+            }
+            builder.into_inner().unwrap().finish().unwrap();
+        }
+
+        let limits = ExtractLimits {
+            max_size: 100,
+            max_files: 10,
+            max_file_size: 60,
+        };
+        let destination = env::temp_dir().join("cratesfyi-hard-links");
+        let res = extract_crate_file(&crate_file, &destination, &limits);
+        let _ = fs::remove_dir_all(&destination);
+        let _ = fs::remove_file(&crate_file);
+        match res {
+            Err(DocBuilderError::CrateTooLarge(_)) => {}
+            _ => panic!("hard links are not counted: {:?}", res),
+        }
+    }
+}
@@ -0,0 +1,84 @@
+//! Documentation of git repositories
+//!
+//! `cratesfyi build git <URL> [--rev <REV>]` builds documentation of
+//! unpublished code. Repository is cloned into a work directory, crate in
+//! root of repository is built like a release and documentation is stored
+//! under `_git/<CRATE>-<COMMIT>` in documentation storage. crates.io crate
+//! names are starting with a letter, they can't collide with `_git`.
+//! Nothing is recorded into database.
+
+use std::io::prelude::*;
+use std::fs;
+use std::path::Path;
+
+use git2::{Repository, Oid, ResetType, Error};
+use toml::Parser;
+
+
+/// Storage prefix of documentation built from git repositories
+pub const GIT_DOC_PREFIX: &'static str = "_git";
+
+
+/// Clones repository at url into path and checks out rev, default branch
+/// is used if rev is None. Returns id of checked out commit.
+pub fn clone_repository(url: &str, rev: Option<&str>, path: &Path) -> Result<Oid, Error> {
+    let repo = try!(Repository::clone(url, path));
+    let object = try!(repo.revparse_single(&format!("{}^{{commit}}", rev.unwrap_or("HEAD"))));
+    try!(repo.reset(&object, ResetType::Hard, None));
+    Ok(object.id())
+}
+
+
+/// Returns name and version of package in Cargo.toml at root of
+/// repository, None if it's not a package, i.e: a virtual manifest
+pub fn package_name_version(root_dir: &Path) -> Option<(String, String)> {
+    let mut content = String::new();
+    if fs::File::open(root_dir.join("Cargo.toml"))
+           .and_then(|mut f| f.read_to_string(&mut content))
+           .is_err() {
+        return None;
+    }
+    parse_package_name_version(&content)
+}
+
+
+fn parse_package_name_version(manifest: &str) -> Option<(String, String)> {
+    let table = Parser::new(manifest).parse();
+    let package = table.as_ref()
+        .and_then(|t| t.get("package"))
+        .and_then(|p| p.as_table());
+    let name = package.and_then(|p| p.get("name")).and_then(|n| n.as_str());
+    let version = package.and_then(|p| p.get("version")).and_then(|v| v.as_str());
+    match (name, version) {
+        (Some(name), Some(version)) => Some((name.to_string(), version.to_string())),
+        _ => None,
+    }
+}
+
+
+/// Returns storage prefix of documentation of a commit, it has same depth
+/// as `<CRATE>/<VERSION>` of releases
+pub fn doc_prefix(name: &str, commit: &Oid) -> String {
+    format!("{}/{}-{}", GIT_DOC_PREFIX, name, commit)
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::parse_package_name_version;
+    use git2::Oid;
+
+    #[test]
+    fn test_package_name_version() {
+        assert_eq!(parse_package_name_version("[package]\nname = \"rand\"\n\
+                                               version = \"0.3.14\""),
+                   Some(("rand".to_string(), "0.3.14".to_string())));
+        assert_eq!(parse_package_name_version("[workspace]\nmembers = [\"rand\"]"), None);
+
+        let commit = Oid::from_str("0554abac6c2e2b9b0a5cf4ff4c5aa0b1c5c9a8d2").unwrap();
+        assert_eq!(doc_prefix("rand", &commit),
+                   "_git/rand-0554abac6c2e2b9b0a5cf4ff4c5aa0b1c5c9a8d2");
+    }
+}
@@ -0,0 +1,303 @@
+//! crates.io-index management
+//!
+//! This module is cloning crates.io-index repository if it doesn't exist
+//! and fast-forwarding it to origin/master before every run.
+
+use std::io;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::collections::HashSet;
+use std::str;
+
+use git2::{Repository, Oid, ResetType, DiffFormat, Error};
+use rustc_serialize::json::Json;
+
+
+/// URL of official crates.io-index repository
+pub const CRATES_IO_INDEX_URL: &'static str = "https://github.com/rust-lang/crates.io-index.git";
+
+
+/// Registry configuration in config.json at root of index
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexConfig {
+    /// Download URL of crates, see download::dl_template
+    pub dl: String,
+    /// Base URL of API
+    pub api: Option<String>,
+}
+
+
+/// A checkout of crates.io-index
+#[derive(Debug)]
+pub struct Index {
+    path: PathBuf,
+    url: String,
+}
+
+
+impl Index {
+    /// Returns a new Index for official crates.io-index repository
+    pub fn new(path: PathBuf) -> Index {
+        Index::from_url(path, CRATES_IO_INDEX_URL.to_string())
+    }
+
+
+    /// Returns a new Index using a different repository url
+    pub fn from_url(path: PathBuf, url: String) -> Index {
+        Index {
+            path: path,
+            url: url,
+        }
+    }
+
+
+    /// Returns path of checkout
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+
+    /// Returns full path of a crate file inside checkout, None if name is
+    /// not a valid crate name
+    pub fn crate_path(&self, name: &str) -> Option<PathBuf> {
+        crate_index_path(name).map(|path| self.path.join(path))
+    }
+
+
+    /// Clones index if it's not exists, otherwise fetches origin and
+    /// fast-forwards checkout. Returns the commit id of HEAD after update.
+    pub fn update(&self) -> Result<Oid, Error> {
+        let repo = try!(self.open_or_clone());
+
+        {
+            let mut remote = try!(repo.find_remote("origin"));
+            try!(remote.fetch(&["refs/heads/master:refs/remotes/origin/master"], None, None));
+        }
+
+        let oid = try!(repo.refname_to_id("refs/remotes/origin/master"));
+        let object = try!(repo.find_object(oid, None));
+        try!(repo.reset(&object, ResetType::Hard, None));
+
+        Ok(oid)
+    }
+
+
+    /// Returns the commit id of current HEAD
+    pub fn head(&self) -> Result<Oid, Error> {
+        let repo = try!(Repository::open(&self.path));
+        let head = try!(repo.head());
+        head.target().ok_or(Error::from_str("HEAD is not pointing to a commit"))
+    }
+
+
+    /// Returns commit time of current HEAD in seconds since epoch
+    pub fn head_time(&self) -> Result<i64, Error> {
+        let repo = try!(Repository::open(&self.path));
+        let oid = try!(self.head());
+        let commit = try!(repo.find_commit(oid));
+        Ok(commit.time().seconds())
+    }
+
+
+    /// Returns versions published between two commits as (name, version) pairs.
+    ///
+    /// Lines modified in index (i.e. yanked versions) are not returned.
+    pub fn new_versions(&self, from: Oid, to: Oid) -> Result<Vec<(String, String)>, Error> {
+        let repo = try!(Repository::open(&self.path));
+        let old_tree = try!(try!(repo.find_commit(from)).tree());
+        let new_tree = try!(try!(repo.find_commit(to)).tree());
+        let diff = try!(repo.diff_tree_to_tree(Some(&old_tree), Some(&new_tree), None));
+
+        let mut added = Vec::new();
+        let mut removed = HashSet::new();
+
+        try!(diff.print(DiffFormat::Patch, |_, _, line| {
+            if let Some(version) = parse_index_line(line.content()) {
+                match line.origin() {
+                    '+' => added.push(version),
+                    '-' => {
+                        removed.insert(version);
+                    }
+                    _ => {}
+                }
+            }
+            true
+        }));
+
+        Ok(added.into_iter().filter(|v| !removed.contains(v)).collect())
+    }
+
+
+    /// Returns yanked flags of every version of a crate as (version, yanked)
+    /// pairs from current checkout
+    pub fn yanked_versions(&self, name: &str) -> io::Result<Vec<(String, bool)>> {
+        let path = try!(self.crate_path(name).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "invalid crate name")
+        }));
+        let file = try!(fs::File::open(path));
+        let mut versions = Vec::new();
+
+        for line in BufReader::new(file).lines() {
+            let line = try!(line);
+            if let Some(version) = parse_yanked(&line) {
+                versions.push(version);
+            }
+        }
+
+        Ok(versions)
+    }
+
+
+    /// Reads config.json at root of checkout
+    pub fn config(&self) -> io::Result<IndexConfig> {
+        let mut content = String::new();
+        try!(try!(fs::File::open(self.path.join("config.json"))).read_to_string(&mut content));
+        parse_index_config(&content).ok_or(io::Error::new(io::ErrorKind::InvalidData,
+                                                          "config.json doesn't have dl"))
+    }
+
+
+    fn open_or_clone(&self) -> Result<Repository, Error> {
+        if self.path.exists() {
+            Repository::open(&self.path)
+        } else {
+            info!("Cloning {} into {}", self.url, self.path.display());
+            Repository::clone(&self.url, &self.path)
+        }
+    }
+}
+
+
+
+/// Returns relative path of a crate file in crates.io-index.
+///
+/// Index layout is deterministic: crates with 1 and 2 characters long names
+/// are placed into `1/` and `2/`, 3 characters long names into `3/{first char}/`
+/// and rest of the crates into `{first two chars}/{second two chars}/`.
+///
+/// Returns None if name is empty or contains anything other than ASCII
+/// alphanumerics, `-` and `_`.
+pub fn crate_index_path(name: &str) -> Option<PathBuf> {
+    let valid = |c: char| match c {
+        'a'...'z' | 'A'...'Z' | '0'...'9' | '-' | '_' => true,
+        _ => false,
+    };
+    if name.is_empty() || !name.chars().all(valid) {
+        return None;
+    }
+
+    let name = name.to_lowercase();
+    let mut path = PathBuf::new();
+
+    match name.len() {
+        1 => path.push("1"),
+        2 => path.push("2"),
+        3 => {
+            path.push("3");
+            path.push(&name[..1]);
+        }
+        _ => {
+            path.push(&name[0..2]);
+            path.push(&name[2..4]);
+        }
+    }
+
+    path.push(&name);
+    Some(path)
+}
+
+
+
+/// Parses name and version from a line of crates.io-index
+fn parse_index_line(line: &[u8]) -> Option<(String, String)> {
+    let json = match str::from_utf8(line).ok().and_then(|l| Json::from_str(l.trim()).ok()) {
+        Some(json) => json,
+        None => return None,
+    };
+
+    let name = json.find("name").and_then(|n| n.as_string());
+    let vers = json.find("vers").and_then(|v| v.as_string());
+
+    match (name, vers) {
+        (Some(name), Some(vers)) => Some((name.to_string(), vers.to_string())),
+        _ => None,
+    }
+}
+
+
+/// Parses config.json of index, returns None if it doesn't have dl
+fn parse_index_config(content: &str) -> Option<IndexConfig> {
+    let json = match Json::from_str(content) {
+        Ok(json) => json,
+        Err(_) => return None,
+    };
+
+    json.find("dl").and_then(|dl| dl.as_string()).map(|dl| {
+        IndexConfig {
+            dl: dl.to_string(),
+            api: json.find("api").and_then(|api| api.as_string()).map(|api| api.to_string()),
+        }
+    })
+}
+
+
+/// Parses version and yanked flag from a line of crates.io-index
+fn parse_yanked(line: &str) -> Option<(String, bool)> {
+    let json = match Json::from_str(line.trim()) {
+        Ok(json) => json,
+        Err(_) => return None,
+    };
+
+    json.find("vers").and_then(|v| v.as_string()).map(|vers| {
+        let yanked = json.find("yanked").and_then(|y| y.as_boolean()).unwrap_or(false);
+        (vers.to_string(), yanked)
+    })
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_crate_index_path() {
+        assert_eq!(crate_index_path("a"), Some(PathBuf::from("1/a")));
+        assert_eq!(crate_index_path("cc"), Some(PathBuf::from("2/cc")));
+        assert_eq!(crate_index_path("url"), Some(PathBuf::from("3/u/url")));
+        assert_eq!(crate_index_path("rand"), Some(PathBuf::from("ra/nd/rand")));
+        assert_eq!(crate_index_path("Inflector"), Some(PathBuf::from("in/fl/inflector")));
+        assert_eq!(crate_index_path("rustc-serialize"),
+                   Some(PathBuf::from("ru/st/rustc-serialize")));
+        assert_eq!(crate_index_path(""), None);
+        assert_eq!(crate_index_path("ö"), None);
+        assert_eq!(crate_index_path("añb"), None);
+        assert_eq!(crate_index_path("../rand"), None);
+    }
+
+
+    #[test]
+    fn test_parse_yanked() {
+        assert_eq!(super::parse_yanked(r#"{"name":"rand","vers":"0.1.0","yanked":true}"#),
+                   Some(("0.1.0".to_string(), true)));
+        assert_eq!(super::parse_yanked(r#"{"name":"rand","vers":"0.1.1","yanked":false}"#),
+                   Some(("0.1.1".to_string(), false)));
+        assert_eq!(super::parse_yanked("not json"), None);
+    }
+
+
+    #[test]
+    fn test_parse_index_config() {
+        assert_eq!(super::parse_index_config(r#"{"dl": "https://crates.io/api/v1/crates",
+                                                 "api": "https://crates.io"}"#),
+                   Some(IndexConfig {
+                       dl: "https://crates.io/api/v1/crates".to_string(),
+                       api: Some("https://crates.io".to_string()),
+                   }));
+        assert_eq!(super::parse_index_config(r#"{"api": "https://crates.io"}"#), None);
+        assert_eq!(super::parse_index_config("not json"), None);
+    }
+}
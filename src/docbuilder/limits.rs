@@ -0,0 +1,204 @@
+//! Resource limits for build commands
+//!
+//! Commands are started in their own process group with `setsid`, memory is
+//! limited with `ulimit -v` and whole process group is killed if command
+//! doesn't finish in time. Output of the command is truncated after
+//! `max_output` bytes.
+//!
+//! Builds can also be restricted to pre-fetched dependencies (`frozen`) and
+//! network can be disabled during builds (`deny_network`). Dependencies are
+//! fetched with `cargo fetch` before build in both cases. Network can only
+//! be disabled in docker sandbox.
+
+use std::io::prelude::*;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::Duration;
+
+use libc;
+use wait_timeout::ChildExt;
+
+
+/// Default wall-clock timeout of a build in seconds
+pub const DEFAULT_BUILD_TIMEOUT: u64 = 15 * 60;
+
+/// Default maximum output size of a build in bytes
+pub const DEFAULT_MAX_OUTPUT: usize = 5 * 1024 * 1024;
+
+/// Exit code used by build-doc subcommand when build is timed out
+pub const BUILD_TIMED_OUT_EXIT_CODE: i32 = 2;
+
+/// This line is written into build log if build is timed out
+pub const BUILD_TIMED_OUT_LOG_MARKER: &'static str = "cratesfyi: build timed out";
+
+/// This line is written into build log if a build script failed while
+/// builds are restricted
+pub const BUILD_SCRIPT_BLOCKED_LOG_MARKER: &'static str =
+    "cratesfyi: a build script failed, network access and fetching dependencies are \
+     disabled during builds";
+
+/// This line is written into build log if build with all features failed
+/// and crate is built again with default features
+pub const ALL_FEATURES_FAILED_LOG_MARKER: &'static str =
+    "cratesfyi: build with all features failed, building with default features";
+
+
+#[derive(Debug, Clone)]
+pub struct BuildLimits {
+    /// Wall-clock timeout of command
+    pub timeout: Duration,
+    /// Virtual memory limit in kilobytes
+    pub memory: Option<u64>,
+    /// Maximum number of bytes captured from stdout and stderr each
+    pub max_output: usize,
+    /// Builds are using pre-fetched dependencies with `--frozen`
+    pub frozen: bool,
+    /// Disables network access of builds in sandbox
+    pub deny_network: bool,
+}
+
+
+/// Failure of a command running with BuildLimits
+#[derive(Debug)]
+pub enum CommandFailure {
+    /// Command exited with an unsuccessful status
+    Failed(String),
+    /// Command killed because it's exceeded timeout
+    TimedOut(String),
+}
+
+
+impl CommandFailure {
+    /// Returns captured output of failed command
+    pub fn message(&self) -> &str {
+        match *self {
+            CommandFailure::Failed(ref m) | CommandFailure::TimedOut(ref m) => m,
+        }
+    }
+
+    /// Returns failure with text inserted before captured output
+    pub fn with_prefix(self, prefix: &str) -> CommandFailure {
+        match self {
+            CommandFailure::Failed(m) => CommandFailure::Failed(format!("{}{}", prefix, m)),
+            CommandFailure::TimedOut(m) => CommandFailure::TimedOut(format!("{}{}", prefix, m)),
+        }
+    }
+}
+
+
+impl Default for BuildLimits {
+    fn default() -> BuildLimits {
+        BuildLimits {
+            timeout: Duration::from_secs(DEFAULT_BUILD_TIMEOUT),
+            memory: None,
+            max_output: DEFAULT_MAX_OUTPUT,
+            frozen: false,
+            deny_network: false,
+        }
+    }
+}
+
+
+impl BuildLimits {
+    /// Returns true if dependencies must be fetched before build
+    pub fn is_restricted(&self) -> bool {
+        self.frozen || self.deny_network
+    }
+
+
+    /// Runs program with args and environment variables in dir and enforces
+    /// limits
+    pub fn run(&self,
+               program: &str,
+               args: &[String],
+               env: &[(String, String)],
+               dir: &Path) -> Result<String, CommandFailure> {
+        let mut command = Command::new("setsid");
+        match self.memory {
+            Some(memory) => {
+                command.arg("sh").arg("-c")
+                    .arg(format!("ulimit -v {} && exec \"$0\" \"$@\"", memory))
+                    .arg(program);
+            }
+            None => {
+                command.arg(program);
+            }
+        }
+        command.args(args).current_dir(dir);
+        for &(ref key, ref value) in env {
+            command.env(key, value);
+        }
+        self.run_command(command)
+    }
+
+
+    /// Runs a prepared command and enforces timeout and output limits
+    pub fn run_command(&self, mut command: Command) -> Result<String, CommandFailure> {
+        let mut child = try!(command.stdout(Stdio::piped())
+                             .stderr(Stdio::piped())
+                             .spawn()
+                             .map_err(|e| CommandFailure::Failed(format!("{}", e))));
+
+        let stdout = read_limited(child.stdout.take().unwrap(), self.max_output);
+        let stderr = read_limited(child.stderr.take().unwrap(), self.max_output);
+
+        let status = match child.wait_timeout(self.timeout) {
+            Ok(status) => status,
+            Err(e) => return Err(CommandFailure::Failed(format!("{}", e))),
+        };
+
+        let timed_out = status.is_none();
+        if timed_out {
+            // setsid makes child leader of a new process group
+            unsafe {
+                libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+            }
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+
+        let mut output = stdout.join().unwrap_or(String::new());
+        output.push_str(&stderr.join().unwrap_or(String::new()));
+
+        if timed_out {
+            output.push_str(&format!("\nBuild killed after {} seconds",
+                                     self.timeout.as_secs()));
+            Err(CommandFailure::TimedOut(output))
+        } else if status.map(|s| s.success()).unwrap_or(false) {
+            Ok(output)
+        } else {
+            Err(CommandFailure::Failed(output))
+        }
+    }
+}
+
+
+/// Reads stream in a thread and keeps only first max bytes of it
+fn read_limited<R: Read + Send + 'static>(mut reader: R,
+                                          max: usize) -> thread::JoinHandle<String> {
+    thread::spawn(move || {
+        let mut output = Vec::new();
+        let mut buf = [0; 8192];
+        let mut truncated = false;
+
+        loop {
+            let n = match reader.read(&mut buf) {
+                Ok(0) | Err(_) => break,
+                Ok(n) => n,
+            };
+            // keep reading after limit to not to block child
+            let remaining = max - output.len();
+            if n > remaining {
+                truncated = true;
+            }
+            output.extend_from_slice(&buf[..::std::cmp::min(n, remaining)]);
+        }
+
+        let mut output = String::from_utf8_lossy(&output).into_owned();
+        if truncated {
+            output.push_str("\n[output truncated]\n");
+        }
+        output
+    })
+}
@@ -0,0 +1,62 @@
+//! Builds of local crates
+//!
+//! `cratesfyi build local <PATH>` lets crate authors check if documentation
+//! of their crate builds before publishing it. Crate is copied into a work
+//! directory and built with same sandbox, flags and
+//! `[package.metadata.docs.rs]` handling as releases. Build log is printed
+//! and nothing is stored, database isn't used either, so build overrides and
+//! active toolchain of server are not applied.
+
+use std::io;
+use std::fs;
+use std::path::Path;
+
+
+/// Returns true if a file at root of crate is left out of the copy, build
+/// artifacts and VCS metadata are not needed to build documentation
+fn skipped(file_name: &str) -> bool {
+    file_name == "target" || file_name == ".git"
+}
+
+
+/// Copies crate in source into destination
+pub fn copy_crate(source: &Path, destination: &Path) -> io::Result<()> {
+    try!(fs::create_dir_all(destination));
+    for entry in try!(source.read_dir()) {
+        let entry = try!(entry);
+        if entry.file_name().to_str().map_or(false, skipped) {
+            continue;
+        }
+        try!(copy_entry(&entry.path(), &destination.join(entry.file_name())));
+    }
+    Ok(())
+}
+
+
+fn copy_entry(source: &Path, destination: &Path) -> io::Result<()> {
+    if try!(fs::metadata(source)).is_dir() {
+        try!(fs::create_dir_all(destination));
+        for entry in try!(source.read_dir()) {
+            let entry = try!(entry);
+            try!(copy_entry(&entry.path(), &destination.join(entry.file_name())));
+        }
+    } else {
+        try!(fs::copy(source, destination));
+    }
+    Ok(())
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::skipped;
+
+    #[test]
+    fn test_skipped() {
+        assert!(skipped("target"));
+        assert!(skipped(".git"));
+        assert!(!skipped("src"));
+        assert!(!skipped("Cargo.toml"));
+    }
+}
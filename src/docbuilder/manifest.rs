@@ -0,0 +1,329 @@
+//! Crate manifests
+//!
+//! Manifests are read with `cargo metadata` instead of using cargo as a
+//! library, cargo's internal API changes too often. `cargo metadata` doesn't
+//! report descriptive fields of packages (authors, description etc.) and
+//! these are read from `[package]` table of Cargo.toml.
+//!
+//! Cargo.toml of a crate can be a virtual manifest of a workspace, package
+//! with crate's name is used in this case.
+
+use std::io;
+use std::io::prelude::*;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+
+use rustc_serialize::json::{self, DecoderError};
+use toml::{Parser, Value};
+
+
+#[derive(Debug)]
+pub enum ManifestError {
+    IoError(io::Error),
+    CommandError(String),
+    DecoderError(DecoderError),
+    PackageNotFound,
+    NoTargets,
+}
+
+
+/// A crate manifest
+#[derive(Debug, Clone)]
+pub struct Manifest {
+    pub name: String,
+    pub version: String,
+    pub targets: Vec<Target>,
+    pub dependencies: Vec<Dependency>,
+    pub features: HashMap<String, Vec<String>>,
+    pub metadata: ManifestMetadata,
+    /// Path of Cargo.toml of package, it's in a member directory if crate
+    /// has a virtual manifest
+    pub manifest_path: PathBuf,
+}
+
+
+/// A target of a crate
+#[derive(Debug, Clone, RustcDecodable)]
+pub struct Target {
+    pub name: String,
+    /// Kinds of target: lib, rlib, bin, example, test, bench, custom-build
+    pub kind: Vec<String>,
+    pub src_path: PathBuf,
+}
+
+
+/// A dependency of a crate
+#[derive(Debug, Clone, RustcEncodable)]
+pub struct Dependency {
+    pub name: String,
+    /// Version requirement
+    pub req: String,
+    /// Kind of dependency: normal, dev or build
+    pub kind: String,
+    pub optional: bool,
+    /// Platform of a target specific dependency, i.e: `cfg(windows)`
+    pub target: Option<String>,
+}
+
+
+/// Descriptive fields of a package
+#[derive(Debug, Clone, Default, RustcEncodable)]
+pub struct ManifestMetadata {
+    pub authors: Vec<String>,
+    pub keywords: Vec<String>,
+    pub description: Option<String>,
+    pub license: Option<String>,
+    pub repository: Option<String>,
+    pub homepage: Option<String>,
+    pub readme: Option<String>,
+}
+
+
+#[derive(RustcDecodable)]
+struct CargoMetadata {
+    packages: Vec<CargoPackage>,
+}
+
+
+#[derive(RustcDecodable)]
+struct CargoPackage {
+    name: String,
+    version: String,
+    dependencies: Vec<CargoDependency>,
+    targets: Vec<Target>,
+    features: HashMap<String, Vec<String>>,
+    manifest_path: PathBuf,
+}
+
+
+#[derive(RustcDecodable)]
+struct CargoDependency {
+    name: String,
+    req: String,
+    kind: Option<String>,
+    optional: bool,
+    target: Option<String>,
+}
+
+
+impl Target {
+    pub fn is_lib(&self) -> bool {
+        self.kind.iter().any(|k| k == "lib" || k == "rlib" || k == "dylib" || k == "staticlib")
+    }
+}
+
+
+/// Reads manifest of package in root_dir. Package in root_dir is used if
+/// there is one, otherwise workspace member named package_name is used.
+pub fn read_manifest(root_dir: &Path, package_name: &str) -> Result<Manifest, ManifestError> {
+    let manifest_path = root_dir.join("Cargo.toml");
+
+    let output = try!(Command::new("cargo")
+                          .arg("metadata")
+                          .arg("--format-version")
+                          .arg("1")
+                          .arg("--no-deps")
+                          .arg("--manifest-path")
+                          .arg(&manifest_path)
+                          .output());
+    if !output.status.success() {
+        return Err(ManifestError::CommandError(String::from_utf8_lossy(&output.stderr)
+                                                   .into_owned()));
+    }
+
+    let mut manifest = try!(manifest_from_metadata(&String::from_utf8_lossy(&output.stdout),
+                                                   &manifest_path,
+                                                   package_name));
+
+    let mut content = String::new();
+    try!(fs::File::open(&manifest.manifest_path)
+         .and_then(|mut f| f.read_to_string(&mut content)));
+    manifest.metadata = package_metadata(&content);
+
+    Ok(manifest)
+}
+
+
+fn manifest_from_metadata(metadata_json: &str,
+                          manifest_path: &Path,
+                          package_name: &str)
+                          -> Result<Manifest, ManifestError> {
+    let cargo_metadata: CargoMetadata = try!(json::decode(metadata_json));
+
+    // metadata contains every member of workspace
+    let mut packages = cargo_metadata.packages;
+    let index = packages.iter()
+        .position(|p| p.manifest_path.as_path() == manifest_path)
+        .or(packages.iter().position(|p| p.name == package_name))
+        .unwrap_or(0);
+    if packages.len() <= index {
+        return Err(ManifestError::PackageNotFound);
+    }
+    let package = packages.swap_remove(index);
+    if package.targets.is_empty() {
+        return Err(ManifestError::NoTargets);
+    }
+
+    let dependencies = package.dependencies
+        .into_iter()
+        .map(|d| {
+            Dependency {
+                name: d.name,
+                req: d.req,
+                kind: d.kind.unwrap_or("normal".to_string()),
+                optional: d.optional,
+                target: d.target,
+            }
+        })
+        .collect();
+
+    Ok(Manifest {
+        name: package.name,
+        version: package.version,
+        targets: package.targets,
+        dependencies: dependencies,
+        features: package.features,
+        metadata: ManifestMetadata::default(),
+        manifest_path: package.manifest_path,
+    })
+}
+
+
+/// Reads descriptive fields from `[package]` table of a manifest
+fn package_metadata(manifest: &str) -> ManifestMetadata {
+    let table = Parser::new(manifest).parse();
+    let package = match table.as_ref()
+        .and_then(|t| t.get("package"))
+        .and_then(|p| p.as_table()) {
+        Some(package) => package,
+        None => return ManifestMetadata::default(),
+    };
+
+    let string = |key: &str| package.get(key).and_then(|v| v.as_str()).map(|v| v.to_string());
+    let string_array = |key: &str| {
+        package.get(key)
+            .and_then(|v| v.as_slice())
+            .map(|v| v.iter().filter_map(|s| s.as_str()).map(|s| s.to_string()).collect())
+            .unwrap_or(Vec::new())
+    };
+
+    ManifestMetadata {
+        authors: string_array("authors"),
+        keywords: string_array("keywords"),
+        description: string("description"),
+        license: string("license"),
+        repository: string("repository"),
+        homepage: string("homepage"),
+        readme: string("readme"),
+    }
+}
+
+
+impl fmt::Display for ManifestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ManifestError::IoError(ref e) => write!(f, "IO error: {}", e),
+            ManifestError::CommandError(ref e) => write!(f, "cargo metadata failed: {}", e),
+            ManifestError::DecoderError(ref e) => write!(f, "JSON decoding error: {}", e),
+            ManifestError::PackageNotFound => write!(f, "Package not found in metadata"),
+            ManifestError::NoTargets => write!(f, "Package doesn't have any target"),
+        }
+    }
+}
+
+
+impl error::Error for ManifestError {
+    fn description(&self) -> &str {
+        "failed to read manifest"
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            ManifestError::IoError(ref e) => Some(e),
+            ManifestError::DecoderError(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+
+impl From<io::Error> for ManifestError {
+    fn from(err: io::Error) -> ManifestError {
+        ManifestError::IoError(err)
+    }
+}
+
+
+impl From<DecoderError> for ManifestError {
+    fn from(err: DecoderError) -> ManifestError {
+        ManifestError::DecoderError(err)
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+    use super::*;
+
+    #[test]
+    fn test_manifest_from_metadata() {
+        let metadata_json = r#"{
+            "packages": [{
+                "name": "rand",
+                "version": "0.3.14",
+                "id": "rand 0.3.14",
+                "source": null,
+                "dependencies": [
+                    { "name": "libc", "source": null, "req": "^0.2", "kind": null,
+                      "optional": false, "uses_default_features": true, "features": [],
+                      "target": null },
+                    { "name": "log", "source": null, "req": "^0.3.0", "kind": "dev",
+                      "optional": false, "uses_default_features": true, "features": [],
+                      "target": null }
+                ],
+                "targets": [
+                    { "kind": [ "lib" ], "name": "rand", "src_path": "/tmp/rand/src/lib.rs" },
+                    { "kind": [ "bench" ], "name": "bench",
+                      "src_path": "/tmp/rand/benches/bench.rs" }
+                ],
+                "features": { "default": [] },
+                "manifest_path": "/tmp/rand/Cargo.toml"
+            }],
+            "version": 1
+        }"#;
+
+        let manifest = manifest_from_metadata(metadata_json,
+                                              Path::new("/tmp/rand/Cargo.toml"),
+                                              "rand")
+            .unwrap();
+        assert_eq!(manifest.name, "rand");
+        assert_eq!(manifest.version, "0.3.14");
+        assert!(manifest.targets[0].is_lib());
+        assert!(!manifest.targets[1].is_lib());
+        assert_eq!(manifest.dependencies[0].kind, "normal");
+        assert_eq!(manifest.dependencies[1].kind, "dev");
+        assert!(manifest.features.contains_key("default"));
+    }
+
+
+    #[test]
+    fn test_package_metadata() {
+        let metadata = package_metadata(r#"
+            [package]
+            name = "rand"
+            authors = [ "The Rust Project Developers" ]
+            license = "MIT/Apache-2.0"
+            keywords = [ "random", "rng" ]
+        "#);
+        assert_eq!(metadata.authors, vec!["The Rust Project Developers".to_string()]);
+        assert_eq!(metadata.keywords, vec!["random".to_string(), "rng".to_string()]);
+        assert_eq!(metadata.license, Some("MIT/Apache-2.0".to_string()));
+        assert!(metadata.description.is_none());
+    }
+}
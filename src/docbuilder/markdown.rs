@@ -0,0 +1,46 @@
+//! README rendering
+//!
+//! READMEs are rendered into HTML when releases are added into database.
+//! Raw HTML in markdown is escaped and links with script schemes are
+//! removed, rendered HTML is safe to insert into pages.
+
+use hoedown::{Markdown, Html, Render};
+use hoedown::{FENCED_CODE, TABLES, AUTOLINK, STRIKETHROUGH, NO_INTRA_EMPHASIS};
+use hoedown::renderer::html;
+use regex::Regex;
+
+
+/// Renders markdown text into sanitized HTML
+pub fn render_markdown(text: &str) -> String {
+    let extensions = FENCED_CODE | TABLES | AUTOLINK | STRIKETHROUGH | NO_INTRA_EMPHASIS;
+    let document = Markdown::new(text).extensions(extensions);
+    let mut renderer = Html::new(html::ESCAPE, 0);
+    let rendered = renderer.render(&document).to_str().unwrap_or("").to_owned();
+
+    // hoedown only escapes raw HTML, remove links like javascript:
+    let unsafe_links = Regex::new(r#"(?i)(href|src)="\s*(javascript|vbscript|data):[^"]*""#)
+        .unwrap();
+    unsafe_links.replace_all(&rendered, "$1=\"#\"")
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_render_markdown() {
+        assert_eq!(render_markdown("# cratesfyi\n\n*Documentation* host").trim(),
+                   "<h1>cratesfyi</h1>\n\n<p><em>Documentation</em> host</p>");
+        assert!(render_markdown("```\nfn main() {}\n```").contains("<pre><code>"));
+    }
+
+    #[test]
+    fn test_render_markdown_sanitizes_html() {
+        let html = render_markdown("<script>alert(1)</script>\n\n[link](javascript:alert(1))");
+        assert!(!html.contains("<script>"));
+        assert!(!html.contains("javascript:"));
+        assert!(html.contains("href=\"#\""));
+    }
+}
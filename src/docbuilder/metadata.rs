@@ -0,0 +1,240 @@
+//! Build options defined by crate authors
+//!
+//! Crate authors can control how their documentation is built with a
+//! `[package.metadata.docs.rs]` table in Cargo.toml:
+//!
+//! ```text
+//! [package.metadata.docs.rs]
+//! features = [ "feature1", "feature2" ]
+//! all-features = true
+//! default-features = false
+//! rustdoc-args = [ "--cfg", "docsrs" ]
+//! default-target = "x86_64-unknown-linux-gnu"
+//! document-private-items = true
+//! ```
+//!
+//! Crates can detect they're built by cratesfyi with environment variables
+//! set for `cargo doc`: `DOCS_RS=1`, `CRATESFYI=1` and `DOCS_RS_TARGET` with
+//! target triple documentation is built for. They are also listed by
+//! `/api/v1/build-environment`.
+
+use std::io::prelude::*;
+use std::fs;
+use std::path::Path;
+
+use toml::{Parser, Value};
+use ::HOST_TARGET;
+
+
+#[derive(Debug, Clone, RustcEncodable)]
+pub struct Metadata {
+    /// Features passed to cargo doc with --features
+    pub features: Option<Vec<String>>,
+    /// Builds documentation with --all-features
+    pub all_features: bool,
+    /// Builds documentation without default features if it's false
+    pub default_features: bool,
+    /// Arguments passed to rustdoc
+    pub rustdoc_args: Option<Vec<String>>,
+    /// Target triple used to build documentation
+    pub default_target: Option<String>,
+    /// Documents private items with --document-private-items
+    pub document_private_items: bool,
+}
+
+
+impl Default for Metadata {
+    fn default() -> Metadata {
+        Metadata {
+            features: None,
+            all_features: false,
+            default_features: true,
+            rustdoc_args: None,
+            default_target: None,
+            document_private_items: false,
+        }
+    }
+}
+
+
+impl Metadata {
+    /// Reads metadata from Cargo.toml in crate root. Returns default metadata
+    /// if Cargo.toml or metadata table doesn't exist.
+    pub fn from_crate_root(root_dir: &Path) -> Metadata {
+        let mut content = String::new();
+        let res = fs::File::open(root_dir.join("Cargo.toml"))
+            .and_then(|mut f| f.read_to_string(&mut content));
+
+        if res.is_err() {
+            return Metadata::default();
+        }
+
+        Metadata::from_str(&content)
+    }
+
+
+    fn from_str(manifest: &str) -> Metadata {
+        let mut metadata = Metadata::default();
+
+        let table = Parser::new(manifest).parse();
+        let docsrs = table.as_ref()
+            .and_then(|t| t.get("package"))
+            .and_then(|p| p.as_table())
+            .and_then(|p| p.get("metadata"))
+            .and_then(|m| m.as_table())
+            .and_then(|m| m.get("docs"))
+            .and_then(|d| d.as_table())
+            .and_then(|d| d.get("rs"))
+            .and_then(|r| r.as_table());
+
+        if let Some(docsrs) = docsrs {
+            metadata.features = docsrs.get("features").and_then(string_array);
+            metadata.all_features = docsrs.get("all-features")
+                .and_then(|v| v.as_bool()).unwrap_or(false);
+            metadata.default_features = docsrs.get("default-features")
+                .and_then(|v| v.as_bool()).unwrap_or(true);
+            metadata.rustdoc_args = docsrs.get("rustdoc-args").and_then(string_array);
+            metadata.default_target = docsrs.get("default-target")
+                .and_then(|v| v.as_str()).map(|v| v.to_string());
+            metadata.document_private_items = docsrs.get("document-private-items")
+                .and_then(|v| v.as_bool()).unwrap_or(false);
+        }
+
+        metadata
+    }
+
+
+    /// Returns arguments for cargo doc
+    pub fn cargo_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+
+        if let Some(ref features) = self.features {
+            args.push("--features".to_string());
+            args.push(features.join(" "));
+        }
+
+        if self.all_features {
+            args.push("--all-features".to_string());
+        }
+
+        if !self.default_features {
+            args.push("--no-default-features".to_string());
+        }
+
+        if let Some(ref target) = self.default_target {
+            args.push("--target".to_string());
+            args.push(target.clone());
+        }
+
+        args
+    }
+
+
+    /// Returns true if crate doesn't choose its features
+    pub fn uses_default_features(&self) -> bool {
+        self.features.is_none() && !self.all_features && self.default_features
+    }
+
+
+    /// Returns target triple documentation is built for
+    pub fn target(&self) -> &str {
+        self.default_target.as_ref().map(|t| &t[..]).unwrap_or(HOST_TARGET)
+    }
+
+
+    /// Returns environment variables of cargo doc telling crate it's built
+    /// by cratesfyi
+    pub fn build_env(&self) -> Vec<(String, String)> {
+        vec![("DOCS_RS".to_string(), "1".to_string()),
+             ("CRATESFYI".to_string(), "1".to_string()),
+             ("DOCS_RS_TARGET".to_string(), self.target().to_string())]
+    }
+
+
+    /// Returns RUSTDOCFLAGS environment variable value. Global arguments of
+    /// builder comes before rustdoc-args of crate and --document-private-items
+    /// is appended if it's enabled. Returns None if there isn't any argument.
+    pub fn rustdoc_flags(&self, global_args: &[String]) -> Option<String> {
+        let mut args: Vec<&str> = global_args.iter()
+            .chain(self.rustdoc_args.iter().flat_map(|a| a.iter()))
+            .map(|a| &a[..])
+            .collect();
+        if self.document_private_items {
+            args.push("--document-private-items");
+        }
+        if args.is_empty() {
+            None
+        } else {
+            Some(args.join(" "))
+        }
+    }
+}
+
+
+fn string_array(value: &Value) -> Option<Vec<String>> {
+    value.as_slice().map(|v| {
+        v.iter().filter_map(|s| s.as_str()).map(|s| s.to_string()).collect()
+    })
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_metadata_from_str() {
+        let manifest = r#"
+            [package]
+            name = "test"
+
+            [package.metadata.docs.rs]
+            features = [ "feature1", "feature2" ]
+            default-features = false
+            rustdoc-args = [ "--cfg", "docsrs" ]
+            default-target = "x86_64-pc-windows-msvc"
+            document-private-items = true
+        "#;
+
+        let metadata = Metadata::from_str(manifest);
+        assert_eq!(metadata.features,
+                   Some(vec!["feature1".to_string(), "feature2".to_string()]));
+        assert!(!metadata.all_features);
+        assert!(!metadata.default_features);
+        assert!(metadata.document_private_items);
+        assert_eq!(metadata.rustdoc_flags(&[]),
+                   Some("--cfg docsrs --document-private-items".to_string()));
+        assert_eq!(metadata.rustdoc_flags(&["--html-in-header".to_string(),
+                                            "katex.html".to_string()]),
+                   Some("--html-in-header katex.html --cfg docsrs \
+                         --document-private-items".to_string()));
+        assert_eq!(metadata.cargo_args(),
+                   vec!["--features", "feature1 feature2", "--no-default-features",
+                        "--target", "x86_64-pc-windows-msvc"]);
+    }
+
+
+    #[test]
+    fn test_metadata_default() {
+        let metadata = Metadata::from_str("[package]\nname = \"test\"");
+        assert!(metadata.default_features);
+        assert!(!metadata.document_private_items);
+        assert!(metadata.uses_default_features());
+        assert!(metadata.cargo_args().is_empty());
+        assert!(metadata.rustdoc_flags(&[]).is_none());
+        assert_eq!(metadata.target(), ::HOST_TARGET);
+    }
+
+
+    #[test]
+    fn test_build_env() {
+        let mut metadata = Metadata::default();
+        metadata.default_target = Some("x86_64-pc-windows-msvc".to_string());
+        let env = metadata.build_env();
+        assert!(env.contains(&("DOCS_RS".to_string(), "1".to_string())));
+        assert!(env.contains(&("CRATESFYI".to_string(), "1".to_string())));
+        assert!(env.contains(&("DOCS_RS_TARGET".to_string(),
+                               "x86_64-pc-windows-msvc".to_string())));
+    }
+}
@@ -24,7 +24,30 @@
 //!
 //! ```text
 //! ./cratesfyi build [FLAGS] [OPTIONS] world
-//! ./cratesfyi build [FLAGS] [OPTIONS] crate <CRATE> <VERSION>
+//! ./cratesfyi build [FLAGS] [OPTIONS] crate <CRATE> [VERSION]
+//! ./cratesfyi build [FLAGS] [OPTIONS] queue
+//! ./cratesfyi build [FLAGS] [OPTIONS] git [--rev <REV>] <URL>
+//! ./cratesfyi build [FLAGS] [OPTIONS] local <PATH>
+//! ./cratesfyi info [--json] <CRATE> <VERSION>
+//! ./cratesfyi queue add <CRATE> [VERSION]
+//! ./cratesfyi queue bump <CRATE> [VERSION]
+//! ./cratesfyi blacklist add [--reason <REASON>] <CRATE>
+//! ./cratesfyi blacklist remove <CRATE>
+//! ./cratesfyi blacklist list
+//! ./cratesfyi override set [OPTIONS] <CRATE>
+//! ./cratesfyi override remove <CRATE>
+//! ./cratesfyi override list
+//! ./cratesfyi toolchain set <TOOLCHAIN>
+//! ./cratesfyi toolchain unset
+//! ./cratesfyi toolchain show
+//! ./cratesfyi rebuild --rustc-older-than <VERSION>
+//! ./cratesfyi rebuild status
+//! ./cratesfyi rebuild crate [--requested-by <NAME>] [--reason <REASON>] <CRATE> <VERSION>
+//! ./cratesfyi daemon [OPTIONS]
+//! ./cratesfyi update-owners
+//! ./cratesfyi update-yanked
+//! ./cratesfyi database init
+//! ./cratesfyi database migrate
 //! ```
 //!
 //! ### Preparing chroot environment
@@ -38,8 +61,9 @@
 //! environment. Don't forget to add a regular user. This program is
 //! using _onur_ username for chroot user for now.
 //!
-//! You also need to clone crates.io-index respository. You can clone repository
-//! from [crates.io-index](https://github.com/rust-lang/crates.io-index).
+//! [crates.io-index](https://github.com/rust-lang/crates.io-index) repository
+//! will be cloned into crates.io-index path if it's not exists and it will be
+//! updated before every build.
 //!
 //! This program is using _sudo_ to use chroot. chroot is only command
 //! used with sudo in this program. Make sure user has privileges to run chroot
@@ -73,25 +97,373 @@
 //!
 //! chroot user's _home directory_ is called _build\_dir_ in program
 //!
+//! ### Docker sandbox
+//!
+//! Instead of chroot, documentation can be built inside a docker container
+//! with `--docker-image <IMAGE>` option. Image must contain rustc and cargo.
+//! Crate sources are mounted into container and rest of the filesystem is
+//! read-only. chroot environment is not required in this mode.
+//!
+//! ### Additional targets
+//!
+//! Documentation can be built for additional platforms with `--target
+//! <TRIPLE>` option (or `targets` in configuration file). Documentation of
+//! an additional target is placed into
+//! `destination/<CRATE>/<VERSION>/<TRIPLE>` and targets are recorded into
+//! `doc_targets` column of releases. Standard library of targets must be
+//! installed in build environment.
+//!
+//! Documentation of a crate is built for `default-target` of
+//! `[package.metadata.docs.rs]` of crate, or for host target if crate
+//! doesn't have one. Operators can choose default target of a crate in
+//! `[default_targets]` table of configuration file. It's recorded into
+//! `default_target` column of releases.
+//!
+//! ### Documentation archives
+//!
+//! Documentation of every release is a few thousands of small files. With
+//! `--archive-docs` option (or `archive_docs = true` in configuration file)
+//! documentation of a release is packed into a single compressed
+//! `destination/<CRATE>/<VERSION>.archive` file after build and web server
+//! reads pages from archives. This option is ignored if documentation is
+//! stored in S3.
+//!
+//! With `precompress_docs = true` in configuration file a gzipped copy of
+//! static text files (search index, scripts, stylesheets) is stored next to
+//! them and web server serves it directly to clients accepting gzip. Other
+//! responses are compressed by web server.
+//!
+//! ### Parallel builds
+//!
+//! Crates in build queue can be built in parallel with `--workers <N>`
+//! option of `build queue` (or `workers` in configuration file). Every
+//! worker builds in its own `build_dir/worker-<N>` directory and uses its
+//! own database connection.
+//!
+//! ### Work directories
+//!
+//! Every build extracts and builds crate in a new `build_dir/build-*`
+//! directory, which is removed after build unless `--keep-build-directory`
+//! is used. Work directories left behind by an interrupted build are removed
+//! when queue is built again. Documentation is written into
+//! `destination/.staging` and it's moved into `destination/<CRATE>/<VERSION>`
+//! only after build is finished successfully.
+//!
+//! Sources of successfully built releases are kept in
+//! `sources_path/<CRATE>/<VERSION>` as they are in crate file. Web server
+//! serves them in `/crate/<CRATE>/<VERSION>/source/`.
+//!
+//! `build` and `daemon` commands are holding `cratesfyi.lock` in prefix (or
+//! `lock_path` in configuration file) while they are running. A second
+//! instance refuses to start unless `--force` is given, lock of an instance
+//! which isn't running anymore is removed.
+//!
+//! After SIGINT or SIGTERM no new crate is built. Crate being built is
+//! finished, if its build is killed by same signal it's kept in build queue
+//! without counting an attempt. Command exits with 128 + signal number.
+//!
+//! ### Crate file cache
+//!
+//! Downloaded crate files can be cached with `crate_cache_path` in
+//! configuration file. Cached files are named after their SHA-256 checksum
+//! and a cached file is only used if its checksum matches crates.io-index.
+//! With `crate_cache_max_size` (in megabytes) oldest cached files are
+//! removed when cache grows bigger. Tests are using a cache in temporary
+//! directory.
+//!
+//! ### Download retries
+//!
+//! A failed crate download is retried `download_retries` times (3 by
+//! default) and delay between retries starts with `download_retry_delay`
+//! seconds and doubles after every retry. Partially downloaded crates are
+//! resumed. Builds of crates which couldn't be downloaded are recorded with
+//! build_status -3 instead of a build failure.
+//!
+//! ### Download mirrors
+//!
+//! Crates are downloaded from `dl` in `config.json` of crates.io-index like
+//! cargo does. URL templates in `download_urls` are used instead if they are
+//! set, `{crate}` and `{version}` are replaced with name and version of
+//! crate. Templates are tried in order and next one is used if a download
+//! fails. Mirror which served crate file is recorded in `download_url` of
+//! build.
+//!
+//! ### Git repositories
+//!
+//! `build git <URL>` builds documentation of unpublished code in a git
+//! repository, `--rev <REV>` checks out a branch, tag or commit. Crate at
+//! root of repository is built in docker sandbox if it's configured,
+//! otherwise cargo is run directly like `build-doc` does in chroot.
+//! Documentation is stored under `_git/<CRATE>-<COMMIT>`, see git module.
+//!
+//! ### Local crates
+//!
+//! `build local <PATH>` builds documentation of a crate in a local
+//! directory the way a published release is built and prints build log,
+//! authors can check if their documentation builds before publishing. See
+//! local module.
+//!
+//! ### Embedding
+//!
+//! Other tools can reuse builder with `DocBuilder::build(name, version,
+//! conn)`, it returns a `BuildResult` with build log and why build failed.
+//! Release must be in crates.io-index. Database connection is optional,
+//! documentation is stored in storage of builder or in destination.
+//! `DocBuilder::release_info` returns `ReleaseInfo` of a release without
+//! building it. Crate model, sandbox and extraction are private to builder.
+//!
+//! ### Alternative registries
+//!
+//! A company-internal registry can be documented instead of crates.io with a
+//! `[registry]` table in configuration file, see `registry` module. Index of
+//! registry is checked out into `crates_io_index_path`, crates are downloaded
+//! from `dl` of registry unless `download_urls` is set and release
+//! information is requested from `api` of registry.
+//!
+//! ### Extraction limits
+//!
+//! Extracted crates are limited to `max_extracted_size` megabytes (512 by
+//! default), `max_extracted_files` entries (50000 by default) and
+//! `max_extracted_file_size` megabytes for a single file (256 by default).
+//! Builds of crates exceeding these limits fail with `CrateTooLarge`. Chroot
+//! builds are using limits from configuration file in chroot.
+//!
+//! ### Retrying failed builds
+//!
+//! A failed build in queue is tried again after a delay, delay starts with a
+//! minute and doubles after every attempt. A crate is marked as failed after
+//! `--max-attempts <N>` attempts (or `max_attempts` in configuration file,
+//! 5 by default). Adding a failed crate into queue again resets its
+//! attempts.
+//!
+//! Queued crates are built in order of their downloads, popular crates are
+//! documented first. `queue add --priority <N>` overrides priority of a
+//! crate and `queue bump <CRATE> [VERSION]` moves a crate to front of queue,
+//! i.e. to rebuild a fixed release of a popular crate urgently.
+//!
+//! ### Features
+//!
+//! Documentation is built with `--all-features`, many crates have a part of
+//! their API behind features. If build with all features fails, crate is
+//! built again with its default features. Crates choosing their features in
+//! `[package.metadata.docs.rs]` are built with them. `--no-all-features`
+//! option (or `all_features = false` in configuration file) builds crates
+//! with default features and `[crate_features]` table in configuration file
+//! sets features of a crate.
+//!
+//! ### rustdoc arguments
+//!
+//! Arguments passed to rustdoc of every crate can be set with
+//! `--rustdoc-arg <ARG>` option (or `rustdoc_args` in configuration file),
+//! i.e: `--rustdoc-arg=--html-in-header --rustdoc-arg=katex.html`. Arguments
+//! from `rustdoc-args` in `[package.metadata.docs.rs]` table of crates are
+//! passed after these arguments.
+//!
+//! ### Private items
+//!
+//! Internal mirrors may want documentation of private items too.
+//! `--document-private-items` option (or `document_private_items = true` in
+//! configuration file) passes `--document-private-items` to rustdoc of every
+//! crate, crates can also enable it with `document-private-items = true` in
+//! their `[package.metadata.docs.rs]` table. Mode used for a release is
+//! stored in `document_private_items` column of releases.
+//!
+//! ### Restricting builds
+//!
+//! Build scripts of crates can access network and download anything during
+//! builds. With `--frozen` option (or `frozen = true` in configuration
+//! file) dependencies are fetched before build and build is run with
+//! `cargo doc --frozen`. With `--deny-network` option (or `deny_network =
+//! true`) builds in docker sandbox have no network access, this option is
+//! same as `--frozen` in chroot environment. A note is written into build
+//! log if a build script fails in a restricted build.
+//!
+//! ### Running tests
+//!
+//! Tests of crates can be compiled or run after documentation is built with
+//! `--run-tests build` or `--run-tests run` option (or `run_tests` in
+//! configuration file). Tests are run with same build limits and result is
+//! stored in `test_status` column of releases: 1 passed, -1 failed, -2 timed
+//! out and 0 if tests are not run.
+//!
+//! ### Toolchain
+//!
+//! Builds are using cargo on PATH of build environment. `--toolchain
+//! <TOOLCHAIN>` option (or `toolchain` in configuration file) runs cargo and
+//! rustc with `+<TOOLCHAIN>` to select a toolchain installed with rustup, i.e:
+//! a pinned nightly. `toolchain set <TOOLCHAIN>` switches the toolchain of
+//! next builds without a restart, `toolchain unset` goes back to configured
+//! toolchain and `toolchain show` shows the active one. Toolchain of every
+//! build is stored in `toolchain` column of builds.
+//!
+//! ### Rebuilding documentation with a new rustc
+//!
+//! rustc and cratesfyi versions of every build are stored in builds table
+//! and versions of latest build are stored in releases table. `rebuild
+//! --rustc-older-than <VERSION>` adds every release whose latest build used
+//! an older rustc into build queue, and `rebuild status` shows how many of
+//! these releases are built since.
+//!
+//! `rebuild crate <CRATE> <VERSION>` moves a single release to front of build
+//! queue and records who requested it. Same is available to holders of
+//! `admin_tokens` with `POST /api/v1/crates/<CRATE>/<VERSION>/rebuild`.
+//!
+//! ### Yanked releases
+//!
+//! Documentation of yanked releases is kept by default. If
+//! `remove_yanked_docs_after` is set in configuration file, documentation
+//! of releases yanked longer than given number of days is removed when
+//! yanked status of releases is updated. Releases are kept in database
+//! without documentation.
+//!
+//! ### Notifications
+//!
+//! Failed builds can be reported to a webhook (`notify_webhook_url`) as a
+//! JSON payload and by email (`notify_email_to`) sent with sendmail. A
+//! failure is a `regressed` event if previous build of crate was
+//! successful, otherwise it's a `failed` event. `notify_events` in
+//! configuration file selects events notifications are sent for.
+//!
+//! ### Progress of world builds
+//!
+//! `build world` logs number of finished and failed crates with build rate
+//! and ETA once in a minute. Progress is saved into database and it's
+//! available in `/api/v1/progress` with number of crates in build queue.
+//!
+//! An interrupted `build world` continues after last finished crate when
+//! it's started again with same rustc version. Versions published since
+//! interrupted build into already finished crates are added into build
+//! queue. `build world --restart` starts from first crate.
+//!
+//! ### Logging
+//!
+//! Log messages are tagged with worker, queue id, crate, version and build
+//! phase, i.e: `[worker=1 queue_id=12 crate=rand version=0.3.14 phase=build]`.
+//! `--log-format json` (or `log_format = "json"` in configuration file)
+//! writes every message as a JSON object for log aggregation.
+//!
+//! ### Blacklist
+//!
+//! Crates in blacklist are never built, they are removed from build queue
+//! and skipped by `build world` and `build crate`. A reason can be given
+//! when a crate is added into blacklist and it's printed when a build is
+//! refused. Blacklist is only checked if builder has a database connection.
+//!
+//! ### Build overrides
+//!
+//! Crates needing special treatment can have a build override with
+//! `override set <CRATE>`: features, default target, timeout, memory limit
+//! and a docker image with extra system packages. They are preferred over
+//! metadata of crate and options of builder. Docker image is only used in
+//! docker sandbox. Overrides are only used if builder has a database
+//! connection.
+//!
 //! ### build subcommand arguments
 //!
 //! Type `./cratesfyi build --help` to get full list of _FLAGS_ and _OPTIONS_.
 
-pub mod crte;
+mod crte;
+pub mod index;
+pub mod download;
+mod extract;
+mod sandbox;
+pub mod limits;
+pub mod metadata;
+pub mod queue;
+pub mod builds;
+pub mod cratesio_api;
+pub mod owners;
+pub mod s3;
+pub mod storage;
+pub mod archive;
+pub mod markdown;
+mod manifest;
+pub mod blacklist;
+pub mod rebuild;
+mod coverage;
+mod workspace;
+mod crate_cache;
+pub mod notify;
+pub mod progress;
+pub mod overrides;
+pub mod toolchain;
+pub mod registry;
+pub mod git;
+pub mod local;
 
 use std::io::prelude::*;
 use std::io;
 use std::fmt;
+use std::error;
 use std::env;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::fs;
 use std::process::{Command, Output};
+use std::time::{Duration, Instant};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::collections::{HashMap, HashSet, BTreeMap};
+use std::thread;
+use time;
 
 use toml;
 use postgres;
-use regex::Regex;
-
-
+use regex::{self, Regex};
+use git2;
+use rustc_serialize::json::ToJson;
+use ::db;
+use ::shutdown::shutdown_requested;
+use ::logging;
+use hyper;
+use hyper::status::StatusCode;
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+
+use self::index::Index;
+use self::sandbox::DockerSandbox;
+use self::limits::{BuildLimits, CommandFailure, BUILD_TIMED_OUT_EXIT_CODE,
+                   BUILD_TIMED_OUT_LOG_MARKER};
+use self::metadata::Metadata;
+use self::cratesio_api::CratesIoClient;
+use self::download::{DownloadOptions, RetryPolicy, DOWNLOAD_FAILED_LOG_MARKER};
+use self::storage::{Storage, StorageError, FileStorage, PrecompressedStorage};
+use self::archive::ArchiveStorage;
+use self::notify::{Notifier, Notification, BuildEvent};
+use self::progress::Progress;
+use self::overrides::BuildOverride;
+use self::registry::Registry;
+
+pub use self::crte::{CrateInfo, CrateOpenError, Feature, ReleaseInfo};
+pub use self::manifest::{Dependency, ManifestError, ManifestMetadata};
+pub use self::coverage::DocCoverage;
+pub use self::crate_cache::CrateCache;
+pub use self::extract::{ExtractLimits, CRATE_TOO_LARGE_LOG_PREFIX};
+
+
+/// Directory in documentation storage holding rustdoc's shared files. Files
+/// are stored in `_static/<RUSTC_VERSION>` and they are served from
+/// `/_static` by web server.
+pub const SHARED_STATIC_DIR: &'static str = "_static";
+
+
+/// rustdoc's static files which are identical in every documentation built
+/// with same rustc version
+const SHARED_FILES_REGEX: &'static str = r"^(.+\.(css|woff|txt)|jquery\.js|playpen\.js|main\.js)$";
+
+
+/// Name of config entry holding last crates.io-index commit seen by
+/// add_new_crates_to_queue
+const LAST_SEEN_INDEX_REFERENCE: &'static str = "last_seen_index_reference";
+
+/// Prefix of work directories of builds in build directory
+const WORK_DIR_PREFIX: &'static str = "build-";
+
+/// Directory in destination and sources_path where files are written
+/// before they're moved into `<CRATE>/<VERSION>`
+const STAGING_DIR: &'static str = ".staging";
+
+
+#[derive(Clone)]
 pub struct DocBuilder {
     keep_build_directory: bool,
     destination: PathBuf,
@@ -105,14 +477,64 @@ pub struct DocBuilder {
     skip_if_log_exists: bool,
     skip_oldest_versions: bool,
     build_only_latest_version: bool,
+    download_options: DownloadOptions,
+    /// Download URLs are set by operator, dl of index is not used
+    download_urls_set: bool,
+    /// Cache of downloaded crate files
+    crate_cache: Option<CrateCache>,
+    extract_limits: ExtractLimits,
+    sandbox: Option<DockerSandbox>,
+    build_limits: BuildLimits,
+    targets: Vec<String>,
+    /// Default targets of crates set by operator, keyed by crate name
+    default_targets: BTreeMap<String, String>,
+    /// Builds crates with --all-features unless they choose their features
+    all_features: bool,
+    /// Features of crates set by operator, keyed by crate name
+    crate_features: BTreeMap<String, Vec<String>>,
+    /// Arguments passed to rustdoc of every crate
+    rustdoc_args: Vec<String>,
+    /// Documents private items of every crate
+    document_private_items: bool,
+    /// rustup toolchain used by builds, cargo on PATH is used if it's None
+    toolchain: Option<String>,
+    /// Registry crates are documented from
+    registry: Registry,
+    cratesio: Arc<CratesIoClient>,
+    storage: Option<Arc<Storage>>,
+    archive_docs: bool,
+    precompress_docs: bool,
+    workers: usize,
+    max_attempts: i32,
+    /// Documentation of releases yanked longer than this is removed, it's
+    /// kept if it's None
+    yanked_docs_grace_period: Option<Duration>,
+    /// Notifications of failed builds
+    notifier: Notifier,
+    /// Continue world build from checkpoint
+    resume_world_build: bool,
+    test_mode: Option<builds::TestMode>,
+    /// rustc, cargo and cratesfyi versions of build environment, keyed by
+    /// toolchain
+    versions: Arc<Mutex<HashMap<Option<String>, (String, String, String)>>>,
     debug: bool,
 }
 
 
 #[derive(Debug)]
 pub enum DocBuilderError {
-    DownloadCrateError(String),
-    ExtractCrateError(String),
+    DownloadCrateError(hyper::Error),
+    DownloadCrateStatusError(StatusCode),
+    DownloadCrateIoError(io::Error),
+    /// Checksum of downloaded crate doesn't match with checksum in
+    /// crates.io-index: (expected, found)
+    ChecksumMismatch(String, String),
+    ExtractCrateError(io::Error),
+    ExtractCrateEmptyArchive,
+    /// Crate archive has an entry outside of crate directory: path of entry
+    ExtractCrateUnsafePath(PathBuf),
+    /// Extracted crate exceeded ExtractLimits: reason
+    CrateTooLarge(String),
     BuildDocForCratePath(io::Error),
     LogFileError(io::Error),
     RustcNotFoundError(String),
@@ -122,17 +544,178 @@ pub enum DocBuilderError {
     RemoveOldDoc(io::Error),
     SkipLogFileExists,
     SkipDocumentationExists,
+    /// Crate is in blacklist: reason
+    CrateBlacklisted(String),
+    /// Crate or version doesn't exist in crates.io-index
+    CrateNotInIndex,
     HandleLocalDependenciesError,
-    LocalDependencyDownloadError(String),
-    LocalDependencyExtractCrateError(String),
     LocalDependencyDownloadDirNotExist,
     LocalDependencyIoError(io::Error),
+    WorkspaceIoError(io::Error),
+    StoreSourcesError(io::Error),
     FailedToBuildCrate,
+    BuildTimedOut,
+    /// Crate couldn't be downloaded after every retry
+    CrateDownloadFailed,
+    /// Build is stopped by a shutdown signal
+    Interrupted,
 
     CopyDocumentationCargoTomlNotFound(io::Error),
     CopyDocumentationLibNameNotFound,
     DocumentationNotFound,
     CopyDocumentationIoError(io::Error),
+
+    UpdateIndexError(git2::Error),
+    CloneRepositoryError(git2::Error),
+    /// Root of git repository doesn't have a package
+    GitRepositoryNotACrate,
+    CopyLocalCrateError(io::Error),
+    /// Directory of a local build doesn't have a package
+    LocalCrateNotACrate,
+    DatabaseError(postgres::error::Error),
+    DatabaseConnectError(postgres::error::ConnectError),
+    StorageError(StorageError),
+}
+
+
+impl fmt::Display for DocBuilderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        use self::DocBuilderError::*;
+        match *self {
+            DownloadCrateError(ref e) => write!(f, "Failed to download crate: {}", e),
+            DownloadCrateStatusError(ref s) => write!(f, "Failed to download crate: {}", s),
+            DownloadCrateIoError(ref e) => write!(f, "Failed to save crate file: {}", e),
+            ChecksumMismatch(ref expected, ref found) => {
+                write!(f, "Checksum mismatch, expected {} found {}", expected, found)
+            }
+            ExtractCrateError(ref e) => write!(f, "Failed to extract crate: {}", e),
+            ExtractCrateEmptyArchive => write!(f, "Crate archive is empty"),
+            ExtractCrateUnsafePath(ref p) => {
+                write!(f, "Crate archive contains an unsafe path: {}", p.display())
+            }
+            CrateTooLarge(ref reason) => write!(f, "Crate is too large: {}", reason),
+            BuildDocForCratePath(ref e) => write!(f, "Failed to read crates.io-index: {}", e),
+            LogFileError(ref e) => write!(f, "Failed to write log file: {}", e),
+            RustcNotFoundError(ref e) => write!(f, "Failed to get rustc version: {}", e),
+            RustcVersionParseError => write!(f, "Failed to parse rustc version"),
+            RemoveBuildDir(ref e) => write!(f, "Failed to remove build directory: {}", e),
+            RemoveCrateFile(ref e) => write!(f, "Failed to remove crate file: {}", e),
+            RemoveOldDoc(ref e) => write!(f, "Failed to remove old documentation: {}", e),
+            SkipLogFileExists => write!(f, "Skipped, build log already exists"),
+            SkipDocumentationExists => write!(f, "Skipped, documentation already exists"),
+            CrateBlacklisted(ref reason) if reason.is_empty() => {
+                write!(f, "Skipped, crate is blacklisted")
+            }
+            CrateBlacklisted(ref reason) => write!(f, "Skipped, crate is blacklisted: {}", reason),
+            CrateNotInIndex => write!(f, "Crate or version not found in crates.io-index"),
+            HandleLocalDependenciesError => write!(f, "Failed to handle local dependencies"),
+            LocalDependencyDownloadDirNotExist => {
+                write!(f, "Local dependency download directory not exists")
+            }
+            LocalDependencyIoError(ref e) => write!(f, "Local dependency error: {}", e),
+            WorkspaceIoError(ref e) => write!(f, "Failed to isolate workspace: {}", e),
+            StoreSourcesError(ref e) => write!(f, "Failed to store sources: {}", e),
+            FailedToBuildCrate => write!(f, "Failed to build crate"),
+            BuildTimedOut => write!(f, "Build timed out"),
+            CrateDownloadFailed => write!(f, "Failed to download crate"),
+            Interrupted => write!(f, "Build interrupted by a shutdown signal"),
+            CopyDocumentationCargoTomlNotFound(ref e) => {
+                write!(f, "Failed to read Cargo.toml: {}", e)
+            }
+            CopyDocumentationLibNameNotFound => write!(f, "Library name not found in Cargo.toml"),
+            DocumentationNotFound => write!(f, "Documentation not found"),
+            CopyDocumentationIoError(ref e) => write!(f, "Failed to copy documentation: {}", e),
+            UpdateIndexError(ref e) => write!(f, "Failed to update crates.io-index: {}", e),
+            CloneRepositoryError(ref e) => write!(f, "Failed to clone repository: {}", e),
+            GitRepositoryNotACrate => {
+                write!(f, "Cargo.toml at root of repository doesn't have a package")
+            }
+            CopyLocalCrateError(ref e) => write!(f, "Failed to copy local crate: {}", e),
+            LocalCrateNotACrate => write!(f, "Directory doesn't have a Cargo.toml with a package"),
+            DatabaseError(ref e) => write!(f, "Database error: {}", e),
+            DatabaseConnectError(ref e) => write!(f, "Failed to connect database: {}", e),
+            StorageError(ref e) => write!(f, "Storage error: {}", e),
+        }
+    }
+}
+
+
+impl error::Error for DocBuilderError {
+    fn description(&self) -> &str {
+        "documentation build error"
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        use self::DocBuilderError::*;
+        match *self {
+            DownloadCrateError(ref e) => Some(e),
+            DownloadCrateIoError(ref e) |
+            ExtractCrateError(ref e) |
+            BuildDocForCratePath(ref e) |
+            LogFileError(ref e) |
+            RemoveBuildDir(ref e) |
+            RemoveCrateFile(ref e) |
+            RemoveOldDoc(ref e) |
+            LocalDependencyIoError(ref e) |
+            WorkspaceIoError(ref e) |
+            CopyLocalCrateError(ref e) |
+            StoreSourcesError(ref e) |
+            CopyDocumentationCargoTomlNotFound(ref e) |
+            CopyDocumentationIoError(ref e) => Some(e),
+            UpdateIndexError(ref e) |
+            CloneRepositoryError(ref e) => Some(e),
+            DatabaseError(ref e) => Some(e),
+            DatabaseConnectError(ref e) => Some(e),
+            StorageError(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+
+impl From<hyper::Error> for DocBuilderError {
+    fn from(err: hyper::Error) -> DocBuilderError {
+        DocBuilderError::DownloadCrateError(err)
+    }
+}
+
+
+impl From<StorageError> for DocBuilderError {
+    fn from(err: StorageError) -> DocBuilderError {
+        DocBuilderError::StorageError(err)
+    }
+}
+
+
+impl From<git2::Error> for DocBuilderError {
+    fn from(err: git2::Error) -> DocBuilderError {
+        DocBuilderError::UpdateIndexError(err)
+    }
+}
+
+
+impl From<postgres::error::Error> for DocBuilderError {
+    fn from(err: postgres::error::Error) -> DocBuilderError {
+        DocBuilderError::DatabaseError(err)
+    }
+}
+
+
+/// Result of an attempted build, see DocBuilder::build
+#[derive(Debug)]
+pub struct BuildResult {
+    /// Build attempt, same as record in builds table
+    pub build: builds::Build,
+    /// Why build failed, None if documentation is built
+    pub error: Option<DocBuilderError>,
+}
+
+
+impl BuildResult {
+    /// Returns true if documentation is built and stored
+    pub fn is_successful(&self) -> bool {
+        self.error.is_none()
+    }
 }
 
 
@@ -187,6 +770,31 @@ impl Default for DocBuilder {
             skip_if_log_exists: false,
             skip_oldest_versions: false,
             build_only_latest_version: false,
+            download_options: DownloadOptions::default(),
+            download_urls_set: false,
+            crate_cache: None,
+            extract_limits: ExtractLimits::default(),
+            sandbox: None,
+            build_limits: BuildLimits::default(),
+            targets: Vec::new(),
+            default_targets: BTreeMap::new(),
+            all_features: true,
+            crate_features: BTreeMap::new(),
+            rustdoc_args: Vec::new(),
+            document_private_items: false,
+            toolchain: None,
+            registry: Registry::crates_io(),
+            cratesio: Arc::new(CratesIoClient::default()),
+            storage: None,
+            archive_docs: false,
+            precompress_docs: false,
+            workers: 1,
+            max_attempts: queue::DEFAULT_MAX_ATTEMPTS,
+            yanked_docs_grace_period: None,
+            notifier: Notifier::default(),
+            resume_world_build: true,
+            test_mode: None,
+            versions: Arc::new(Mutex::new(HashMap::new())),
             debug: false,
         }
     }
@@ -200,7 +808,7 @@ impl fmt::Debug for DocBuilder {
                 crates_io_index_path: {:?}, logs_path: {:?}, \
                 sources_path: {:?}, chroot_user: {:?}, \
                 keep_build_directory: {:?}, skip_if_exists: {:?}, \
-                skip_if_log_exists: {:?}, debug: {:?} }}",
+                skip_if_log_exists: {:?}, sandbox: {:?}, debug: {:?} }}",
                 self.destination,
                 self.chroot_path,
                 self.build_dir,
@@ -211,6 +819,7 @@ impl fmt::Debug for DocBuilder {
                 self.keep_build_directory,
                 self.skip_if_exists,
                 self.skip_if_log_exists,
+                self.sandbox,
                 self.debug)
     }
 }
@@ -248,6 +857,14 @@ impl DocBuilder {
         self.build_dir_path();
     }
 
+    /// Set build directory, crates are downloaded, extracted and built in
+    /// this directory. Chroot builds can only use a directory inside home
+    /// directory of chroot user. It's reset to home directory of chroot user
+    /// by chroot_path and chroot_user.
+    pub fn build_dir(&mut self, path: PathBuf) {
+        self.build_dir = path;
+    }
+
     /// Set build dir
     pub fn build_dir_path(&mut self) {
         let mut build_dir_path = PathBuf::from(&self.chroot_path);
@@ -272,6 +889,11 @@ impl DocBuilder {
         self.logs_path = path;
     }
 
+    /// Set sources path
+    pub fn sources_path(&mut self, path: PathBuf) {
+        self.sources_path = path;
+    }
+
     pub fn keep_build_directory(&mut self, b: bool) {
         self.keep_build_directory = b;
     }
@@ -288,16 +910,240 @@ impl DocBuilder {
         self.skip_oldest_versions = b;
     }
 
+    /// Build only latest stable and not yanked version of crates
     pub fn build_only_latest_version(&mut self, b: bool) {
         self.build_only_latest_version = b;
     }
 
+    /// Build documentation in a docker container instead of chroot
+    pub fn docker_image(&mut self, image: String) {
+        self.sandbox = Some(DockerSandbox::new(image));
+    }
+
+    /// Set timeout, memory and output limits of builds
+    pub fn build_limits(&mut self, limits: BuildLimits) {
+        self.build_limits = limits;
+    }
+
+    /// Set additional targets to build documentation for. Documentation of
+    /// a target is placed into `destination/{crate}/{version}/{target}`.
+    pub fn targets(&mut self, targets: Vec<String>) {
+        self.targets = targets;
+    }
+
+    /// Set default targets of crates, they are used instead of
+    /// `default-target` of crates
+    pub fn default_targets(&mut self, default_targets: BTreeMap<String, String>) {
+        self.default_targets = default_targets;
+    }
+
+    /// Builds documentation with all features of crates, crates choosing
+    /// their features in metadata are not affected. Enabled by default.
+    pub fn all_features(&mut self, b: bool) {
+        self.all_features = b;
+    }
+
+    /// Set features of crates, they are used instead of features in metadata
+    /// of crates
+    pub fn crate_features(&mut self, crate_features: BTreeMap<String, Vec<String>>) {
+        self.crate_features = crate_features;
+    }
+
+    /// Applies build override of a crate. Docker image is only used if
+    /// builds are running in docker sandbox.
+    pub fn apply_build_override(&mut self, name: &str, build_override: &BuildOverride) {
+        if let Some(ref features) = build_override.features {
+            self.crate_features.insert(name.to_string(), features.clone());
+        }
+        if let Some(ref default_target) = build_override.default_target {
+            self.default_targets.insert(name.to_string(), default_target.clone());
+        }
+        if let Some(timeout) = build_override.timeout {
+            self.build_limits.timeout = Duration::from_secs(timeout as u64);
+        }
+        if let Some(memory_limit) = build_override.memory_limit {
+            self.build_limits.memory = Some(memory_limit as u64);
+        }
+        if let Some(ref image) = build_override.docker_image {
+            if self.sandbox.is_some() {
+                self.sandbox = Some(DockerSandbox::new(image.clone()));
+            }
+        }
+    }
+
+    /// Replaces default target and features of metadata if operator set
+    /// them for crate, enables documentation of private items if it's
+    /// enabled for every crate
+    pub fn override_metadata(&self, name: &str, metadata: &mut Metadata) {
+        if let Some(target) = self.default_targets.get(name) {
+            metadata.default_target = Some(target.clone());
+        }
+        if let Some(features) = self.crate_features.get(name) {
+            metadata.features = Some(features.clone());
+            metadata.all_features = false;
+        }
+        if self.document_private_items {
+            metadata.document_private_items = true;
+        }
+    }
+
+    /// Set timeout of crate downloads, None disables timeout
+    pub fn download_timeout(&mut self, timeout: Option<Duration>) {
+        self.download_options.timeout = timeout;
+    }
+
+    /// Set retries of failed crate downloads
+    pub fn download_retry(&mut self, policy: RetryPolicy) {
+        self.download_options.retry_policy = policy;
+    }
+
+    /// Set URL templates of crate downloads, i.e:
+    /// `https://static.crates.io/crates/{crate}/{crate}-{version}.crate`.
+    /// Mirrors are tried in given order.
+    pub fn download_urls(&mut self, urls: Vec<String>) {
+        self.download_options.urls = urls;
+        self.download_urls_set = true;
+    }
+
+    /// Returns options of crate downloads. Crates are downloaded from dl in
+    /// config.json of index unless download URLs are set, default URLs are
+    /// used if config.json can't be read.
+    pub fn download_options(&self) -> DownloadOptions {
+        let mut options = self.download_options.clone();
+        if !self.download_urls_set {
+            match self.index().config() {
+                Ok(config) => options.urls = vec![download::dl_template(&config.dl)],
+                Err(e) => warn!("Failed to read config.json of index: {}", e),
+            }
+        }
+        options
+    }
+
+    /// Set cache of downloaded crate files
+    pub fn crate_cache(&mut self, cache: CrateCache) {
+        self.crate_cache = Some(cache);
+    }
+
+    /// Set size limits of extracted crates
+    pub fn extract_limits(&mut self, limits: ExtractLimits) {
+        self.extract_limits = limits;
+    }
+
+    /// Set arguments passed to rustdoc of every crate, arguments from
+    /// `[package.metadata.docs.rs]` of crates are appended to these
+    pub fn rustdoc_args(&mut self, args: Vec<String>) {
+        self.rustdoc_args = args;
+    }
+
+    /// Documents private items of every crate. Crates can enable it in
+    /// their metadata even if it's disabled.
+    pub fn document_private_items(&mut self, b: bool) {
+        self.document_private_items = b;
+    }
+
+    /// Set rustup toolchain of builds, i.e: `nightly-2016-06-10`
+    pub fn toolchain(&mut self, toolchain: String) {
+        self.toolchain = Some(toolchain);
+    }
+
+    /// Returns arguments of cargo with toolchain of builds
+    pub fn cargo_args(&self, args: &[String]) -> Vec<String> {
+        toolchain::with_toolchain(self.toolchain.as_ref().map(|t| &t[..]), args)
+    }
+
+    /// Set registry crates are documented from, crates.io by default.
+    /// Crates are downloaded from dl of registry. API client should use API
+    /// of registry, see CratesIoClient::api_url.
+    pub fn registry(&mut self, registry: Registry) {
+        self.download_options.urls = vec![registry.download_url_template()];
+        self.download_urls_set = true;
+        self.registry = registry;
+    }
+
+    /// Set crates.io API client used to get release information
+    pub fn cratesio_client(&mut self, client: CratesIoClient) {
+        self.cratesio = Arc::new(client);
+    }
+
+    /// Set number of workers building crates in queue in parallel
+    pub fn workers(&mut self, workers: usize) {
+        self.workers = workers;
+    }
+
+    /// Set maximum number of build attempts of a queued crate
+    pub fn max_attempts(&mut self, max_attempts: i32) {
+        self.max_attempts = max_attempts;
+    }
+
+    /// Set grace period of documentation of yanked releases, documentation
+    /// is removed by update_yanked after release is yanked for this long.
+    /// Documentation of yanked releases is kept if it's None.
+    pub fn remove_yanked_docs(&mut self, grace_period: Option<Duration>) {
+        self.yanked_docs_grace_period = grace_period;
+    }
+
+    /// Set notifier of failed builds, notifications are only sent if build
+    /// has a database connection
+    pub fn notifier(&mut self, notifier: Notifier) {
+        self.notifier = notifier;
+    }
+
+    /// Continue an interrupted world build from its checkpoint, it's
+    /// enabled by default
+    pub fn resume_world_build(&mut self, b: bool) {
+        self.resume_world_build = b;
+    }
+
+    /// Run tests of crates after building documentation, tests are not run
+    /// by default
+    pub fn run_tests(&mut self, mode: Option<builds::TestMode>) {
+        self.test_mode = mode;
+    }
+
+    /// Set storage of documentation, destination directory is used if it's
+    /// not set
+    pub fn storage(&mut self, storage: Arc<Storage>) {
+        self.storage = Some(storage);
+    }
+
+    /// Packs documentation of releases into archives if it's set, ignored
+    /// if a storage is set
+    pub fn archive_docs(&mut self, b: bool) {
+        self.archive_docs = b;
+    }
+
+    /// Stores a gzipped copy of static text files of documentation
+    pub fn precompress_docs(&mut self, b: bool) {
+        self.precompress_docs = b;
+    }
+
+    /// Returns storage of documentation
+    pub fn doc_storage(&self) -> Arc<Storage> {
+        if let Some(ref storage) = self.storage {
+            return self.precompressed(storage.clone());
+        }
+        if self.archive_docs {
+            return self.precompressed(Arc::new(ArchiveStorage::new(self.destination.clone())));
+        }
+        self.precompressed(Arc::new(FileStorage::new(self.destination.clone())))
+    }
+
+    /// Wraps storage into a PrecompressedStorage if precompress_docs is set
+    fn precompressed(&self, storage: Arc<Storage>) -> Arc<Storage> {
+        if self.precompress_docs {
+            Arc::new(PrecompressedStorage::new(storage))
+        } else {
+            storage
+        }
+    }
+
 
     pub fn check_paths(&self) -> Result<(), DocBuilderPathError> {
         if !self.destination.exists() {
             return Err(DocBuilderPathError::DestinationPathNotExists)
         }
-        if !self.chroot_path.exists() {
+        // chroot is not used when builds are running in a sandbox
+        if self.sandbox.is_none() && !self.chroot_path.exists() {
             return Err(DocBuilderPathError::ChrootPathNotExists)
         }
         if !self.build_dir.exists() {
@@ -320,83 +1166,557 @@ impl DocBuilder {
         Ok(())
     }
 
-    /// This functions reads files in crates.io-index and tries to build
-    /// documentation for crates.
-    pub fn build_doc_for_every_crate(&self) -> Result<(), DocBuilderError> {
-        self.build_doc_for_crate_path(&self.crates_io_index_path)
+    /// Returns index checkout of registry used by DocBuilder
+    pub fn index(&self) -> Index {
+        Index::from_url(PathBuf::from(&self.crates_io_index_path),
+                        self.registry.index_url.clone())
     }
 
 
-    fn build_doc_for_crate_path(&self, path: &PathBuf) -> Result<(), DocBuilderError> {
-        for dir in try!(path.read_dir().map_err(DocBuilderError::BuildDocForCratePath)) {
+    /// Clones crates.io-index if it's not exists or fast-forwards it
+    pub fn update_crates_io_index(&self) -> Result<(), DocBuilderError> {
+        info!("Updating crates.io-index");
+        self.index().update().map(|_| ()).map_err(DocBuilderError::UpdateIndexError)
+    }
 
-            let path = dir.unwrap().path();
 
-            // skip files under .git and config.json
-            if path.to_str().unwrap().contains(".git") ||
-                path.file_name().unwrap() == "config.json" {
-                    continue;
+    /// This functions reads files in crates.io-index and tries to build
+    /// documentation for crates. Progress is logged periodically and it's
+    /// saved into database if a connection is given.
+    ///
+    /// With a connection, a checkpoint is saved after every crate and an
+    /// interrupted build is resumed from it if rustc version is same.
+    pub fn build_doc_for_every_crate(&self,
+                                     conn: Option<&postgres::Connection>)
+                                     -> Result<(), DocBuilderError> {
+        let mut files = try!(crate_files(&self.crates_io_index_path)
+                             .map_err(DocBuilderError::BuildDocForCratePath));
+        let total = files.len();
+        let head = format!("{}", try!(self.index().head()
+                                      .map_err(DocBuilderError::UpdateIndexError)));
+        let (rustc_version, _, _) = try!(self.get_versions()
+                                         .map_err(DocBuilderError::RustcNotFoundError));
+
+        if let (Some(conn), true) = (conn, self.resume_world_build) {
+            let checkpoint = try!(progress::load_checkpoint(conn)
+                                  .map_err(DocBuilderError::DatabaseError));
+            if let Some(checkpoint) = checkpoint {
+                if checkpoint.rustc_version == rustc_version {
+                    try!(self.resume_from_checkpoint(conn, &checkpoint, &mut files));
+                } else {
+                    info!("Ignoring checkpoint of world build with {}",
+                          checkpoint.rustc_version);
                 }
+            }
+        }
 
-            if path.is_dir() {
-                try!(self.build_doc_for_crate_path(&path));
-                continue;
+        let mut progress = Progress::new(total);
+        progress.completed = total - files.len();
+        info!("Building documentation of {} crates", files.len());
+
+        for file in files {
+            if shutdown_requested() {
+                break;
             }
 
+            let path = self.crates_io_index_path.join(&file);
             if let Ok(crte) = crte::Crate::from_cargo_index_file(path) {
-                self.build_doc_for_crate(&crte);
+                let successful = self.build_doc_for_crate(&crte, conn);
+                progress.add(successful);
+            }
+
+            if let Some(conn) = conn {
+                // a crate interrupted by signal is built again in next run
+                if !shutdown_requested() {
+                    let checkpoint = progress::Checkpoint {
+                        rustc_version: rustc_version.clone(),
+                        index_commit: head.clone(),
+                        last_crate: file,
+                    };
+                    try!(progress::save_checkpoint(conn, &checkpoint)
+                         .map_err(DocBuilderError::DatabaseError));
+                }
+            }
+
+            if progress.report_due() {
+                info!("{}", progress.summary());
+                if let Some(conn) = conn {
+                    if let Err(e) = progress::save_progress(conn, &progress, false) {
+                        warn!("Failed to save progress: {}", e);
+                    }
+                }
+            }
+        }
+
+        let finished = !shutdown_requested();
+        info!("{}", progress.summary());
+        if let Some(conn) = conn {
+            if finished {
+                try!(progress::clear_checkpoint(conn).map_err(DocBuilderError::DatabaseError));
+            }
+            if let Err(e) = progress::save_progress(conn, &progress, finished) {
+                warn!("Failed to save progress: {}", e);
+            }
+        }
+        Ok(())
+    }
+
+
+    /// Removes crates finished before checkpoint from files. Versions
+    /// published into finished crates after checkpoint's index commit are
+    /// added into build queue.
+    fn resume_from_checkpoint(&self,
+                              conn: &postgres::Connection,
+                              checkpoint: &progress::Checkpoint,
+                              files: &mut Vec<PathBuf>)
+                              -> Result<(), DocBuilderError> {
+        info!("Resuming world build after {}", checkpoint.last_crate.display());
+        files.retain(|file| *file > checkpoint.last_crate);
+
+        let index = self.index();
+        let head = try!(index.head().map_err(DocBuilderError::UpdateIndexError));
+        let old = try!(git2::Oid::from_str(&checkpoint.index_commit)
+                       .map_err(DocBuilderError::UpdateIndexError));
+        if old == head {
+            return Ok(());
+        }
+
+        for (name, version) in try!(index.new_versions(old, head)
+                                    .map_err(DocBuilderError::UpdateIndexError)) {
+            if index::crate_index_path(&name).map_or(false, |p| p <= checkpoint.last_crate) {
+                info!("Adding {}-{} into build queue", name, version);
+                try!(queue::add_crate_to_queue(conn, &name, &version)
+                     .map_err(DocBuilderError::DatabaseError));
+            }
+        }
+        Ok(())
+    }
+
+
+    /// Updates crates.io-index and adds versions published since last seen
+    /// index commit into build queue. Returns number of queued versions.
+    ///
+    /// Nothing is queued in first run, only current commit is saved.
+    pub fn add_new_crates_to_queue(&self,
+                                   conn: &postgres::Connection) -> Result<usize, DocBuilderError> {
+        let index = self.index();
+        let head = try!(index.update().map_err(DocBuilderError::UpdateIndexError));
+
+        let last_seen = try!(db::get_config(conn, LAST_SEEN_INDEX_REFERENCE)
+                             .map_err(DocBuilderError::DatabaseError))
+            .and_then(|r| r.as_string().and_then(|r| git2::Oid::from_str(r).ok()));
+
+        let mut queued = 0;
+
+        if let Some(last_seen) = last_seen {
+            for (name, version) in try!(index.new_versions(last_seen, head)
+                                        .map_err(DocBuilderError::UpdateIndexError)) {
+                info!("Adding {}-{} into build queue", name, version);
+                try!(queue::add_crate_to_queue(conn, &name, &version)
+                     .map_err(DocBuilderError::DatabaseError));
+                queued += 1;
+            }
+        }
+
+        try!(db::set_config(conn, LAST_SEEN_INDEX_REFERENCE, &format!("{}", head).to_json())
+             .map_err(DocBuilderError::DatabaseError));
+
+        Ok(queued)
+    }
+
+
+    /// Builds crates in build queue until queue is empty.
+    ///
+    /// Crates are removed from queue after successful builds, failed builds
+    /// are rescheduled until they reach maximum attempt count. Returns number
+    /// of built crates.
+    pub fn build_packages_queue(&self, conn: &postgres::Connection) -> Result<usize, DocBuilderError> {
+        let mut built_crates = 0;
+        try!(self.remove_work_dirs().map_err(DocBuilderError::RemoveBuildDir));
+
+        while !shutdown_requested() {
+            let queued = match try!(queue::next_crate(conn)
+                                    .map_err(DocBuilderError::DatabaseError)) {
+                Some(queued) => queued,
+                None => break,
+            };
+            if try!(self.build_queued_crate(conn, &queued)) {
+                built_crates += 1;
+            }
+        }
+
+        Ok(built_crates)
+    }
+
+
+    /// Builds crates in queue with workers of DocBuilder in parallel and
+    /// returns number of built crates. Every worker gets a new database
+    /// connection from connect.
+    pub fn build_packages_queue_parallel<F>(&self, connect: F) -> Result<usize, DocBuilderError>
+        where F: Fn() -> Result<postgres::Connection, postgres::error::ConnectError>,
+              F: Send + Sync + 'static
+    {
+        // toolchain may be updated between two runs of daemon
+        self.clear_versions();
+
+        if self.workers <= 1 {
+            let conn = try!(connect().map_err(DocBuilderError::DatabaseConnectError));
+            return self.build_packages_queue(&conn);
+        }
+
+        let connect = Arc::new(connect);
+        // ids of crates being built by workers
+        let in_progress = Arc::new(Mutex::new(HashSet::new()));
+        let built_crates = Arc::new(AtomicUsize::new(0));
+        let failed_crates = Arc::new(AtomicUsize::new(0));
+
+        let workers: Vec<_> = (0..self.workers).map(|worker| {
+            // every worker has its own work directory
+            let mut docbuilder = self.clone();
+            docbuilder.build_dir.push(format!("worker-{}", worker));
+
+            let connect = connect.clone();
+            let in_progress = in_progress.clone();
+            let built_crates = built_crates.clone();
+            let failed_crates = failed_crates.clone();
+
+            thread::spawn(move || {
+                let _log_context = logging::scope(|ctx| ctx.worker = Some(worker));
+                let res = connect()
+                    .map_err(DocBuilderError::DatabaseConnectError)
+                    .and_then(|conn| {
+                        docbuilder.run_queue_worker(&conn, &in_progress,
+                                                    &built_crates, &failed_crates)
+                    });
+                if let Err(e) = res {
+                    error!("Worker stopped: {}", e);
+                }
+            })
+        }).collect();
+
+        for worker in workers {
+            if worker.join().is_err() {
+                error!("A build worker panicked");
             }
         }
 
+        Ok(built_crates.load(Ordering::SeqCst))
+    }
+
+
+    /// Builds crates from queue until queue is empty
+    fn run_queue_worker(&self,
+                        conn: &postgres::Connection,
+                        in_progress: &Mutex<HashSet<i32>>,
+                        built_crates: &AtomicUsize,
+                        failed_crates: &AtomicUsize)
+                        -> Result<(), DocBuilderError> {
+        try!(fs::create_dir_all(&self.build_dir).map_err(DocBuilderError::RemoveBuildDir));
+        try!(self.remove_work_dirs().map_err(DocBuilderError::RemoveBuildDir));
+
+        while !shutdown_requested() {
+            // claim next crate which isn't being built by another worker
+            let queued = {
+                let mut in_progress = in_progress.lock().unwrap();
+                let ids: Vec<i32> = in_progress.iter().cloned().collect();
+                match try!(queue::next_crate_except(conn, &ids)
+                           .map_err(DocBuilderError::DatabaseError)) {
+                    Some(queued) => {
+                        in_progress.insert(queued.id);
+                        queued
+                    }
+                    None => return Ok(()),
+                }
+            };
+
+            info!("Building {}-{}", queued.name, queued.version);
+            let res = self.build_queued_crate(conn, &queued);
+            in_progress.lock().unwrap().remove(&queued.id);
+
+            if try!(res) {
+                built_crates.fetch_add(1, Ordering::SeqCst);
+            } else {
+                failed_crates.fetch_add(1, Ordering::SeqCst);
+            }
+            info!("Finished {}-{}, {} crates built and {} failed so far",
+                  queued.name,
+                  queued.version,
+                  built_crates.load(Ordering::SeqCst),
+                  failed_crates.load(Ordering::SeqCst));
+        }
+
         Ok(())
     }
 
 
+    /// Builds a crate from queue. Crate is removed from queue and true is
+    /// returned if build is successful, otherwise attempt count of crate is
+    /// increased and crate is rescheduled.
+    fn build_queued_crate(&self,
+                          conn: &postgres::Connection,
+                          queued: &queue::QueuedCrate)
+                          -> Result<bool, DocBuilderError> {
+        let _log_context = logging::scope(|ctx| {
+            ctx.queue_id = Some(queued.id);
+            ctx.crate_version(&queued.name, &queued.version);
+        });
+
+        // crate doesn't exist in crates.io-index, there is no point to try
+        // it again
+        let res = self.crate_from_index(&queued.name, &queued.version)
+            .and_then(|(crte, version_index)| {
+                self.build_doc_for_crate_version(&crte, version_index, Some(conn))
+            });
+
+        match res {
+            Ok(_) |
+            Err(DocBuilderError::SkipDocumentationExists) |
+            Err(DocBuilderError::SkipLogFileExists) => {
+                try!(queue::remove_crate_from_queue(conn, queued.id)
+                     .map_err(DocBuilderError::DatabaseError));
+                Ok(true)
+            }
+            // build killed by a shutdown signal isn't counted as an attempt
+            Err(DocBuilderError::Interrupted) => {
+                info!("Build of {}-{} interrupted, it's kept in queue",
+                      queued.name, queued.version);
+                Ok(false)
+            }
+            // blacklisted crates are never built and crates missing from
+            // index can't be built, no need to keep them in queue
+            Err(e @ DocBuilderError::CrateNotInIndex) => {
+                try!(queue::remove_crate_from_queue(conn, queued.id)
+                     .map_err(DocBuilderError::DatabaseError));
+                warn!("Dropped {}-{} from queue: {}", queued.name, queued.version, e);
+                Ok(false)
+            }
+            Err(e @ DocBuilderError::CrateBlacklisted(_)) => {
+                try!(queue::remove_crate_from_queue(conn, queued.id)
+                     .map_err(DocBuilderError::DatabaseError));
+                warn!("{}-{}: {}", queued.name, queued.version, e);
+                Ok(false)
+            }
+            Err(e) => {
+                let attempt = try!(queue::increase_attempt(conn, queued.id, self.max_attempts)
+                                   .map_err(DocBuilderError::DatabaseError));
+                error!("Failed to build docs for crate {}-{} (attempt {} of {}): {:?}",
+                       queued.name, queued.version, attempt, self.max_attempts, e);
+                if attempt >= self.max_attempts {
+                    warn!("Giving up on {}-{}", queued.name, queued.version);
+                } else {
+                    info!("Retrying {}-{} in {} seconds",
+                          queued.name, queued.version, queue::retry_delay(attempt));
+                }
+                Ok(false)
+            }
+        }
+    }
+
+
+    /// Deletes a crate completely: documentation, sources, build logs and
+    /// database rows of crate. Files are removed even if crate isn't in
+    /// database. Returns false if crate doesn't exist in database.
+    pub fn delete_crate(&self,
+                        conn: &postgres::Connection,
+                        name: &str) -> Result<bool, DocBuilderError> {
+        // an empty name would delete every crate
+        if name.is_empty() || name.contains('/') || name.starts_with('.') {
+            return Ok(false);
+        }
+
+        let found = try!(db::delete_crate(conn, name).map_err(DocBuilderError::DatabaseError));
+
+        try!(self.doc_storage().delete_prefix(name));
+        for path in &[self.sources_path.join(name), self.logs_path.join(name)] {
+            if path.is_dir() {
+                try!(fs::remove_dir_all(path).map_err(DocBuilderError::RemoveOldDoc));
+            }
+        }
+
+        Ok(found)
+    }
+
+
+    /// Updates yanked status of releases from crates.io-index. Returns number
+    /// of changed releases. Documentation of releases yanked longer than
+    /// grace period is removed if it's enabled with remove_yanked_docs.
+    ///
+    /// crates.io-index must be updated before calling this function.
+    pub fn update_yanked(&self, conn: &postgres::Connection) -> Result<usize, DocBuilderError> {
+        let index = self.index();
+        let crates = try!(conn.query("SELECT id, name FROM crates", &[])
+                          .map_err(DocBuilderError::DatabaseError));
+        let mut changed = 0;
+
+        for row in &crates {
+            let crate_id: i32 = row.get(0);
+            let name: String = row.get(1);
+
+            let versions = match index.yanked_versions(&name) {
+                Ok(versions) => versions,
+                Err(e) => {
+                    warn!("Failed to read {} from crates.io-index: {}", name, e);
+                    continue;
+                }
+            };
+
+            let releases = try!(conn.query("SELECT id, version, yanked FROM releases \
+                                            WHERE crate_id = $1",
+                                           &[&crate_id])
+                                .map_err(DocBuilderError::DatabaseError));
+
+            for release in &releases {
+                let release_id: i32 = release.get(0);
+                let version: String = release.get(1);
+                let yanked: Option<bool> = release.get(2);
+
+                let index_yanked = versions.iter()
+                    .find(|&&(ref v, _)| *v == version)
+                    .map(|&(_, y)| y);
+
+                if let Some(index_yanked) = index_yanked {
+                    if yanked != Some(index_yanked) {
+                        try!(conn.execute("UPDATE releases \
+                                           SET yanked = $2, \
+                                               yanked_at = CASE WHEN $2 \
+                                                           THEN CURRENT_TIMESTAMP END \
+                                           WHERE id = $1",
+                                          &[&release_id, &index_yanked])
+                             .map_err(DocBuilderError::DatabaseError));
+                        changed += 1;
+                    }
+                }
+            }
+        }
+
+        if let Some(grace_period) = self.yanked_docs_grace_period {
+            let removed = try!(self.remove_yanked_docs_older_than(conn, grace_period));
+            info!("Documentation of {} yanked releases removed", removed);
+        }
+
+        Ok(changed)
+    }
+
+
+    /// Removes documentation of releases yanked longer than grace_period.
+    /// Releases are kept in database with rustdoc_status 0. Returns number of
+    /// releases whose documentation is removed.
+    fn remove_yanked_docs_older_than(&self,
+                                     conn: &postgres::Connection,
+                                     grace_period: Duration)
+                                     -> Result<usize, DocBuilderError> {
+        let rows = try!(conn.query("SELECT releases.id, crates.name, releases.version \
+                                    FROM releases \
+                                    INNER JOIN crates ON crates.id = releases.crate_id \
+                                    WHERE releases.yanked AND \
+                                          releases.rustdoc_status = 1 AND \
+                                          releases.yanked_at < CURRENT_TIMESTAMP - \
+                                              $1::float8 * INTERVAL '1 second'",
+                                   &[&(grace_period.as_secs() as f64)])
+                        .map_err(DocBuilderError::DatabaseError));
+
+        let storage = self.doc_storage();
+        for row in &rows {
+            let release_id: i32 = row.get(0);
+            let name: String = row.get(1);
+            let version: String = row.get(2);
+
+            info!("Removing documentation of yanked release {}-{}", name, version);
+            try!(storage.delete_prefix(&format!("{}/{}", name, version)));
+            try!(conn.execute("UPDATE releases SET rustdoc_status = 0 WHERE id = $1",
+                              &[&release_id])
+                 .map_err(DocBuilderError::DatabaseError));
+        }
+
+        Ok(rows.len())
+    }
+
+
+    /// Refreshes owners of every crate in database from crates.io
+    pub fn update_owners(&self,
+                         conn: &postgres::Connection) -> Result<usize, CrateOpenError> {
+        owners::update_all_owners(conn, &self.cratesio)
+    }
+
+
     /// Builds documentation for crate
     ///
-    /// This function will try to build documentation for every version of crate
-    pub fn build_doc_for_crate(&self, crte: &crte::Crate) {
+    /// This function will try to build documentation for every version of
+    /// crate, or only latest version if build_only_latest_version is set.
+    /// Returns false if build of a version failed, skipped versions are not
+    /// failures.
+    fn build_doc_for_crate(&self,
+                           crte: &crte::Crate,
+                           conn: Option<&postgres::Connection>) -> bool {
+        if self.build_only_latest_version {
+            match crte.latest_version() {
+                Some(i) => {
+                    let _log_context = logging::scope(|ctx| {
+                        ctx.crate_version(&crte.name, &crte.versions[i]);
+                    });
+                    if let Err(e) = self.build_doc_for_crate_version(crte, i, conn) {
+                        log_build_error(&crte.name, &crte.versions[i], &e);
+                        return is_skipped(&e);
+                    }
+                }
+                None => info!("{} doesn't have a stable version which isn't yanked", crte.name),
+            }
+            return true;
+        }
+
+        let mut successful = true;
         for i in 0..crte.versions.len() {
-            if let Err(e) = self.build_doc_for_crate_version(crte, i) {
-                println!("Failed to build docs for crate {}-{}: {:#?}",
-                         &crte.name, &crte.versions[i], e);
+            let _log_context = logging::scope(|ctx| {
+                ctx.crate_version(&crte.name, &crte.versions[i]);
+            });
+            if let Err(e) = self.build_doc_for_crate_version(crte, i, conn) {
+                log_build_error(&crte.name, &crte.versions[i], &e);
+                successful &= is_skipped(&e);
+
+                // every version of a blacklisted crate is blacklisted
+                match e {
+                    DocBuilderError::CrateBlacklisted(_) |
+                    DocBuilderError::Interrupted => break,
+                    _ => {}
+                }
 
                 // Skip oldest versions if its set
                 if self.skip_oldest_versions {
                     match e {
                         DocBuilderError::SkipDocumentationExists |
-                            DocBuilderError::SkipLogFileExists => {},
+                            DocBuilderError::SkipLogFileExists |
+                            DocBuilderError::BuildTimedOut => {},
                         _ => {
-                            println!("Skipping building oldest versions of {}", crte.name);
+                            info!("Skipping building oldest versions of {}", crte.name);
                             break
                         }
                     }
                 }
             }
-
-            // if self.build_only_latest_version is true
-            // we are skipping oldest versions of crate
-            if self.build_only_latest_version {
-                break;
-            }
         }
+        successful
+    }
+
+
+    /// Returns path of build log of a crate
+    fn log_path_for_crate(&self, crte: &crte::Crate, version_index: usize) -> PathBuf {
+        self.logs_path
+            .join(&crte.name)
+            .join(format!("{}-{}.log", &crte.name, &crte.versions[version_index]))
     }
 
 
     fn open_log_for_crate(&self,
                           crte: &crte::Crate,
                           version_index: usize) -> Result<fs::File, DocBuilderError> {
-        let mut log_path = PathBuf::from(&self.logs_path);
-        log_path.push(&crte.name);
+        let log_path = self.log_path_for_crate(crte, version_index);
 
-        if !log_path.exists() {
-            try!(fs::create_dir_all(&log_path).map_err(DocBuilderError::LogFileError));
+        if let Some(parent) = log_path.parent() {
+            if !parent.exists() {
+                try!(fs::create_dir_all(parent).map_err(DocBuilderError::LogFileError));
+            }
         }
 
-        log_path.push(format!("{}-{}.log",
-                              &crte.name,
-                              &crte.versions[version_index]));
-
         if self.skip_if_log_exists && log_path.exists() {
             return Err(DocBuilderError::SkipLogFileExists);
         }
@@ -412,6 +1732,24 @@ impl DocBuilder {
 
 
 
+    /// Removes work directories left behind by interrupted builds
+    fn remove_work_dirs(&self) -> io::Result<()> {
+        if !self.build_dir.is_dir() {
+            return Ok(());
+        }
+        for entry in try!(self.build_dir.read_dir()) {
+            let entry = try!(entry);
+            if entry.file_name().to_string_lossy().starts_with(WORK_DIR_PREFIX) &&
+               try!(entry.file_type()).is_dir() {
+                info!("Removing work directory of an interrupted build: {}",
+                      entry.path().display());
+                try!(fs::remove_dir_all(entry.path()));
+            }
+        }
+        Ok(())
+    }
+
+
     /// Returns package folder inside build directory
     fn crate_root_dir(&self, crte: &crte::Crate, version_index: usize) -> PathBuf {
         let mut package_root = PathBuf::from(&self.build_dir);
@@ -423,13 +1761,8 @@ impl DocBuilder {
     fn remove_old_doc(&self,
                       crte: &crte::Crate,
                       version_index: usize) -> Result<(), DocBuilderError> {
-        let mut path = PathBuf::from(&self.destination);
-        path.push(format!("{}/{}", crte.name, crte.versions[version_index]));
-
-        if path.exists() {
-            try!(fs::remove_dir_all(path).map_err(DocBuilderError::RemoveOldDoc));
-        }
-
+        let prefix = format!("{}/{}", crte.name, crte.versions[version_index]);
+        try!(self.doc_storage().delete_prefix(&prefix));
         Ok(())
     }
 
@@ -468,13 +1801,29 @@ impl DocBuilder {
             return Ok(())
         }
 
-        let mut destination = PathBuf::from(&self.destination);
-        destination.push(format!("{}/{}", &crte.name, &crte.versions[version_index]));
-        if destination.exists() {
-            return Err(DocBuilderError::SkipDocumentationExists)
+        let prefix = format!("{}/{}", &crte.name, &crte.versions[version_index]);
+        if try!(self.doc_storage().exists(&prefix)) {
+            return Err(DocBuilderError::SkipDocumentationExists)
+        }
+
+        Ok(())
+    }
+
+
+    /// Returns Err(DocBuilderError::CrateBlacklisted) if crate is in blacklist.
+    /// Blacklist is only checked when a database connection is available.
+    fn is_crate_blacklisted(&self,
+                            crte: &crte::Crate,
+                            conn: Option<&postgres::Connection>) -> Result<(), DocBuilderError> {
+        let conn = match conn {
+            Some(conn) => conn,
+            None => return Ok(()),
+        };
+
+        match try!(blacklist::blocked_reason(conn, &crte.name)) {
+            Some(reason) => Err(DocBuilderError::CrateBlacklisted(reason)),
+            None => Ok(()),
         }
-
-        Ok(())
     }
 
 
@@ -533,23 +1882,277 @@ impl DocBuilder {
     ///
     /// This operation involves following process:
     ///
-    /// * Cleaning up build directory
+    /// * Creating a work directory for this build in build directory
     /// * Downloading crate
-    /// * Extracting it into build directory (chroot dir home directory)
+    /// * Extracting it into work directory (chroot dir home directory)
     /// * Building crate documentation with chroot
     /// * Checking build directory for if crate actually has any documentation
-    /// * Copying crate documentation into destination path
-    /// * Cleaning up build directory
-    /// * Removing downloaded crate file
-    pub fn build_doc_for_crate_version(&self,
-                                       crte: &crte::Crate,
-                                       version_index: usize) -> Result<(), DocBuilderError> {
+    /// * Copying crate documentation into destination path, binary crates
+    ///   don't have any library documentation to copy
+    /// * Removing work directory
+    fn build_doc_for_crate_version(&self,
+                                   crte: &crte::Crate,
+                                   version_index: usize,
+                                   conn: Option<&postgres::Connection>)
+                                   -> Result<(), DocBuilderError> {
+        self.build_release(crte, version_index, conn).and_then(|(_, res)| res)
+    }
+
+
+    /// Builds documentation of a release, this is the entry point for tools
+    /// embedding DocBuilder. Database is only used if conn is given, it's
+    /// used like build subcommand uses it: blacklist, build overrides and
+    /// active toolchain are checked and build is recorded. Documentation is
+    /// stored in storage of DocBuilder, or in destination if it doesn't
+    /// have one.
+    ///
+    /// Release is loaded from crates.io-index to verify checksum of crate,
+    /// CrateNotInIndex is returned if it's not in index. Err is returned if
+    /// build couldn't be attempted, i.e: documentation already exists.
+    /// Failure of an attempted build is in BuildResult.
+    pub fn build(&self,
+                 name: &str,
+                 version: &str,
+                 conn: Option<&postgres::Connection>)
+                 -> Result<BuildResult, DocBuilderError> {
+        let (crte, version_index) = try!(self.crate_from_index(name, version));
+        let (build, res) = try!(self.build_release(&crte, version_index, conn));
+        Ok(BuildResult {
+            build: build,
+            error: res.err(),
+        })
+    }
+
+
+    /// Returns latest stable version of a crate in crates.io-index, or its
+    /// first version if it doesn't have a stable version
+    pub fn latest_version(&self, name: &str) -> Result<String, DocBuilderError> {
+        let crte = try!(crte::Crate::from_cargo_index_path(name, &self.crates_io_index_path)
+                        .map_err(|_| DocBuilderError::CrateNotInIndex));
+        let latest = crte.latest_version().unwrap_or(0);
+        crte.versions.get(latest).cloned().ok_or(DocBuilderError::CrateNotInIndex)
+    }
+
+
+    /// Builds documentation of a release in dir, this is what build-doc
+    /// subcommand runs inside chroot. Checksum is taken from crates.io-index
+    /// if release is in it.
+    #[doc(hidden)]
+    pub fn build_crate_doc_in_dir(&self,
+                                  name: &str,
+                                  version: &str,
+                                  dir: &Path)
+                                  -> Result<(), DocBuilderError> {
+        let (crte, version_index) = self.crate_from_index(name, version)
+            .unwrap_or_else(|_| (crte::Crate::new(name.to_string(), vec![version.to_string()]), 0));
+        crte.build_crate_doc(version_index, dir, self)
+    }
+
+
+    /// Runs tests of a release built with build_crate_doc_in_dir in dir,
+    /// this is what test-crate subcommand runs inside chroot
+    #[doc(hidden)]
+    pub fn test_crate_in_dir(&self,
+                             name: &str,
+                             version: &str,
+                             dir: &Path,
+                             mode: builds::TestMode)
+                             -> Result<String, CommandFailure> {
+        let crte = crte::Crate::new(name.to_string(), vec![version.to_string()]);
+        crte.run_tests(0, dir, self, mode)
+    }
+
+
+    /// Loads a crate from crates.io-index and returns it with index of
+    /// version, index has checksums and yanked status of versions
+    fn crate_from_index(&self,
+                        name: &str,
+                        version: &str)
+                        -> Result<(crte::Crate, usize), DocBuilderError> {
+        let crte = try!(crte::Crate::from_cargo_index_path(name, &self.crates_io_index_path)
+                        .map_err(|_| DocBuilderError::CrateNotInIndex));
+        let version_index = try!(crte.get_version_index(version)
+                                 .ok_or(DocBuilderError::CrateNotInIndex));
+        Ok((crte, version_index))
+    }
+
+
+    /// Builds a release in its work directory and returns build attempt and
+    /// result of build
+    fn build_release(&self,
+                     crte: &crte::Crate,
+                     version_index: usize,
+                     conn: Option<&postgres::Connection>)
+                     -> Result<(builds::Build, Result<(), DocBuilderError>), DocBuilderError> {
+        let _log_context = logging::scope(|ctx| {
+            ctx.crate_version(&crte.name, &crte.versions[version_index]);
+        });
+
+        if shutdown_requested() {
+            return Err(DocBuilderError::Interrupted);
+        }
         try!(self.is_crate_doc_exists(&crte, version_index));
+        try!(self.is_crate_blacklisted(&crte, conn));
+
+        // every build has its own work directory, an interrupted build can't
+        // leave a half extracted crate behind for next build
+        let work_dir = self.build_dir.join(format!("{}{}",
+                                                   WORK_DIR_PREFIX,
+                                                   unique_name(crte, version_index)));
+        try!(fs::create_dir_all(&work_dir).map_err(DocBuilderError::RemoveBuildDir));
+        let mut docbuilder = self.clone();
+        docbuilder.build_dir = work_dir.clone();
+        if let Some(conn) = conn {
+            match toolchain::get_active_toolchain(conn) {
+                Ok(Some(toolchain)) => docbuilder.toolchain = Some(toolchain),
+                Ok(None) => {}
+                Err(e) => warn!("Failed to get active toolchain: {}", e),
+            }
+            match overrides::get_override(conn, &crte.name) {
+                Ok(Some(build_override)) => {
+                    info!("Using build override of {}: {:?}", crte.name, build_override);
+                    docbuilder.apply_build_override(&crte.name, &build_override);
+                }
+                Ok(None) => {}
+                Err(e) => warn!("Failed to get build override of {}: {}", crte.name, e),
+            }
+        }
+
+        let res = docbuilder.build_doc_in_work_dir(crte, version_index, conn);
+
+        if !self.keep_build_directory {
+            if let Err(e) = fs::remove_dir_all(&work_dir) {
+                warn!("Failed to remove work directory {}: {}", work_dir.display(), e);
+            }
+        }
+
+        res
+    }
+
+
+    /// Builds documentation of crate at root of a git repository, see git
+    /// module. rev can be a branch, tag or commit, default branch is used if
+    /// it's None. Returns storage prefix of documentation.
+    pub fn build_doc_for_git_repository(&self,
+                                        url: &str,
+                                        rev: Option<&str>)
+                                        -> Result<String, DocBuilderError> {
+        self.in_work_dir("git", |docbuilder| docbuilder.build_git_repository_in_work_dir(url, rev))
+    }
+
+
+    /// Builds documentation of a local crate like a release, see local
+    /// module. Returns output of build, it's Err if build failed.
+    pub fn build_doc_for_local_crate(&self,
+                                     path: &Path)
+                                     -> Result<Result<String, CommandFailure>, DocBuilderError> {
+        self.in_work_dir("local", |docbuilder| {
+            let (name, version) = try!(git::package_name_version(path)
+                                       .ok_or(DocBuilderError::LocalCrateNotACrate));
+            let crte = crte::Crate::new(name, vec![version]);
+            try!(local::copy_crate(path, &docbuilder.crate_root_dir(&crte, 0))
+                 .map_err(DocBuilderError::CopyLocalCrateError));
+
+            info!("Building documentation for local crate {}", crte.canonical_name(0));
+            Ok(crte.build_doc(0, &docbuilder.build_dir, docbuilder))
+        })
+    }
+
+
+    /// Returns ReleaseInfo of a release without building it, see
+    /// crte::Crate::release_info. Release is loaded from crates.io-index
+    /// like in build.
+    pub fn release_info(&self,
+                        name: &str,
+                        version: &str)
+                        -> Result<ReleaseInfo, CrateOpenError> {
+        let (crte, version_index) = try!(self.crate_from_index(name, version)
+                                         .map_err(CrateOpenError::DocBuilderError));
+        self.in_work_dir("info", |docbuilder| Ok(crte.release_info(version_index, docbuilder)))
+            .map_err(CrateOpenError::DocBuilderError)
+            .and_then(|info| info)
+    }
+
+
+    /// Runs f with a DocBuilder building in a new work directory, work
+    /// directory is removed afterwards unless keep_build_directory is set
+    fn in_work_dir<T, F>(&self, kind: &str, f: F) -> Result<T, DocBuilderError>
+        where F: FnOnce(&DocBuilder) -> Result<T, DocBuilderError>
+    {
+        let work_dir = self.build_dir.join(format!("{}{}-{}",
+                                                   WORK_DIR_PREFIX,
+                                                   kind,
+                                                   time::precise_time_ns()));
+        try!(fs::create_dir_all(&work_dir).map_err(DocBuilderError::RemoveBuildDir));
+        let mut docbuilder = self.clone();
+        docbuilder.build_dir = work_dir.clone();
+
+        let res = f(&docbuilder);
+
+        if !self.keep_build_directory {
+            if let Err(e) = fs::remove_dir_all(&work_dir) {
+                warn!("Failed to remove work directory {}: {}", work_dir.display(), e);
+            }
+        }
+
+        res
+    }
+
+
+    fn build_git_repository_in_work_dir(&self,
+                                        url: &str,
+                                        rev: Option<&str>)
+                                        -> Result<String, DocBuilderError> {
+        info!("Cloning {}", url);
+        let clone_dir = self.build_dir.join("repository");
+        let commit = try!(git::clone_repository(url, rev, &clone_dir)
+                          .map_err(DocBuilderError::CloneRepositoryError));
+        let (name, version) = try!(git::package_name_version(&clone_dir)
+                                   .ok_or(DocBuilderError::GitRepositoryNotACrate));
+
+        // crate is built in a directory named like an extracted crate
+        let crte = crte::Crate::new(name, vec![version]);
+        try!(fs::rename(&clone_dir, self.crate_root_dir(&crte, 0))
+             .map_err(DocBuilderError::RemoveBuildDir));
+
+        let (rustc_version, _, _) = try!(self.get_versions()
+                                         .map_err(DocBuilderError::RustcNotFoundError));
+
+        info!("Building documentation for {} at {}", crte.canonical_name(0), commit);
+        let res = crte.build_doc(0, &self.build_dir, self);
+        info!("cargo doc --no-deps --verbose\n{}", match res {
+            Ok(ref m) => &m[..],
+            Err(ref f) => f.message(),
+        });
+        match res {
+            Ok(_) => {}
+            Err(CommandFailure::TimedOut(_)) => return Err(DocBuilderError::BuildTimedOut),
+            Err(CommandFailure::Failed(_)) => return Err(DocBuilderError::FailedToBuildCrate),
+        }
+
+        let rustc_version = try!(parse_rustc_version(&rustc_version));
+        let shared_prefix = format!("{}/{}", SHARED_STATIC_DIR, rustc_version);
+        let prefix = git::doc_prefix(&crte.name, &commit);
+        let storage = self.doc_storage();
+        try!(storage.delete_prefix(&prefix));
+        try!(self.store_doc(&*storage, &*storage, &prefix, &crte, 0, &shared_prefix));
+
+        Ok(prefix)
+    }
+
 
+    /// Builds documentation of a crate in build_dir of DocBuilder, it's a
+    /// work directory created by build_release
+    fn build_doc_in_work_dir(&self,
+                             crte: &crte::Crate,
+                             version_index: usize,
+                             conn: Option<&postgres::Connection>)
+                             -> Result<(builds::Build, Result<(), DocBuilderError>),
+                                       DocBuilderError> {
         // TODO try to replace noob style logging
         let mut log_file = try!(self.open_log_for_crate(&crte, version_index));
 
-        println!("Building documentation for {}-{}", crte.name, crte.versions[version_index]);
+        info!("Building documentation for {}-{}", crte.name, crte.versions[version_index]);
 
         let (rustc_version, cargo_version, cratesfyi_version) =
             try!(self.get_versions().map_err(DocBuilderError::RustcNotFoundError));
@@ -559,35 +2162,263 @@ impl DocBuilder {
              .map_err(DocBuilderError::LogFileError));
 
         // build docs
-        let (status, message) = match self.build_doc_in_chroot(&crte, version_index) {
-            Ok(m) => (true, m),
-            Err(m) => (false, m),
+        logging::set_phase("build");
+        let build_start = Instant::now();
+        let res = if self.sandbox.is_some() {
+            self.build_doc_in_sandbox(&crte, version_index)
+        } else {
+            self.build_doc_in_chroot(&crte, version_index)
         };
-        try!(write!(log_file, "{}", message)
-             .map_err(DocBuilderError::LogFileError));
+        let build_time = build_start.elapsed();
+
+        // build process most likely received same signal, a failed build
+        // isn't recorded and it's tried again after restart
+        if res.is_err() && shutdown_requested() {
+            drop(log_file);
+            let _ = fs::remove_file(self.log_path_for_crate(crte, version_index));
+            return Err(DocBuilderError::Interrupted);
+        }
+
+        try!(write!(log_file, "{}", match res {
+                Ok(ref m) => &m[..],
+                Err(ref f) => f.message(),
+            })
+            .map_err(DocBuilderError::LogFileError));
+
+        let mut build = builds::Build {
+            rustc_version: rustc_version.trim().to_string(),
+            toolchain: self.toolchain.clone(),
+            cratesfyi_version: cratesfyi_version.trim().to_string(),
+            output: match res {
+                Ok(ref m) => m.clone(),
+                Err(ref f) => f.message().to_string(),
+            },
+            successful: res.is_ok(),
+            timed_out: match res {
+                Err(CommandFailure::TimedOut(_)) => true,
+                _ => false,
+            },
+            download_failed: match res {
+                Err(CommandFailure::Failed(ref m)) => m.contains(DOWNLOAD_FAILED_LOG_MARKER),
+                _ => false,
+            },
+            download_url: match res {
+                Ok(ref m) => download::downloaded_from(m),
+                Err(ref f) => download::downloaded_from(f.message()),
+            }.map(|url| url.to_string()),
+            build_time_ms: (build_time.as_secs() * 1000 +
+                            build_time.subsec_nanos() as u64 / 1_000_000) as i64,
+            doc_size_bytes: None,
+            source_size_bytes: None,
+            doc_coverage: None,
+            test_status: 0,
+        };
+
+        // run tests if documentation is built
+        if let (Ok(_), Some(mode)) = (res.as_ref(), self.test_mode) {
+            logging::set_phase("test");
+            let test_res = if self.sandbox.is_some() {
+                crte.run_tests(version_index, &self.build_dir, self, mode)
+            } else {
+                self.test_crate_in_chroot(&crte, version_index, mode)
+            };
+            let test_output = match test_res {
+                Ok(ref m) => &m[..],
+                Err(ref f) => f.message(),
+            };
+            try!(write!(log_file, "\n{}", test_output).map_err(DocBuilderError::LogFileError));
+            build.output.push_str(&format!("\n{}", test_output));
+            build.test_status = builds::test_status(test_res.is_ok(), match test_res {
+                Err(CommandFailure::TimedOut(_)) => true,
+                _ => false,
+            });
+        }
+
+        let res = match res {
+            Ok(_) if !self.is_library(&crte, version_index) => {
+                // binaries don't have library documentation to copy
+                Ok(())
+            }
+            Ok(_) => {
+                // copy docs
+                logging::set_phase("copy-doc");
+                self.copy_doc(&crte, version_index, rustc_version).map(|size| {
+                    build.doc_size_bytes = Some(size as i64);
+                    build.doc_coverage = self.doc_coverage(&crte, version_index);
+                })
+            }
+            Err(CommandFailure::TimedOut(_)) => {
+                try!(writeln!(log_file, "\n{}", BUILD_TIMED_OUT_LOG_MARKER)
+                     .map_err(DocBuilderError::LogFileError));
+                Err(DocBuilderError::BuildTimedOut)
+            }
+            Err(CommandFailure::Failed(_)) if build.download_failed => {
+                Err(DocBuilderError::CrateDownloadFailed)
+            }
+            Err(CommandFailure::Failed(ref m)) if extract::crate_too_large_reason(m).is_some() => {
+                let reason = extract::crate_too_large_reason(m).unwrap_or("");
+                Err(DocBuilderError::CrateTooLarge(reason.to_string()))
+            }
+            Err(CommandFailure::Failed(_)) => Err(DocBuilderError::FailedToBuildCrate),
+        };
+        // copying documentation can fail after a successful build
+        build.successful = res.is_ok();
+
+        // sources are stored before release is added into database, crate
+        // info is read from sources_path if it's available
+        if res.is_ok() {
+            logging::set_phase("store-sources");
+            match self.store_sources(crte, version_index) {
+                Ok(path) => build.source_size_bytes = dir_size(&path).ok().map(|s| s as i64),
+                Err(e) => {
+                    warn!("Failed to store sources of {}: {}",
+                          crte.canonical_name(version_index), e);
+                }
+            }
+        }
+
+        // record build attempt into database
+        if let Some(conn) = conn {
+            logging::set_phase("database");
+            if let Err(e) = self.add_build_into_database(conn, crte, version_index, &build) {
+                warn!("Failed to add build of {} into database: {:?}",
+                      crte.canonical_name(version_index), e);
+            }
+        }
+
+        Ok((build, res))
+    }
 
-        if status {
-            // copy docs
-            try!(self.copy_doc(&crte, version_index, rustc_version));
-            Ok(())
+
+    /// Keeps pristine sources of a release in `sources_path/<CRATE>/<VERSION>`
+    /// and returns this path. Crate file in work directory is extracted
+    /// again, build modifies extracted crate.
+    fn store_sources(&self,
+                     crte: &crte::Crate,
+                     version_index: usize) -> Result<PathBuf, DocBuilderError> {
+        let destination = self.sources_path
+            .join(&crte.name)
+            .join(&crte.versions[version_index]);
+        if destination.exists() {
+            return Ok(destination);
+        }
+
+        // sources are extracted into sources_path first, rename can't move
+        // them from another filesystem
+        let staging_dir = self.sources_path
+            .join(STAGING_DIR)
+            .join(unique_name(crte, version_index));
+        let res = extract::extract_crate_file(&crte.crate_file(version_index, &self.build_dir),
+                                              &staging_dir,
+                                              &self.extract_limits)
+            .and_then(|root| {
+                try!(fs::create_dir_all(&self.sources_path.join(&crte.name))
+                     .map_err(DocBuilderError::StoreSourcesError));
+                fs::rename(&root, &destination).map_err(DocBuilderError::StoreSourcesError)
+            });
+        let _ = fs::remove_dir_all(&staging_dir);
+        res.map(|_| destination)
+    }
+
+
+    /// Adds release of a crate and its build attempt into database
+    fn add_build_into_database(&self,
+                               conn: &postgres::Connection,
+                               crte: &crte::Crate,
+                               version_index: usize,
+                               build: &builds::Build) -> Result<(), crte::CrateOpenError> {
+        let release_id = try!(crte.add_crate_into_database(version_index, conn, &self));
+        // status of previous build is needed to detect regressions
+        let previous_status = if build.successful {
+            0
         } else {
-            Err(DocBuilderError::FailedToBuildCrate)
+            try!(builds::crate_build_status(conn, &crte.name))
+        };
+        try!(builds::add_build_into_database(conn, release_id, build));
+
+        if !build.successful {
+            let notification = Notification {
+                event: if previous_status == 1 {
+                    BuildEvent::Regressed
+                } else {
+                    BuildEvent::Failed
+                },
+                name: &crte.name,
+                version: &crte.versions[version_index],
+                build_status: build.build_status(),
+                rustc_version: &build.rustc_version,
+                output: &build.output,
+            };
+            if let Err(e) = self.notifier.notify(&notification) {
+                warn!("Failed to send notification of {}: {}",
+                      crte.canonical_name(version_index), e);
+            }
         }
+        Ok(())
     }
 
 
-    /// This function will get rustc and cargo versions
+    /// This function will get rustc, cargo and cratesfyi versions. Versions
+    /// are only read once for every toolchain and cached until
+    /// clear_versions is called.
     fn get_versions(&self) -> Result<(String, String, String), String> {
+        let mut cached = self.versions.lock().unwrap();
+        if let Some(versions) = cached.get(&self.toolchain) {
+            return Ok(versions.clone());
+        }
+
+        let versions = try!(self.read_versions());
+        cached.insert(self.toolchain.clone(), versions.clone());
+        Ok(versions)
+    }
+
+
+    /// Clears cached versions, versions are read again before next build
+    pub fn clear_versions(&self) {
+        self.versions.lock().unwrap().clear();
+    }
 
+
+    /// Returns rustc version of a toolchain in build environment, i.e. to
+    /// check if toolchain is installed
+    pub fn toolchain_version(&self, toolchain: &str) -> Result<String, String> {
+        let mut docbuilder = self.clone();
+        docbuilder.toolchain = Some(toolchain.to_string());
+        docbuilder.read_versions().map(|(rustc_version, _, _)| rustc_version)
+    }
+
+
+    /// Runs rustc, cargo and cratesfyi in build environment to get their
+    /// versions
+    fn read_versions(&self) -> Result<(String, String, String), String> {
+        let version_args = toolchain::with_toolchain(self.toolchain.as_ref().map(|t| &t[..]),
+                                                     &["--version".to_string()]);
+
+        if let Some(ref sandbox) = self.sandbox {
+            let mut rustc_args = vec!["rustc"];
+            rustc_args.extend(version_args.iter().map(|a| &a[..]));
+            let mut cargo_args = vec!["cargo"];
+            cargo_args.extend(version_args.iter().map(|a| &a[..]));
+            let rustc_version = try!(sandbox.run_without_mount(&rustc_args));
+            let cargo_version = try!(sandbox.run_without_mount(&cargo_args));
+            let cratesfyi_version = format!("cratesfyi {}", ::BUILD_VERSION);
+            return Ok((rustc_version, cargo_version, cratesfyi_version));
+        }
+
+        let version_args = version_args.join(" ");
         let rustc_version = try!(command_result(Command::new("sudo") .arg("chroot")
                                                 .arg(&self.chroot_path)
                                                 .arg("su").arg("-").arg(&self.chroot_user)
-                                                .arg("-c").arg("rustc --version").output()
+                                                .arg("-c")
+                                                .arg(format!("rustc {}", version_args))
+                                                .output()
                                                 .unwrap()));
         let cargo_version = try!(command_result(Command::new("sudo") .arg("chroot")
                                                 .arg(&self.chroot_path)
                                                 .arg("su").arg("-").arg(&self.chroot_user)
-                                                .arg("-c").arg("cargo --version").output()
+                                                .arg("-c")
+                                                .arg(format!("cargo {}", version_args))
+                                                .output()
                                                 .unwrap()));
         let cratesfyi_version = try!(command_result(Command::new("sudo") .arg("chroot")
                                                     .arg(&self.chroot_path)
@@ -598,38 +2429,311 @@ impl DocBuilder {
     }
 
 
-    fn copy_doc(&self, crte: &crte::Crate, version_index: usize, rustc_version: String) -> Result<(), DocBuilderError> {
+    /// Returns false if crate in build directory doesn't have a library
+    /// target
+    fn is_library(&self, crte: &crte::Crate, version_index: usize) -> bool {
+        crte.manifest(version_index, &self.build_dir)
+            .map(|m| m.targets.iter().any(|t| t.is_lib()))
+            .unwrap_or(true)
+    }
+
+
+    /// Returns documentation coverage of library of a crate
+    fn doc_coverage(&self,
+                    crte: &crte::Crate,
+                    version_index: usize) -> Option<coverage::DocCoverage> {
+        // documentation of dependencies is also in doc directory, only
+        // library of crate is checked
+        let lib_name = crte.manifest(version_index, &self.build_dir).ok()
+            .and_then(|m| m.targets.into_iter().find(|t| t.is_lib()))
+            .map(|t| t.name)
+            .unwrap_or(crte.name.clone())
+            .replace("-", "_");
+
+        match coverage::doc_coverage(&self.doc_path(crte, version_index).join(lib_name)) {
+            Ok(coverage) => Some(coverage),
+            Err(e) => {
+                warn!("Failed to get documentation coverage of {}: {}",
+                      crte.canonical_name(version_index), e);
+                None
+            }
+        }
+    }
 
-        // remove old documentation just in case
-        try!(self.remove_old_doc(&crte, version_index));
 
+    /// Returns documentation directory of a crate in build directory
+    fn doc_path(&self, crte: &crte::Crate, version_index: usize) -> PathBuf {
         let mut doc_path = self.crate_root_dir(crte, version_index);
-        doc_path.push("target/doc");
+        doc_path.push("target");
+
+        // documentation is placed into target/<target>/doc if crate
+        // requested a default target
+        let metadata = crte.docs_metadata(&self.crate_root_dir(crte, version_index), self);
+        if let Some(ref target) = metadata.default_target {
+            doc_path.push(target);
+        }
+        doc_path.push("doc");
+        doc_path
+    }
+
 
+    /// Copies documentation into storage and returns size of documentation
+    /// in bytes
+    ///
+    /// Documentation in local destination directory is written into a
+    /// staging directory first and it's moved into place after every file is
+    /// copied, old documentation stays available until then.
+    fn copy_doc(&self, crte: &crte::Crate, version_index: usize, rustc_version: String) -> Result<u64, DocBuilderError> {
         let rustc_version = try!(parse_rustc_version(&rustc_version[..]));
 
-        // copy documentation into destination/crate/version
-        let mut destination = PathBuf::from(&self.destination);
-        destination.push(format!("{}/{}", &crte.name, &crte.versions[version_index]));
-        try!(copy_files_and_handle_html(&doc_path, &destination, true, &rustc_version[..]));
+        let storage = self.doc_storage();
+        let shared_prefix = format!("{}/{}", SHARED_STATIC_DIR, rustc_version);
+        let prefix = format!("{}/{}", &crte.name, &crte.versions[version_index]);
+
+        if self.storage.is_some() {
+            // remove old documentation just in case
+            try!(self.remove_old_doc(&crte, version_index));
+            return self.store_doc(&*storage,
+                                  &*storage,
+                                  &prefix,
+                                  crte,
+                                  version_index,
+                                  &shared_prefix);
+        }
 
-        Ok(())
+        let staging_dir = self.destination.join(STAGING_DIR).join(unique_name(crte, version_index));
+        let release_storage: Arc<Storage> = Arc::new(FileStorage::new(staging_dir.join("doc")));
+        let release_storage = self.precompressed(release_storage);
+        let res = self.store_doc(&*storage,
+                                 &*release_storage,
+                                 "",
+                                 crte,
+                                 version_index,
+                                 &shared_prefix)
+            .and_then(|doc_size| {
+                promote_dir(&staging_dir, &self.destination.join(&prefix))
+                    .map(|_| doc_size)
+                    .map_err(DocBuilderError::CopyDocumentationIoError)
+            });
+        if staging_dir.exists() {
+            let _ = fs::remove_dir_all(&staging_dir);
+        }
+        let doc_size = try!(res);
+
+        // pack documentation of release into an archive
+        if self.archive_docs {
+            try!(ArchiveStorage::new(self.destination.clone())
+                     .pack_release(&crte.name, &crte.versions[version_index]));
+        }
+
+        Ok(doc_size)
+    }
+
+
+    /// Stores documentation of release into release_storage with prefix and
+    /// shared files into storage. Returns size of documentation in bytes.
+    fn store_doc(&self,
+                 storage: &Storage,
+                 release_storage: &Storage,
+                 prefix: &str,
+                 crte: &crte::Crate,
+                 version_index: usize,
+                 shared_prefix: &str)
+                 -> Result<u64, DocBuilderError> {
+        let doc_path = self.doc_path(crte, version_index);
+        let metadata = crte.docs_metadata(&self.crate_root_dir(crte, version_index), self);
+
+        // store documentation into crate/version
+        let shared_files = SharedFiles {
+            names: try!(store_shared_files(storage, &doc_path, shared_prefix)),
+            doc_path: doc_path.clone(),
+            root: format!("../../{}/", shared_prefix),
+        };
+        try!(store_doc_files(release_storage, &doc_path, prefix, &shared_files));
+        let mut doc_size = try!(dir_size(&doc_path)
+                                .map_err(DocBuilderError::CopyDocumentationIoError));
+
+        // store documentation of additional targets into
+        // crate/version/target
+        for target in &self.targets {
+            if Some(target) == metadata.default_target.as_ref() {
+                continue;
+            }
+
+            let mut target_doc_path = self.crate_root_dir(crte, version_index);
+            target_doc_path.push("target");
+            target_doc_path.push(target);
+            target_doc_path.push("doc");
+            if !target_doc_path.exists() {
+                continue;
+            }
+
+            let shared_files = SharedFiles {
+                names: try!(store_shared_files(storage, &target_doc_path, shared_prefix)),
+                doc_path: target_doc_path.clone(),
+                root: format!("../../../{}/", shared_prefix),
+            };
+            try!(store_doc_files(release_storage,
+                                 &target_doc_path,
+                                 &format!("{}/{}", prefix, target),
+                                 &shared_files));
+            doc_size += try!(dir_size(&target_doc_path)
+                             .map_err(DocBuilderError::CopyDocumentationIoError));
+        }
+
+        Ok(doc_size)
     }
 
 
     /// Build documentation of a crate in chroot environment
+    ///
+    /// Build limits are passed to build-doc subcommand running in chroot.
     fn build_doc_in_chroot(&self,
                            crte: &crte::Crate,
-                           version_index: usize) -> Result<String, String> {
-        command_result(Command::new("sudo")
-                       .arg("chroot")
-                       .arg(&self.chroot_path)
-                       .arg("su").arg("-").arg(&self.chroot_user)
-                       .arg("-c")
-                       .arg(format!("cratesfyi build-doc -c {} {}",
-                                    &crte.name, &crte.versions[version_index]))
-                       .output()
-                       .unwrap())
+                           version_index: usize) -> Result<String, CommandFailure> {
+        // parallel workers are building in subdirectories of home directory
+        let home = self.chroot_path.join("home").join(&self.chroot_user);
+        let mut build_doc_command = match self.build_dir.strip_prefix(&home) {
+            Ok(dir) if dir.components().next().is_some() => {
+                format!("mkdir -p {0} && cd {0} && ", dir.display())
+            }
+            _ => String::new(),
+        };
+        build_doc_command.push_str(&format!("cratesfyi build-doc -c --timeout {} \
+                                             --max-output {}",
+                                            self.build_limits.timeout.as_secs(),
+                                            self.build_limits.max_output));
+        if let Some(memory) = self.build_limits.memory {
+            build_doc_command.push_str(&format!(" --memory-limit {}", memory));
+        }
+        // network can't be disabled in chroot, fetching dependencies is
+        // disabled instead
+        if self.build_limits.is_restricted() {
+            build_doc_command.push_str(" --frozen");
+        }
+        for target in &self.targets {
+            build_doc_command.push_str(&format!(" --target {}", target));
+        }
+        for arg in &self.rustdoc_args {
+            // arguments are starting with -, they can only be passed with =
+            build_doc_command.push_str(&format!(" --rustdoc-arg={}", shell_quote(arg)));
+        }
+        if !self.all_features {
+            build_doc_command.push_str(" --no-all-features");
+        }
+        if self.document_private_items {
+            build_doc_command.push_str(" --document-private-items");
+        }
+        if let Some(ref toolchain) = self.toolchain {
+            build_doc_command.push_str(&format!(" --toolchain {}", shell_quote(toolchain)));
+        }
+        if let Some(features) = self.crate_features.get(&crte.name) {
+            build_doc_command.push_str(&format!(" --features {}",
+                                                shell_quote(&features.join(" "))));
+        }
+        build_doc_command.push_str(&format!(" {} {}",
+                                            &crte.name, &crte.versions[version_index]));
+
+        let output = Command::new("sudo")
+                         .arg("chroot")
+                         .arg(&self.chroot_path)
+                         .arg("su").arg("-").arg(&self.chroot_user)
+                         .arg("-c")
+                         .arg(build_doc_command)
+                         .output()
+                         .unwrap();
+
+        let timed_out = output.status.code() == Some(BUILD_TIMED_OUT_EXIT_CODE);
+        match command_result(output) {
+            Ok(m) => Ok(m),
+            Err(m) => if timed_out {
+                Err(CommandFailure::TimedOut(m))
+            } else {
+                Err(CommandFailure::Failed(m))
+            },
+        }
+    }
+
+
+    /// Runs tests of a crate in chroot environment. Crate must be built
+    /// with build_doc_in_chroot before.
+    fn test_crate_in_chroot(&self,
+                            crte: &crte::Crate,
+                            version_index: usize,
+                            mode: builds::TestMode) -> Result<String, CommandFailure> {
+        let home = self.chroot_path.join("home").join(&self.chroot_user);
+        let mut test_command = match self.build_dir.strip_prefix(&home) {
+            Ok(dir) if dir.components().next().is_some() => {
+                format!("cd {} && ", dir.display())
+            }
+            _ => String::new(),
+        };
+        test_command.push_str(&format!("cratesfyi test-crate --timeout {} --max-output {}",
+                                       self.build_limits.timeout.as_secs(),
+                                       self.build_limits.max_output));
+        if let Some(memory) = self.build_limits.memory {
+            test_command.push_str(&format!(" --memory-limit {}", memory));
+        }
+        if self.build_limits.is_restricted() {
+            test_command.push_str(" --frozen");
+        }
+        if mode == builds::TestMode::Run {
+            test_command.push_str(" --run");
+        }
+        if let Some(ref toolchain) = self.toolchain {
+            test_command.push_str(&format!(" --toolchain {}", shell_quote(toolchain)));
+        }
+        test_command.push_str(&format!(" {} {}", &crte.name, &crte.versions[version_index]));
+
+        let output = Command::new("sudo")
+                         .arg("chroot")
+                         .arg(&self.chroot_path)
+                         .arg("su").arg("-").arg(&self.chroot_user)
+                         .arg("-c")
+                         .arg(test_command)
+                         .output()
+                         .unwrap();
+
+        let timed_out = output.status.code() == Some(BUILD_TIMED_OUT_EXIT_CODE);
+        match command_result(output) {
+            Ok(m) => Ok(m),
+            Err(m) => if timed_out {
+                Err(CommandFailure::TimedOut(m))
+            } else {
+                Err(CommandFailure::Failed(m))
+            },
+        }
+    }
+
+
+    /// Build documentation of a crate in docker sandbox
+    ///
+    /// Crate is downloaded and extracted into build directory and only
+    /// cargo doc is running inside container.
+    fn build_doc_in_sandbox(&self,
+                            crte: &crte::Crate,
+                            version_index: usize) -> Result<String, CommandFailure> {
+        crte.prepare_crate(version_index, &self.build_dir, self)
+            .map_err(|e| {
+                let mut message = format!("Failed to prepare crate: {:?}", e);
+                if download::is_download_error(&e) {
+                    message.push_str(&format!("\n{}", DOWNLOAD_FAILED_LOG_MARKER));
+                }
+                if let DocBuilderError::CrateTooLarge(ref reason) = e {
+                    message.push_str(&format!("\n{}{}", CRATE_TOO_LARGE_LOG_PREFIX, reason));
+                }
+                CommandFailure::Failed(message)
+            })
+            .and_then(|(_, url)| {
+                // URL of downloaded crate is recorded from build log
+                let downloaded_from = url.map(|url| {
+                        format!("{}{}\n", download::DOWNLOADED_FROM_LOG_PREFIX, url)
+                    })
+                    .unwrap_or(String::new());
+                crte.build_doc(version_index, &self.build_dir, self)
+                    .map(|m| downloaded_from.clone() + &m)
+                    .map_err(|f| f.with_prefix(&downloaded_from))
+            })
     }
 
 
@@ -660,7 +2764,7 @@ impl DocBuilder {
 
             if let Err(e) = crte::Crate::from_cargo_index_file(path)
                 .map(|c| self.download_source_of_a_crate(&c)) {
-                    println!("Failed to download crate: {:?}", e);
+                    error!("Failed to download crate: {:?}", e);
                 }
         }
 
@@ -668,11 +2772,12 @@ impl DocBuilder {
     }
 
     /// Downloads sources of a crate and extracts it into self.sources_path
-    // FIXME: This function is using CWD
     fn download_source_of_a_crate(&self, crte: &crte::Crate) -> Result<(), DocBuilderError> {
+        // crates are extracted into build directory before copying
+        let dir = self.build_dir.clone();
 
         for version_index in 0..crte.versions.len() {
-            let source = PathBuf::from(crte.canonical_name(version_index));
+            let source = dir.join(crte.canonical_name(version_index));
 
             let mut destination = PathBuf::from(&self.sources_path);
             destination.push(&crte.name);
@@ -683,15 +2788,21 @@ impl DocBuilder {
                 continue;
             }
 
-            println!("Downloading sources of {}", crte.canonical_name(version_index));
+            let _log_context = logging::scope(|ctx| {
+                ctx.crate_version(&crte.name, &crte.versions[version_index]);
+            });
+            info!("Downloading sources of {}", crte.canonical_name(version_index));
 
-            try!(crte.download_crate(version_index).map_err(DocBuilderError::DownloadCrateError));
-            try!(crte.extract_crate(version_index).map_err(DocBuilderError::DownloadCrateError));
+            try!(crte.download_crate_cached(version_index,
+                                            &dir,
+                                            &self.download_options(),
+                                            self.crate_cache.as_ref()));
+            try!(crte.extract_crate(version_index, &dir, &self.extract_limits));
 
             try!(copy_files(&source, &destination));
 
             try!(fs::remove_dir_all(&source).map_err(DocBuilderError::CopyDocumentationIoError));
-            try!(crte.remove_crate_file(version_index));
+            try!(crte.remove_crate_file(version_index, &dir));
         }
 
         Ok(())
@@ -704,8 +2815,7 @@ impl DocBuilder {
     }
 
 
-    // FIXME: This is so similar to build_doc_for_crate_path
-    //        I can use a function like crates_from_path and a closure
+    // FIXME: This can use crate_files
     fn add_all_crates_into_database_from_path(&self,
                                               conn: &postgres::Connection,
                                               path: &PathBuf) -> Result<(), DocBuilderError> {
@@ -726,7 +2836,12 @@ impl DocBuilder {
 
             if let Ok(crte) = crte::Crate::from_cargo_index_file(path) {
                 //self.build_doc_for_crate(&crte);
-                crte.add_crate_into_database(0, &conn, &self).unwrap();
+                let _log_context = logging::scope(|ctx| {
+                    ctx.crate_version(&crte.name, &crte.versions[0]);
+                });
+                if let Err(e) = crte.add_crate_into_database(0, &conn, &self) {
+                    error!("Failed to add {} into database: {}", crte.canonical_name(0), e);
+                }
             }
         }
         Ok(())
@@ -760,14 +2875,6 @@ fn parse_rustc_version(version: &str) -> Result<String, DocBuilderError> {
 /// A simple function to copy files from source to destination
 fn copy_files(source: &PathBuf,
               destination: &PathBuf) -> Result<(), DocBuilderError> {
-    copy_files_and_handle_html(source, destination, false, "")
-}
-
-
-fn copy_files_and_handle_html(source: &PathBuf,
-              destination: &PathBuf,
-              handle_html: bool,
-              rustc_version: &str) -> Result<(), DocBuilderError> {
 
     // Make sure destination directory is exists
     if !destination.exists() {
@@ -775,10 +2882,6 @@ fn copy_files_and_handle_html(source: &PathBuf,
              .map_err(DocBuilderError::LocalDependencyIoError));
     }
 
-    // Avoid copying duplicated files
-    let dup_regex = Regex::new(r"(\.lock|\.txt|\.woff|jquery\.js|playpen\.js|main\.js|\.css)$")
-        .unwrap();
-
     for file in try!(source.read_dir().map_err(DocBuilderError::LocalDependencyIoError)) {
 
         let file = try!(file.map_err(DocBuilderError::LocalDependencyIoError));
@@ -788,14 +2891,7 @@ fn copy_files_and_handle_html(source: &PathBuf,
         let metadata = try!(file.metadata().map_err(DocBuilderError::LocalDependencyIoError));
 
         if metadata.is_dir() {
-            try!(fs::create_dir_all(&destination_full_path)
-                 .map_err(DocBuilderError::LocalDependencyIoError));
-            try!(copy_files_and_handle_html(&file.path(), &destination_full_path, handle_html,
-            &rustc_version));
-        } else if handle_html && file.file_name().into_string().unwrap().ends_with(".html") {
-            try!(copy_html(&file.path(), &destination_full_path, rustc_version));
-        } else if handle_html && dup_regex.is_match(&file.file_name().into_string().unwrap()[..]) {
-            continue;
+            try!(copy_files(&file.path(), &destination_full_path));
         } else {
             try!(fs::copy(&file.path(), &destination_full_path)
                  .map_err(DocBuilderError::LocalDependencyIoError));
@@ -806,38 +2902,211 @@ fn copy_files_and_handle_html(source: &PathBuf,
 }
 
 
-fn copy_html(source: &PathBuf,
-             destination: &PathBuf,
-             rustc_version: &str) -> Result<(), DocBuilderError> {
+/// rustdoc's shared files of a documentation available in shared directory
+struct SharedFiles {
+    /// Names of shared files
+    names: Vec<String>,
+    /// Documentation root containing shared files
+    doc_path: PathBuf,
+    /// Path of shared directory relative to documentation root
+    root: String,
+}
+
+
+/// Stores rustdoc's shared files in doc_path into shared_prefix unless they
+/// already exist and returns names of files available in shared_prefix.
+/// A file is not shared if shared_prefix has a different file with same name.
+fn store_shared_files(storage: &Storage,
+                      doc_path: &Path,
+                      shared_prefix: &str) -> Result<Vec<String>, DocBuilderError> {
+    let shared_regex = Regex::new(SHARED_FILES_REGEX).unwrap();
+    let mut names = Vec::new();
 
-    let source_file = try!(fs::File::open(source)
-                           .map_err(DocBuilderError::CopyDocumentationIoError));
-    let mut destination_file = try!(fs::OpenOptions::new()
-                                    .write(true).create(true).open(destination)
-                                    .map_err(DocBuilderError::CopyDocumentationIoError));
+    for file in try!(doc_path.read_dir().map_err(DocBuilderError::CopyDocumentationIoError)) {
+        let file = try!(file.map_err(DocBuilderError::CopyDocumentationIoError));
+        let name = file.file_name().into_string().unwrap();
+        if !file.path().is_file() || !shared_regex.is_match(&name) {
+            continue;
+        }
 
-    let reader = io::BufReader::new(source_file);
+        let content = try!(read_file(&file.path()));
+        let shared_file = format!("{}/{}", shared_prefix, name);
+        match try!(storage.get_file(&shared_file)) {
+            Some(ref shared_content) if sha256(shared_content) != sha256(&content) => continue,
+            Some(_) => {}
+            None => try!(storage.store_file(&shared_file, &content)),
+        }
 
-    let replace_regex = Regex::new(r#"(href|src)="(.*)(main|jquery|rustdoc)\.(css|js)""#).unwrap();
-    let replace_str = format!("$1=\"../../$2$3-{}.$4\"", rustc_version);
+        names.push(name);
+    }
 
-    for line in reader.lines() {
-        let mut line = try!(line.map_err(DocBuilderError::CopyDocumentationIoError));
+    Ok(names)
+}
 
-        // replace css links
-        line = replace_regex.replace_all(&line[..], &replace_str[..]);
 
-        try!(destination_file.write(line.as_bytes())
-             .map_err(DocBuilderError::CopyDocumentationIoError));
-        // need to write consumed newline
-        try!(destination_file.write(&['\n' as u8])
-             .map_err(DocBuilderError::CopyDocumentationIoError));
+/// Returns a regex and replacement string pointing links of shared files
+/// into shared directory
+fn shared_links_replacement(shared_files: &SharedFiles) -> Option<(Regex, String)> {
+    if shared_files.names.is_empty() {
+        return None;
     }
 
+    let names: Vec<String> = shared_files.names.iter().map(|n| regex::quote(n)).collect();
+    let replace_regex = Regex::new(&format!(r#"(href|src)="((?:\.\./)*)({})""#,
+                                            names.join("|")))
+        .unwrap();
+    Some((replace_regex, format!("$1=\"$2{}$3\"", shared_files.root)))
+}
+
+
+/// Stores documentation files in source into storage with prefix. Shared
+/// files are skipped and their links in html files are pointed to shared
+/// directory.
+fn store_doc_files(storage: &Storage,
+                   source: &PathBuf,
+                   prefix: &str,
+                   shared_files: &SharedFiles) -> Result<(), DocBuilderError> {
+    let replacement = shared_links_replacement(shared_files);
+
+    for file in try!(source.read_dir().map_err(DocBuilderError::CopyDocumentationIoError)) {
+
+        let file = try!(file.map_err(DocBuilderError::CopyDocumentationIoError));
+        let file_name = file.file_name().into_string().unwrap();
+        let path = format!("{}/{}", prefix, file_name);
+
+        let metadata = try!(file.metadata().map_err(DocBuilderError::CopyDocumentationIoError));
+
+        if metadata.is_dir() {
+            try!(store_doc_files(storage, &file.path(), &path, shared_files));
+        } else if file_name.ends_with(".lock") ||
+                  (source == &shared_files.doc_path && shared_files.names.contains(&file_name)) {
+            continue;
+        } else {
+            let mut content = try!(read_file(&file.path()));
+            if file_name.ends_with(".html") {
+                if let Some((ref regex, ref replace)) = replacement {
+                    let html = regex.replace_all(&String::from_utf8_lossy(&content), &replace[..]);
+                    content = html.into_bytes();
+                }
+            }
+            try!(storage.store_file(&path, &content));
+        }
+
+    }
     Ok(())
 }
 
 
+/// Returns a name which is unique for every build of a release, i.e:
+/// `rand-0.3.14-1466004187123456789`
+fn unique_name(crte: &crte::Crate, version_index: usize) -> String {
+    format!("{}-{}", crte.canonical_name(version_index), time::precise_time_ns())
+}
+
+
+/// Moves documentation in `staging_dir/doc` into release_dir. Old
+/// documentation is moved into staging_dir first, a directory can't be
+/// renamed over a non-empty directory.
+fn promote_dir(staging_dir: &Path, release_dir: &Path) -> io::Result<()> {
+    // release might not have any file
+    try!(fs::create_dir_all(staging_dir.join("doc")));
+    if let Some(parent) = release_dir.parent() {
+        try!(fs::create_dir_all(parent));
+    }
+    if release_dir.exists() {
+        try!(fs::rename(release_dir, staging_dir.join("old")));
+    }
+    fs::rename(staging_dir.join("doc"), release_dir)
+}
+
+
+/// Quotes an argument of a shell command
+fn shell_quote(arg: &str) -> String {
+    format!("'{}'", arg.replace("'", "'\\''"))
+}
+
+
+/// Returns total size of files in a directory
+fn dir_size(path: &Path) -> io::Result<u64> {
+    let mut size = 0;
+    for entry in try!(path.read_dir()) {
+        let entry = try!(entry);
+        let metadata = try!(entry.metadata());
+        if metadata.is_dir() {
+            size += try!(dir_size(&entry.path()));
+        } else {
+            size += metadata.len();
+        }
+    }
+    Ok(size)
+}
+
+
+fn read_file(path: &Path) -> Result<Vec<u8>, DocBuilderError> {
+    let mut content = Vec::new();
+    try!(fs::File::open(path)
+         .and_then(|mut f| f.read_to_end(&mut content))
+         .map_err(DocBuilderError::CopyDocumentationIoError));
+    Ok(content)
+}
+
+
+fn sha256(content: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.input(content);
+    hasher.result_str()
+}
+
+
+/// Logs error of a version build, skipped versions are not logged as errors
+fn log_build_error(name: &str, version: &str, err: &DocBuilderError) {
+    if is_skipped(err) {
+        info!("Skipped build of {}-{}: {}", name, version, err);
+    } else {
+        error!("Failed to build docs for crate {}-{}: {}", name, version, err);
+    }
+}
+
+
+/// Returns true if a version is skipped instead of a failed build
+fn is_skipped(err: &DocBuilderError) -> bool {
+    match *err {
+        DocBuilderError::SkipDocumentationExists |
+        DocBuilderError::SkipLogFileExists |
+        DocBuilderError::CrateBlacklisted(_) |
+        DocBuilderError::Interrupted => true,
+        _ => false,
+    }
+}
+
+
+/// Returns sorted paths of crate files in crates.io-index relative to root
+fn crate_files(root: &Path) -> io::Result<Vec<PathBuf>> {
+    fn walk(root: &Path, path: &Path, files: &mut Vec<PathBuf>) -> io::Result<()> {
+        for entry in try!(path.read_dir()) {
+            let path = try!(entry).path();
+            // skip .git and config.json
+            if path.file_name().map_or(true, |n| {
+                n.to_string_lossy().starts_with('.') || n == "config.json"
+            }) {
+                continue;
+            }
+            if path.is_dir() {
+                try!(walk(root, &path, files));
+            } else {
+                files.push(path.strip_prefix(root).unwrap().to_path_buf());
+            }
+        }
+        Ok(())
+    }
+
+    let mut files = Vec::new();
+    try!(walk(root, root, &mut files));
+    files.sort();
+    Ok(files)
+}
+
+
 fn generate_paths(prefix: PathBuf) -> (PathBuf, PathBuf, PathBuf, PathBuf, PathBuf, PathBuf) {
 
     let mut destination = PathBuf::from(&prefix);
@@ -861,3 +3130,114 @@ fn generate_paths(prefix: PathBuf) -> (PathBuf, PathBuf, PathBuf, PathBuf, PathB
 
     (destination, chroot_path, build_dir, crates_io_index_path, logs_path, sources_path)
 }
+
+
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+    use std::env;
+    use std::fs;
+    use std::collections::BTreeMap;
+    use super::{SharedFiles, shared_links_replacement, shell_quote, promote_dir, crate_files};
+    use std::time::Duration;
+    use super::DocBuilder;
+    use super::metadata::Metadata;
+    use super::overrides::BuildOverride;
+
+    #[test]
+    fn test_shared_links_replacement() {
+        let shared_files = SharedFiles {
+            names: vec!["main.css".to_string(), "jquery.js".to_string()],
+            doc_path: PathBuf::from("target/doc"),
+            root: "../../_static/20160512-b6e8a6b/".to_string(),
+        };
+        let (regex, replace) = shared_links_replacement(&shared_files).unwrap();
+
+        let line = r#"<link href="../../main.css"><script src="../search-index.js">"#;
+        assert_eq!(regex.replace_all(line, &replace[..]),
+                   "<link href=\"../../../../_static/20160512-b6e8a6b/main.css\">\
+                    <script src=\"../search-index.js\">");
+    }
+
+    #[test]
+    fn test_override_default_target() {
+        let mut docbuilder = DocBuilder::default();
+        let mut default_targets = BTreeMap::new();
+        default_targets.insert("winapi".to_string(), "x86_64-pc-windows-msvc".to_string());
+        docbuilder.default_targets(default_targets);
+
+        let mut crate_features = BTreeMap::new();
+        crate_features.insert("winapi".to_string(), vec!["everything".to_string()]);
+        docbuilder.crate_features(crate_features);
+
+        let mut metadata = Metadata::default();
+        docbuilder.override_metadata("rand", &mut metadata);
+        assert_eq!(metadata.default_target, None);
+        assert!(metadata.uses_default_features());
+        docbuilder.override_metadata("winapi", &mut metadata);
+        assert_eq!(metadata.target(), "x86_64-pc-windows-msvc");
+        assert_eq!(metadata.features, Some(vec!["everything".to_string()]));
+    }
+
+    #[test]
+    fn test_apply_build_override() {
+        let mut docbuilder = DocBuilder::default();
+        let build_override = BuildOverride {
+            features: Some(vec!["v110".to_string()]),
+            timeout: Some(3600),
+            docker_image: Some("cratesfyi/build-openssl".to_string()),
+            ..BuildOverride::default()
+        };
+        docbuilder.apply_build_override("openssl", &build_override);
+        assert_eq!(docbuilder.build_limits.timeout, Duration::from_secs(3600));
+        assert_eq!(docbuilder.crate_features.get("openssl"), Some(&vec!["v110".to_string()]));
+        // docker image is not used without sandbox
+        assert!(docbuilder.sandbox.is_none());
+
+        let mut metadata = Metadata::default();
+        docbuilder.override_metadata("openssl", &mut metadata);
+        assert_eq!(metadata.features, Some(vec!["v110".to_string()]));
+    }
+
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(shell_quote("--cfg"), "'--cfg'");
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+    }
+
+    #[test]
+    fn test_promote_dir() {
+        let root = env::temp_dir().join("cratesfyi-test-promote-dir");
+        let _ = fs::remove_dir_all(&root);
+        let staging_dir = root.join(".staging/rand-0.3.14-1");
+        let release_dir = root.join("rand/0.3.14");
+        fs::create_dir_all(staging_dir.join("doc/rand")).unwrap();
+        fs::create_dir_all(release_dir.join("old")).unwrap();
+
+        promote_dir(&staging_dir, &release_dir).unwrap();
+        assert!(release_dir.join("rand").is_dir());
+        assert!(!release_dir.join("old").exists());
+        assert!(!staging_dir.join("doc").exists());
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn test_crate_files() {
+        let root = env::temp_dir().join("cratesfyi-test-crate-files");
+        let _ = fs::remove_dir_all(&root);
+        for dir in &[".git", "ra/nd", "1", "3/a"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        for file in &[".git/HEAD", "config.json", "ra/nd/rand", "1/a", "3/a/abc"] {
+            fs::File::create(root.join(file)).unwrap();
+        }
+
+        assert_eq!(crate_files(&root).unwrap(),
+                   vec![PathBuf::from("1/a"), PathBuf::from("3/a/abc"),
+                        PathBuf::from("ra/nd/rand")]);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
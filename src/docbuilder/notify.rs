@@ -0,0 +1,331 @@
+//! Build failure notifications
+//!
+//! Failed builds are reported by POSTing a JSON payload to a webhook and/or
+//! by email sent with sendmail. A failure is a regression if previous build
+//! of crate was successful. Notifications are only sent for enabled events.
+
+use std::io;
+use std::io::prelude::*;
+use std::error;
+use std::fmt;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use hyper;
+use hyper::client::Client;
+use hyper::header::Headers;
+use hyper::status::StatusCode;
+use rustc_serialize::json::{Json, ToJson};
+
+
+/// Default path of sendmail program
+pub const DEFAULT_SENDMAIL_PATH: &'static str = "/usr/sbin/sendmail";
+
+/// Default sender address of notification emails
+pub const DEFAULT_EMAIL_FROM: &'static str = "cratesfyi@localhost";
+
+/// Number of last lines of build output included into notifications
+pub const OUTPUT_TAIL_LINES: usize = 20;
+
+/// Timeout of webhook requests in seconds
+pub const WEBHOOK_TIMEOUT: u64 = 30;
+
+
+/// Events notifications can be sent for
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BuildEvent {
+    /// Build failed
+    Failed,
+    /// Build failed and previous build of crate was successful
+    Regressed,
+}
+
+
+impl BuildEvent {
+    pub fn from_str(event: &str) -> Option<BuildEvent> {
+        match event {
+            "failed" => Some(BuildEvent::Failed),
+            "regressed" => Some(BuildEvent::Regressed),
+            _ => None,
+        }
+    }
+
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            BuildEvent::Failed => "failed",
+            BuildEvent::Regressed => "regressed",
+        }
+    }
+}
+
+
+/// A failed build to notify about
+#[derive(Debug)]
+pub struct Notification<'a> {
+    pub event: BuildEvent,
+    pub name: &'a str,
+    pub version: &'a str,
+    pub build_status: i32,
+    pub rustc_version: &'a str,
+    pub output: &'a str,
+}
+
+
+impl<'a> Notification<'a> {
+    /// Returns last OUTPUT_TAIL_LINES lines of build output
+    fn output_tail(&self) -> String {
+        let lines: Vec<&str> = self.output.lines().collect();
+        let start = lines.len().saturating_sub(OUTPUT_TAIL_LINES);
+        lines[start..].join("\n")
+    }
+
+
+    fn subject(&self) -> String {
+        match self.event {
+            BuildEvent::Failed => format!("Build of {}-{} failed", self.name, self.version),
+            BuildEvent::Regressed => {
+                format!("Build of {}-{} failed, previous build was successful",
+                        self.name,
+                        self.version)
+            }
+        }
+    }
+}
+
+
+impl<'a> ToJson for Notification<'a> {
+    fn to_json(&self) -> Json {
+        let mut tree = BTreeMap::new();
+        tree.insert("event".to_string(), self.event.as_str().to_json());
+        tree.insert("crate".to_string(), self.name.to_json());
+        tree.insert("version".to_string(), self.version.to_json());
+        tree.insert("build_status".to_string(), self.build_status.to_json());
+        tree.insert("rustc_version".to_string(), self.rustc_version.to_json());
+        tree.insert("output".to_string(), self.output_tail().to_json());
+        Json::Object(tree)
+    }
+}
+
+
+#[derive(Debug)]
+pub enum NotifyError {
+    WebhookError(hyper::Error),
+    WebhookStatusError(StatusCode),
+    SendmailError(io::Error),
+    SendmailFailed,
+}
+
+
+impl fmt::Display for NotifyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            NotifyError::WebhookError(ref e) => write!(f, "Webhook request failed: {}", e),
+            NotifyError::WebhookStatusError(ref s) => {
+                write!(f, "Unexpected response status from webhook: {}", s)
+            }
+            NotifyError::SendmailError(ref e) => write!(f, "Failed to run sendmail: {}", e),
+            NotifyError::SendmailFailed => write!(f, "sendmail failed"),
+        }
+    }
+}
+
+
+impl error::Error for NotifyError {
+    fn description(&self) -> &str {
+        "notification error"
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            NotifyError::WebhookError(ref e) => Some(e),
+            NotifyError::SendmailError(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+
+/// Sends notifications of failed builds
+#[derive(Debug, Clone)]
+pub struct Notifier {
+    webhook_url: Option<String>,
+    /// Recipients of emails, emails are not sent if it's empty
+    email_to: Vec<String>,
+    email_from: String,
+    sendmail_path: PathBuf,
+    events: Vec<BuildEvent>,
+}
+
+
+impl Default for Notifier {
+    fn default() -> Notifier {
+        Notifier {
+            webhook_url: None,
+            email_to: Vec::new(),
+            email_from: DEFAULT_EMAIL_FROM.to_string(),
+            sendmail_path: PathBuf::from(DEFAULT_SENDMAIL_PATH),
+            events: vec![BuildEvent::Failed, BuildEvent::Regressed],
+        }
+    }
+}
+
+
+impl Notifier {
+    /// Sets URL payloads are POSTed to
+    pub fn webhook_url(&mut self, url: String) {
+        self.webhook_url = Some(url);
+    }
+
+
+    /// Sets recipients of notification emails
+    pub fn email_to(&mut self, to: Vec<String>) {
+        self.email_to = to;
+    }
+
+
+    /// Sets sender address of notification emails
+    pub fn email_from(&mut self, from: String) {
+        self.email_from = from;
+    }
+
+
+    /// Sets path of sendmail program
+    pub fn sendmail_path(&mut self, path: PathBuf) {
+        self.sendmail_path = path;
+    }
+
+
+    /// Sets events notifications are sent for, both events are enabled by
+    /// default
+    pub fn events(&mut self, events: Vec<BuildEvent>) {
+        self.events = events;
+    }
+
+
+    /// Returns true if notifications are sent for event
+    pub fn is_enabled(&self, event: BuildEvent) -> bool {
+        (self.webhook_url.is_some() || !self.email_to.is_empty()) &&
+            self.events.contains(&event)
+    }
+
+
+    /// Sends notification to webhook and email recipients. Both of them are
+    /// tried, first error is returned.
+    pub fn notify(&self, notification: &Notification) -> Result<(), NotifyError> {
+        if !self.is_enabled(notification.event) {
+            return Ok(());
+        }
+
+        let webhook_res = match self.webhook_url {
+            Some(ref url) => self.post_webhook(url, notification),
+            None => Ok(()),
+        };
+        let email_res = if self.email_to.is_empty() {
+            Ok(())
+        } else {
+            self.send_email(notification)
+        };
+
+        webhook_res.and(email_res)
+    }
+
+
+    fn post_webhook(&self, url: &str, notification: &Notification) -> Result<(), NotifyError> {
+        let mut client = Client::new();
+        client.set_read_timeout(Some(Duration::from_secs(WEBHOOK_TIMEOUT)));
+        client.set_write_timeout(Some(Duration::from_secs(WEBHOOK_TIMEOUT)));
+
+        let mut headers = Headers::new();
+        headers.set_raw("Content-Type", vec![b"application/json".to_vec()]);
+
+        let payload = notification.to_json().to_string();
+        let res = try!(client.post(url)
+                       .headers(headers)
+                       .body(&payload[..])
+                       .send()
+                       .map_err(NotifyError::WebhookError));
+
+        if !res.status.is_success() {
+            return Err(NotifyError::WebhookStatusError(res.status));
+        }
+        Ok(())
+    }
+
+
+    fn send_email(&self, notification: &Notification) -> Result<(), NotifyError> {
+        let mut child = try!(Command::new(&self.sendmail_path)
+                             .arg("-t")
+                             // don't treat a line with a single dot as end of input,
+                             // build output can contain one
+                             .arg("-oi")
+                             .stdin(Stdio::piped())
+                             .spawn()
+                             .map_err(NotifyError::SendmailError));
+
+        {
+            let stdin = child.stdin.as_mut().unwrap();
+            try!(stdin.write_all(self.email_message(notification).as_bytes())
+                 .map_err(NotifyError::SendmailError));
+        }
+
+        let status = try!(child.wait().map_err(NotifyError::SendmailError));
+        if !status.success() {
+            return Err(NotifyError::SendmailFailed);
+        }
+        Ok(())
+    }
+
+
+    /// Returns email message with headers
+    fn email_message(&self, notification: &Notification) -> String {
+        format!("From: {}\r\nTo: {}\r\nSubject: {}\r\n\
+                 Content-Type: text/plain; charset=utf-8\r\n\r\n\
+                 {}\n\nrustc version: {}\nBuild status: {}\n\n{}\n",
+                self.email_from,
+                self.email_to.join(", "),
+                notification.subject(),
+                notification.subject(),
+                notification.rustc_version,
+                notification.build_status,
+                notification.output_tail())
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use rustc_serialize::json::ToJson;
+
+    #[test]
+    fn test_notification() {
+        let output: Vec<String> = (0..30).map(|i| format!("line {}", i)).collect();
+        let output = output.join("\n");
+        let notification = Notification {
+            event: BuildEvent::Regressed,
+            name: "rand",
+            version: "0.3.14",
+            build_status: -1,
+            rustc_version: "rustc 1.9.0",
+            output: &output,
+        };
+
+        let json = notification.to_json();
+        assert_eq!(json.find("event").and_then(|e| e.as_string()), Some("regressed"));
+        assert_eq!(json.find("crate").and_then(|c| c.as_string()), Some("rand"));
+        let tail = json.find("output").and_then(|o| o.as_string()).unwrap();
+        assert!(tail.starts_with("line 10\n"));
+        assert!(tail.ends_with("line 29"));
+
+        let mut notifier = Notifier::default();
+        assert!(!notifier.is_enabled(BuildEvent::Failed));
+        notifier.webhook_url("http://localhost/hook".to_string());
+        notifier.events(vec![BuildEvent::Regressed]);
+        assert!(!notifier.is_enabled(BuildEvent::Failed));
+        assert!(notifier.is_enabled(BuildEvent::Regressed));
+    }
+}
@@ -0,0 +1,124 @@
+//! Build overrides of crates
+//!
+//! Some crates need special treatment their metadata doesn't declare, i.e: a
+//! docker image with extra system packages, a longer timeout or a feature
+//! list. Overrides are stored in build_overrides table keyed by crate name
+//! and they are applied to every build of crate.
+
+use postgres::Connection;
+use postgres::error::Error;
+use rustc_serialize::json::{Json, ToJson};
+
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct BuildOverride {
+    /// Features used instead of features in metadata of crate
+    pub features: Option<Vec<String>>,
+    /// Target used instead of default target of crate
+    pub default_target: Option<String>,
+    /// Build timeout in seconds
+    pub timeout: Option<i32>,
+    /// Virtual memory limit of build in kilobytes
+    pub memory_limit: Option<i64>,
+    /// Docker image used instead of image of builder
+    pub docker_image: Option<String>,
+    /// Why crate needs an override
+    pub note: Option<String>,
+}
+
+
+fn features_from_json(json: Option<Json>) -> Option<Vec<String>> {
+    json.as_ref().and_then(|j| j.as_array()).map(|features| {
+        features.iter().filter_map(|f| f.as_string()).map(String::from).collect()
+    })
+}
+
+
+/// Returns build overrides of every crate ordered by name, or only override
+/// of a crate if name is given
+fn query_overrides(conn: &Connection,
+                   name: Option<&str>) -> Result<Vec<(String, BuildOverride)>, Error> {
+    let rows = try!(conn.query("SELECT name, features, default_target, timeout, \
+                                       memory_limit, docker_image, note \
+                                FROM build_overrides \
+                                WHERE $1::TEXT IS NULL OR name = $1 \
+                                ORDER BY name",
+                               &[&name]));
+    Ok(rows.iter().map(|row| {
+        (row.get(0), BuildOverride {
+            features: features_from_json(row.get(1)),
+            default_target: row.get(2),
+            timeout: row.get(3),
+            memory_limit: row.get(4),
+            docker_image: row.get(5),
+            note: row.get(6),
+        })
+    }).collect())
+}
+
+
+/// Returns build override of a crate
+pub fn get_override(conn: &Connection, name: &str) -> Result<Option<BuildOverride>, Error> {
+    query_overrides(conn, Some(name)).map(|overrides| overrides.into_iter().next().map(|o| o.1))
+}
+
+
+/// Sets build override of a crate, existing override is replaced
+pub fn set_override(conn: &Connection,
+                    name: &str,
+                    build_override: &BuildOverride) -> Result<(), Error> {
+    let features = build_override.features.as_ref().map(|f| f.to_json());
+    let updated = try!(conn.execute("UPDATE build_overrides \
+                                     SET features = $2, default_target = $3, timeout = $4, \
+                                         memory_limit = $5, docker_image = $6, note = $7 \
+                                     WHERE name = $1",
+                                    &[&name,
+                                      &features,
+                                      &build_override.default_target,
+                                      &build_override.timeout,
+                                      &build_override.memory_limit,
+                                      &build_override.docker_image,
+                                      &build_override.note]));
+    if updated == 0 {
+        try!(conn.execute("INSERT INTO build_overrides (name, features, default_target, \
+                                                        timeout, memory_limit, docker_image, \
+                                                        note) \
+                           VALUES ($1, $2, $3, $4, $5, $6, $7)",
+                          &[&name,
+                            &features,
+                            &build_override.default_target,
+                            &build_override.timeout,
+                            &build_override.memory_limit,
+                            &build_override.docker_image,
+                            &build_override.note]));
+    }
+    Ok(())
+}
+
+
+/// Removes build override of a crate. Returns false if crate didn't have one.
+pub fn remove_override(conn: &Connection, name: &str) -> Result<bool, Error> {
+    conn.execute("DELETE FROM build_overrides WHERE name = $1", &[&name]).map(|n| n > 0)
+}
+
+
+/// Returns build overrides of every crate ordered by crate name
+pub fn list_overrides(conn: &Connection) -> Result<Vec<(String, BuildOverride)>, Error> {
+    query_overrides(conn, None)
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::features_from_json;
+    use rustc_serialize::json::Json;
+
+    #[test]
+    fn test_features_from_json() {
+        assert_eq!(features_from_json(Json::from_str(r#"["v102", "v110"]"#).ok()),
+                   Some(vec!["v102".to_string(), "v110".to_string()]));
+        assert_eq!(features_from_json(Some(Json::Array(Vec::new()))), Some(Vec::new()));
+        assert_eq!(features_from_json(None), None);
+    }
+}
@@ -0,0 +1,91 @@
+//! Crate owners
+//!
+//! Owners are written when a release is added into database, and
+//! `update_all_owners` re-syncs owners of every crate since ownership
+//! can change without a new release.
+
+use postgres::Connection;
+use slug::slugify;
+
+use super::crte::CrateOpenError;
+use super::cratesio_api::{CratesIoClient, Owner};
+
+
+/// Replaces owners of a crate. Relationships with users who are not an
+/// owner anymore are removed.
+pub fn update_crate_owners(conn: &Connection,
+                           crate_id: i32,
+                           owners: &[Owner]) -> Result<(), CrateOpenError> {
+    let mut owner_ids: Vec<i32> = Vec::new();
+
+    for owner in owners {
+        let login = &owner.login[..];
+        let avatar = owner.avatar.as_ref().map(|s| &s[..]).unwrap_or("");
+        let email = owner.email.as_ref().map(|s| &s[..]).unwrap_or("");
+        let name = owner.name.as_ref().map(|s| &s[..]).unwrap_or("");
+        let slug = slugify(&name);
+
+        if login.is_empty() {
+            continue;
+        }
+
+        let owner_id: i32 = {
+            let rows = try!(conn.query("SELECT id FROM owners WHERE login = $1", &[&login]));
+            if rows.len() > 0 {
+                let owner_id: i32 = rows.get(0).get(0);
+                // keep user information fresh
+                try!(conn.execute("UPDATE owners SET avatar = $2, name = $3, email = $4 \
+                                   WHERE id = $1",
+                                  &[&owner_id, &avatar, &name, &email]));
+                owner_id
+            } else {
+                try!(conn.query("INSERT INTO owners (login, slug, avatar, name, email) \
+                                VALUES ($1, $2, $3, $4, $5) RETURNING id",
+                                &[&login, &slug, &avatar, &name, &email]))
+                    .get(0).get(0)
+            }
+        };
+
+        // add relationship
+        let _ = conn.query("INSERT INTO owner_rels (cid, oid) VALUES ($1, $2)",
+                           &[&crate_id, &owner_id]);
+        owner_ids.push(owner_id);
+    }
+
+    // remove stale relationships
+    let rows = try!(conn.query("SELECT oid FROM owner_rels WHERE cid = $1", &[&crate_id]));
+    for row in &rows {
+        let owner_id: i32 = row.get(0);
+        if !owner_ids.contains(&owner_id) {
+            try!(conn.execute("DELETE FROM owner_rels WHERE cid = $1 AND oid = $2",
+                              &[&crate_id, &owner_id]));
+        }
+    }
+
+    Ok(())
+}
+
+
+/// Refreshes owners of every crate in database. Returns number of updated
+/// crates. A failed crate doesn't stop the update.
+pub fn update_all_owners(conn: &Connection,
+                         client: &CratesIoClient) -> Result<usize, CrateOpenError> {
+    let rows = try!(conn.query("SELECT id, name FROM crates ORDER BY id", &[]));
+    let mut updated = 0;
+
+    for row in &rows {
+        let crate_id: i32 = row.get(0);
+        let name: String = row.get(1);
+
+        let res = client.get_owners(&name)
+            .map_err(CrateOpenError::from)
+            .and_then(|owners| update_crate_owners(conn, crate_id, &owners));
+
+        match res {
+            Ok(_) => updated += 1,
+            Err(e) => warn!("Failed to update owners of {}: {}", name, e),
+        }
+    }
+
+    Ok(updated)
+}
@@ -0,0 +1,247 @@
+//! Progress of whole registry builds
+//!
+//! `build world` counts crates in crates.io-index before it starts and
+//! tracks number of finished and failed crates. A summary with build rate
+//! and ETA is logged once in REPORT_INTERVAL and progress is saved into
+//! config table, status API is serving it from there.
+//!
+//! Crates are built in sorted order and a checkpoint is saved after every
+//! crate. An interrupted world build is resumed after last finished crate if
+//! it's started again with same rustc.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use postgres::Connection;
+use postgres::error::Error;
+use rustc_serialize::json::{Json, ToJson};
+use time;
+use ::db;
+
+
+/// Interval of progress summaries in seconds
+pub const REPORT_INTERVAL: u64 = 60;
+
+/// Name of progress of latest world build in config table
+pub const WORLD_BUILD_PROGRESS: &'static str = "world_build_progress";
+
+/// Name of checkpoint of an unfinished world build in config table
+pub const WORLD_BUILD_CHECKPOINT: &'static str = "world_build_checkpoint";
+
+
+#[derive(Debug)]
+pub struct Progress {
+    /// Number of crates in crates.io-index
+    pub total: usize,
+    /// Number of finished crates, failed crates are included
+    pub completed: usize,
+    /// Number of crates with a failed version
+    pub failed: usize,
+    started_at: time::Timespec,
+    started: Instant,
+    last_report: Instant,
+}
+
+
+impl Progress {
+    pub fn new(total: usize) -> Progress {
+        Progress {
+            total: total,
+            completed: 0,
+            failed: 0,
+            started_at: time::get_time(),
+            started: Instant::now(),
+            last_report: Instant::now(),
+        }
+    }
+
+
+    /// Adds a finished crate
+    pub fn add(&mut self, successful: bool) {
+        self.completed += 1;
+        if !successful {
+            self.failed += 1;
+        }
+    }
+
+
+    /// Returns true once in REPORT_INTERVAL
+    pub fn report_due(&mut self) -> bool {
+        if self.last_report.elapsed() >= Duration::from_secs(REPORT_INTERVAL) {
+            self.last_report = Instant::now();
+            true
+        } else {
+            false
+        }
+    }
+
+
+    /// Returns number of finished crates per minute after elapsed time
+    fn rate(&self, elapsed: Duration) -> f64 {
+        let minutes = elapsed.as_secs() as f64 / 60.0;
+        if minutes > 0.0 {
+            self.completed as f64 / minutes
+        } else {
+            0.0
+        }
+    }
+
+
+    /// Returns estimated time of remaining crates in seconds
+    fn eta(&self, elapsed: Duration) -> Option<u64> {
+        let rate = self.rate(elapsed);
+        if rate > 0.0 {
+            Some((self.total.saturating_sub(self.completed) as f64 / rate * 60.0) as u64)
+        } else {
+            None
+        }
+    }
+
+
+    /// Returns a human readable summary
+    pub fn summary(&self) -> String {
+        let elapsed = self.started.elapsed();
+        let percent = if self.total > 0 {
+            self.completed as f64 * 100.0 / self.total as f64
+        } else {
+            100.0
+        };
+        format!("Finished {} of {} crates ({:.1}%), {} failed, {:.1} crates/min, ETA {}",
+                self.completed,
+                self.total,
+                percent,
+                self.failed,
+                self.rate(elapsed),
+                self.eta(elapsed)
+                    .map(|eta| format!("{}h {}m", eta / 3600, eta % 3600 / 60))
+                    .unwrap_or("unknown".to_string()))
+    }
+
+
+    /// Returns JSON representation saved into config table
+    pub fn to_json(&self, finished: bool) -> Json {
+        let elapsed = self.started.elapsed();
+        let mut tree = BTreeMap::new();
+        tree.insert("total".to_string(), (self.total as u64).to_json());
+        tree.insert("completed".to_string(), (self.completed as u64).to_json());
+        tree.insert("failed".to_string(), (self.failed as u64).to_json());
+        tree.insert("rate".to_string(), self.rate(elapsed).to_json());
+        tree.insert("eta".to_string(), self.eta(elapsed).to_json());
+        tree.insert("started_at".to_string(),
+                    time::at_utc(self.started_at).rfc3339().to_string().to_json());
+        tree.insert("updated_at".to_string(),
+                    time::now_utc().rfc3339().to_string().to_json());
+        tree.insert("finished".to_string(), finished.to_json());
+        Json::Object(tree)
+    }
+}
+
+
+/// Position of an unfinished world build
+#[derive(Debug, Clone, PartialEq)]
+pub struct Checkpoint {
+    /// rustc version of world build
+    pub rustc_version: String,
+    /// crates.io-index commit crates are built from
+    pub index_commit: String,
+    /// Path of last finished crate file relative to crates.io-index
+    pub last_crate: PathBuf,
+}
+
+
+impl Checkpoint {
+    fn from_json(json: &Json) -> Option<Checkpoint> {
+        let field = |name| json.find(name).and_then(|f| f.as_string()).map(|f| f.to_string());
+        match (field("rustc_version"), field("index_commit"), field("last_crate")) {
+            (Some(rustc_version), Some(index_commit), Some(last_crate)) => {
+                Some(Checkpoint {
+                    rustc_version: rustc_version,
+                    index_commit: index_commit,
+                    last_crate: PathBuf::from(last_crate),
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+
+impl ToJson for Checkpoint {
+    fn to_json(&self) -> Json {
+        let mut tree = BTreeMap::new();
+        tree.insert("rustc_version".to_string(), self.rustc_version.to_json());
+        tree.insert("index_commit".to_string(), self.index_commit.to_json());
+        tree.insert("last_crate".to_string(),
+                    self.last_crate.to_string_lossy().into_owned().to_json());
+        Json::Object(tree)
+    }
+}
+
+
+/// Saves checkpoint of world build into config table
+pub fn save_checkpoint(conn: &Connection, checkpoint: &Checkpoint) -> Result<(), Error> {
+    db::set_config(conn, WORLD_BUILD_CHECKPOINT, &checkpoint.to_json())
+}
+
+
+/// Returns checkpoint of an unfinished world build
+pub fn load_checkpoint(conn: &Connection) -> Result<Option<Checkpoint>, Error> {
+    db::get_config(conn, WORLD_BUILD_CHECKPOINT)
+        .map(|json| json.and_then(|json| Checkpoint::from_json(&json)))
+}
+
+
+/// Removes checkpoint after world build is finished
+pub fn clear_checkpoint(conn: &Connection) -> Result<(), Error> {
+    db::set_config(conn, WORLD_BUILD_CHECKPOINT, &Json::Null)
+}
+
+
+/// Saves progress of world build into config table
+pub fn save_progress(conn: &Connection, progress: &Progress, finished: bool) -> Result<(), Error> {
+    db::set_config(conn, WORLD_BUILD_PROGRESS, &progress.to_json(finished))
+}
+
+
+/// Returns saved progress of latest world build
+pub fn load_progress(conn: &Connection) -> Result<Option<Json>, Error> {
+    db::get_config(conn, WORLD_BUILD_PROGRESS)
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::PathBuf;
+    use std::time::Duration;
+    use rustc_serialize::json::{Json, ToJson};
+
+    #[test]
+    fn test_progress() {
+        let mut progress = Progress::new(100);
+        assert_eq!(progress.eta(Duration::from_secs(0)), None);
+
+        for i in 0..20 {
+            progress.add(i % 10 != 0);
+        }
+        assert_eq!(progress.completed, 20);
+        assert_eq!(progress.failed, 2);
+        assert_eq!(progress.rate(Duration::from_secs(120)), 10.0);
+        // 80 crates left with 10 crates per minute
+        assert_eq!(progress.eta(Duration::from_secs(120)), Some(8 * 60));
+    }
+
+
+    #[test]
+    fn test_checkpoint_json() {
+        let checkpoint = Checkpoint {
+            rustc_version: "rustc 1.9.0".to_string(),
+            index_commit: "0123abcd".to_string(),
+            last_crate: PathBuf::from("ra/nd/rand"),
+        };
+        assert_eq!(Checkpoint::from_json(&checkpoint.to_json()), Some(checkpoint));
+        assert_eq!(Checkpoint::from_json(&Json::Null), None);
+    }
+}
@@ -0,0 +1,232 @@
+//! Persistent build queue
+//!
+//! Crates waiting to be built are stored in queue table. A crate is removed
+//! from queue after a successful build. Failed builds are increasing attempt
+//! count and crate is tried again after a delay, delay is doubled after
+//! every attempt. Crates are marked as failed and not tried again after
+//! maximum number of attempts.
+//!
+//! Crates with a higher priority are built first. Default priority of a crate
+//! is its total number of downloads in database, so popular crates are
+//! documented before the long tail. Crates which aren't in database yet have
+//! priority 0. A bumped crate gets a priority higher than every queued crate
+//! and it's built next.
+
+use postgres::Connection;
+use postgres::error::Error;
+
+
+/// Default maximum number of build attempts of a queued crate
+pub const DEFAULT_MAX_ATTEMPTS: i32 = 5;
+
+/// Delay before second attempt of a failed build in seconds
+pub const RETRY_BASE_DELAY: i64 = 60;
+
+/// Maximum delay between two attempts in seconds
+pub const MAX_RETRY_DELAY: i64 = 24 * 60 * 60;
+
+
+/// A crate waiting in build queue
+#[derive(Debug)]
+pub struct QueuedCrate {
+    pub id: i32,
+    pub name: String,
+    pub version: String,
+    pub attempt: i32,
+    pub priority: i64,
+}
+
+
+/// Returns total number of downloads of every release of a crate
+pub fn crate_downloads(conn: &Connection, name: &str) -> Result<i64, Error> {
+    let rows = try!(conn.query("SELECT COALESCE(SUM(releases.downloads), 0)::BIGINT \
+                                FROM releases \
+                                INNER JOIN crates ON crates.id = releases.crate_id \
+                                WHERE crates.name = $1",
+                               &[&name]));
+    Ok(rows.get(0).get(0))
+}
+
+
+/// Adds a crate into build queue with its downloads as priority. Priority of
+/// an already queued crate is never lowered, a failed crate is queued again
+/// with no attempts.
+pub fn add_crate_to_queue(conn: &Connection, name: &str, version: &str) -> Result<(), Error> {
+    let priority = try!(crate_downloads(conn, name));
+    add_crate_to_queue_with_priority(conn, name, version, priority)
+}
+
+
+/// Adds a crate into build queue with given priority. Priority of an already
+/// queued crate is only raised, a bumped crate keeps its place in queue.
+pub fn add_crate_to_queue_with_priority(conn: &Connection,
+                                        name: &str,
+                                        version: &str,
+                                        priority: i64)
+                                        -> Result<(), Error> {
+    let rows = try!(conn.query("SELECT id, failed FROM queue WHERE name = $1 AND version = $2",
+                               &[&name, &version]));
+    if rows.len() == 0 {
+        try!(conn.execute("INSERT INTO queue (name, version, priority) VALUES ($1, $2, $3)",
+                          &[&name, &version, &priority]));
+    } else if rows.get(0).get::<_, bool>(1) {
+        try!(conn.execute("UPDATE queue \
+                           SET attempt = 0, failed = FALSE, \
+                               priority = GREATEST(priority, $2), \
+                               next_attempt_at = CURRENT_TIMESTAMP \
+                           WHERE id = $1",
+                          &[&rows.get(0).get::<_, i32>(0), &priority]));
+    } else {
+        try!(conn.execute("UPDATE queue SET priority = GREATEST(priority, $2) WHERE id = $1",
+                          &[&rows.get(0).get::<_, i32>(0), &priority]));
+    }
+    Ok(())
+}
+
+
+/// Moves queued versions of a crate to front of queue, attempts of failed
+/// versions are reset. Only given version is bumped if it's given, and it's
+/// added into queue if it's not queued. Returns number of bumped versions.
+pub fn bump_crate(conn: &Connection, name: &str, version: Option<&str>) -> Result<u64, Error> {
+    if let Some(version) = version {
+        try!(add_crate_to_queue(conn, name, version));
+    }
+    conn.execute("UPDATE queue \
+                  SET priority = (SELECT COALESCE(MAX(priority), 0) + 1 FROM queue), \
+                      attempt = 0, failed = FALSE, next_attempt_at = CURRENT_TIMESTAMP \
+                  WHERE name = $1 AND ($2::TEXT IS NULL OR version = $2)",
+                 &[&name, &version])
+}
+
+
+/// Returns next crate to build. Crates with least attempts comes first, then
+/// crates with highest priority and oldest crates. Failed crates waiting for
+/// their next attempt are skipped.
+pub fn next_crate(conn: &Connection) -> Result<Option<QueuedCrate>, Error> {
+    next_crate_except(conn, &[])
+}
+
+
+/// Returns next crate to build skipping crates with given ids, parallel
+/// builders are using this to skip crates being built by other workers.
+pub fn next_crate_except(conn: &Connection, ids: &[i32]) -> Result<Option<QueuedCrate>, Error> {
+    let except = if ids.is_empty() {
+        String::new()
+    } else {
+        let ids: Vec<String> = ids.iter().map(|id| id.to_string()).collect();
+        format!("AND id NOT IN ({})", ids.join(", "))
+    };
+    let query = format!("SELECT id, name, version, attempt, priority \
+                         FROM queue \
+                         WHERE NOT failed AND next_attempt_at <= CURRENT_TIMESTAMP {} \
+                         ORDER BY attempt ASC, priority DESC, id ASC \
+                         LIMIT 1",
+                        except);
+    let rows = try!(conn.query(&query, &[]));
+
+    if rows.len() == 0 {
+        return Ok(None);
+    }
+
+    let row = rows.get(0);
+    Ok(Some(QueuedCrate {
+        id: row.get(0),
+        name: row.get(1),
+        version: row.get(2),
+        attempt: row.get(3),
+        priority: row.get(4),
+    }))
+}
+
+
+/// Removes a crate from build queue
+pub fn remove_crate_from_queue(conn: &Connection, id: i32) -> Result<(), Error> {
+    conn.execute("DELETE FROM queue WHERE id = $1", &[&id]).map(|_| ())
+}
+
+
+/// Returns delay in seconds before next attempt of a crate failed attempt times
+pub fn retry_delay(attempt: i32) -> i64 {
+    if attempt <= 1 {
+        return RETRY_BASE_DELAY;
+    }
+    // 2^(attempt - 1) overflows quickly, stop doubling at maximum delay
+    let mut delay = RETRY_BASE_DELAY;
+    for _ in 1..attempt {
+        delay *= 2;
+        if delay >= MAX_RETRY_DELAY {
+            return MAX_RETRY_DELAY;
+        }
+    }
+    delay
+}
+
+
+/// Increases attempt count of a queued crate and reschedules it with
+/// retry_delay. Crate is marked as failed if it reached max_attempts.
+/// Returns new attempt count.
+pub fn increase_attempt(conn: &Connection, id: i32, max_attempts: i32) -> Result<i32, Error> {
+    let rows = try!(conn.query("UPDATE queue SET attempt = attempt + 1 \
+                                WHERE id = $1 RETURNING attempt",
+                               &[&id]));
+    if rows.len() == 0 {
+        return Ok(0);
+    }
+
+    let attempt: i32 = rows.get(0).get(0);
+    let failed = attempt >= max_attempts;
+    try!(conn.execute("UPDATE queue \
+                       SET failed = $2, \
+                           next_attempt_at = CURRENT_TIMESTAMP + $3 * INTERVAL '1 second' \
+                       WHERE id = $1",
+                      &[&id, &failed, &(retry_delay(attempt) as f64)]));
+    Ok(attempt)
+}
+
+
+/// Returns number of crates waiting in queue
+pub fn queue_length(conn: &Connection) -> Result<i64, Error> {
+    let rows = try!(conn.query("SELECT COUNT(*) FROM queue WHERE NOT failed", &[]));
+    Ok(rows.get(0).get(0))
+}
+
+
+/// Returns number of crates given up after maximum number of attempts
+pub fn failed_length(conn: &Connection) -> Result<i64, Error> {
+    let rows = try!(conn.query("SELECT COUNT(*) FROM queue WHERE failed", &[]));
+    Ok(rows.get(0).get(0))
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_retry_delay() {
+        assert_eq!(retry_delay(1), RETRY_BASE_DELAY);
+        assert_eq!(retry_delay(2), RETRY_BASE_DELAY * 2);
+        assert_eq!(retry_delay(3), RETRY_BASE_DELAY * 4);
+        assert_eq!(retry_delay(100), MAX_RETRY_DELAY);
+    }
+
+
+    #[test]
+    #[ignore]
+    fn test_bumped_crate_keeps_priority() {
+        use ::db::connect_db;
+        let conn = connect_db().unwrap();
+        let name = "cratesfyi-test-bumped-crate";
+        add_crate_to_queue_with_priority(&conn, name, "0.1.0", 1).unwrap();
+        bump_crate(&conn, name, None).unwrap();
+
+        // watcher queues the same version again with its downloads
+        add_crate_to_queue_with_priority(&conn, name, "0.1.0", 1).unwrap();
+        let next = next_crate(&conn).unwrap().unwrap();
+        assert_eq!(next.name, name);
+        assert!(next.priority > 1);
+
+        conn.execute("DELETE FROM queue WHERE name = $1", &[&name]).unwrap();
+    }
+}
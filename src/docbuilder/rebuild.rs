@@ -0,0 +1,173 @@
+//! Rebuilds of releases built with an old rustc
+//!
+//! New rustdoc versions are fixing rendering bugs and changing output of
+//! documentation. A rebuild adds every release whose latest build used a
+//! rustc older than a given version into build queue. Releases of a rebuild
+//! are stored in rebuild_releases table and progress of a rebuild is the
+//! number of these releases built after rebuild is started.
+//!
+//! A single release can be rebuilt on request, i.e. after an infrastructure
+//! bug. Requested releases are moved to front of build queue and requester
+//! is recorded in rebuild_requests table.
+
+use postgres::Connection;
+use postgres::error::Error;
+use semver::Version;
+use time;
+
+use super::queue::{add_crate_to_queue, bump_crate};
+
+
+/// Progress of a rebuild
+#[derive(Debug)]
+pub struct RebuildProgress {
+    pub id: i32,
+    pub rustc_older_than: String,
+    pub created_at: time::Timespec,
+    /// Number of releases added into queue
+    pub total: i64,
+    /// Number of releases built after rebuild is started
+    pub built: i64,
+    /// Number of releases built successfully after rebuild is started
+    pub successful: i64,
+}
+
+
+/// Parses version from output of `rustc --version`,
+/// i.e: `rustc 1.9.0 (e4e8b6668 2016-05-18)`
+pub fn parse_rustc_version(rustc_version: &str) -> Option<Version> {
+    rustc_version.split_whitespace()
+        .nth(1)
+        .and_then(|v| Version::parse(v).ok())
+}
+
+
+/// Returns id, crate name and version of releases whose latest build used a
+/// rustc older than given version
+pub fn outdated_releases(conn: &Connection,
+                         older_than: &Version)
+                         -> Result<Vec<(i32, String, String)>, Error> {
+    // rustc_version of a release is the version used in its latest build
+    let rows = try!(conn.query("SELECT releases.id, crates.name, releases.version, \
+                                       releases.rustc_version \
+                                FROM releases \
+                                INNER JOIN crates ON crates.id = releases.crate_id \
+                                WHERE releases.yanked IS NOT TRUE AND \
+                                      releases.rustc_version IS NOT NULL \
+                                ORDER BY releases.id",
+                               &[]));
+
+    Ok(rows.iter()
+        .filter(|row| {
+            let rustc_version: String = row.get(3);
+            // builds with an unknown rustc version are rebuilt too
+            parse_rustc_version(&rustc_version).map(|v| v < *older_than).unwrap_or(true)
+        })
+        .map(|row| (row.get(0), row.get(1), row.get(2)))
+        .collect())
+}
+
+
+/// Starts a rebuild of every release built with a rustc older than given
+/// version. Returns id of rebuild and number of queued releases.
+pub fn start_rebuild(conn: &Connection, older_than: &Version) -> Result<(i32, usize), Error> {
+    let releases = try!(outdated_releases(conn, older_than));
+
+    let trans = try!(conn.transaction());
+    let rows = try!(trans.query("INSERT INTO rebuilds (rustc_older_than) VALUES ($1) \
+                                 RETURNING id",
+                                &[&older_than.to_string()]));
+    let rebuild_id: i32 = rows.get(0).get(0);
+
+    for &(release_id, _, _) in &releases {
+        try!(trans.execute("INSERT INTO rebuild_releases (rebuild_id, rid) VALUES ($1, $2)",
+                           &[&rebuild_id, &release_id]));
+    }
+    try!(trans.commit());
+
+    for &(_, ref name, ref version) in &releases {
+        try!(add_crate_to_queue(conn, name, version));
+    }
+
+    Ok((rebuild_id, releases.len()))
+}
+
+
+/// Moves a release to front of build queue and records requester. Returns
+/// false if release is not in database.
+pub fn request_rebuild(conn: &Connection,
+                       name: &str,
+                       version: &str,
+                       requested_by: &str,
+                       reason: Option<&str>)
+                       -> Result<bool, Error> {
+    let rows = try!(conn.query("SELECT releases.id \
+                                FROM releases \
+                                INNER JOIN crates ON crates.id = releases.crate_id \
+                                WHERE crates.name = $1 AND releases.version = $2",
+                               &[&name, &version]));
+    if rows.len() == 0 {
+        return Ok(false);
+    }
+
+    try!(conn.execute("INSERT INTO rebuild_requests (name, version, requested_by, reason) \
+                       VALUES ($1, $2, $3, $4)",
+                      &[&name, &version, &requested_by, &reason]));
+    try!(bump_crate(conn, name, Some(version)));
+    Ok(true)
+}
+
+
+/// Returns progress of every rebuild, latest rebuild comes first
+pub fn rebuild_progress(conn: &Connection) -> Result<Vec<RebuildProgress>, Error> {
+    let rows = try!(conn.query("SELECT rebuilds.id, rebuilds.rustc_older_than, \
+                                       rebuilds.created_at, \
+                                       COUNT(rebuild_releases.rid), \
+                                       COUNT(latest_builds.rid), \
+                                       COUNT(latest_builds.rid) \
+                                           FILTER (WHERE latest_builds.successful) \
+                                FROM rebuilds \
+                                LEFT JOIN rebuild_releases \
+                                       ON rebuild_releases.rebuild_id = rebuilds.id \
+                                LEFT JOIN LATERAL ( \
+                                    SELECT rid, successful \
+                                    FROM builds \
+                                    WHERE builds.rid = rebuild_releases.rid AND \
+                                          builds.build_time >= rebuilds.created_at \
+                                    ORDER BY build_time DESC, id DESC \
+                                    LIMIT 1 \
+                                ) AS latest_builds ON TRUE \
+                                GROUP BY rebuilds.id \
+                                ORDER BY rebuilds.id DESC",
+                               &[]));
+
+    Ok(rows.iter()
+        .map(|row| {
+            RebuildProgress {
+                id: row.get(0),
+                rustc_older_than: row.get(1),
+                created_at: row.get(2),
+                total: row.get(3),
+                built: row.get(4),
+                successful: row.get(5),
+            }
+        })
+        .collect())
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use semver::Version;
+
+    #[test]
+    fn test_parse_rustc_version() {
+        assert_eq!(parse_rustc_version("rustc 1.9.0 (e4e8b6668 2016-05-18)"),
+                   Some(Version::parse("1.9.0").unwrap()));
+        assert_eq!(parse_rustc_version("rustc 1.11.0-nightly (0554abac6 2016-06-10)"),
+                   Some(Version::parse("1.11.0-nightly").unwrap()));
+        assert!(parse_rustc_version("rustc").is_none());
+    }
+}
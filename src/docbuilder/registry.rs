@@ -0,0 +1,88 @@
+//! Crate registries
+//!
+//! crates.io is documented by default. A company-internal registry can be
+//! documented instead with a `[registry]` table in configuration file:
+//!
+//! ```text
+//! [registry]
+//! index_url = "https://git.example.com/crates-index.git"
+//! dl = "https://crates.example.com/api/v1/crates"
+//! api = "https://crates.example.com"
+//! ```
+//!
+//! `dl` and `api` are same as keys in `config.json` of registry index. `dl`
+//! can be a template with `{crate}` and `{version}` markers, otherwise
+//! `/{crate}/{version}/download` is appended to it like cargo does. `api` is
+//! base URL of a crates.io compatible API.
+
+use super::index::CRATES_IO_INDEX_URL;
+use super::download::dl_template;
+
+
+/// A registry crates are documented from
+#[derive(Debug, Clone, PartialEq, RustcDecodable)]
+pub struct Registry {
+    /// URL of index repository
+    pub index_url: String,
+    /// Download URL of crates
+    pub dl: String,
+    /// Base URL of API
+    pub api: String,
+}
+
+
+impl Default for Registry {
+    fn default() -> Registry {
+        Registry::crates_io()
+    }
+}
+
+
+impl Registry {
+    /// Returns official crates.io registry
+    pub fn crates_io() -> Registry {
+        Registry {
+            index_url: CRATES_IO_INDEX_URL.to_string(),
+            dl: "https://crates.io/api/v1/crates".to_string(),
+            api: "https://crates.io".to_string(),
+        }
+    }
+
+
+    /// Returns download URL template of crates, see download::download_url
+    pub fn download_url_template(&self) -> String {
+        dl_template(&self.dl)
+    }
+
+
+    /// Returns base URL of API endpoints, i.e: `https://crates.io/api/v1`
+    pub fn api_url(&self) -> String {
+        format!("{}/api/v1", self.api.trim_right_matches('/'))
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use docbuilder::cratesio_api::CRATESIO_API_URL;
+
+    #[test]
+    fn test_registry_urls() {
+        assert_eq!(Registry::crates_io().api_url(), CRATESIO_API_URL);
+
+        let mut registry = Registry {
+            index_url: "https://git.example.com/crates-index.git".to_string(),
+            dl: "https://crates.example.com/api/v1/crates/".to_string(),
+            api: "https://crates.example.com/".to_string(),
+        };
+        assert_eq!(registry.download_url_template(),
+                   "https://crates.example.com/api/v1/crates/{crate}/{version}/download");
+        assert_eq!(registry.api_url(), "https://crates.example.com/api/v1");
+
+        registry.dl = "https://dl.example.com/{crate}-{version}.crate".to_string();
+        assert_eq!(registry.download_url_template(),
+                   "https://dl.example.com/{crate}-{version}.crate");
+    }
+}
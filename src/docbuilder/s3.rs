@@ -0,0 +1,398 @@
+//! S3-compatible storage of generated documentation
+//!
+//! S3Client implements `Storage` and documentation is stored in a bucket
+//! with the same layout as destination directory (`<CRATE>/<VERSION>/...`).
+//! Requests are signed with AWS Signature Version 4 and path-style URLs are
+//! used to support S3-compatible servers.
+
+use std::io;
+use std::io::prelude::*;
+use std::error;
+use std::fmt;
+use std::time::Duration;
+
+use hyper;
+use hyper::client::Client;
+use hyper::header::Headers;
+use hyper::status::StatusCode;
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+use crypto::hmac::Hmac;
+use crypto::mac::Mac;
+use rustc_serialize::hex::ToHex;
+use regex::Regex;
+use time;
+
+
+/// Default endpoint of S3
+pub const DEFAULT_ENDPOINT: &'static str = "https://s3.amazonaws.com";
+
+/// Default region of bucket
+pub const DEFAULT_REGION: &'static str = "us-east-1";
+
+/// Timeout of a single request
+pub const REQUEST_TIMEOUT: u64 = 60;
+
+
+#[derive(Debug)]
+pub enum S3Error {
+    HttpError(hyper::Error),
+    StatusError(StatusCode),
+    IoError(io::Error),
+}
+
+
+impl fmt::Display for S3Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            S3Error::HttpError(ref e) => write!(f, "HTTP error: {}", e),
+            S3Error::StatusError(ref s) => write!(f, "Unexpected response status: {}", s),
+            S3Error::IoError(ref e) => write!(f, "IO error: {}", e),
+        }
+    }
+}
+
+
+impl error::Error for S3Error {
+    fn description(&self) -> &str {
+        "S3 error"
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            S3Error::HttpError(ref e) => Some(e),
+            S3Error::IoError(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+
+/// Client of a S3 bucket
+#[derive(Clone)]
+pub struct S3Client {
+    endpoint: String,
+    bucket: String,
+    region: String,
+    access_key: String,
+    secret_key: String,
+}
+
+
+impl fmt::Debug for S3Client {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "S3Client {{ endpoint: {:?}, bucket: {:?}, region: {:?}, access_key: {:?} }}",
+               self.endpoint,
+               self.bucket,
+               self.region,
+               self.access_key)
+    }
+}
+
+
+impl S3Client {
+    /// Returns a new client. endpoint must not end with a slash.
+    pub fn new(endpoint: &str,
+               bucket: &str,
+               region: &str,
+               access_key: &str,
+               secret_key: &str) -> S3Client {
+        S3Client {
+            endpoint: endpoint.trim_right_matches('/').to_string(),
+            bucket: bucket.to_string(),
+            region: region.to_string(),
+            access_key: access_key.to_string(),
+            secret_key: secret_key.to_string(),
+        }
+    }
+
+
+    /// Uploads an object into bucket
+    pub fn put_object(&self, key: &str, content: &[u8], mime: &str) -> Result<(), S3Error> {
+        let (url, mut headers) = self.signed_request("PUT", key, &[], content);
+        headers.set_raw("Content-Type", vec![mime.as_bytes().to_vec()]);
+
+        let res = try!(client().put(&url)
+                       .headers(headers)
+                       .body(content)
+                       .send()
+                       .map_err(S3Error::HttpError));
+
+        if res.status != StatusCode::Ok {
+            return Err(S3Error::StatusError(res.status));
+        }
+
+        Ok(())
+    }
+
+
+    /// Returns content of an object, None if object doesn't exist
+    pub fn get_object(&self, key: &str) -> Result<Option<Vec<u8>>, S3Error> {
+        let (url, headers) = self.signed_request("GET", key, &[], &[]);
+
+        let mut res = try!(client().get(&url)
+                           .headers(headers)
+                           .send()
+                           .map_err(S3Error::HttpError));
+
+        match res.status {
+            StatusCode::Ok => {}
+            StatusCode::NotFound | StatusCode::Forbidden => return Ok(None),
+            status => return Err(S3Error::StatusError(status)),
+        }
+
+        let mut content = Vec::new();
+        try!(res.read_to_end(&mut content).map_err(S3Error::IoError));
+        Ok(Some(content))
+    }
+
+
+    /// Returns true if object exists
+    pub fn head_object(&self, key: &str) -> Result<bool, S3Error> {
+        let (url, headers) = self.signed_request("HEAD", key, &[], &[]);
+
+        let res = try!(client().head(&url)
+                       .headers(headers)
+                       .send()
+                       .map_err(S3Error::HttpError));
+
+        match res.status {
+            StatusCode::Ok => Ok(true),
+            StatusCode::NotFound | StatusCode::Forbidden => Ok(false),
+            status => Err(S3Error::StatusError(status)),
+        }
+    }
+
+
+    /// Deletes an object
+    pub fn delete_object(&self, key: &str) -> Result<(), S3Error> {
+        let (url, headers) = self.signed_request("DELETE", key, &[], &[]);
+
+        let res = try!(client().delete(&url)
+                       .headers(headers)
+                       .send()
+                       .map_err(S3Error::HttpError));
+
+        match res.status {
+            StatusCode::Ok | StatusCode::NoContent => Ok(()),
+            status => Err(S3Error::StatusError(status)),
+        }
+    }
+
+
+    /// Returns keys of objects starting with prefix. Only first max_keys
+    /// keys are returned if max_keys is given.
+    pub fn list_objects(&self,
+                        prefix: &str,
+                        max_keys: Option<usize>) -> Result<Vec<String>, S3Error> {
+        let key_regex = Regex::new(r"<Key>([^<]*)</Key>").unwrap();
+        let token_regex = Regex::new(r"<NextContinuationToken>([^<]*)</NextContinuationToken>")
+            .unwrap();
+
+        let mut keys = Vec::new();
+        let mut continuation_token: Option<String> = None;
+        let max_keys_str = max_keys.map(|m| m.to_string());
+
+        loop {
+            let (url, headers) = {
+                let mut query = vec![("list-type", "2"), ("prefix", prefix)];
+                if let Some(ref token) = continuation_token {
+                    query.push(("continuation-token", &token[..]));
+                }
+                if let Some(ref max_keys) = max_keys_str {
+                    query.push(("max-keys", &max_keys[..]));
+                }
+                self.signed_request("GET", "", &query, &[])
+            };
+            let mut res = try!(client().get(&url)
+                               .headers(headers)
+                               .send()
+                               .map_err(S3Error::HttpError));
+            if res.status != StatusCode::Ok {
+                return Err(S3Error::StatusError(res.status));
+            }
+
+            let mut body = String::new();
+            try!(res.read_to_string(&mut body).map_err(S3Error::IoError));
+
+            for cap in key_regex.captures_iter(&body) {
+                keys.push(xml_unescape(cap.at(1).unwrap()));
+            }
+
+            continuation_token = token_regex.captures(&body)
+                .and_then(|c| c.at(1))
+                .map(|t| xml_unescape(t));
+            if continuation_token.is_none() || max_keys.map_or(false, |m| keys.len() >= m) {
+                break;
+            }
+        }
+
+        Ok(keys)
+    }
+
+
+    /// Returns URL and signed headers of a request
+    fn signed_request(&self,
+                      method: &str,
+                      key: &str,
+                      query: &[(&str, &str)],
+                      payload: &[u8]) -> (String, Headers) {
+        let now = time::now_utc();
+        let amz_date = now.strftime("%Y%m%dT%H%M%SZ").unwrap().to_string();
+        let date = now.strftime("%Y%m%d").unwrap().to_string();
+
+        let host = self.endpoint
+            .trim_left_matches("https://")
+            .trim_left_matches("http://")
+            .to_string();
+        let uri = format!("/{}/{}", self.bucket, uri_encode(key, false));
+        let payload_hash = sha256_hex(payload);
+
+        let mut query: Vec<String> = query.iter()
+            .map(|&(k, v)| format!("{}={}", uri_encode(k, true), uri_encode(v, true)))
+            .collect();
+        query.sort();
+        let query = query.join("&");
+
+        let signed_headers = "host;x-amz-content-sha256;x-amz-date";
+        let canonical_request = format!("{}\n{}\n{}\nhost:{}\nx-amz-content-sha256:{}\n\
+                                         x-amz-date:{}\n\n{}\n{}",
+                                        method,
+                                        uri,
+                                        query,
+                                        host,
+                                        payload_hash,
+                                        amz_date,
+                                        signed_headers,
+                                        payload_hash);
+
+        let scope = format!("{}/{}/s3/aws4_request", date, self.region);
+        let string_to_sign = format!("AWS4-HMAC-SHA256\n{}\n{}\n{}",
+                                     amz_date,
+                                     scope,
+                                     sha256_hex(canonical_request.as_bytes()));
+
+        let key = signing_key(&self.secret_key, &date, &self.region, "s3");
+        let signature = hmac_sha256(&key, string_to_sign.as_bytes()).to_hex();
+
+        let mut headers = Headers::new();
+        headers.set_raw("x-amz-date", vec![amz_date.into_bytes()]);
+        headers.set_raw("x-amz-content-sha256", vec![payload_hash.into_bytes()]);
+        headers.set_raw("Authorization",
+                        vec![format!("AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, \
+                                      Signature={}",
+                                     self.access_key,
+                                     scope,
+                                     signed_headers,
+                                     signature)
+                                 .into_bytes()]);
+
+        let url = if query.is_empty() {
+            format!("{}{}", self.endpoint, uri)
+        } else {
+            format!("{}{}?{}", self.endpoint, uri, query)
+        };
+        (url, headers)
+    }
+}
+
+
+fn client() -> Client {
+    let mut client = Client::new();
+    client.set_read_timeout(Some(Duration::from_secs(REQUEST_TIMEOUT)));
+    client.set_write_timeout(Some(Duration::from_secs(REQUEST_TIMEOUT)));
+    client
+}
+
+
+/// Returns content type of an object from its extension
+pub fn content_type(key: &str) -> &'static str {
+    match key.rsplit('.').next().unwrap_or("") {
+        "html" => "text/html; charset=utf-8",
+        "css" => "text/css",
+        "js" => "application/javascript",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "woff" => "application/font-woff",
+        "txt" => "text/plain; charset=utf-8",
+        _ => "application/octet-stream",
+    }
+}
+
+
+/// URI encodes a string, slashes are only encoded if encode_slash is true
+fn uri_encode(s: &str, encode_slash: bool) -> String {
+    let mut encoded = String::new();
+    for byte in s.bytes() {
+        match byte {
+            b'A'...b'Z' | b'a'...b'z' | b'0'...b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            b'/' if !encode_slash => encoded.push('/'),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+
+fn xml_unescape(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+
+fn sha256_hex(data: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.input(data);
+    hasher.result_str()
+}
+
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut hmac = Hmac::new(Sha256::new(), key);
+    hmac.input(data);
+    hmac.result().code().to_vec()
+}
+
+
+/// Derives signing key of a request scope
+fn signing_key(secret_key: &str, date: &str, region: &str, service: &str) -> Vec<u8> {
+    let key = hmac_sha256(format!("AWS4{}", secret_key).as_bytes(), date.as_bytes());
+    let key = hmac_sha256(&key, region.as_bytes());
+    let key = hmac_sha256(&key, service.as_bytes());
+    hmac_sha256(&key, b"aws4_request")
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::{signing_key, uri_encode, content_type};
+    use rustc_serialize::hex::ToHex;
+
+    #[test]
+    fn test_signing_key() {
+        // example from AWS Signature Version 4 documentation
+        let key = signing_key("wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+                              "20150830",
+                              "us-east-1",
+                              "iam");
+        assert_eq!(key.to_hex(),
+                   "c4afb1cc5771d871763a393e44b703571b55cc28424d1a5e86da6ed3c154a4b9");
+    }
+
+
+    #[test]
+    fn test_uri_encode() {
+        assert_eq!(uri_encode("rand/0.3.14/rand/index.html", false),
+                   "rand/0.3.14/rand/index.html");
+        assert_eq!(uri_encode("rand/0.3.14/", true), "rand%2F0.3.14%2F");
+        assert_eq!(uri_encode("a b+c", false), "a%20b%2Bc");
+        assert_eq!(content_type("rand/0.3.14/main.css"), "text/css");
+    }
+}
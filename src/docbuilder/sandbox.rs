@@ -0,0 +1,152 @@
+//! Sandboxed build environment
+//!
+//! Builds are running inside a docker container. Crate sources are mounted
+//! read-write into `/build` and root filesystem of container is read-only.
+//! Since `target` directory is placed inside mounted sources, generated
+//! documentation is available in `package_root/target/doc` after build and
+//! it's copied into destination by DocBuilder.
+//!
+//! `CARGO_HOME` is `package_root/.cargo`, cargo can't write its registry
+//! into read-only root filesystem. Container has no network access if
+//! `deny_network` is set in build limits, dependencies are fetched into
+//! `package_root/.cargo` by a container with network access before build in
+//! this case.
+
+use std::path::Path;
+use std::process::Command;
+
+use super::command_result;
+use super::limits::{BuildLimits, CommandFailure};
+
+
+/// Mount point of crate sources inside container
+const SANDBOX_BUILD_DIR: &'static str = "/build";
+
+
+#[derive(Debug, Clone)]
+pub struct DockerSandbox {
+    image: String,
+}
+
+
+impl DockerSandbox {
+    /// Returns a new sandbox using docker image
+    pub fn new(image: String) -> DockerSandbox {
+        DockerSandbox {
+            image: image,
+        }
+    }
+
+
+    /// Returns name of docker image
+    pub fn image(&self) -> &str {
+        &self.image
+    }
+
+
+    /// Runs a command inside a new container with package_root mounted
+    /// into build directory. Container is removed after command exits.
+    ///
+    /// Memory limit is enforced by docker and container is killed if
+    /// command exceeds timeout. Container has no network access if
+    /// deny_network is set.
+    pub fn run(&self,
+               name: &str,
+               package_root: &Path,
+               args: &[String],
+               env: &[(String, String)],
+               limits: &BuildLimits) -> Result<String, CommandFailure> {
+        let mut command = Command::new("docker");
+        command.args(&self.run_args(name, package_root, args, env, limits));
+
+        let res = limits.run_command(command);
+
+        // killing docker client is not enough to stop container
+        if let Err(CommandFailure::TimedOut(_)) = res {
+            let _ = Command::new("docker").arg("kill").arg(name).output();
+        }
+
+        res
+    }
+
+
+    /// Returns arguments of `docker` running command in a container
+    fn run_args(&self,
+                name: &str,
+                package_root: &Path,
+                args: &[String],
+                env: &[(String, String)],
+                limits: &BuildLimits) -> Vec<String> {
+        let mut docker_args: Vec<String> = vec![
+            "run".to_string(),
+            "--rm".to_string(),
+            "--name".to_string(), name.to_string(),
+            "--read-only".to_string(),
+            "--tmpfs".to_string(), "/tmp".to_string(),
+            "-v".to_string(), format!("{}:{}:rw", package_root.display(), SANDBOX_BUILD_DIR),
+            "-w".to_string(), SANDBOX_BUILD_DIR.to_string(),
+            "-e".to_string(), format!("CARGO_TARGET_DIR={}/target", SANDBOX_BUILD_DIR),
+            // root filesystem is read-only, cargo home must be writable and
+            // pre-fetched dependencies must be in mounted directory
+            "-e".to_string(), format!("CARGO_HOME={}/.cargo", SANDBOX_BUILD_DIR),
+        ];
+
+        if limits.deny_network {
+            docker_args.push("--net".to_string());
+            docker_args.push("none".to_string());
+        }
+
+        for &(ref key, ref value) in env {
+            docker_args.push("-e".to_string());
+            docker_args.push(format!("{}={}", key, value));
+        }
+
+        if let Some(memory) = limits.memory {
+            docker_args.push("--memory".to_string());
+            docker_args.push(format!("{}k", memory));
+        }
+
+        docker_args.push(self.image.clone());
+        docker_args.extend(args.iter().cloned());
+        docker_args
+    }
+
+
+    /// Runs a command inside a new container without any mounts
+    pub fn run_without_mount(&self, args: &[&str]) -> Result<String, String> {
+        let output = try!(Command::new("docker")
+                          .arg("run")
+                          .arg("--rm")
+                          .arg("--read-only")
+                          .arg(&self.image)
+                          .args(args)
+                          .output()
+                          .map_err(|e| format!("Failed to run docker: {}", e)));
+
+        command_result(output)
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::DockerSandbox;
+    use docbuilder::limits::BuildLimits;
+    use std::path::Path;
+
+    #[test]
+    fn test_run_args() {
+        let sandbox = DockerSandbox::new("cratesfyi-build".to_string());
+        let args = sandbox.run_args("build-rand",
+                                    Path::new("/home/cratesfyi/rand-0.3.14"),
+                                    &["cargo".to_string(), "doc".to_string()],
+                                    &[],
+                                    &BuildLimits::default());
+        // cargo home is writable without restricted limits too
+        assert!(args.windows(2).any(|a| a[0] == "-e" && a[1] == "CARGO_HOME=/build/.cargo"));
+        assert!(args.contains(&"/home/cratesfyi/rand-0.3.14:/build:rw".to_string()));
+        assert!(!args.contains(&"--net".to_string()));
+        assert_eq!(&args[args.len() - 3..], &["cratesfyi-build", "cargo", "doc"]);
+    }
+}
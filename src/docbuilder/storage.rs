@@ -0,0 +1,286 @@
+//! Storage of generated documentation
+//!
+//! Documentation is read and written through `Storage` trait with paths
+//! relative to storage root, i.e: `rand/0.3.14/rand/index.html`. Local
+//! filesystem is used by default and documentation can be stored in a S3
+//! bucket instead.
+//!
+//! `PrecompressedStorage` stores a gzipped copy of static text files next to
+//! them, i.e: `search-index.js.gz`, web server serves them directly to
+//! clients accepting gzip. HTML pages are not precompressed, web server
+//! inserts cratesfyi header into them.
+
+use std::io;
+use std::io::prelude::*;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::error;
+use std::fmt;
+
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use super::s3::{S3Client, S3Error, content_type};
+
+
+/// Extensions of files stored with a gzipped copy
+const PRECOMPRESSED_EXTENSIONS: &'static [&'static str] = &["css", "js", "json", "svg", "txt"];
+
+
+#[derive(Debug)]
+pub enum StorageError {
+    IoError(io::Error),
+    S3Error(S3Error),
+}
+
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StorageError::IoError(ref e) => write!(f, "IO error: {}", e),
+            StorageError::S3Error(ref e) => write!(f, "S3 error: {}", e),
+        }
+    }
+}
+
+
+impl error::Error for StorageError {
+    fn description(&self) -> &str {
+        "storage error"
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            StorageError::IoError(ref e) => Some(e),
+            StorageError::S3Error(ref e) => Some(e),
+        }
+    }
+}
+
+
+impl From<io::Error> for StorageError {
+    fn from(err: io::Error) -> StorageError {
+        StorageError::IoError(err)
+    }
+}
+
+
+impl From<S3Error> for StorageError {
+    fn from(err: S3Error) -> StorageError {
+        StorageError::S3Error(err)
+    }
+}
+
+
+/// Storage backend of documentation
+pub trait Storage: Send + Sync {
+    /// Stores a file, existing file is overwritten
+    fn store_file(&self, path: &str, content: &[u8]) -> Result<(), StorageError>;
+
+    /// Returns content of a file, None if file doesn't exist
+    fn get_file(&self, path: &str) -> Result<Option<Vec<u8>>, StorageError>;
+
+    /// Returns true if path is a file or a directory containing files
+    fn exists(&self, path: &str) -> Result<bool, StorageError>;
+
+    /// Deletes every file in directory prefix
+    fn delete_prefix(&self, prefix: &str) -> Result<(), StorageError>;
+}
+
+
+/// Storage in a local directory
+pub struct FileStorage {
+    root: PathBuf,
+}
+
+
+impl FileStorage {
+    pub fn new(root: PathBuf) -> FileStorage {
+        FileStorage {
+            root: root,
+        }
+    }
+
+
+    fn full_path(&self, path: &str) -> PathBuf {
+        self.root.join(path.trim_matches('/'))
+    }
+}
+
+
+impl Storage for FileStorage {
+    fn store_file(&self, path: &str, content: &[u8]) -> Result<(), StorageError> {
+        let path = self.full_path(path);
+        if let Some(parent) = path.parent() {
+            try!(fs::create_dir_all(parent));
+        }
+        try!(fs::File::create(path).and_then(|mut f| f.write_all(content)));
+        Ok(())
+    }
+
+
+    fn get_file(&self, path: &str) -> Result<Option<Vec<u8>>, StorageError> {
+        let path = self.full_path(path);
+        if !path.is_file() {
+            return Ok(None);
+        }
+
+        let mut content = Vec::new();
+        try!(fs::File::open(path).and_then(|mut f| f.read_to_end(&mut content)));
+        Ok(Some(content))
+    }
+
+
+    fn exists(&self, path: &str) -> Result<bool, StorageError> {
+        Ok(self.full_path(path).exists())
+    }
+
+
+    fn delete_prefix(&self, prefix: &str) -> Result<(), StorageError> {
+        let path = self.full_path(prefix);
+        if path.is_dir() {
+            try!(fs::remove_dir_all(path));
+        }
+        Ok(())
+    }
+}
+
+
+/// Returns path of gzipped copy of a file if files like it are precompressed
+pub fn precompressed_path(path: &str) -> Option<String> {
+    let extension = match path.rfind('.') {
+        Some(pos) if !path[pos..].contains('/') => &path[pos + 1..],
+        _ => return None,
+    };
+    if PRECOMPRESSED_EXTENSIONS.contains(&extension) {
+        Some(format!("{}.gz", path))
+    } else {
+        None
+    }
+}
+
+
+/// Storage storing a gzipped copy of static text files into inner storage
+pub struct PrecompressedStorage {
+    inner: Arc<Storage>,
+}
+
+
+impl PrecompressedStorage {
+    pub fn new(inner: Arc<Storage>) -> PrecompressedStorage {
+        PrecompressedStorage {
+            inner: inner,
+        }
+    }
+}
+
+
+impl Storage for PrecompressedStorage {
+    fn store_file(&self, path: &str, content: &[u8]) -> Result<(), StorageError> {
+        try!(self.inner.store_file(path, content));
+        if let Some(gz_path) = precompressed_path(path) {
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::Best);
+            try!(encoder.write_all(content));
+            try!(self.inner.store_file(&gz_path, &try!(encoder.finish())));
+        }
+        Ok(())
+    }
+
+
+    fn get_file(&self, path: &str) -> Result<Option<Vec<u8>>, StorageError> {
+        self.inner.get_file(path)
+    }
+
+
+    fn exists(&self, path: &str) -> Result<bool, StorageError> {
+        self.inner.exists(path)
+    }
+
+
+    fn delete_prefix(&self, prefix: &str) -> Result<(), StorageError> {
+        self.inner.delete_prefix(prefix)
+    }
+}
+
+
+impl Storage for S3Client {
+    fn store_file(&self, path: &str, content: &[u8]) -> Result<(), StorageError> {
+        let key = path.trim_matches('/');
+        Ok(try!(self.put_object(key, content, content_type(key))))
+    }
+
+
+    fn get_file(&self, path: &str) -> Result<Option<Vec<u8>>, StorageError> {
+        Ok(try!(self.get_object(path.trim_matches('/'))))
+    }
+
+
+    fn exists(&self, path: &str) -> Result<bool, StorageError> {
+        let key = path.trim_matches('/');
+        if try!(self.head_object(key)) {
+            return Ok(true);
+        }
+        let dir = format!("{}/", key);
+        Ok(!try!(self.list_objects(&dir, Some(1))).is_empty())
+    }
+
+
+    fn delete_prefix(&self, prefix: &str) -> Result<(), StorageError> {
+        let dir = format!("{}/", prefix.trim_matches('/'));
+        for key in try!(self.list_objects(&dir, None)) {
+            try!(self.delete_object(&key));
+        }
+        Ok(())
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use std::env;
+    use std::fs;
+    use std::io::Read;
+    use std::sync::Arc;
+    use flate2::read::GzDecoder;
+    use super::*;
+
+    #[test]
+    fn test_file_storage() {
+        let root = env::temp_dir().join("cratesfyi-test-file-storage");
+        let storage = FileStorage::new(root.clone());
+
+        storage.store_file("rand/0.3.14/rand/index.html", b"<html></html>").unwrap();
+        assert_eq!(storage.get_file("rand/0.3.14/rand/index.html").unwrap(),
+                   Some(b"<html></html>".to_vec()));
+        assert!(storage.exists("rand/0.3.14").unwrap());
+        assert!(storage.get_file("rand/0.3.14/missing.html").unwrap().is_none());
+
+        storage.delete_prefix("rand/0.3.14").unwrap();
+        assert!(!storage.exists("rand/0.3.14").unwrap());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+
+    #[test]
+    fn test_precompressed_storage() {
+        assert_eq!(precompressed_path("rand/0.3.14/search-index.js"),
+                   Some("rand/0.3.14/search-index.js.gz".to_string()));
+        assert_eq!(precompressed_path("rand/0.3.14/rand/index.html"), None);
+        assert_eq!(precompressed_path("rand/0.3.14/rand.d/LICENSE"), None);
+
+        let root = env::temp_dir().join("cratesfyi-test-precompressed-storage");
+        let storage = PrecompressedStorage::new(Arc::new(FileStorage::new(root.clone())));
+        storage.store_file("rand/0.3.14/main.css", b"body {}").unwrap();
+        storage.store_file("rand/0.3.14/index.html", b"<html></html>").unwrap();
+
+        let gzipped = storage.get_file("rand/0.3.14/main.css.gz").unwrap().unwrap();
+        let mut content = String::new();
+        GzDecoder::new(&gzipped[..]).unwrap().read_to_string(&mut content).unwrap();
+        assert_eq!(content, "body {}");
+        assert!(storage.get_file("rand/0.3.14/index.html.gz").unwrap().is_none());
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
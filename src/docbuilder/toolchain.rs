@@ -0,0 +1,72 @@
+//! Rust toolchain of builds
+//!
+//! Builds are using `cargo` on PATH of build environment by default. If a
+//! toolchain is configured, i.e: a pinned nightly like `nightly-2016-06-10`,
+//! cargo and rustc are run with `+<toolchain>` and rustup proxies are
+//! selecting the toolchain. Toolchain must be installed with rustup in build
+//! environment.
+//!
+//! Active toolchain is stored in config table and it's used instead of
+//! `toolchain` in configuration file. It's changed with
+//! `cratesfyi toolchain set <TOOLCHAIN>` and it's used by next builds.
+
+use postgres::Connection;
+use postgres::error::Error;
+use rustc_serialize::json::{Json, ToJson};
+use ::db;
+
+
+const ACTIVE_TOOLCHAIN: &'static str = "active_toolchain";
+
+
+/// Returns true if name can be a rustup toolchain, i.e: `nightly`,
+/// `nightly-2016-06-10` or `1.9.0-x86_64-unknown-linux-gnu`
+pub fn is_valid_toolchain(name: &str) -> bool {
+    !name.is_empty() && !name.starts_with('-') &&
+    name.chars().all(|c| c.is_alphanumeric() || c == '-' || c == '.' || c == '_')
+}
+
+
+/// Returns arguments of a rustup proxy (cargo or rustc) selecting toolchain
+pub fn with_toolchain(toolchain: Option<&str>, args: &[String]) -> Vec<String> {
+    let mut toolchain_args: Vec<String> = toolchain.map(|t| format!("+{}", t))
+        .into_iter()
+        .collect();
+    toolchain_args.extend(args.iter().cloned());
+    toolchain_args
+}
+
+
+/// Returns active toolchain, None if it's not set
+pub fn get_active_toolchain(conn: &Connection) -> Result<Option<String>, Error> {
+    db::get_config(conn, ACTIVE_TOOLCHAIN)
+        .map(|value| value.as_ref().and_then(|v| v.as_string()).map(|v| v.to_string()))
+}
+
+
+/// Sets active toolchain of next builds, toolchain of configuration is used
+/// if it's None
+pub fn set_active_toolchain(conn: &Connection, toolchain: Option<&str>) -> Result<(), Error> {
+    let value = toolchain.map_or(Json::Null, |t| t.to_json());
+    db::set_config(conn, ACTIVE_TOOLCHAIN, &value)
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::{is_valid_toolchain, with_toolchain};
+
+    #[test]
+    fn test_toolchain() {
+        assert!(is_valid_toolchain("nightly-2016-06-10"));
+        assert!(is_valid_toolchain("1.9.0-x86_64-unknown-linux-gnu"));
+        assert!(!is_valid_toolchain(""));
+        assert!(!is_valid_toolchain("--help"));
+        assert!(!is_valid_toolchain("nightly; rm -rf ~"));
+
+        let args = vec!["doc".to_string(), "--no-deps".to_string()];
+        assert_eq!(with_toolchain(Some("nightly"), &args), vec!["+nightly", "doc", "--no-deps"]);
+        assert_eq!(with_toolchain(None, &args), args);
+    }
+}
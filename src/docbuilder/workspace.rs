@@ -0,0 +1,119 @@
+//! Workspace handling
+//!
+//! Crates published from a workspace can still have workspace settings in
+//! their Cargo.toml. Members of workspace or the workspace root pointed by
+//! `package.workspace` are not included in crate file and cargo fails to
+//! build these crates. Workspace settings are replaced with an empty
+//! `[workspace]` table before build, this makes crate root its own
+//! workspace.
+//!
+//! A virtual manifest (a manifest with a `[workspace]` table and without a
+//! `[package]`) is kept as it is and member package of crate is built with
+//! `cargo doc -p`.
+
+use std::io;
+use std::io::prelude::*;
+use std::fs;
+use std::path::Path;
+
+use toml::{Parser, Table, Value};
+
+
+/// Returns true if Cargo.toml in root_dir is a virtual manifest
+pub fn is_virtual_manifest(root_dir: &Path) -> bool {
+    read_cargo_toml(root_dir)
+        .ok()
+        .and_then(|content| Parser::new(&content).parse())
+        .map(|table| table.contains_key("workspace") && !table.contains_key("package"))
+        .unwrap_or(false)
+}
+
+
+/// Replaces workspace settings of Cargo.toml in root_dir with an empty
+/// `[workspace]` table. Returns true if Cargo.toml is rewritten.
+pub fn isolate_workspace(root_dir: &Path) -> io::Result<bool> {
+    let content = try!(read_cargo_toml(root_dir));
+    match isolated_manifest(&content) {
+        Some(manifest) => {
+            let mut file = try!(fs::File::create(root_dir.join("Cargo.toml")));
+            try!(file.write_all(manifest.as_bytes()));
+            Ok(true)
+        }
+        None => Ok(false),
+    }
+}
+
+
+/// Returns manifest without workspace settings, None if manifest doesn't
+/// have any workspace setting or it's a virtual manifest
+fn isolated_manifest(manifest: &str) -> Option<String> {
+    let mut table = match Parser::new(manifest).parse() {
+        Some(table) => table,
+        None => return None,
+    };
+
+    let package_workspace = match table.get_mut("package") {
+        Some(&mut Value::Table(ref mut package)) => package.remove("workspace").is_some(),
+        // virtual manifest
+        _ => return None,
+    };
+
+    let is_empty_workspace = match table.get("workspace") {
+        Some(&Value::Table(ref workspace)) => workspace.is_empty(),
+        _ => false,
+    };
+
+    if !package_workspace && (is_empty_workspace || !table.contains_key("workspace")) {
+        return None;
+    }
+
+    table.insert("workspace".to_string(), Value::Table(Table::new()));
+    Some(Value::Table(table).to_string())
+}
+
+
+fn read_cargo_toml(root_dir: &Path) -> io::Result<String> {
+    let mut content = String::new();
+    try!(fs::File::open(root_dir.join("Cargo.toml"))
+         .and_then(|mut f| f.read_to_string(&mut content)));
+    Ok(content)
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::isolated_manifest;
+    use toml::Parser;
+
+    #[test]
+    fn test_isolated_manifest() {
+        let manifest = isolated_manifest(r#"
+            [package]
+            name = "foo"
+            version = "0.1.0"
+
+            [workspace]
+            members = [ "foo-derive", "foo-cli" ]
+        "#).unwrap();
+        let table = Parser::new(&manifest).parse().unwrap();
+        assert!(table["workspace"].as_table().unwrap().is_empty());
+        assert_eq!(table["package"].as_table().unwrap()["name"].as_str(), Some("foo"));
+
+        let manifest = isolated_manifest(r#"
+            [package]
+            name = "foo-derive"
+            version = "0.1.0"
+            workspace = ".."
+        "#).unwrap();
+        let table = Parser::new(&manifest).parse().unwrap();
+        assert!(!table["package"].as_table().unwrap().contains_key("workspace"));
+        assert!(table.contains_key("workspace"));
+
+        // nothing to change
+        assert!(isolated_manifest("[package]\nname = \"foo\"").is_none());
+        assert!(isolated_manifest("[package]\nname = \"foo\"\n[workspace]").is_none());
+        // virtual manifest
+        assert!(isolated_manifest("[workspace]\nmembers = [ \"foo\" ]").is_none());
+    }
+}
@@ -4,11 +4,19 @@ extern crate log;
 extern crate rustc_serialize;
 extern crate toml;
 extern crate regex;
-extern crate cargo;
 extern crate postgres;
+extern crate openssl;
 extern crate hyper;
 extern crate time;
 extern crate slug;
+extern crate git2;
+extern crate semver;
+extern crate tar;
+extern crate flate2;
+extern crate crypto;
+extern crate libc;
+extern crate wait_timeout;
+extern crate hoedown;
 
 // Web interface dependencies
 extern crate iron;
@@ -20,6 +28,11 @@ extern crate mount;
 pub mod docbuilder;
 pub mod db;
 pub mod web;
+pub mod daemon;
+pub mod config;
+pub mod lock;
+pub mod shutdown;
+pub mod logging;
 
 
 /// Version string generated at build time contains last git
@@ -28,3 +41,8 @@ pub const BUILD_VERSION: &'static str = concat!(
     env!("CARGO_PKG_VERSION"),
     include!(concat!(env!("OUT_DIR"), "/git_version"))
 );
+
+
+/// Target triple cratesfyi is built for, documentation is built for this
+/// target unless a crate selects another default target
+pub const HOST_TARGET: &'static str = include!(concat!(env!("OUT_DIR"), "/host_target"));
@@ -0,0 +1,165 @@
+//! Instance lock
+//!
+//! build and daemon commands are holding a lock file while they are running,
+//! two overlapping instances would update crates.io-index and write
+//! documentation into same paths. Lock is an exclusive `flock` on lock file,
+//! it's released by kernel when its owner exits, so a lock can't be stale.
+//! Lock file contains pid of its owner to report which instance is running.
+
+use std::io::prelude::*;
+use std::io;
+use std::fs;
+use std::fmt;
+use std::error;
+use std::path::Path;
+use std::os::unix::io::AsRawFd;
+
+use libc;
+
+
+/// Default name of lock file in prefix
+pub const LOCK_FILE_NAME: &'static str = "cratesfyi.lock";
+
+
+#[derive(Debug)]
+pub enum LockError {
+    /// Lock is held by a running process: pid of process
+    Locked(i32),
+    IoError(io::Error),
+}
+
+
+impl fmt::Display for LockError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LockError::Locked(pid) => {
+                write!(f, "Another cratesfyi instance is running with pid {}", pid)
+            }
+            LockError::IoError(ref e) => write!(f, "Failed to acquire lock: {}", e),
+        }
+    }
+}
+
+
+impl error::Error for LockError {
+    fn description(&self) -> &str {
+        "lock error"
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            LockError::IoError(ref e) => Some(e),
+            LockError::Locked(_) => None,
+        }
+    }
+}
+
+
+/// Lock file, lock is released when Lock is dropped
+#[derive(Debug)]
+pub struct Lock {
+    /// Locked lock file, None if lock of another instance is ignored
+    file: Option<fs::File>,
+}
+
+
+impl Lock {
+    /// Acquires lock file at path. Lock held by another process is only
+    /// ignored if force is true.
+    pub fn acquire(path: &Path, force: bool) -> Result<Lock, LockError> {
+        if let Some(parent) = path.parent() {
+            try!(fs::create_dir_all(parent).map_err(LockError::IoError));
+        }
+
+        // lock file is never truncated before it's locked, pid of owner
+        // must stay readable
+        let mut file = try!(fs::OpenOptions::new()
+                            .read(true)
+                            .write(true)
+                            .create(true)
+                            .open(path)
+                            .map_err(LockError::IoError));
+
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+            let e = io::Error::last_os_error();
+            if e.kind() != io::ErrorKind::WouldBlock {
+                return Err(LockError::IoError(e));
+            }
+            // owner might not have written its pid yet
+            let pid = read_pid(path).unwrap_or(0);
+            if !force {
+                return Err(LockError::Locked(pid));
+            }
+            warn!("Ignoring lock of running process {}: {}", pid, path.display());
+            return Ok(Lock { file: None });
+        }
+
+        let pid = unsafe { libc::getpid() };
+        try!(file.set_len(0)
+             .and_then(|_| writeln!(file, "{}", pid))
+             .map_err(LockError::IoError));
+
+        Ok(Lock { file: Some(file) })
+    }
+
+
+    /// Returns true if lock is held, false if lock of another instance is
+    /// ignored with force
+    pub fn is_locked(&self) -> bool {
+        self.file.is_some()
+    }
+}
+
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        // lock file isn't removed, another instance might be waiting on it.
+        // Closing file releases the lock, pid is cleared so it's not reported
+        // as running after we exit.
+        if let Some(ref file) = self.file {
+            let _ = file.set_len(0);
+        }
+    }
+}
+
+
+/// Reads pid from a lock file
+fn read_pid(path: &Path) -> Option<i32> {
+    let mut content = String::new();
+    fs::File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut content))
+        .ok()
+        .and_then(|_| content.trim().parse().ok())
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::env;
+    use std::fs;
+    use std::io::Write;
+
+    #[test]
+    fn test_lock() {
+        let dir = env::temp_dir().join("cratesfyi-lock-test");
+        let path = dir.join(LOCK_FILE_NAME);
+
+        {
+            let lock = Lock::acquire(&path, false).unwrap();
+            assert!(lock.is_locked());
+            match Lock::acquire(&path, false) {
+                Err(LockError::Locked(pid)) => assert!(pid > 0),
+                res => panic!("lock acquired twice: {:?}", res),
+            }
+            assert!(!Lock::acquire(&path, true).unwrap().is_locked());
+        }
+
+        // lock file of an exited instance doesn't prevent locking
+        fs::File::create(&path).and_then(|mut f| writeln!(f, "{}", i32::max_value())).unwrap();
+        assert!(Lock::acquire(&path, false).unwrap().is_locked());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
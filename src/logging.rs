@@ -0,0 +1,240 @@
+//! Contextual logging
+//!
+//! Every log message is tagged with context of current thread: build
+//! worker, queue id, crate, version and build phase. Messages are written
+//! into stdout as plain text or as one JSON object per line for log
+//! aggregation.
+//!
+//! Context is changed with `scope`, previous context is restored when
+//! returned guard is dropped:
+//!
+//! ```ignore
+//! let _guard = logging::scope(|ctx| ctx.crate_version("rand", "0.3.14"));
+//! logging::set_phase("build");
+//! info!("Building documentation");
+//! ```
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+use log::{self, LogLevel, LogLevelFilter, LogRecord, LogMetadata, SetLoggerError};
+use rustc_serialize::json::{Json, ToJson};
+use time;
+
+
+thread_local!(static CONTEXT: RefCell<LogContext> = RefCell::new(LogContext::default()));
+
+
+/// Output format of log messages
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+
+impl LogFormat {
+    pub fn from_str(format: &str) -> Option<LogFormat> {
+        match format {
+            "text" => Some(LogFormat::Text),
+            "json" => Some(LogFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+
+/// Context of log messages of a thread
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LogContext {
+    pub worker: Option<usize>,
+    pub queue_id: Option<i32>,
+    pub crate_name: Option<String>,
+    pub version: Option<String>,
+    /// Phase of build, i.e: build, test, copy-doc
+    pub phase: Option<&'static str>,
+}
+
+
+impl LogContext {
+    /// Sets crate and version, phase of previous crate is cleared
+    pub fn crate_version(&mut self, name: &str, version: &str) {
+        self.crate_name = Some(name.to_string());
+        self.version = Some(version.to_string());
+        self.phase = None;
+    }
+
+
+    /// Returns context as space separated key=value pairs
+    fn to_text(&self) -> String {
+        let mut fields = Vec::new();
+        if let Some(worker) = self.worker {
+            fields.push(format!("worker={}", worker));
+        }
+        if let Some(queue_id) = self.queue_id {
+            fields.push(format!("queue_id={}", queue_id));
+        }
+        if let Some(ref crate_name) = self.crate_name {
+            fields.push(format!("crate={}", crate_name));
+        }
+        if let Some(ref version) = self.version {
+            fields.push(format!("version={}", version));
+        }
+        if let Some(phase) = self.phase {
+            fields.push(format!("phase={}", phase));
+        }
+        fields.join(" ")
+    }
+
+
+    fn insert_into(&self, tree: &mut BTreeMap<String, Json>) {
+        if let Some(worker) = self.worker {
+            tree.insert("worker".to_string(), (worker as u64).to_json());
+        }
+        if let Some(queue_id) = self.queue_id {
+            tree.insert("queue_id".to_string(), queue_id.to_json());
+        }
+        if let Some(ref crate_name) = self.crate_name {
+            tree.insert("crate".to_string(), crate_name.to_json());
+        }
+        if let Some(ref version) = self.version {
+            tree.insert("version".to_string(), version.to_json());
+        }
+        if let Some(phase) = self.phase {
+            tree.insert("phase".to_string(), phase.to_json());
+        }
+    }
+}
+
+
+/// Restores previous context of thread when it's dropped
+pub struct ContextGuard {
+    previous: Option<LogContext>,
+}
+
+
+impl Drop for ContextGuard {
+    fn drop(&mut self) {
+        if let Some(previous) = self.previous.take() {
+            CONTEXT.with(|ctx| *ctx.borrow_mut() = previous);
+        }
+    }
+}
+
+
+/// Changes log context of current thread until returned guard is dropped
+pub fn scope<F: FnOnce(&mut LogContext)>(update: F) -> ContextGuard {
+    CONTEXT.with(|ctx| {
+        let mut ctx = ctx.borrow_mut();
+        let previous = ctx.clone();
+        update(&mut ctx);
+        ContextGuard { previous: Some(previous) }
+    })
+}
+
+
+/// Sets build phase of current context, it's cleared when enclosing scope
+/// is dropped
+pub fn set_phase(phase: &'static str) {
+    CONTEXT.with(|ctx| ctx.borrow_mut().phase = Some(phase));
+}
+
+
+/// Returns log context of current thread
+pub fn context() -> LogContext {
+    CONTEXT.with(|ctx| ctx.borrow().clone())
+}
+
+
+struct Logger {
+    format: LogFormat,
+}
+
+
+impl log::Log for Logger {
+    fn enabled(&self, metadata: &LogMetadata) -> bool {
+        metadata.level() <= LogLevel::Info
+    }
+
+    fn log(&self, record: &LogRecord) {
+        if self.enabled(record.metadata()) {
+            println!("{}", format_record(self.format, record.level(), &record.args().to_string(),
+                                         &context()));
+        }
+    }
+}
+
+
+fn format_record(format: LogFormat, level: LogLevel, message: &str, ctx: &LogContext) -> String {
+    let now = time::now().to_utc().rfc3339().to_string();
+    match format {
+        LogFormat::Text => {
+            let ctx = ctx.to_text();
+            if ctx.is_empty() {
+                format!("{} {} - {}", now, level, message)
+            } else {
+                format!("{} {} [{}] - {}", now, level, ctx, message)
+            }
+        }
+        LogFormat::Json => {
+            let mut tree = BTreeMap::new();
+            tree.insert("time".to_string(), now.to_json());
+            tree.insert("level".to_string(), level.to_string().to_json());
+            tree.insert("message".to_string(), message.to_json());
+            ctx.insert_into(&mut tree);
+            Json::Object(tree).to_string()
+        }
+    }
+}
+
+
+/// Initializes logger writing info and more important messages into stdout
+pub fn init(format: LogFormat) -> Result<(), SetLoggerError> {
+    log::set_logger(|max_log_level| {
+        max_log_level.set(LogLevelFilter::Info);
+        Box::new(Logger { format: format })
+    })
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::format_record;
+    use log::LogLevel;
+    use rustc_serialize::json::Json;
+
+    #[test]
+    fn test_scope() {
+        {
+            let _worker = scope(|ctx| ctx.worker = Some(1));
+            {
+                let _crte = scope(|ctx| ctx.crate_version("rand", "0.3.14"));
+                set_phase("build");
+                assert_eq!(context().crate_name, Some("rand".to_string()));
+                assert_eq!(context().phase, Some("build"));
+            }
+            assert_eq!(context(), LogContext { worker: Some(1), ..LogContext::default() });
+        }
+        assert_eq!(context(), LogContext::default());
+    }
+
+
+    #[test]
+    fn test_format_record() {
+        let mut ctx = LogContext::default();
+        ctx.crate_version("rand", "0.3.14");
+        ctx.queue_id = Some(12);
+
+        let text = format_record(LogFormat::Text, LogLevel::Info, "Building", &ctx);
+        assert!(text.ends_with(" INFO [queue_id=12 crate=rand version=0.3.14] - Building"));
+
+        let json = Json::from_str(&format_record(LogFormat::Json, LogLevel::Info, "Building",
+                                                 &ctx)).unwrap();
+        assert_eq!(json.find("crate").and_then(|c| c.as_string()), Some("rand"));
+        assert_eq!(json.find("queue_id").and_then(|q| q.as_i64()), Some(12));
+        assert_eq!(json.find("message").and_then(|m| m.as_string()), Some("Building"));
+        assert!(json.find("phase").is_none());
+    }
+}
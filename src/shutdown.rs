@@ -0,0 +1,42 @@
+//! Graceful shutdown
+//!
+//! build and daemon commands are catching SIGINT and SIGTERM. Crate being
+//! built is finished, or it's kept in build queue if its build is killed by
+//! signal, and no new crate is started after a signal is received.
+
+use std::sync::atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
+
+use libc;
+
+
+/// Number of received signal, 0 if no signal is received
+static RECEIVED_SIGNAL: AtomicUsize = ATOMIC_USIZE_INIT;
+
+
+extern "C" fn handle_signal(signal: libc::c_int) {
+    RECEIVED_SIGNAL.store(signal as usize, Ordering::SeqCst);
+}
+
+
+/// Installs SIGINT and SIGTERM handlers requesting shutdown
+pub fn install_signal_handlers() {
+    unsafe {
+        libc::signal(libc::SIGINT, handle_signal as libc::sighandler_t);
+        libc::signal(libc::SIGTERM, handle_signal as libc::sighandler_t);
+    }
+}
+
+
+/// Returns true if SIGINT or SIGTERM is received
+pub fn shutdown_requested() -> bool {
+    received_signal().is_some()
+}
+
+
+/// Returns number of received shutdown signal
+pub fn received_signal() -> Option<i32> {
+    match RECEIVED_SIGNAL.load(Ordering::SeqCst) {
+        0 => None,
+        signal => Some(signal as i32),
+    }
+}
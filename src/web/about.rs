@@ -0,0 +1,49 @@
+//! About page
+
+use std::collections::BTreeMap;
+
+use iron::prelude::*;
+use iron::status;
+use handlebars_iron::Template;
+use rustc_serialize::json::{Json, ToJson};
+use ::db::{disk_usage, CrateDiskUsage};
+use super::{Page, DbConnection};
+
+
+/// Number of crates listed in largest crates
+const LARGEST_CRATES: i64 = 10;
+
+
+/// Returns size in megabytes with one decimal
+fn megabytes(bytes: i64) -> String {
+    format!("{:.1} MB", bytes as f64 / (1024.0 * 1024.0))
+}
+
+
+impl ToJson for CrateDiskUsage {
+    fn to_json(&self) -> Json {
+        let mut tree = BTreeMap::new();
+        tree.insert("name".to_string(), self.name.to_json());
+        tree.insert("releases".to_string(), self.releases.to_json());
+        tree.insert("doc_size".to_string(), megabytes(self.doc_size_bytes).to_json());
+        tree.insert("source_size".to_string(), megabytes(self.source_size_bytes).to_json());
+        Json::Object(tree)
+    }
+}
+
+
+pub fn about_handler(req: &mut Request) -> IronResult<Response> {
+    let crates = {
+        let conn = req.extensions.get::<DbConnection>().unwrap();
+        match disk_usage(conn, LARGEST_CRATES) {
+            Ok(crates) => crates,
+            Err(e) => return Err(IronError::new(e, status::InternalServerError)),
+        }
+    };
+
+    let mut content = BTreeMap::new();
+    content.insert("crates".to_string(), crates.to_json());
+    let mut resp = Response::new();
+    resp.set_mut(Template::new("about", Page::new("About", content))).set_mut(status::Ok);
+    Ok(resp)
+}
@@ -0,0 +1,365 @@
+//! JSON status API
+//!
+//! `/api/v1/crates/:name` returns status of latest release of a crate and
+//! `/api/v1/crates/:name/:version` returns status of a specific release.
+//! Not found responses have crates with similar names in `suggestions`.
+//! `/api/v1/progress` returns progress of latest world build and number of
+//! crates in build queue. `/api/v1/build-environment` returns environment
+//! variables set for `cargo doc` of every crate, `DOCS_RS_TARGET` is target
+//! of a build without `default-target`.
+//!
+//! `POST /api/v1/crates/:name/:version/rebuild` moves a release to front of
+//! build queue. Requests need a token from `admin_tokens` in configuration
+//! file in `Authorization: Bearer <TOKEN>` header, owner of token is recorded
+//! as requester. An optional reason can be sent as `{"reason": "..."}`.
+
+use std::io::prelude::*;
+use std::collections::BTreeMap;
+
+use iron::prelude::*;
+use iron::{Handler, status};
+use iron::headers::ContentType;
+use router::Router;
+use postgres::Connection;
+use postgres::error::Error;
+use rustc_serialize::json::{Json, ToJson};
+use time;
+use super::{DbConnection, base_url, doc_targets_from_json};
+use ::docbuilder::{queue, progress, rebuild};
+use ::docbuilder::metadata::Metadata;
+use ::db::similar_crate_names;
+use super::rustdoc::SUGGESTIONS;
+
+
+/// Status of a release
+pub struct ReleaseStatus {
+    pub name: String,
+    pub version: String,
+    pub build_status: i32,
+    pub rustdoc_status: i32,
+    pub target_name: Option<String>,
+    pub release_time: Option<time::Timespec>,
+    pub yanked: bool,
+    /// Additional targets with documentation
+    pub doc_targets: Vec<String>,
+    /// False if crate doesn't have a library target
+    pub is_library: bool,
+    /// Target triple documentation is built for, None for releases added
+    /// before it's recorded
+    pub default_target: Option<String>,
+    /// True if private items are documented
+    pub document_private_items: bool,
+}
+
+
+impl ReleaseStatus {
+    /// Returns JSON representation, doc_url is null if release doesn't
+    /// have documentation
+    fn to_json_with_base_url(&self, base_url: &str) -> Json {
+        let doc_url = match self.target_name {
+            Some(ref target_name) if self.rustdoc_status == 1 && self.is_library => {
+                Some(format!("{}/{}/{}/{}/", base_url, self.name, self.version, target_name))
+            }
+            _ => None,
+        };
+
+        let mut tree = BTreeMap::new();
+        tree.insert("name".to_string(), self.name.to_json());
+        tree.insert("version".to_string(), self.version.to_json());
+        tree.insert("build_status".to_string(), self.build_status.to_json());
+        tree.insert("rustdoc_status".to_string(), self.rustdoc_status.to_json());
+        tree.insert("doc_url".to_string(), doc_url.to_json());
+        tree.insert("release_time".to_string(),
+                    self.release_time.map(|t| time::at_utc(t).rfc3339().to_string()).to_json());
+        tree.insert("yanked".to_string(), self.yanked.to_json());
+        tree.insert("doc_targets".to_string(), self.doc_targets.to_json());
+        tree.insert("is_library".to_string(), self.is_library.to_json());
+        tree.insert("default_target".to_string(), self.default_target.to_json());
+        tree.insert("document_private_items".to_string(),
+                    self.document_private_items.to_json());
+        Json::Object(tree)
+    }
+}
+
+
+/// Returns status of a release. Latest release is used if version is not
+/// given.
+pub fn get_release_status(conn: &Connection,
+                          name: &str,
+                          version: Option<&str>) -> Result<Option<ReleaseStatus>, Error> {
+    let query = "SELECT crates.name, releases.version, releases.build_status, \
+                        releases.rustdoc_status, releases.target_name, \
+                        releases.release_time, releases.yanked, releases.doc_targets, \
+                        releases.is_library, releases.default_target, \
+                        releases.document_private_items \
+                 FROM releases \
+                 INNER JOIN crates ON crates.id = releases.crate_id \
+                 WHERE crates.name = $1 AND ($2::TEXT IS NULL OR releases.version = $2) \
+                 ORDER BY releases.release_time DESC \
+                 LIMIT 1";
+    let rows = try!(conn.query(query, &[&name, &version]));
+
+    if rows.len() == 0 {
+        return Ok(None);
+    }
+
+    let row = rows.get(0);
+    let yanked: Option<bool> = row.get(6);
+    let document_private_items: Option<bool> = row.get(10);
+    Ok(Some(ReleaseStatus {
+        name: row.get(0),
+        version: row.get(1),
+        build_status: row.get(2),
+        rustdoc_status: row.get(3),
+        target_name: row.get(4),
+        release_time: row.get(5),
+        yanked: yanked.unwrap_or(false),
+        doc_targets: doc_targets_from_json(row.get(7)),
+        is_library: row.get(8),
+        default_target: row.get(9),
+        document_private_items: document_private_items.unwrap_or(false),
+    }))
+}
+
+
+pub fn release_status_handler(req: &mut Request) -> IronResult<Response> {
+    let (name, version) = {
+        let router = req.extensions.get::<Router>().unwrap();
+        (router.find("name").unwrap_or("").to_string(),
+         router.find("version").map(|v| v.to_string()))
+    };
+
+    let (release_status, suggestions) = {
+        let conn = req.extensions.get::<DbConnection>().unwrap();
+        match get_release_status(conn, &name, version.as_ref().map(|v| &v[..])) {
+            Ok(None) => {
+                let suggestions = similar_crate_names(conn, &name, SUGGESTIONS)
+                    .unwrap_or(Vec::new());
+                (None, suggestions)
+            }
+            Ok(release_status) => (release_status, Vec::new()),
+            Err(e) => return Err(IronError::new(e, status::InternalServerError)),
+        }
+    };
+
+    let (resp_status, json) = match release_status {
+        Some(release_status) => {
+            (status::Ok, release_status.to_json_with_base_url(&base_url(req)))
+        }
+        None => {
+            let mut tree = BTreeMap::new();
+            tree.insert("error".to_string(), "not found".to_json());
+            tree.insert("suggestions".to_string(), suggestions.to_json());
+            (status::NotFound, Json::Object(tree))
+        }
+    };
+
+    let mut resp = Response::with((resp_status, json.to_string()));
+    resp.headers.set(ContentType("application/json".parse().unwrap()));
+    Ok(resp)
+}
+
+
+
+/// Returns progress of latest world build and build queue
+pub fn get_progress(conn: &Connection) -> Result<Json, Error> {
+    let mut queue_tree = BTreeMap::new();
+    queue_tree.insert("waiting".to_string(), try!(queue::queue_length(conn)).to_json());
+    queue_tree.insert("failed".to_string(), try!(queue::failed_length(conn)).to_json());
+
+    let mut tree = BTreeMap::new();
+    tree.insert("world".to_string(), try!(progress::load_progress(conn)).unwrap_or(Json::Null));
+    tree.insert("queue".to_string(), Json::Object(queue_tree));
+    Ok(Json::Object(tree))
+}
+
+
+pub fn progress_handler(req: &mut Request) -> IronResult<Response> {
+    let json = {
+        let conn = req.extensions.get::<DbConnection>().unwrap();
+        match get_progress(conn) {
+            Ok(json) => json,
+            Err(e) => return Err(IronError::new(e, status::InternalServerError)),
+        }
+    };
+
+    let mut resp = Response::with((status::Ok, json.to_string()));
+    resp.headers.set(ContentType("application/json".parse().unwrap()));
+    Ok(resp)
+}
+
+
+pub fn build_environment_handler(_: &mut Request) -> IronResult<Response> {
+    let mut tree = BTreeMap::new();
+    for (key, value) in Metadata::default().build_env() {
+        tree.insert(key, value.to_json());
+    }
+
+    let mut resp = Response::with((status::Ok, Json::Object(tree).to_string()));
+    resp.headers.set(ContentType("application/json".parse().unwrap()));
+    Ok(resp)
+}
+
+
+
+/// Handler of rebuild requests
+pub struct RebuildHandler {
+    /// Tokens keyed by their owners
+    tokens: BTreeMap<String, String>,
+}
+
+
+impl RebuildHandler {
+    pub fn new(tokens: BTreeMap<String, String>) -> RebuildHandler {
+        RebuildHandler { tokens: tokens }
+    }
+
+
+    /// Returns owner of token in Authorization header
+    fn requester(&self, req: &Request) -> Option<String> {
+        let header = match req.headers.get_raw("Authorization") {
+            Some(values) if values.len() == 1 => String::from_utf8_lossy(&values[0]).into_owned(),
+            _ => return None,
+        };
+        find_token_owner(&self.tokens, &header).map(|owner| owner.to_string())
+    }
+}
+
+
+impl Handler for RebuildHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let requested_by = match self.requester(req) {
+            Some(requested_by) => requested_by,
+            None => return Ok(json_response(status::Unauthorized, error_json("unauthorized"))),
+        };
+
+        let (name, version) = {
+            let router = req.extensions.get::<Router>().unwrap();
+            (router.find("name").unwrap_or("").to_string(),
+             router.find("version").unwrap_or("").to_string())
+        };
+
+        let mut body = String::new();
+        if req.body.read_to_string(&mut body).is_err() {
+            return Ok(json_response(status::BadRequest, error_json("invalid body")));
+        }
+        let reason = Json::from_str(&body)
+            .ok()
+            .and_then(|json| json.find("reason").and_then(|r| r.as_string()).map(String::from));
+
+        let queued = {
+            let conn = req.extensions.get::<DbConnection>().unwrap();
+            match rebuild::request_rebuild(conn, &name, &version, &requested_by,
+                                           reason.as_ref().map(|r| &r[..])) {
+                Ok(queued) => queued,
+                Err(e) => return Err(IronError::new(e, status::InternalServerError)),
+            }
+        };
+
+        if !queued {
+            return Ok(json_response(status::NotFound, error_json("not found")));
+        }
+
+        info!("Rebuild of {}-{} requested by {}", name, version, requested_by);
+        let mut tree = BTreeMap::new();
+        tree.insert("name".to_string(), name.to_json());
+        tree.insert("version".to_string(), version.to_json());
+        tree.insert("requested_by".to_string(), requested_by.to_json());
+        tree.insert("queued".to_string(), true.to_json());
+        Ok(json_response(status::Ok, Json::Object(tree)))
+    }
+}
+
+
+/// Returns owner of token in a `Bearer <TOKEN>` Authorization header
+fn find_token_owner<'a>(tokens: &'a BTreeMap<String, String>, header: &str) -> Option<&'a str> {
+    let mut parts = header.split_whitespace();
+    let token = match (parts.next(), parts.next(), parts.next()) {
+        (Some(scheme), Some(token), None) if scheme.to_lowercase() == "bearer" => token,
+        _ => return None,
+    };
+    tokens.iter()
+        .find(|&(_, t)| !t.is_empty() && constant_time_eq(t.as_bytes(), token.as_bytes()))
+        .map(|(owner, _)| &owner[..])
+}
+
+
+/// Compares tokens without leaking length of common prefix through timing
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b.iter()).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+
+fn error_json(error: &str) -> Json {
+    let mut tree = BTreeMap::new();
+    tree.insert("error".to_string(), error.to_json());
+    Json::Object(tree)
+}
+
+
+fn json_response(resp_status: status::Status, json: Json) -> Response {
+    let mut resp = Response::with((resp_status, json.to_string()));
+    resp.headers.set(ContentType("application/json".parse().unwrap()));
+    resp
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::find_token_owner;
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn test_release_status_json() {
+        let mut release_status = ReleaseStatus {
+            name: "rand".to_string(),
+            version: "0.3.14".to_string(),
+            build_status: 1,
+            rustdoc_status: 1,
+            target_name: Some("rand".to_string()),
+            release_time: None,
+            yanked: false,
+            doc_targets: vec!["i686-pc-windows-gnu".to_string()],
+            is_library: true,
+            default_target: Some("x86_64-unknown-linux-gnu".to_string()),
+            document_private_items: false,
+        };
+
+        let json = release_status.to_json_with_base_url("https://example.com");
+        assert_eq!(json.find("doc_url").and_then(|u| u.as_string()),
+                   Some("https://example.com/rand/0.3.14/rand/"));
+        assert!(json.find("release_time").unwrap().is_null());
+        assert_eq!(json.find("doc_targets").and_then(|t| t.as_array()).map(|t| t.len()),
+                   Some(1));
+
+        release_status.rustdoc_status = 0;
+        let json = release_status.to_json_with_base_url("https://example.com");
+        assert!(json.find("doc_url").unwrap().is_null());
+
+        release_status.rustdoc_status = 1;
+        release_status.is_library = false;
+        let json = release_status.to_json_with_base_url("https://example.com");
+        assert!(json.find("doc_url").unwrap().is_null());
+        assert_eq!(json.find("is_library").and_then(|l| l.as_boolean()), Some(false));
+    }
+
+
+    #[test]
+    fn test_find_token_owner() {
+        let mut tokens = BTreeMap::new();
+        tokens.insert("onur".to_string(), "secret".to_string());
+        tokens.insert("disabled".to_string(), String::new());
+
+        assert_eq!(find_token_owner(&tokens, "Bearer secret"), Some("onur"));
+        assert_eq!(find_token_owner(&tokens, "bearer secret"), Some("onur"));
+        assert_eq!(find_token_owner(&tokens, "Bearer secre"), None);
+        assert_eq!(find_token_owner(&tokens, "Bearer "), None);
+        assert_eq!(find_token_owner(&tokens, "secret"), None);
+        assert_eq!(find_token_owner(&tokens, "Basic secret"), None);
+    }
+}
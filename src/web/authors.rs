@@ -0,0 +1,122 @@
+//! Author pages
+//!
+//! `/authors/:slug` lists crates with a release by an author, with latest
+//! version and build status of every crate. Authors are stored from
+//! `authors` of Cargo.toml when a release is added into database.
+
+use iron::prelude::*;
+use iron::status;
+use router::Router;
+use handlebars_iron::Template;
+use postgres::Connection;
+use postgres::error::Error;
+use super::{Page, DbConnection, CrateSummary, CrateList};
+
+
+/// Number of crates shown in a page
+pub const CRATES_PER_PAGE: i64 = 30;
+
+
+/// Returns id and name of an author
+pub fn get_author(conn: &Connection, slug: &str) -> Result<Option<(i32, String)>, Error> {
+    let rows = try!(conn.query("SELECT id, name FROM authors WHERE slug = $1", &[&slug]));
+    Ok(if rows.len() == 0 {
+        None
+    } else {
+        Some((rows.get(0).get(0), rows.get(0).get(1)))
+    })
+}
+
+
+/// Returns crates with a release by an author ordered by name. Latest
+/// release of every crate is returned, even if it's not by author.
+pub fn get_author_crates(conn: &Connection,
+                         author_id: i32,
+                         limit: i64,
+                         offset: i64) -> Result<Vec<CrateSummary>, Error> {
+    let rows = try!(conn.query("SELECT crates.name, latest.version, latest.description, \
+                                       latest.build_status, latest.release_time \
+                                FROM crates \
+                                INNER JOIN LATERAL ( \
+                                    SELECT version, description, build_status, release_time \
+                                    FROM releases \
+                                    WHERE releases.crate_id = crates.id \
+                                    ORDER BY release_time DESC NULLS LAST, id DESC \
+                                    LIMIT 1 \
+                                ) AS latest ON TRUE \
+                                WHERE crates.id IN ( \
+                                    SELECT releases.crate_id \
+                                    FROM author_rels \
+                                    INNER JOIN releases ON releases.id = author_rels.rid \
+                                    WHERE author_rels.aid = $1 \
+                                ) \
+                                ORDER BY crates.name \
+                                LIMIT $2 OFFSET $3",
+                               &[&author_id, &limit, &offset]));
+
+    Ok(rows.iter().map(|row| {
+        CrateSummary {
+            name: row.get(0),
+            version: row.get(1),
+            description: row.get(2),
+            build_status: row.get(3),
+            release_time: row.get(4),
+        }
+    }).collect())
+}
+
+
+pub fn author_handler(req: &mut Request) -> IronResult<Response> {
+    let (slug, page) = {
+        let router = req.extensions.get::<Router>().unwrap();
+        (router.find("slug").unwrap_or("").to_string(),
+         router.find("page").and_then(|p| p.parse::<i64>().ok()).unwrap_or(1))
+    };
+
+    if page < 1 {
+        return Ok(Response::with(status::NotFound));
+    }
+
+    let author = {
+        let conn = req.extensions.get::<DbConnection>().unwrap();
+        // one more crate is requested to find out if there is a next page
+        match get_author(conn, &slug).and_then(|author| match author {
+            Some((id, name)) => {
+                get_author_crates(conn, id, CRATES_PER_PAGE + 1, (page - 1) * CRATES_PER_PAGE)
+                    .map(|crates| Some((name, crates)))
+            }
+            None => Ok(None),
+        }) {
+            Ok(author) => author,
+            Err(e) => return Err(IronError::new(e, status::InternalServerError)),
+        }
+    };
+
+    let (name, mut crates) = match author {
+        Some(author) => author,
+        None => return Ok(Response::with(status::NotFound)),
+    };
+
+    let has_next_page = crates.len() as i64 > CRATES_PER_PAGE;
+    crates.truncate(CRATES_PER_PAGE as usize);
+
+    let prev_page = if page > 1 {
+        Some(format!("/authors/{}/{}", slug, page - 1))
+    } else {
+        None
+    };
+    let next_page = if has_next_page {
+        Some(format!("/authors/{}/{}", slug, page + 1))
+    } else {
+        None
+    };
+    let list = CrateList {
+        crates: crates,
+        prev_page: prev_page,
+        next_page: next_page,
+    };
+    let mut resp = Response::new();
+    resp.set_mut(Template::new("crate_list", Page::new(&format!("Crates of {}", name), list)))
+        .set_mut(status::Ok);
+    Ok(resp)
+}
@@ -0,0 +1,176 @@
+//! Build status badges
+
+use iron::prelude::*;
+use iron::status;
+use iron::headers::{ContentType, CacheControl, CacheDirective};
+use router::Router;
+use postgres::Connection;
+use super::DbConnection;
+
+
+/// Build status shown in a badge
+#[derive(Debug, PartialEq)]
+pub enum BadgeStatus {
+    Passing,
+    Failing,
+    TimedOut,
+    /// Crate doesn't have a library target
+    Binary,
+    Unknown,
+}
+
+
+impl BadgeStatus {
+    /// Returns badge status from build_status, rustdoc_status and is_library
+    /// columns
+    pub fn from_status(build_status: i32, rustdoc_status: i32, is_library: bool) -> BadgeStatus {
+        match build_status {
+            1 if !is_library => BadgeStatus::Binary,
+            1 if rustdoc_status == 1 => BadgeStatus::Passing,
+            1 | -1 => BadgeStatus::Failing,
+            -2 => BadgeStatus::TimedOut,
+            _ => BadgeStatus::Unknown,
+        }
+    }
+
+
+    fn text(&self) -> &'static str {
+        match *self {
+            BadgeStatus::Passing => "passing",
+            BadgeStatus::Failing => "failing",
+            BadgeStatus::TimedOut => "timed out",
+            BadgeStatus::Binary => "binary",
+            BadgeStatus::Unknown => "unknown",
+        }
+    }
+
+
+    fn color(&self) -> &'static str {
+        match *self {
+            BadgeStatus::Passing => "#4c1",
+            BadgeStatus::Failing => "#e05d44",
+            BadgeStatus::TimedOut => "#dfb317",
+            BadgeStatus::Binary => "#007ec6",
+            BadgeStatus::Unknown => "#9f9f9f",
+        }
+    }
+}
+
+
+/// Renders a shields style badge
+pub fn badge_svg(subject: &str, status: &BadgeStatus) -> String {
+    // Approximate width of Verdana 11px characters
+    fn text_width(text: &str) -> usize {
+        text.chars().count() * 7 + 10
+    }
+
+    let subject_width = text_width(subject);
+    let status_width = text_width(status.text());
+    let width = subject_width + status_width;
+
+    format!("<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"20\">\
+             <linearGradient id=\"b\" x2=\"0\" y2=\"100%\">\
+             <stop offset=\"0\" stop-color=\"#bbb\" stop-opacity=\".1\"/>\
+             <stop offset=\"1\" stop-opacity=\".1\"/>\
+             </linearGradient>\
+             <mask id=\"a\"><rect width=\"{width}\" height=\"20\" rx=\"3\" fill=\"#fff\"/></mask>\
+             <g mask=\"url(#a)\">\
+             <path fill=\"#555\" d=\"M0 0h{sw}v20H0z\"/>\
+             <path fill=\"{color}\" d=\"M{sw} 0h{stw}v20H{sw}z\"/>\
+             <path fill=\"url(#b)\" d=\"M0 0h{width}v20H0z\"/>\
+             </g>\
+             <g fill=\"#fff\" text-anchor=\"middle\" \
+             font-family=\"DejaVu Sans,Verdana,Geneva,sans-serif\" font-size=\"11\">\
+             <text x=\"{sx}\" y=\"15\" fill=\"#010101\" fill-opacity=\".3\">{subject}</text>\
+             <text x=\"{sx}\" y=\"14\">{subject}</text>\
+             <text x=\"{stx}\" y=\"15\" fill=\"#010101\" fill-opacity=\".3\">{status}</text>\
+             <text x=\"{stx}\" y=\"14\">{status}</text>\
+             </g></svg>",
+            width = width,
+            sw = subject_width,
+            stw = status_width,
+            sx = subject_width / 2,
+            stx = subject_width + status_width / 2,
+            color = status.color(),
+            subject = subject,
+            status = status.text())
+}
+
+
+/// Returns badge status of a crate. Latest release is used if version
+/// is not given.
+pub fn crate_badge_status(conn: &Connection, name: &str, version: Option<&str>) -> BadgeStatus {
+    let rows = match version {
+        Some(version) => {
+            conn.query("SELECT releases.build_status, releases.rustdoc_status, \
+                               releases.is_library \
+                        FROM releases \
+                        INNER JOIN crates ON crates.id = releases.crate_id \
+                        WHERE crates.name = $1 AND releases.version = $2",
+                       &[&name, &version])
+        }
+        None => {
+            conn.query("SELECT releases.build_status, releases.rustdoc_status, \
+                               releases.is_library \
+                        FROM releases \
+                        INNER JOIN crates ON crates.id = releases.crate_id \
+                        WHERE crates.name = $1 \
+                        ORDER BY releases.release_time DESC \
+                        LIMIT 1",
+                       &[&name])
+        }
+    };
+
+    match rows {
+        Ok(ref rows) if rows.len() > 0 => {
+            let row = rows.get(0);
+            BadgeStatus::from_status(row.get(0), row.get(1), row.get(2))
+        }
+        _ => BadgeStatus::Unknown,
+    }
+}
+
+
+pub fn badge_handler(req: &mut Request) -> IronResult<Response> {
+    let name = req.extensions.get::<Router>().unwrap().find("name").unwrap_or("").to_string();
+    let version = req.url.query.as_ref()
+        .and_then(|q| q.split('&').find(|p| p.starts_with("version=")))
+        .map(|p| p[8..].to_string());
+
+    let status = {
+        let conn = req.extensions.get::<DbConnection>().unwrap();
+        crate_badge_status(conn, &name, version.as_ref().map(|v| &v[..]))
+    };
+
+    let mut resp = Response::with((status::Ok, badge_svg("docs", &status)));
+    resp.headers.set(ContentType("image/svg+xml".parse().unwrap()));
+    resp.headers.set(CacheControl(vec![CacheDirective::NoCache]));
+    Ok(resp)
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_badge_status() {
+        assert_eq!(BadgeStatus::from_status(1, 1, true), BadgeStatus::Passing);
+        assert_eq!(BadgeStatus::from_status(1, 0, true), BadgeStatus::Failing);
+        assert_eq!(BadgeStatus::from_status(1, 0, false), BadgeStatus::Binary);
+        assert_eq!(BadgeStatus::from_status(-1, 0, true), BadgeStatus::Failing);
+        assert_eq!(BadgeStatus::from_status(-1, 0, false), BadgeStatus::Failing);
+        assert_eq!(BadgeStatus::from_status(-2, 0, true), BadgeStatus::TimedOut);
+        assert_eq!(BadgeStatus::from_status(0, 0, true), BadgeStatus::Unknown);
+    }
+
+
+    #[test]
+    fn test_badge_svg() {
+        let svg = badge_svg("docs", &BadgeStatus::Passing);
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.contains(">passing<"));
+        assert!(svg.contains("#4c1"));
+    }
+}
@@ -0,0 +1,176 @@
+//! Build logs
+//!
+//! `/crate/:name/:version/builds` lists build attempts of a release and
+//! `/builds/:id` shows full output of a build.
+
+use std::collections::BTreeMap;
+
+use iron::prelude::*;
+use iron::status;
+use router::Router;
+use handlebars_iron::Template;
+use postgres::Connection;
+use postgres::error::Error;
+use rustc_serialize::json::{Json, ToJson};
+use time;
+use super::{Page, DbConnection, duration_to_str};
+
+
+/// A build attempt of a release
+pub struct BuildInfo {
+    pub id: i32,
+    pub rustc_version: String,
+    pub cratesfyi_version: String,
+    pub successful: bool,
+    pub timed_out: bool,
+    pub download_failed: bool,
+    pub build_time: time::Timespec,
+}
+
+
+impl BuildInfo {
+    fn status(&self) -> &'static str {
+        if self.successful {
+            "success"
+        } else if self.timed_out {
+            "timed out"
+        } else if self.download_failed {
+            "download failed"
+        } else {
+            "failed"
+        }
+    }
+}
+
+
+impl ToJson for BuildInfo {
+    fn to_json(&self) -> Json {
+        let mut tree = BTreeMap::new();
+        tree.insert("id".to_string(), self.id.to_json());
+        tree.insert("status".to_string(), self.status().to_json());
+        tree.insert("rustc_version".to_string(), self.rustc_version.to_json());
+        tree.insert("cratesfyi_version".to_string(), self.cratesfyi_version.to_json());
+        tree.insert("build_time".to_string(), duration_to_str(self.build_time).to_json());
+        Json::Object(tree)
+    }
+}
+
+
+/// Returns build attempts of a release, latest build first
+pub fn get_builds(conn: &Connection, name: &str, version: &str) -> Result<Vec<BuildInfo>, Error> {
+    let rows = try!(conn.query("SELECT builds.id, builds.rustc_version, \
+                                       builds.cratesfyi_version, builds.successful, \
+                                       builds.timed_out, builds.build_time, \
+                                       builds.download_failed \
+                                FROM builds \
+                                INNER JOIN releases ON releases.id = builds.rid \
+                                INNER JOIN crates ON crates.id = releases.crate_id \
+                                WHERE crates.name = $1 AND releases.version = $2 \
+                                ORDER BY builds.build_time DESC, builds.id DESC",
+                               &[&name, &version]));
+
+    Ok(rows.iter().map(|row| {
+        BuildInfo {
+            id: row.get(0),
+            rustc_version: row.get(1),
+            cratesfyi_version: row.get(2),
+            successful: row.get(3),
+            timed_out: row.get(4),
+            download_failed: row.get(6),
+            build_time: row.get(5),
+        }
+    }).collect())
+}
+
+
+/// Returns (name, version, build, output) of a build
+pub fn get_build(conn: &Connection,
+                 id: i32) -> Result<Option<(String, String, BuildInfo, String)>, Error> {
+    let rows = try!(conn.query("SELECT crates.name, releases.version, \
+                                       builds.id, builds.rustc_version, \
+                                       builds.cratesfyi_version, builds.successful, \
+                                       builds.timed_out, builds.build_time, builds.output, \
+                                       builds.download_failed \
+                                FROM builds \
+                                INNER JOIN releases ON releases.id = builds.rid \
+                                INNER JOIN crates ON crates.id = releases.crate_id \
+                                WHERE builds.id = $1",
+                               &[&id]));
+
+    if rows.len() == 0 {
+        return Ok(None);
+    }
+
+    let row = rows.get(0);
+    let output: Option<String> = row.get(8);
+    Ok(Some((row.get(0),
+             row.get(1),
+             BuildInfo {
+                 id: row.get(2),
+                 rustc_version: row.get(3),
+                 cratesfyi_version: row.get(4),
+                 successful: row.get(5),
+                 timed_out: row.get(6),
+                 download_failed: row.get(9),
+                 build_time: row.get(7),
+             },
+             output.unwrap_or(String::new()))))
+}
+
+
+pub fn builds_handler(req: &mut Request) -> IronResult<Response> {
+    let (name, version) = {
+        let router = req.extensions.get::<Router>().unwrap();
+        (router.find("name").unwrap_or("").to_string(),
+         router.find("version").unwrap_or("").to_string())
+    };
+
+    let builds = {
+        let conn = req.extensions.get::<DbConnection>().unwrap();
+        match get_builds(conn, &name, &version) {
+            Ok(builds) => builds,
+            Err(e) => return Err(IronError::new(e, status::InternalServerError)),
+        }
+    };
+
+    if builds.is_empty() {
+        return Ok(Response::with(status::NotFound));
+    }
+
+    let mut content = BTreeMap::new();
+    content.insert("builds".to_string(), builds.to_json());
+    let page = Page::new(&format!("Builds of {}-{}", name, version), content);
+    let mut resp = Response::new();
+    resp.set_mut(Template::new("builds", page)).set_mut(status::Ok);
+    Ok(resp)
+}
+
+
+pub fn build_handler(req: &mut Request) -> IronResult<Response> {
+    let id = match req.extensions.get::<Router>().unwrap().find("id")
+        .and_then(|id| id.parse::<i32>().ok()) {
+        Some(id) => id,
+        None => return Ok(Response::with(status::NotFound)),
+    };
+
+    let build = {
+        let conn = req.extensions.get::<DbConnection>().unwrap();
+        match get_build(conn, id) {
+            Ok(build) => build,
+            Err(e) => return Err(IronError::new(e, status::InternalServerError)),
+        }
+    };
+
+    match build {
+        Some((name, version, build, output)) => {
+            let title = format!("Build #{} of {}-{}", build.id, name, version);
+            let mut content = BTreeMap::new();
+            content.insert("build".to_string(), build.to_json());
+            content.insert("output".to_string(), output.to_json());
+            let mut resp = Response::new();
+            resp.set_mut(Template::new("build", Page::new(&title, content))).set_mut(status::Ok);
+            Ok(resp)
+        }
+        None => Ok(Response::with(status::NotFound)),
+    }
+}
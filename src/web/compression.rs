@@ -0,0 +1,93 @@
+//! Gzip compression of responses
+//!
+//! Text responses are compressed if client accepts gzip encoding. Responses
+//! which are already encoded, i.e: pre-compressed documentation files, are
+//! left as they are.
+
+use std::io::{self, Write};
+
+use iron::prelude::*;
+use iron::AfterMiddleware;
+use iron::headers::{AcceptEncoding, ContentEncoding, ContentLength, ContentType, ETag,
+                    EntityTag, Encoding};
+use iron::response::{WriteBody, ResponseBody};
+use flate2::Compression;
+use flate2::write::GzEncoder;
+
+
+/// Compresses text responses with gzip
+pub struct GzipMiddleware;
+
+
+/// Body compressing another body while it's written
+struct GzipBody(Box<WriteBody + Send>);
+
+
+impl WriteBody for GzipBody {
+    fn write_body(&mut self, res: &mut ResponseBody) -> io::Result<()> {
+        let mut encoder = GzEncoder::new(res, Compression::Default);
+        try!(self.0.write_body(&mut ResponseBody::new(&mut encoder)));
+        try!(encoder.finish()).flush()
+    }
+}
+
+
+/// Returns true if client accepts gzip encoding
+pub fn accepts_gzip(req: &Request) -> bool {
+    req.headers.get::<AcceptEncoding>().map_or(false, |accept| {
+        accept.iter().any(|q| q.item == Encoding::Gzip && q.quality.0 > 0)
+    })
+}
+
+
+/// Returns true if content type is worth compressing
+fn compressible(content_type: &str) -> bool {
+    content_type.starts_with("text/") || content_type.contains("javascript") ||
+    content_type.contains("json") || content_type.contains("xml")
+}
+
+
+impl AfterMiddleware for GzipMiddleware {
+    fn after(&self, req: &mut Request, mut resp: Response) -> IronResult<Response> {
+        let content_type = match resp.headers.get::<ContentType>() {
+            Some(content_type) => content_type.to_string(),
+            None => return Ok(resp),
+        };
+        if !accepts_gzip(req) || !compressible(&content_type) ||
+           resp.headers.has::<ContentEncoding>() {
+            return Ok(resp);
+        }
+
+        let body = match resp.body.take() {
+            Some(body) => body,
+            None => return Ok(resp),
+        };
+        resp.body = Some(Box::new(GzipBody(body)));
+        resp.headers.remove::<ContentLength>();
+        resp.headers.set(ContentEncoding(vec![Encoding::Gzip]));
+        resp.headers.set_raw("Vary", vec![b"Accept-Encoding".to_vec()]);
+        // compressed content is not byte for byte same anymore
+        let etag = resp.headers.get::<ETag>().map(|etag| etag.0.tag().to_string());
+        if let Some(etag) = etag {
+            resp.headers.set(ETag(EntityTag::new(true, etag)));
+        }
+        Ok(resp)
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::compressible;
+
+    #[test]
+    fn test_compressible() {
+        assert!(compressible("text/html; charset=utf-8"));
+        assert!(compressible("application/javascript"));
+        assert!(compressible("image/svg+xml"));
+        assert!(compressible("application/json"));
+        assert!(!compressible("image/png"));
+        assert!(!compressible("application/font-woff"));
+    }
+}
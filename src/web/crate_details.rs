@@ -0,0 +1,333 @@
+//! Crate details
+//!
+//! `/crate/:name/:version` shows metadata of a release: description, README
+//! or crate documentation, dependencies, authors, keywords, license, links
+//! and every version of crate with its build status. `/crate/:name` shows
+//! latest release.
+
+use std::collections::BTreeMap;
+
+use iron::prelude::*;
+use iron::status;
+use router::Router;
+use postgres::Connection;
+use postgres::error::Error;
+use handlebars_iron::Template;
+use rustc_serialize::json::{Json, ToJson};
+use slug::slugify;
+use time;
+use ::docbuilder::markdown::render_markdown;
+use super::{Page, DbConnection, duration_to_str, build_status_text};
+use super::rustdoc::{cache_control, LATEST_MAX_AGE};
+
+
+/// A dependency of a release
+#[derive(Debug, PartialEq)]
+pub struct Dependency {
+    pub name: String,
+    pub req: String,
+    /// Kind of dependency: normal, dev or build
+    pub kind: String,
+    pub optional: bool,
+}
+
+
+/// A version of a crate in version table
+pub struct VersionInfo {
+    pub version: String,
+    pub build_status: i32,
+    pub yanked: bool,
+    pub release_time: Option<time::Timespec>,
+}
+
+
+pub struct CrateDetails {
+    pub name: String,
+    pub version: String,
+    pub description: Option<String>,
+    /// Crate level documentation in markdown
+    pub rustdoc: Option<String>,
+    pub readme_html: Option<String>,
+    pub dependencies: Vec<Dependency>,
+    /// Authors as written in Cargo.toml, i.e: `Name <email>`
+    pub authors: Vec<String>,
+    pub keywords: Vec<String>,
+    pub license: Option<String>,
+    pub repository_url: Option<String>,
+    pub homepage_url: Option<String>,
+    pub release_time: Option<time::Timespec>,
+    pub yanked: bool,
+    pub build_status: i32,
+    pub rustdoc_status: i32,
+    pub target_name: Option<String>,
+    /// Every version of crate, latest version first
+    pub versions: Vec<VersionInfo>,
+}
+
+
+/// Returns strings of a JSON array, other values are skipped
+fn strings_from_json(json: Option<Json>) -> Vec<String> {
+    json.as_ref()
+        .and_then(|j| j.as_array())
+        .map(|values| values.iter().filter_map(|v| v.as_string()).map(String::from).collect())
+        .unwrap_or(Vec::new())
+}
+
+
+/// Parses dependencies column of releases
+fn dependencies_from_json(json: Option<Json>) -> Vec<Dependency> {
+    let dependencies = match json.as_ref().and_then(|j| j.as_array()) {
+        Some(dependencies) => dependencies,
+        None => return Vec::new(),
+    };
+    dependencies.iter()
+        .filter_map(|d| {
+            let field = |name| d.find(name).and_then(|f| f.as_string()).map(String::from);
+            field("name").map(|name| {
+                Dependency {
+                    name: name,
+                    req: field("req").unwrap_or("*".to_string()),
+                    kind: field("kind").unwrap_or("normal".to_string()),
+                    optional: d.find("optional").and_then(|o| o.as_boolean()).unwrap_or(false),
+                }
+            })
+        })
+        .collect()
+}
+
+
+/// Returns every version of a crate, latest version first
+pub fn get_versions(conn: &Connection, crate_id: i32) -> Result<Vec<VersionInfo>, Error> {
+    let rows = try!(conn.query("SELECT version, build_status, yanked, release_time \
+                                FROM releases \
+                                WHERE crate_id = $1 \
+                                ORDER BY release_time DESC NULLS LAST, id DESC",
+                               &[&crate_id]));
+    Ok(rows.iter().map(|row| {
+        VersionInfo {
+            version: row.get(0),
+            build_status: row.get(1),
+            yanked: row.get::<_, Option<bool>>(2).unwrap_or(false),
+            release_time: row.get(3),
+        }
+    }).collect())
+}
+
+
+/// Returns details of a release, latest non-yanked release is used if
+/// version is not given
+pub fn get_crate_details(conn: &Connection,
+                         name: &str,
+                         version: Option<&str>) -> Result<Option<CrateDetails>, Error> {
+    let rows = try!(conn.query("SELECT crates.id, releases.version, releases.description, \
+                                       releases.description_long, releases.readme_html, \
+                                       releases.dependencies, releases.authors, \
+                                       releases.keywords, releases.license, \
+                                       releases.repository_url, releases.homepage_url, \
+                                       releases.release_time, releases.yanked, \
+                                       releases.build_status, releases.rustdoc_status, \
+                                       releases.target_name \
+                                FROM releases \
+                                INNER JOIN crates ON crates.id = releases.crate_id \
+                                WHERE crates.name = $1 AND \
+                                      ($2::TEXT IS NULL OR releases.version = $2) \
+                                ORDER BY releases.yanked IS TRUE, \
+                                         releases.release_time DESC NULLS LAST, \
+                                         releases.id DESC \
+                                LIMIT 1",
+                               &[&name, &version]));
+
+    if rows.len() == 0 {
+        return Ok(None);
+    }
+
+    let row = rows.get(0);
+    Ok(Some(CrateDetails {
+        name: name.to_string(),
+        version: row.get(1),
+        description: row.get(2),
+        rustdoc: row.get(3),
+        readme_html: row.get(4),
+        dependencies: dependencies_from_json(row.get(5)),
+        authors: strings_from_json(row.get(6)),
+        keywords: strings_from_json(row.get(7)),
+        license: row.get(8),
+        repository_url: row.get(9),
+        homepage_url: row.get(10),
+        release_time: row.get(11),
+        yanked: row.get::<_, Option<bool>>(12).unwrap_or(false),
+        build_status: row.get(13),
+        rustdoc_status: row.get(14),
+        target_name: row.get(15),
+        versions: try!(get_versions(conn, row.get(0))),
+    }))
+}
+
+
+/// Returns name and slug of every author or keyword, authors are named
+/// without their emails
+fn links_json(names: &[String]) -> Json {
+    names.iter()
+        .map(|name| {
+            let name = name.split('<').next().unwrap_or("").trim();
+            let mut tree = BTreeMap::new();
+            tree.insert("name".to_string(), name.to_json());
+            tree.insert("slug".to_string(), slugify(name).to_json());
+            Json::Object(tree)
+        })
+        .collect::<Vec<Json>>()
+        .to_json()
+}
+
+
+impl ToJson for Dependency {
+    fn to_json(&self) -> Json {
+        let mut tree = BTreeMap::new();
+        tree.insert("name".to_string(), self.name.to_json());
+        tree.insert("req".to_string(), self.req.to_json());
+        // kind is only shown for dev and build dependencies
+        if self.kind != "normal" {
+            tree.insert("kind".to_string(), self.kind.to_json());
+        }
+        tree.insert("optional".to_string(), self.optional.to_json());
+        Json::Object(tree)
+    }
+}
+
+
+impl ToJson for CrateDetails {
+    fn to_json(&self) -> Json {
+        let mut tree = BTreeMap::new();
+        tree.insert("name".to_string(), self.name.to_json());
+        tree.insert("version".to_string(), self.version.to_json());
+        tree.insert("description".to_string(), self.description.to_json());
+        tree.insert("yanked".to_string(), self.yanked.to_json());
+        match self.target_name {
+            Some(ref target_name) if self.rustdoc_status == 1 => {
+                tree.insert("documentation_url".to_string(),
+                            format!("/{}/{}/{}/", self.name, self.version, target_name)
+                                .to_json());
+            }
+            _ => {}
+        }
+        tree.insert("repository_url".to_string(), self.repository_url.to_json());
+        tree.insert("homepage_url".to_string(), self.homepage_url.to_json());
+        tree.insert("license".to_string(), self.license.to_json());
+        tree.insert("release_time".to_string(),
+                    self.release_time.map(duration_to_str).to_json());
+        tree.insert("authors".to_string(), links_json(&self.authors));
+        tree.insert("keywords".to_string(), links_json(&self.keywords));
+        tree.insert("dependencies".to_string(), self.dependencies.to_json());
+        let versions: Vec<Json> = self.versions
+            .iter()
+            .map(|v| {
+                let mut tree = BTreeMap::new();
+                tree.insert("version".to_string(), v.version.to_json());
+                tree.insert("url".to_string(),
+                            format!("/crate/{}/{}", self.name, v.version).to_json());
+                tree.insert("release_time".to_string(),
+                            v.release_time.map(duration_to_str).to_json());
+                tree.insert("status".to_string(), build_status_text(v.build_status).to_json());
+                tree.insert("yanked".to_string(), v.yanked.to_json());
+                Json::Object(tree)
+            })
+            .collect();
+        tree.insert("versions".to_string(), versions.to_json());
+        // README is rendered and sanitized when release is added into database
+        tree.insert("readme_html".to_string(), self.readme_html.to_json());
+        tree.insert("rustdoc_html".to_string(),
+                    self.rustdoc.as_ref().map(|r| render_markdown(r)).to_json());
+        Json::Object(tree)
+    }
+}
+
+
+pub fn crate_details_handler(req: &mut Request) -> IronResult<Response> {
+    let (name, version) = {
+        let router = req.extensions.get::<Router>().unwrap();
+        (router.find("name").unwrap_or("").to_string(),
+         router.find("version").map(|v| v.to_string()))
+    };
+
+    let details = {
+        let conn = req.extensions.get::<DbConnection>().unwrap();
+        match get_crate_details(conn, &name, version.as_ref().map(|v| &v[..])) {
+            Ok(details) => details,
+            Err(e) => return Err(IronError::new(e, status::InternalServerError)),
+        }
+    };
+
+    match details {
+        Some(details) => {
+            let title = format!("{}-{}", details.name, details.version);
+            let mut resp = Response::new();
+            resp.set_mut(Template::new("crate_details", Page::new(&title, details)))
+                .set_mut(status::Ok);
+            // page of latest version changes with every release
+            if version.is_none() {
+                resp.headers.set(cache_control(LATEST_MAX_AGE));
+            }
+            Ok(resp)
+        }
+        None => Ok(Response::with(status::NotFound)),
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::dependencies_from_json;
+    use rustc_serialize::json::{Json, ToJson};
+
+    #[test]
+    fn test_crate_details_json() {
+        let dependencies = Json::from_str(r#"[{"name": "libc", "req": "^0.2", "kind": "normal",
+                                              "optional": false, "target": null},
+                                             {"name": "log", "req": "^0.3", "kind": "dev",
+                                              "optional": true, "target": null}]"#)
+            .unwrap();
+        let details = CrateDetails {
+            name: "rand".to_string(),
+            version: "0.3.14".to_string(),
+            description: Some("Random number generators".to_string()),
+            rustdoc: Some("Utilities for *random* numbers".to_string()),
+            readme_html: None,
+            dependencies: dependencies_from_json(Some(dependencies)),
+            authors: vec!["The Rust Project Developers <rust@example.com>".to_string()],
+            keywords: vec!["random".to_string()],
+            license: Some("MIT/Apache-2.0".to_string()),
+            repository_url: Some("https://github.com/rust-lang/rand".to_string()),
+            homepage_url: None,
+            release_time: None,
+            yanked: false,
+            build_status: 1,
+            rustdoc_status: 1,
+            target_name: Some("rand".to_string()),
+            versions: vec![VersionInfo {
+                version: "0.3.14".to_string(),
+                build_status: 1,
+                yanked: false,
+                release_time: None,
+            }],
+        };
+
+        let json = details.to_json();
+        let find = |path: &[&str]| json.find_path(path).and_then(|v| v.as_string());
+        assert_eq!(find(&["documentation_url"]), Some("/rand/0.3.14/rand/"));
+        let author = &json["authors"][0];
+        assert_eq!(author.find("slug").and_then(|s| s.as_string()),
+                   Some("the-rust-project-developers"));
+        assert_eq!(author.find("name").and_then(|s| s.as_string()),
+                   Some("The Rust Project Developers"));
+        assert_eq!(json["keywords"][0].find("slug").and_then(|s| s.as_string()),
+                   Some("random"));
+        assert!(json["dependencies"][0].find("kind").is_none());
+        assert_eq!(json["dependencies"][1].find("kind").and_then(|k| k.as_string()),
+                   Some("dev"));
+        assert_eq!(json["versions"][0].find("url").and_then(|u| u.as_string()),
+                   Some("/crate/rand/0.3.14"));
+        assert!(find(&["rustdoc_html"]).unwrap().contains("<em>random</em>"));
+    }
+}
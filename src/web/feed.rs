@@ -0,0 +1,126 @@
+//! Atom feed of newly built documentation
+
+use iron::prelude::*;
+use iron::status;
+use iron::headers::ContentType;
+use postgres::Connection;
+use postgres::error::Error;
+use time;
+use super::{DbConnection, base_url, escape_html};
+
+
+/// Number of entries in feed
+pub const FEED_LENGTH: i64 = 50;
+
+
+/// A successfully documented release
+pub struct FeedEntry {
+    pub name: String,
+    pub version: String,
+    pub description: Option<String>,
+    pub release_time: time::Timespec,
+}
+
+
+/// Returns latest successfully documented releases
+pub fn get_feed_entries(conn: &Connection, limit: i64) -> Result<Vec<FeedEntry>, Error> {
+    let rows = try!(conn.query("SELECT crates.name, \
+                                       releases.version, \
+                                       releases.description, \
+                                       releases.release_time \
+                                FROM releases \
+                                INNER JOIN crates ON releases.crate_id = crates.id \
+                                WHERE releases.build_status = 1 AND \
+                                      releases.rustdoc_status = 1 AND \
+                                      releases.release_time IS NOT NULL \
+                                ORDER BY releases.release_time DESC \
+                                LIMIT $1",
+                               &[&limit]));
+
+    Ok(rows.iter().map(|row| {
+        FeedEntry {
+            name: row.get(0),
+            version: row.get(1),
+            description: row.get(2),
+            release_time: row.get(3),
+        }
+    }).collect())
+}
+
+
+/// Renders an Atom feed. base_url is used to generate links to documentation
+/// and it must not end with a slash.
+pub fn atom_feed(entries: &[FeedEntry], base_url: &str) -> String {
+    let updated = entries.first()
+        .map(|e| e.release_time)
+        .unwrap_or(time::get_time());
+
+    let mut feed = String::new();
+    feed.push_str("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    feed.push_str("<feed xmlns=\"http://www.w3.org/2005/Atom\">\n");
+    feed.push_str("<title>cratesfyi: Recently documented crates</title>\n");
+    feed.push_str(&format!("<id>{}/releases/feed</id>\n", base_url));
+    feed.push_str(&format!("<link rel=\"self\" href=\"{}/releases/feed\"/>\n", base_url));
+    feed.push_str(&format!("<updated>{}</updated>\n", time::at_utc(updated).rfc3339()));
+
+    for entry in entries {
+        let url = format!("{}/{}/{}/", base_url, entry.name, entry.version);
+        feed.push_str("<entry>\n");
+        feed.push_str(&format!("<title>{}-{}</title>\n",
+                               escape_html(&entry.name),
+                               escape_html(&entry.version)));
+        feed.push_str(&format!("<id>{}</id>\n", escape_html(&url)));
+        feed.push_str(&format!("<link href=\"{}\"/>\n", escape_html(&url)));
+        feed.push_str(&format!("<updated>{}</updated>\n",
+                               time::at_utc(entry.release_time).rfc3339()));
+        feed.push_str("<author><name>crates.io</name></author>\n");
+        if let Some(ref description) = entry.description {
+            feed.push_str(&format!("<summary>{}</summary>\n", escape_html(description)));
+        }
+        feed.push_str("</entry>\n");
+    }
+
+    feed.push_str("</feed>\n");
+    feed
+}
+
+
+pub fn feed_handler(req: &mut Request) -> IronResult<Response> {
+    let base_url = base_url(req);
+
+    let entries = {
+        let conn = req.extensions.get::<DbConnection>().unwrap();
+        match get_feed_entries(conn, FEED_LENGTH) {
+            Ok(entries) => entries,
+            Err(e) => return Err(IronError::new(e, status::InternalServerError)),
+        }
+    };
+
+    let mut resp = Response::with((status::Ok, atom_feed(&entries, &base_url)));
+    resp.headers.set(ContentType("application/atom+xml; charset=utf-8".parse().unwrap()));
+    Ok(resp)
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use time;
+
+    #[test]
+    fn test_atom_feed() {
+        let entries = vec![FeedEntry {
+            name: "rand".to_string(),
+            version: "0.3.14".to_string(),
+            description: Some("Random <numbers> & more".to_string()),
+            release_time: time::Timespec::new(1462000000, 0),
+        }];
+
+        let feed = atom_feed(&entries, "https://example.com");
+        assert!(feed.contains("<title>rand-0.3.14</title>"));
+        assert!(feed.contains("<link href=\"https://example.com/rand/0.3.14/\"/>"));
+        assert!(feed.contains("<summary>Random &lt;numbers&gt; &amp; more</summary>"));
+        assert!(feed.ends_with("</feed>\n"));
+    }
+}
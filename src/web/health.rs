@@ -0,0 +1,185 @@
+//! Health check
+//!
+//! `/health` returns status of database, crates.io-index, build queue and
+//! builds as JSON. Response status is 503 if a component is unhealthy, it
+//! can be used by load balancers and monitoring. Handler connects to
+//! database itself, a database failure is reported instead of failing
+//! the request.
+
+use std::collections::BTreeMap;
+
+use iron::prelude::*;
+use iron::{Handler, status};
+use iron::headers::ContentType;
+use postgres::Connection;
+use postgres::error::Error;
+use rustc_serialize::json::{Json, ToJson};
+use time;
+use ::config::Config;
+use ::docbuilder::queue::queue_length;
+use ::docbuilder::index::Index;
+
+
+/// Default maximum age of crates.io-index HEAD commit in seconds
+pub const DEFAULT_MAX_INDEX_AGE: i64 = 60 * 60;
+
+/// Default maximum number of crates waiting in build queue
+pub const DEFAULT_MAX_QUEUE_LENGTH: i64 = 1000;
+
+/// Default maximum time since last successful build in seconds
+pub const DEFAULT_MAX_BUILD_AGE: i64 = 24 * 60 * 60;
+
+
+/// Limits of a healthy service
+#[derive(Debug, Clone)]
+pub struct HealthThresholds {
+    pub max_index_age: i64,
+    pub max_queue_length: i64,
+    pub max_build_age: i64,
+}
+
+
+impl Default for HealthThresholds {
+    fn default() -> HealthThresholds {
+        HealthThresholds {
+            max_index_age: DEFAULT_MAX_INDEX_AGE,
+            max_queue_length: DEFAULT_MAX_QUEUE_LENGTH,
+            max_build_age: DEFAULT_MAX_BUILD_AGE,
+        }
+    }
+}
+
+
+pub struct HealthHandler {
+    config: Config,
+    thresholds: HealthThresholds,
+    index: Index,
+}
+
+
+impl HealthHandler {
+    pub fn new(config: Config) -> HealthHandler {
+        HealthHandler {
+            thresholds: config.health_thresholds(),
+            index: config.docbuilder().index(),
+            config: config,
+        }
+    }
+
+
+    /// Returns status of every component
+    fn checks(&self) -> BTreeMap<String, Json> {
+        let now = time::get_time().sec;
+        let mut checks = BTreeMap::new();
+
+        match self.config.connect_db() {
+            Ok(conn) => {
+                checks.insert("database".to_string(), check(true, vec![]));
+                checks.insert("queue".to_string(), match queue_length(&conn) {
+                    Ok(length) => {
+                        check(length <= self.thresholds.max_queue_length,
+                              vec![("length", length.to_json())])
+                    }
+                    Err(e) => check(false, vec![("error", e.to_string().to_json())]),
+                });
+                checks.insert("builds".to_string(), match last_successful_build(&conn) {
+                    Ok(Some(build_time)) => {
+                        check(now - build_time.sec <= self.thresholds.max_build_age,
+                              vec![("last_successful_build",
+                                    time::at_utc(build_time).rfc3339().to_string().to_json()),
+                                   ("age", (now - build_time.sec).to_json())])
+                    }
+                    Ok(None) => check(false, vec![("last_successful_build", Json::Null)]),
+                    Err(e) => check(false, vec![("error", e.to_string().to_json())]),
+                });
+            }
+            Err(e) => {
+                checks.insert("database".to_string(),
+                              check(false, vec![("error", e.to_string().to_json())]));
+            }
+        }
+
+        // index is only available on build machine
+        if self.index.path().exists() {
+            checks.insert("index".to_string(), match self.index.head_time() {
+                Ok(commit_time) => {
+                    check(now - commit_time <= self.thresholds.max_index_age,
+                          vec![("last_commit",
+                                time::at_utc(time::Timespec::new(commit_time, 0))
+                                    .rfc3339().to_string().to_json()),
+                               ("age", (now - commit_time).to_json())])
+                }
+                Err(e) => check(false, vec![("error", e.to_string().to_json())]),
+            });
+        }
+
+        checks
+    }
+}
+
+
+impl Handler for HealthHandler {
+    fn handle(&self, _: &mut Request) -> IronResult<Response> {
+        let checks = self.checks();
+        let healthy = is_healthy(&checks);
+
+        let mut tree = BTreeMap::new();
+        tree.insert("healthy".to_string(), healthy.to_json());
+        tree.insert("checks".to_string(), Json::Object(checks));
+
+        let status = if healthy {
+            status::Ok
+        } else {
+            status::ServiceUnavailable
+        };
+        let mut resp = Response::with((status, Json::Object(tree).to_string()));
+        resp.headers.set(ContentType("application/json".parse().unwrap()));
+        Ok(resp)
+    }
+}
+
+
+/// Returns status of a component as JSON object
+fn check(healthy: bool, fields: Vec<(&str, Json)>) -> Json {
+    let mut tree = BTreeMap::new();
+    tree.insert("healthy".to_string(), healthy.to_json());
+    for (name, value) in fields {
+        tree.insert(name.to_string(), value);
+    }
+    Json::Object(tree)
+}
+
+
+/// Returns true if every component is healthy
+fn is_healthy(checks: &BTreeMap<String, Json>) -> bool {
+    checks.values()
+        .all(|c| c.find("healthy").and_then(|h| h.as_boolean()).unwrap_or(false))
+}
+
+
+/// Returns build time of latest successful build
+fn last_successful_build(conn: &Connection) -> Result<Option<time::Timespec>, Error> {
+    let rows = try!(conn.query("SELECT MAX(build_time) FROM builds WHERE successful", &[]));
+    Ok(rows.get(0).get(0))
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+    use rustc_serialize::json::{Json, ToJson};
+    use super::{check, is_healthy};
+
+    #[test]
+    fn test_is_healthy() {
+        let mut checks = BTreeMap::new();
+        assert!(is_healthy(&checks));
+        checks.insert("database".to_string(), check(true, vec![]));
+        checks.insert("queue".to_string(), check(true, vec![("length", 3i64.to_json())]));
+        assert!(is_healthy(&checks));
+        checks.insert("index".to_string(), check(false, vec![("age", 7200i64.to_json())]));
+        assert!(!is_healthy(&checks));
+        assert_eq!(checks["queue"].find("length"), Some(&Json::I64(3)));
+    }
+}
@@ -0,0 +1,181 @@
+//! Syntax highlighting of Rust sources
+//!
+//! A small tokenizer wrapping comments, strings, keywords and numbers into
+//! spans with `comment`, `string`, `kw` and `number` classes. It doesn't
+//! try to understand Rust completely, unknown text is only escaped.
+
+use super::escape_html;
+
+
+const KEYWORDS: &'static [&'static str] = &["as", "box", "break", "const", "continue", "crate",
+                                            "else", "enum", "extern", "false", "fn", "for",
+                                            "if", "impl", "in", "let", "loop", "match", "mod",
+                                            "move", "mut", "pub", "ref", "return", "self",
+                                            "Self", "static", "struct", "super", "trait",
+                                            "true", "type", "unsafe", "use", "where", "while"];
+
+
+fn span(html: &mut String, class: &str, text: &[char]) {
+    let text: String = text.iter().cloned().collect();
+    html.push_str(&format!("<span class=\"{}\">{}</span>", class, escape_html(&text)));
+}
+
+
+/// Returns end of a string starting at start, start is index of opening quote
+fn string_end(chars: &[char], start: usize, quote: char) -> usize {
+    let mut i = start + 1;
+    while i < chars.len() {
+        if chars[i] == '\\' {
+            i += 2;
+            continue;
+        }
+        if chars[i] == quote {
+            return i + 1;
+        }
+        i += 1;
+    }
+    chars.len()
+}
+
+
+/// Returns end of a raw string if a raw string starts at start (`r"`, `r#"`)
+fn raw_string_end(chars: &[char], start: usize) -> Option<usize> {
+    let mut i = start + 1;
+    let mut hashes = 0;
+    while i < chars.len() && chars[i] == '#' {
+        hashes += 1;
+        i += 1;
+    }
+    if i >= chars.len() || chars[i] != '"' {
+        return None;
+    }
+    i += 1;
+    while i < chars.len() {
+        let closing = chars[i + 1..].iter().take_while(|&&c| c == '#').count();
+        if chars[i] == '"' && closing >= hashes {
+            return Some(i + 1 + hashes);
+        }
+        i += 1;
+    }
+    Some(chars.len())
+}
+
+
+/// Returns end of a block comment, nested comments are supported
+fn block_comment_end(chars: &[char], start: usize) -> usize {
+    let mut depth = 0;
+    let mut i = start;
+    while i + 1 < chars.len() {
+        if chars[i] == '/' && chars[i + 1] == '*' {
+            depth += 1;
+            i += 2;
+        } else if chars[i] == '*' && chars[i + 1] == '/' {
+            depth -= 1;
+            i += 2;
+            if depth == 0 {
+                return i;
+            }
+        } else {
+            i += 1;
+        }
+    }
+    chars.len()
+}
+
+
+/// Returns end of a char literal, or None if it's a lifetime
+fn char_end(chars: &[char], start: usize) -> Option<usize> {
+    match chars.get(start + 1) {
+        Some(&'\\') => Some(string_end(chars, start, '\'')),
+        Some(_) if chars.get(start + 2) == Some(&'\'') => Some(start + 3),
+        _ => None,
+    }
+}
+
+
+/// Highlights Rust source code and returns escaped HTML
+pub fn highlight_rust(source: &str) -> String {
+    let chars: Vec<char> = source.chars().collect();
+    let mut html = String::with_capacity(source.len() * 2);
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).cloned();
+
+        if c == '/' && next == Some('/') {
+            let end = chars[i..].iter().position(|&c| c == '\n').map_or(chars.len(), |p| i + p);
+            span(&mut html, "comment", &chars[i..end]);
+            i = end;
+        } else if c == '/' && next == Some('*') {
+            let end = block_comment_end(&chars, i);
+            span(&mut html, "comment", &chars[i..end]);
+            i = end;
+        } else if c == '"' {
+            let end = string_end(&chars, i, '"');
+            span(&mut html, "string", &chars[i..end]);
+            i = end;
+        } else if c == 'r' && (i == 0 || !is_ident_char(chars[i - 1])) &&
+                  raw_string_end(&chars, i).is_some() {
+            let end = raw_string_end(&chars, i).unwrap();
+            span(&mut html, "string", &chars[i..end]);
+            i = end;
+        } else if c == '\'' && char_end(&chars, i).is_some() {
+            let end = char_end(&chars, i).unwrap();
+            span(&mut html, "string", &chars[i..end]);
+            i = end;
+        } else if c.is_digit(10) {
+            let end = ident_end(&chars, i);
+            span(&mut html, "number", &chars[i..end]);
+            i = end;
+        } else if is_ident_char(c) {
+            let end = ident_end(&chars, i);
+            let ident: String = chars[i..end].iter().cloned().collect();
+            if KEYWORDS.contains(&&ident[..]) {
+                span(&mut html, "kw", &chars[i..end]);
+            } else {
+                html.push_str(&ident);
+            }
+            i = end;
+        } else {
+            html.push_str(&escape_html(&c.to_string()));
+            i += 1;
+        }
+    }
+
+    html
+}
+
+
+fn is_ident_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+
+fn ident_end(chars: &[char], start: usize) -> usize {
+    chars[start..].iter().position(|&c| !is_ident_char(c)).map_or(chars.len(), |p| start + p)
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::highlight_rust;
+
+    #[test]
+    fn test_highlight_rust() {
+        assert_eq!(highlight_rust("fn main() {}"),
+                   "<span class=\"kw\">fn</span> main() {}");
+        assert_eq!(highlight_rust("let s = \"a\\\"<b\"; // done"),
+                   "<span class=\"kw\">let</span> s = \
+                    <span class=\"string\">&quot;a\\&quot;&lt;b&quot;</span>; \
+                    <span class=\"comment\">// done</span>");
+        assert_eq!(highlight_rust("fn f<'a>(c: char) { 'x'; 42 }"),
+                   "<span class=\"kw\">fn</span> f&lt;'a&gt;(c: char) { \
+                    <span class=\"string\">'x'</span>; <span class=\"number\">42</span> }");
+        assert_eq!(highlight_rust("r#\"raw\"# /* a /* b */ c */"),
+                   "<span class=\"string\">r#&quot;raw&quot;#</span> \
+                    <span class=\"comment\">/* a /* b */ c */</span>");
+        assert_eq!(highlight_rust("for_each"), "for_each");
+    }
+}
@@ -0,0 +1,205 @@
+//! Keyword pages
+//!
+//! `/keyword/:slug` lists crates with a release tagged with a keyword, with
+//! latest version and build status of every crate. Crates are ordered by
+//! total downloads by default, `?sort=recent` orders them by their latest
+//! release.
+
+use iron::prelude::*;
+use iron::status;
+use router::Router;
+use handlebars_iron::Template;
+use postgres::Connection;
+use postgres::error::Error;
+use rustc_serialize::json::{Json, ToJson};
+use super::{Page, DbConnection, CrateSummary, CrateList, query_param};
+
+
+/// Number of crates shown in a page
+pub const CRATES_PER_PAGE: i64 = 30;
+
+
+/// Order of crates in a keyword page
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KeywordSort {
+    /// Most downloaded crate first
+    Downloads,
+    /// Crate with latest release first
+    Recent,
+}
+
+
+impl KeywordSort {
+    pub fn from_str(sort: &str) -> Option<KeywordSort> {
+        match sort {
+            "downloads" => Some(KeywordSort::Downloads),
+            "recent" => Some(KeywordSort::Recent),
+            _ => None,
+        }
+    }
+
+
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            KeywordSort::Downloads => "downloads",
+            KeywordSort::Recent => "recent",
+        }
+    }
+
+
+    fn order_by(&self) -> &'static str {
+        match *self {
+            KeywordSort::Downloads => "downloads DESC, crates.name",
+            KeywordSort::Recent => "latest.release_time DESC NULLS LAST, crates.name",
+        }
+    }
+}
+
+
+/// Returns id and name of a keyword
+pub fn get_keyword(conn: &Connection, slug: &str) -> Result<Option<(i32, String)>, Error> {
+    let rows = try!(conn.query("SELECT id, name FROM keywords WHERE slug = $1", &[&slug]));
+    Ok(if rows.len() == 0 {
+        None
+    } else {
+        Some((rows.get(0).get(0), rows.get(0).get(1)))
+    })
+}
+
+
+/// Returns crates with a release tagged with a keyword. Latest release of
+/// every crate is returned, even if it's not tagged with keyword.
+pub fn get_keyword_crates(conn: &Connection,
+                          keyword_id: i32,
+                          sort: KeywordSort,
+                          limit: i64,
+                          offset: i64) -> Result<Vec<CrateSummary>, Error> {
+    let query = format!("SELECT crates.name, latest.version, latest.description, \
+                                latest.build_status, latest.release_time, \
+                                (SELECT COALESCE(SUM(downloads), 0) \
+                                 FROM releases \
+                                 WHERE releases.crate_id = crates.id) AS downloads \
+                         FROM crates \
+                         INNER JOIN LATERAL ( \
+                             SELECT version, description, build_status, release_time \
+                             FROM releases \
+                             WHERE releases.crate_id = crates.id \
+                             ORDER BY release_time DESC NULLS LAST, id DESC \
+                             LIMIT 1 \
+                         ) AS latest ON TRUE \
+                         WHERE crates.id IN ( \
+                             SELECT releases.crate_id \
+                             FROM keyword_rels \
+                             INNER JOIN releases ON releases.id = keyword_rels.rid \
+                             WHERE keyword_rels.kid = $1 \
+                         ) \
+                         ORDER BY {} \
+                         LIMIT $2 OFFSET $3",
+                        sort.order_by());
+    let rows = try!(conn.query(&query, &[&keyword_id, &limit, &offset]));
+
+    Ok(rows.iter().map(|row| {
+        CrateSummary {
+            name: row.get(0),
+            version: row.get(1),
+            description: row.get(2),
+            build_status: row.get(3),
+            release_time: row.get(4),
+        }
+    }).collect())
+}
+
+
+/// Returns URL of a page of a keyword, default sort is not included
+fn page_url(slug: &str, sort: KeywordSort, page: i64) -> String {
+    match sort {
+        KeywordSort::Downloads => format!("/keyword/{}/{}", slug, page),
+        _ => format!("/keyword/{}/{}?sort={}", slug, page, sort.as_str()),
+    }
+}
+
+
+pub fn keyword_handler(req: &mut Request) -> IronResult<Response> {
+    let (slug, page) = {
+        let router = req.extensions.get::<Router>().unwrap();
+        (router.find("slug").unwrap_or("").to_string(),
+         router.find("page").and_then(|p| p.parse::<i64>().ok()).unwrap_or(1))
+    };
+    let sort = query_param(req, "sort")
+        .and_then(|s| KeywordSort::from_str(&s))
+        .unwrap_or(KeywordSort::Downloads);
+
+    if page < 1 {
+        return Ok(Response::with(status::NotFound));
+    }
+
+    let keyword = {
+        let conn = req.extensions.get::<DbConnection>().unwrap();
+        // one more crate is requested to find out if there is a next page
+        match get_keyword(conn, &slug).and_then(|keyword| match keyword {
+            Some((id, name)) => {
+                get_keyword_crates(conn, id, sort, CRATES_PER_PAGE + 1,
+                                   (page - 1) * CRATES_PER_PAGE)
+                    .map(|crates| Some((name, crates)))
+            }
+            None => Ok(None),
+        }) {
+            Ok(keyword) => keyword,
+            Err(e) => return Err(IronError::new(e, status::InternalServerError)),
+        }
+    };
+
+    let (name, mut crates) = match keyword {
+        Some(keyword) => keyword,
+        None => return Ok(Response::with(status::NotFound)),
+    };
+
+    let has_next_page = crates.len() as i64 > CRATES_PER_PAGE;
+    crates.truncate(CRATES_PER_PAGE as usize);
+
+    let prev_page = if page > 1 {
+        Some(page_url(&slug, sort, page - 1))
+    } else {
+        None
+    };
+    let next_page = if has_next_page {
+        Some(page_url(&slug, sort, page + 1))
+    } else {
+        None
+    };
+    let other_sort = match sort {
+        KeywordSort::Downloads => KeywordSort::Recent,
+        KeywordSort::Recent => KeywordSort::Downloads,
+    };
+    let mut content = CrateList {
+        crates: crates,
+        prev_page: prev_page,
+        next_page: next_page,
+    }.to_json();
+    if let Json::Object(ref mut tree) = content {
+        tree.insert("sort".to_string(), sort.as_str().to_json());
+        tree.insert("other_sort".to_string(), other_sort.as_str().to_json());
+        tree.insert("other_sort_url".to_string(), page_url(&slug, other_sort, 1).to_json());
+    }
+    let page = Page::new(&format!("Crates tagged with {}", name), content);
+    let mut resp = Response::new();
+    resp.set_mut(Template::new("crate_list", page)).set_mut(status::Ok);
+    Ok(resp)
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use super::page_url;
+
+    #[test]
+    fn test_page_url() {
+        assert_eq!(KeywordSort::from_str("recent"), Some(KeywordSort::Recent));
+        assert_eq!(KeywordSort::from_str("name"), None);
+        assert_eq!(page_url("gamedev", KeywordSort::Downloads, 2), "/keyword/gamedev/2");
+        assert_eq!(page_url("gamedev", KeywordSort::Recent, 2),
+                   "/keyword/gamedev/2?sort=recent");
+    }
+}
@@ -1,11 +1,27 @@
 
 
-mod recent;
-
+pub mod recent;
+mod rustdoc;
+pub mod badge;
+pub mod feed;
+pub mod api;
+pub mod sitemap;
+pub mod builds;
+pub mod about;
+pub mod health;
+pub mod search;
+pub mod authors;
+pub mod keywords;
+pub mod crate_details;
+pub mod source;
+mod highlight;
+mod compression;
+
+use std::str;
 use std::path::Path;
 use std::collections::BTreeMap;
 
-use ::db;
+use ::config::Config;
 
 use postgres;
 use iron::prelude::*;
@@ -47,7 +63,9 @@ impl<T: ToJson> Page<T> {
 
 
 // Database connection BeforeMiddleware filter
-struct DbConnection;
+struct DbConnection {
+    config: Config,
+}
 
 
 impl typemap::Key for DbConnection { type Value = postgres::Connection; }
@@ -55,13 +73,155 @@ impl typemap::Key for DbConnection { type Value = postgres::Connection; }
 
 impl BeforeMiddleware for DbConnection {
     fn before(&self, req: &mut Request) -> IronResult<()> {
-        req.extensions.insert::<DbConnection>(db::connect_db().unwrap());
+        req.extensions.insert::<DbConnection>(self.config.connect_db().unwrap());
         Ok(())
     }
 }
 
 
 
+/// Escapes HTML and XML special characters of fragments which are not
+/// rendered by templates, i.e: feeds and highlighted sources
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+
+
+/// Returns build status of a release as text
+fn build_status_text(build_status: i32) -> &'static str {
+    if build_status == 1 {
+        "documentation available"
+    } else {
+        "build failed"
+    }
+}
+
+
+
+/// Latest release of a crate shown in crate lists
+pub struct CrateSummary {
+    pub name: String,
+    pub version: String,
+    pub description: Option<String>,
+    pub build_status: i32,
+    pub release_time: Option<time::Timespec>,
+}
+
+
+
+impl ToJson for CrateSummary {
+    fn to_json(&self) -> Json {
+        let mut tree = BTreeMap::new();
+        tree.insert("name".to_string(), self.name.to_json());
+        tree.insert("version".to_string(), self.version.to_json());
+        tree.insert("description".to_string(), self.description.to_json());
+        tree.insert("status".to_string(), build_status_text(self.build_status).to_json());
+        tree.insert("release_time".to_string(),
+                    self.release_time.map(duration_to_str).to_json());
+        Json::Object(tree)
+    }
+}
+
+
+
+/// A page of crates with links to previous and next pages, rendered by
+/// crate_list template
+struct CrateList {
+    crates: Vec<CrateSummary>,
+    prev_page: Option<String>,
+    next_page: Option<String>,
+}
+
+
+impl ToJson for CrateList {
+    fn to_json(&self) -> Json {
+        let mut tree = BTreeMap::new();
+        tree.insert("crates".to_string(), self.crates.to_json());
+        tree.insert("prev_page".to_string(), self.prev_page.to_json());
+        tree.insert("next_page".to_string(), self.next_page.to_json());
+        Json::Object(tree)
+    }
+}
+
+
+
+/// Returns scheme, host and port of request without a trailing slash
+fn base_url(req: &Request) -> String {
+    match req.url.port {
+        80 | 443 => format!("{}://{}", req.url.scheme, req.url.host),
+        port => format!("{}://{}:{}", req.url.scheme, req.url.host, port),
+    }
+}
+
+
+
+/// Returns URL decoded value of a query string parameter
+fn query_param(req: &Request, name: &str) -> Option<String> {
+    req.url.query.as_ref().and_then(|query| {
+        query.split('&')
+            .filter_map(|pair| {
+                let mut pair = pair.splitn(2, '=');
+                match (pair.next(), pair.next()) {
+                    (Some(key), value) if url_decode(key) == name => {
+                        Some(url_decode(value.unwrap_or("")))
+                    }
+                    _ => None,
+                }
+            })
+            .next()
+    })
+}
+
+
+
+/// Decodes `+` and percent encoded bytes of a query string component
+fn url_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                match str::from_utf8(&bytes[i + 1..i + 3])
+                    .ok()
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                    Some(byte) => {
+                        decoded.push(byte);
+                        i += 2;
+                    }
+                    None => decoded.push(b'%'),
+                }
+            }
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+
+
+/// Percent encodes a query string component
+fn url_encode(s: &str) -> String {
+    let mut encoded = String::with_capacity(s.len());
+    for byte in s.bytes() {
+        match byte {
+            b'a'...b'z' | b'A'...b'Z' | b'0'...b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+
+
 fn duration_to_str(ts: time::Timespec) -> String {
 
     let tm = time::at(ts);
@@ -90,13 +250,58 @@ fn duration_to_str(ts: time::Timespec) -> String {
 }
 
 
+/// Converts doc_targets column of releases into a list of targets
+fn doc_targets_from_json(json: Option<Json>) -> Vec<String> {
+    json.as_ref()
+        .and_then(|j| j.as_array())
+        .map(|targets| {
+            targets.iter().filter_map(|t| t.as_string()).map(|t| t.to_string()).collect()
+        })
+        .unwrap_or(Vec::new())
+}
+
 
-/// Starts main web application of cratesfyi on localhost:3000
-pub fn start_cratesfyi_server() {
+
+/// Starts main web application of cratesfyi on configured address
+///
+/// Generated documentation and rustdoc's shared files are served from
+/// documentation storage.
+pub fn start_cratesfyi_server(config: &Config) {
+    let storage = config.storage();
 
     // router
     let mut router = Router::new();
+    router.get("/", recent::recent_crates);
     router.get("/recent", recent::recent_crates);
+    router.get("/recent/:page", recent::recent_crates);
+    router.get("/about", about::about_handler);
+    router.get("/search", search::search_handler);
+    router.get("/api/v1/search", search::search_api_handler);
+    router.get("/authors/:slug", authors::author_handler);
+    router.get("/authors/:slug/:page", authors::author_handler);
+    router.get("/keyword/:slug", keywords::keyword_handler);
+    router.get("/keyword/:slug/:page", keywords::keyword_handler);
+    router.get("/releases/feed", feed::feed_handler);
+    router.get("/sitemap.xml", sitemap::sitemap_index_handler);
+    router.get("/sitemap/:page", sitemap::sitemap_handler);
+    router.get("/crate/:name/badge.svg", badge::badge_handler);
+    router.get("/crate/:name", crate_details::crate_details_handler);
+    router.get("/crate/:name/:version", crate_details::crate_details_handler);
+    router.get("/crate/:name/:version/builds", builds::builds_handler);
+    router.get("/crate/:name/:version/source",
+               source::SourceHandler::new(config.sources_path()));
+    router.get("/crate/:name/:version/source/*",
+               source::SourceHandler::new(config.sources_path()));
+    router.get("/builds/:id", builds::build_handler);
+    router.get("/api/v1/progress", api::progress_handler);
+    router.get("/api/v1/build-environment", api::build_environment_handler);
+    router.get("/api/v1/crates/:name", api::release_status_handler);
+    router.get("/api/v1/crates/:name/:version", api::release_status_handler);
+    router.post("/api/v1/crates/:name/:version/rebuild",
+                api::RebuildHandler::new(config.admin_tokens()));
+    router.get("/:crate", rustdoc::latest_version_redirect);
+    router.get("/:crate/:version", rustdoc::RustdocHandler::new(storage.clone()));
+    router.get("/:crate/:version/*", rustdoc::RustdocHandler::new(storage.clone()));
 
     // templates
     let mut hbse = HandlebarsEngine::new2();
@@ -108,16 +313,64 @@ pub fn start_cratesfyi_server() {
 
     // router chain for db and hbs stuff
     let mut router_chain = Chain::new(router);
-    router_chain.link_before(DbConnection);
+    router_chain.link_before(DbConnection { config: config.clone() });
     router_chain.link_after(hbse);
 
     // mount for static files
     let mut mount = Mount::new();
+    // health check connects database itself, it's not using DbConnection
     mount
+        .mount("/health", health::HealthHandler::new(config.clone()))
         .mount("/", router_chain)
         .mount("/static", Static::new(Path::new("templates/raw")));
 
+    // every response is compressed if client accepts it
+    let mut chain = Chain::new(mount);
+    chain.link_after(compression::GzipMiddleware);
 
-    println!("cratesfyi started on http://localhost:3000/");
-    Iron::new(mount).http("localhost:3000").unwrap();
+    let address = config.web_address();
+    println!("cratesfyi started on http://{}/", address);
+    Iron::new(chain).http(&address[..]).unwrap();
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::{url_decode, url_encode, CrateSummary, CrateList};
+    use rustc_serialize::json::ToJson;
+
+    #[test]
+    fn test_url_decode() {
+        assert_eq!(url_decode("serde+json"), "serde json");
+        assert_eq!(url_decode("c%2B%2B%20ffi"), "c++ ffi");
+        assert_eq!(url_decode("100%"), "100%");
+        assert_eq!(url_decode("%zz"), "%zz");
+        assert_eq!(url_decode(&url_encode("c++ & ffi")), "c++ & ffi");
+    }
+
+
+    #[test]
+    fn test_crate_list_json() {
+        let list = CrateList {
+            crates: vec![CrateSummary {
+                name: "rand".to_string(),
+                version: "0.3.14".to_string(),
+                description: Some("Random <numbers>".to_string()),
+                build_status: 1,
+                release_time: None,
+            }],
+            prev_page: Some("/authors/onur/1".to_string()),
+            next_page: None,
+        };
+
+        let json = list.to_json();
+        let c = &json.find("crates").and_then(|c| c.as_array()).unwrap()[0];
+        assert_eq!(c.find("status").and_then(|s| s.as_string()),
+                   Some("documentation available"));
+        assert!(c.find("release_time").unwrap().is_null());
+        assert_eq!(json.find("prev_page").and_then(|p| p.as_string()),
+                   Some("/authors/onur/1"));
+        assert!(json.find("next_page").unwrap().is_null());
+    }
 }
@@ -4,16 +4,24 @@ use std::collections::BTreeMap;
 
 use iron::prelude::*;
 use iron::status;
+use router::Router;
 use handlebars_iron::Template;
-use super::{Page, DbConnection, duration_to_str};
+use postgres::Connection;
+use postgres::error::Error;
+use super::{Page, DbConnection, duration_to_str, build_status_text};
 use rustc_serialize::json::{Json, ToJson};
 
 
-struct RecentCrate {
-    name: String,
-    version: String,
-    description: String,
-    release_time: String
+/// Number of releases shown in a page
+pub const RELEASES_PER_PAGE: i64 = 30;
+
+
+pub struct RecentCrate {
+    pub name: String,
+    pub version: String,
+    pub description: Option<String>,
+    pub release_time: String,
+    pub build_status: i32,
 }
 
 impl ToJson for RecentCrate {
@@ -23,41 +31,118 @@ impl ToJson for RecentCrate {
         tree.insert("version".to_string(), self.version.to_json());
         tree.insert("description".to_string(), self.description.to_json());
         tree.insert("release_time".to_string(), self.release_time.to_json());
+        tree.insert("build_status".to_string(), self.build_status.to_json());
+        tree.insert("status".to_string(), build_status_text(self.build_status).to_json());
         Json::Object(tree)
     }
 }
 
 
-pub fn recent_crates(req: &mut Request) -> IronResult<Response> {
-    let ref conn = *req.extensions.get::<DbConnection>().unwrap();
-    let query = "
-        SELECT crates.name,
-               releases.version,
-               releases.description,
-               releases.release_time
-        FROM releases, crates
-        WHERE releases.crate_id = crates.id
-        ORDER BY releases.release_time DESC
-        LIMIT 50
-    ";
-
-
-    let mut recent_crates: Vec<RecentCrate> = Vec::new();
-
-    for row in &conn.query(query, &[]).unwrap() {
-        recent_crates.push(
-            RecentCrate {
-                name: row.get(0),
-                version: row.get(1),
-                description: row.get(2),
-                release_time: duration_to_str(row.get(3))
-            }
-        );
+struct RecentCrates {
+    crates: Vec<RecentCrate>,
+    page: i64,
+    has_next_page: bool,
+}
+
+impl ToJson for RecentCrates {
+    fn to_json(&self) -> Json {
+        let mut tree = BTreeMap::new();
+        tree.insert("crates".to_string(), self.crates.to_json());
+        tree.insert("page".to_string(), self.page.to_json());
+        if self.page > 1 {
+            tree.insert("prev_page".to_string(), (self.page - 1).to_json());
+        }
+        if self.has_next_page {
+            tree.insert("next_page".to_string(), (self.page + 1).to_json());
+        }
+        Json::Object(tree)
     }
+}
+
 
-    let content = Page::new("Recent crates", recent_crates);
+/// Returns most recently released crates
+pub fn get_recent_crates(conn: &Connection,
+                         limit: i64,
+                         offset: i64) -> Result<Vec<RecentCrate>, Error> {
+    let rows = try!(conn.query("SELECT crates.name, \
+                                       releases.version, \
+                                       releases.description, \
+                                       releases.release_time, \
+                                       releases.build_status \
+                                FROM releases \
+                                INNER JOIN crates ON releases.crate_id = crates.id \
+                                WHERE releases.release_time IS NOT NULL \
+                                ORDER BY releases.release_time DESC \
+                                LIMIT $1 OFFSET $2",
+                               &[&limit, &offset]));
+
+    Ok(rows.iter().map(|row| {
+        RecentCrate {
+            name: row.get(0),
+            version: row.get(1),
+            description: row.get(2),
+            release_time: duration_to_str(row.get(3)),
+            build_status: row.get(4),
+        }
+    }).collect())
+}
+
+
+/// Returns offset of first release in a page, None if page is out of range
+fn page_offset(page: i64) -> Option<i64> {
+    if page < 1 {
+        return None;
+    }
+    (page - 1).checked_mul(RELEASES_PER_PAGE)
+}
+
+
+pub fn recent_crates(req: &mut Request) -> IronResult<Response> {
+    let page = req.extensions.get::<Router>().unwrap().find("page")
+        .and_then(|p| p.parse::<i64>().ok())
+        .unwrap_or(1);
+
+    let offset = match page_offset(page) {
+        Some(offset) => offset,
+        None => return Ok(Response::with(status::NotFound)),
+    };
+
+    // one more crate is requested to find out if there is a next page
+    let mut crates = {
+        let conn = req.extensions.get::<DbConnection>().unwrap();
+        match get_recent_crates(conn, RELEASES_PER_PAGE + 1, offset) {
+            Ok(crates) => crates,
+            Err(e) => return Err(IronError::new(e, status::InternalServerError)),
+        }
+    };
+
+    let has_next_page = crates.len() as i64 > RELEASES_PER_PAGE;
+    crates.truncate(RELEASES_PER_PAGE as usize);
+
+    let content = Page::new("Recent crates", RecentCrates {
+        crates: crates,
+        page: page,
+        has_next_page: has_next_page,
+    });
     let mut resp = Response::new();
     resp.set_mut(Template::new("recent", content)).set_mut(status::Ok);
 
     Ok(resp)
 }
+
+
+
+#[cfg(test)]
+mod test {
+    use super::{page_offset, RELEASES_PER_PAGE};
+    use std::i64;
+
+    #[test]
+    fn test_page_offset() {
+        assert_eq!(page_offset(1), Some(0));
+        assert_eq!(page_offset(3), Some(2 * RELEASES_PER_PAGE));
+        assert_eq!(page_offset(0), None);
+        assert_eq!(page_offset(-1), None);
+        assert_eq!(page_offset(i64::MAX), None);
+    }
+}
@@ -0,0 +1,496 @@
+//! Serves generated documentation from documentation storage
+//!
+//! Documentation of a version never changes after it's built, files are
+//! served with a strong ETag and cached for a long time. HTML pages are
+//! cached for a shorter time, cratesfyi header in them lists new versions
+//! and yanked status. Redirects to latest version are cached only briefly.
+
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::collections::BTreeMap;
+
+use iron::prelude::*;
+use iron::{Handler, status};
+use iron::headers::{ContentType, LastModified, HttpDate, ETag, EntityTag, IfNoneMatch,
+                    CacheControl, CacheDirective, ContentEncoding, Encoding};
+use iron::mime::Mime;
+use iron::Url;
+use iron::modifiers::Redirect;
+use router::Router;
+use handlebars_iron::Template;
+use rustc_serialize::json::ToJson;
+use postgres::Connection;
+use postgres::error::Error;
+use time;
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+use ::docbuilder::storage::{Storage, precompressed_path};
+use ::db::similar_crate_names;
+use super::{Page, DbConnection, base_url, escape_html, doc_targets_from_json};
+use super::compression::accepts_gzip;
+
+
+/// Number of suggested crates shown in not found pages
+pub const SUGGESTIONS: i64 = 5;
+
+/// Cache lifetime of documentation files in seconds
+pub const DOC_MAX_AGE: u32 = 365 * 24 * 60 * 60;
+
+/// Cache lifetime of documentation pages in seconds
+pub const HTML_MAX_AGE: u32 = 24 * 60 * 60;
+
+/// Cache lifetime of redirects and pages of latest version in seconds
+pub const LATEST_MAX_AGE: u32 = 5 * 60;
+
+
+/// Handler serving `{crate}/{version}/...` paths of documentation storage
+pub struct RustdocHandler {
+    storage: Arc<Storage>,
+}
+
+
+impl RustdocHandler {
+    pub fn new(storage: Arc<Storage>) -> RustdocHandler {
+        RustdocHandler {
+            storage: storage,
+        }
+    }
+
+
+    /// Returns path and content of requested file, index.html is used for
+    /// directories
+    fn read_file(&self, req: &Request) -> IronResult<Option<(PathBuf, Vec<u8>)>> {
+        // do not let anyone to escape from storage root
+        if req.url.path.iter().any(|c| c == "..") {
+            return Ok(None);
+        }
+
+        let path = req.url.path.join("/").trim_matches('/').to_string();
+        for path in vec![path.clone(), format!("{}/index.html", path)] {
+            match self.storage.get_file(&path) {
+                Ok(Some(content)) => return Ok(Some((PathBuf::from(path), content))),
+                Ok(None) => {}
+                Err(e) => return Err(IronError::new(e, status::InternalServerError)),
+            }
+        }
+
+        Ok(None)
+    }
+
+
+    /// Returns path and content of gzipped copy of requested file if it's
+    /// precompressed
+    fn read_gzipped_file(&self, req: &Request) -> IronResult<Option<(PathBuf, Vec<u8>)>> {
+        if req.url.path.iter().any(|c| c == "..") {
+            return Ok(None);
+        }
+
+        let path = req.url.path.join("/").trim_matches('/').to_string();
+        let gz_path = match precompressed_path(&path) {
+            Some(gz_path) => gz_path,
+            None => return Ok(None),
+        };
+        match self.storage.get_file(&gz_path) {
+            Ok(content) => Ok(content.map(|content| (PathBuf::from(path), content))),
+            Err(e) => Err(IronError::new(e, status::InternalServerError)),
+        }
+    }
+}
+
+
+impl Handler for RustdocHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        let gzipped = if accepts_gzip(req) {
+            try!(self.read_gzipped_file(req))
+        } else {
+            None
+        };
+        let is_gzipped = gzipped.is_some();
+        let (path, content) = match gzipped {
+            Some(file) => file,
+            None => {
+                match try!(self.read_file(req)) {
+                    Some(file) => file,
+                    None => return Ok(Response::with(status::NotFound)),
+                }
+            }
+        };
+
+        let release = if req.url.path.len() >= 2 {
+            let conn = req.extensions.get::<DbConnection>().unwrap();
+            release_info(conn, &req.url.path[0], &req.url.path[1]).unwrap_or(None)
+        } else {
+            None
+        };
+
+        let mime = content_type(&path);
+
+        // cratesfyi header is injected into html pages
+        let content = match release {
+            Some(ref release) if path.extension().map_or(false, |e| e == "html") => {
+                let mut header = topbar(&req.url.path[0], &req.url.path[1], release);
+                if release.yanked {
+                    header.push_str(&yanked_warning(&req.url.path[0], &req.url.path[1]));
+                }
+                insert_after_body(content, &header)
+            }
+            _ => content,
+        };
+
+        // release time of crate is used as Last-Modified
+        let release_time = release.and_then(|r| r.release_time);
+        let etag = content_etag(&content);
+
+        let mut resp = if etag_matches(req.headers.get::<IfNoneMatch>(), &etag) {
+            Response::with(status::NotModified)
+        } else {
+            let mut resp = Response::with((status::Ok, content));
+            resp.headers.set(ContentType(mime));
+            resp
+        };
+        resp.headers.set(ETag(etag));
+        resp.headers.set(cache_control(max_age(&path)));
+        if is_gzipped {
+            resp.headers.set(ContentEncoding(vec![Encoding::Gzip]));
+            resp.headers.set_raw("Vary", vec![b"Accept-Encoding".to_vec()]);
+        }
+        if let Some(release_time) = release_time {
+            resp.headers.set(LastModified(HttpDate(time::at_utc(release_time))));
+        }
+
+        Ok(resp)
+    }
+}
+
+
+/// Returns (version, target_name) of latest non-yanked release of a crate
+/// with documentation
+pub fn latest_documented_version(conn: &Connection,
+                                 name: &str) -> Result<Option<(String, String)>, Error> {
+    let rows = try!(conn.query("SELECT releases.version, releases.target_name \
+                                FROM releases \
+                                INNER JOIN crates ON crates.id = releases.crate_id \
+                                WHERE crates.name = $1 AND \
+                                      releases.rustdoc_status = 1 AND \
+                                      releases.yanked IS NOT TRUE AND \
+                                      releases.target_name IS NOT NULL \
+                                ORDER BY releases.release_time DESC \
+                                LIMIT 1",
+                               &[&name]));
+    Ok(if rows.len() == 0 {
+        None
+    } else {
+        Some((rows.get(0).get(0), rows.get(0).get(1)))
+    })
+}
+
+
+/// Returns version of latest release of a crate if it doesn't have a
+/// library target
+pub fn latest_binary_version(conn: &Connection, name: &str) -> Result<Option<String>, Error> {
+    let rows = try!(conn.query("SELECT releases.version, releases.is_library \
+                                FROM releases \
+                                INNER JOIN crates ON crates.id = releases.crate_id \
+                                WHERE crates.name = $1 AND releases.yanked IS NOT TRUE \
+                                ORDER BY releases.release_time DESC \
+                                LIMIT 1",
+                               &[&name]));
+    Ok(if rows.len() == 0 || rows.get(0).get::<_, bool>(1) {
+        None
+    } else {
+        Some(rows.get(0).get(0))
+    })
+}
+
+
+/// Redirects /:crate to documentation of latest successfully built version.
+/// Binary crates don't have documentation and a notice is shown instead.
+pub fn latest_version_redirect(req: &mut Request) -> IronResult<Response> {
+    let name = req.extensions.get::<Router>().unwrap().find("crate").unwrap_or("").to_string();
+
+    let (latest, binary, suggestions) = {
+        let conn = req.extensions.get::<DbConnection>().unwrap();
+        match latest_documented_version(conn, &name)
+            .and_then(|latest| latest_binary_version(conn, &name).map(|b| (latest, b))) {
+            Ok((None, None)) => {
+                // suggestions are not important enough to fail request
+                let suggestions = similar_crate_names(conn, &name, SUGGESTIONS)
+                    .unwrap_or(Vec::new());
+                (None, None, suggestions)
+            }
+            Ok((latest, binary)) => (latest, binary, Vec::new()),
+            Err(e) => return Err(IronError::new(e, status::InternalServerError)),
+        }
+    };
+
+    if let Some(version) = binary {
+        return Ok(binary_notice(&name, &version));
+    }
+
+    match latest {
+        Some((version, target_name)) => {
+            let url = Url::parse(&format!("{}/{}/{}/{}/index.html",
+                                          base_url(req), name, version, target_name))
+                .unwrap();
+            let mut resp = Response::with((status::Found, Redirect(url)));
+            resp.headers.set(cache_control(LATEST_MAX_AGE));
+            Ok(resp)
+        }
+        None => Ok(crate_not_found(&name, &suggestions)),
+    }
+}
+
+
+/// Renders not found page of a crate with suggested crates
+fn crate_not_found(name: &str, suggestions: &[String]) -> Response {
+    let mut content = BTreeMap::new();
+    content.insert("name".to_string(), name.to_json());
+    content.insert("suggestions".to_string(), suggestions.to_json());
+    let mut resp = Response::new();
+    resp.set_mut(Template::new("crate_not_found", Page::new("Crate not found", content)))
+        .set_mut(status::NotFound);
+    resp
+}
+
+
+/// Renders a notice page for binary crates instead of a build failure
+fn binary_notice(name: &str, version: &str) -> Response {
+    let mut content = BTreeMap::new();
+    content.insert("name".to_string(), name.to_json());
+    content.insert("version".to_string(), version.to_json());
+    let mut resp = Response::new();
+    resp.set_mut(Template::new("binary_crate",
+                               Page::new(&format!("{}-{}", name, version), content)))
+        .set_mut(status::Ok);
+    resp
+}
+
+
+/// Release information used in cratesfyi header
+pub struct ReleaseInfo {
+    pub release_time: Option<time::Timespec>,
+    pub yanked: bool,
+    pub repository_url: Option<String>,
+    pub target_name: Option<String>,
+    /// Additional targets with documentation
+    pub doc_targets: Vec<String>,
+    /// Documented versions of crate, latest version first
+    pub versions: Vec<String>,
+}
+
+
+/// Returns release information of a crate version
+pub fn release_info(conn: &Connection,
+                    name: &str,
+                    version: &str) -> Result<Option<ReleaseInfo>, Error> {
+    let rows = try!(conn.query("SELECT releases.release_time, releases.yanked, \
+                                       releases.repository_url, releases.target_name, \
+                                       releases.doc_targets \
+                                FROM releases \
+                                INNER JOIN crates ON crates.id = releases.crate_id \
+                                WHERE crates.name = $1 AND releases.version = $2",
+                               &[&name, &version]));
+    if rows.len() == 0 {
+        return Ok(None);
+    }
+
+    let row = rows.get(0);
+    let yanked: Option<bool> = row.get(1);
+
+    let versions = try!(conn.query("SELECT releases.version \
+                                    FROM releases \
+                                    INNER JOIN crates ON crates.id = releases.crate_id \
+                                    WHERE crates.name = $1 AND releases.rustdoc_status = 1 \
+                                    ORDER BY releases.release_time DESC",
+                                   &[&name]));
+
+    Ok(Some(ReleaseInfo {
+        release_time: row.get(0),
+        yanked: yanked.unwrap_or(false),
+        repository_url: row.get(2),
+        target_name: row.get(3),
+        doc_targets: doc_targets_from_json(row.get(4)),
+        versions: versions.iter().map(|r| r.get(0)).collect(),
+    }))
+}
+
+
+/// Renders navigation bar with crate name, version switcher, platform
+/// selector and links
+fn topbar(name: &str, version: &str, release: &ReleaseInfo) -> String {
+    let mut html = format!("<div class=\"cratesfyi-topbar\">\
+                            <a href=\"/{name}\">{name}</a> \
+                            <select onchange=\"window.location.href = this.value\">",
+                           name = escape_html(name));
+
+    for v in &release.versions {
+        html.push_str(&format!("<option value=\"/{}/{}/\"{}>{}</option>",
+                               escape_html(name),
+                               escape_html(v),
+                               if v == version { " selected" } else { "" },
+                               escape_html(v)));
+    }
+    html.push_str("</select>");
+
+    // platform selector is only shown if release has documentation for
+    // additional targets
+    if !release.doc_targets.is_empty() {
+        let crate_path = release.target_name.as_ref()
+            .map(|t| format!("{}/", escape_html(t)))
+            .unwrap_or(String::new());
+        html.push_str(&format!(" <select onchange=\"window.location.href = this.value\">\
+                                <option value=\"/{}/{}/{}\">Default platform</option>",
+                               escape_html(name),
+                               escape_html(version),
+                               crate_path));
+        for target in &release.doc_targets {
+            html.push_str(&format!("<option value=\"/{}/{}/{}/{}\">{}</option>",
+                                   escape_html(name),
+                                   escape_html(version),
+                                   escape_html(target),
+                                   crate_path,
+                                   escape_html(target)));
+        }
+        html.push_str("</select>");
+    }
+
+    html.push_str(&format!(" <a href=\"https://crates.io/crates/{}\">crates.io</a>",
+                           escape_html(name)));
+    if let Some(ref repository_url) = release.repository_url {
+        html.push_str(&format!(" <a href=\"{}\">Repository</a>", escape_html(repository_url)));
+    }
+    html.push_str(&format!(" <a href=\"/crate/{}/{}/builds\">Builds</a></div>",
+                           escape_html(name),
+                           escape_html(version)));
+    html
+}
+
+
+/// Returns warning banner of a yanked release
+fn yanked_warning(name: &str, version: &str) -> String {
+    format!("<div class=\"cratesfyi-yanked-warning\">\
+             {}-{} has been yanked from crates.io</div>",
+            escape_html(name),
+            escape_html(version))
+}
+
+
+/// Inserts html after opening body tag of a page
+fn insert_after_body(content: Vec<u8>, html: &str) -> Vec<u8> {
+    let page = String::from_utf8_lossy(&content).into_owned();
+    let body_end = page.find("<body")
+        .and_then(|start| page[start..].find('>').map(|end| start + end + 1));
+
+    match body_end {
+        Some(pos) => format!("{}{}{}", &page[..pos], html, &page[pos..]).into_bytes(),
+        None => content,
+    }
+}
+
+
+/// Returns a strong ETag of content
+fn content_etag(content: &[u8]) -> EntityTag {
+    let mut hasher = Sha256::new();
+    hasher.input(content);
+    EntityTag::new(false, hasher.result_str())
+}
+
+
+/// Returns true if an If-None-Match header matches etag
+fn etag_matches(if_none_match: Option<&IfNoneMatch>, etag: &EntityTag) -> bool {
+    match if_none_match {
+        Some(&IfNoneMatch::Any) => true,
+        Some(&IfNoneMatch::Items(ref tags)) => tags.iter().any(|tag| tag.weak_eq(etag)),
+        None => false,
+    }
+}
+
+
+/// Returns cache lifetime of a documentation file
+fn max_age(path: &PathBuf) -> u32 {
+    if path.extension().map_or(false, |e| e == "html") {
+        HTML_MAX_AGE
+    } else {
+        DOC_MAX_AGE
+    }
+}
+
+
+/// Returns a Cache-Control header of public responses
+pub fn cache_control(max_age: u32) -> CacheControl {
+    CacheControl(vec![CacheDirective::Public, CacheDirective::MaxAge(max_age)])
+}
+
+
+fn content_type(path: &PathBuf) -> Mime {
+    let mime = match path.extension().and_then(|e| e.to_str()).unwrap_or("") {
+        "html" => "text/html; charset=utf-8",
+        "css" => "text/css",
+        "js" => "application/javascript",
+        "svg" => "image/svg+xml",
+        "png" => "image/png",
+        "woff" => "application/font-woff",
+        "txt" => "text/plain; charset=utf-8",
+        _ => "application/octet-stream",
+    };
+    mime.parse().unwrap()
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+    use iron::headers::{EntityTag, IfNoneMatch};
+    use super::{ReleaseInfo, topbar, yanked_warning, insert_after_body};
+    use super::{content_etag, etag_matches, max_age, DOC_MAX_AGE, HTML_MAX_AGE};
+
+    #[test]
+    fn test_insert_after_body() {
+        let html = b"<html><body class=\"rustdoc\"><p>docs</p></body></html>".to_vec();
+        let html = String::from_utf8(insert_after_body(html, &yanked_warning("rand", "0.1.0")))
+            .unwrap();
+        assert!(html.starts_with("<html><body class=\"rustdoc\">\
+                                  <div class=\"cratesfyi-yanked-warning\">"));
+        assert!(html.contains("rand-0.1.0 has been yanked"));
+
+        let text = b"no body".to_vec();
+        assert_eq!(insert_after_body(text.clone(), "<div></div>"), text);
+    }
+
+
+    #[test]
+    fn test_topbar() {
+        let release = ReleaseInfo {
+            release_time: None,
+            yanked: false,
+            repository_url: Some("https://github.com/rust-lang/rand".to_string()),
+            target_name: Some("rand".to_string()),
+            doc_targets: vec!["i686-pc-windows-gnu".to_string()],
+            versions: vec!["0.3.14".to_string(), "0.3.13".to_string()],
+        };
+        let html = topbar("rand", "0.3.13", &release);
+        assert!(html.contains("<option value=\"/rand/0.3.13/\" selected>0.3.13</option>"));
+        assert!(html.contains("<option value=\"/rand/0.3.14/\">0.3.14</option>"));
+        assert!(html.contains("href=\"https://github.com/rust-lang/rand\""));
+        assert!(html.contains("<option value=\"/rand/0.3.13/i686-pc-windows-gnu/rand/\">"));
+    }
+
+
+    #[test]
+    fn test_caching() {
+        let etag = content_etag(b"docs");
+        assert_eq!(etag, content_etag(b"docs"));
+        assert!(etag != content_etag(b"new docs"));
+        assert!(!etag.weak);
+
+        assert!(etag_matches(Some(&IfNoneMatch::Items(vec![etag.clone()])), &etag));
+        assert!(etag_matches(Some(&IfNoneMatch::Any), &etag));
+        let other = EntityTag::new(false, "other".to_string());
+        assert!(!etag_matches(Some(&IfNoneMatch::Items(vec![other])), &etag));
+        assert!(!etag_matches(None, &etag));
+
+        assert_eq!(max_age(&PathBuf::from("rand/0.3.14/rand/index.html")), HTML_MAX_AGE);
+        assert_eq!(max_age(&PathBuf::from("rand/0.3.14/main.css")), DOC_MAX_AGE);
+    }
+}
@@ -0,0 +1,153 @@
+//! Search
+//!
+//! `/search?q=<QUERY>` lists crates matching query ordered by rank with
+//! description, latest matching version and build status.
+//! `/api/v1/search?q=<QUERY>` returns same results as JSON. Both of them
+//! accept a `page` parameter.
+
+use std::collections::BTreeMap;
+
+use iron::prelude::*;
+use iron::status;
+use iron::headers::ContentType;
+use handlebars_iron::Template;
+use postgres::error::Error;
+use rustc_serialize::json::{Json, ToJson};
+use ::db::{self, SearchResult};
+use super::{Page, DbConnection, CrateSummary, query_param, url_encode};
+
+
+/// Number of results shown in a page
+pub const RESULTS_PER_PAGE: i64 = 20;
+
+
+impl ToJson for SearchResult {
+    fn to_json(&self) -> Json {
+        let mut tree = BTreeMap::new();
+        tree.insert("name".to_string(), self.name.to_json());
+        tree.insert("version".to_string(), self.version.to_json());
+        tree.insert("description".to_string(), self.description.to_json());
+        tree.insert("build_status".to_string(), self.build_status.to_json());
+        tree.insert("rank".to_string(), (self.rank as f64).to_json());
+        Json::Object(tree)
+    }
+}
+
+
+/// Query and page of a search request
+struct SearchQuery {
+    query: String,
+    page: i64,
+}
+
+
+impl SearchQuery {
+    fn from_request(req: &Request) -> SearchQuery {
+        SearchQuery {
+            query: query_param(req, "q").map(|q| q.trim().to_string()).unwrap_or(String::new()),
+            page: query_param(req, "page")
+                .and_then(|p| p.parse().ok())
+                .map(|p: i64| if p < 1 { 1 } else { p })
+                .unwrap_or(1),
+        }
+    }
+
+
+    /// Returns results of page and true if there is a next page
+    fn results(&self, req: &Request) -> Result<(Vec<SearchResult>, bool), Error> {
+        if self.query.is_empty() {
+            return Ok((Vec::new(), false));
+        }
+
+        let conn = req.extensions.get::<DbConnection>().unwrap();
+        // one more crate is requested to find out if there is a next page
+        let mut results = try!(db::search_crates(conn,
+                                                 &self.query,
+                                                 RESULTS_PER_PAGE + 1,
+                                                 (self.page - 1) * RESULTS_PER_PAGE));
+        let has_next_page = results.len() as i64 > RESULTS_PER_PAGE;
+        results.truncate(RESULTS_PER_PAGE as usize);
+        Ok((results, has_next_page))
+    }
+}
+
+
+pub fn search_handler(req: &mut Request) -> IronResult<Response> {
+    let search = SearchQuery::from_request(req);
+    let (results, has_next_page) = match search.results(req) {
+        Ok(results) => results,
+        Err(e) => return Err(IronError::new(e, status::InternalServerError)),
+    };
+
+    // results are listed like other crate lists
+    let results: Vec<CrateSummary> = results.into_iter()
+        .map(|result| {
+            CrateSummary {
+                name: result.name,
+                version: result.version,
+                description: result.description,
+                build_status: result.build_status,
+                release_time: None,
+            }
+        })
+        .collect();
+
+    let query = url_encode(&search.query);
+    let mut content = BTreeMap::new();
+    content.insert("query".to_string(), search.query.to_json());
+    content.insert("results".to_string(), results.to_json());
+    if search.page > 1 {
+        content.insert("prev_page".to_string(),
+                       format!("/search?q={}&page={}", query, search.page - 1).to_json());
+    }
+    if has_next_page {
+        content.insert("next_page".to_string(),
+                       format!("/search?q={}&page={}", query, search.page + 1).to_json());
+    }
+
+    let mut resp = Response::new();
+    resp.set_mut(Template::new("search", Page::new("Search", content))).set_mut(status::Ok);
+    Ok(resp)
+}
+
+
+pub fn search_api_handler(req: &mut Request) -> IronResult<Response> {
+    let search = SearchQuery::from_request(req);
+    let (results, has_next_page) = match search.results(req) {
+        Ok(results) => results,
+        Err(e) => return Err(IronError::new(e, status::InternalServerError)),
+    };
+
+    let mut tree = BTreeMap::new();
+    tree.insert("query".to_string(), search.query.to_json());
+    tree.insert("page".to_string(), search.page.to_json());
+    tree.insert("has_next_page".to_string(), has_next_page.to_json());
+    tree.insert("results".to_string(), results.to_json());
+
+    let mut resp = Response::with((status::Ok, Json::Object(tree).to_string()));
+    resp.headers.set(ContentType("application/json".parse().unwrap()));
+    Ok(resp)
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use ::db::SearchResult;
+    use rustc_serialize::json::ToJson;
+
+    #[test]
+    fn test_search_result_json() {
+        let result = SearchResult {
+            name: "rand".to_string(),
+            version: "0.3.14".to_string(),
+            description: None,
+            build_status: 1,
+            rank: 0.5,
+        };
+        let json = result.to_json();
+        assert_eq!(json.find("name").and_then(|n| n.as_string()), Some("rand"));
+        assert_eq!(json.find("build_status").and_then(|s| s.as_i64()), Some(1));
+        assert!(json.find("description").unwrap().is_null());
+    }
+}
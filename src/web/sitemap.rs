@@ -0,0 +1,174 @@
+//! sitemap.xml generation
+//!
+//! `/sitemap.xml` is a sitemap index pointing to `/sitemap/:page` sitemaps.
+//! Every sitemap contains at most SITEMAP_MAX_URLS latest documented
+//! releases.
+
+use iron::prelude::*;
+use iron::status;
+use iron::headers::ContentType;
+use router::Router;
+use postgres::Connection;
+use postgres::error::Error;
+use time;
+use super::{DbConnection, base_url};
+
+
+/// Maximum number of URLs allowed in a sitemap
+pub const SITEMAP_MAX_URLS: i64 = 50000;
+
+
+/// Latest documented release of a crate
+pub struct SitemapEntry {
+    pub name: String,
+    pub version: String,
+    pub target_name: Option<String>,
+    pub release_time: Option<time::Timespec>,
+}
+
+
+/// Returns number of sitemaps needed to cover every documented crate
+pub fn sitemap_count(conn: &Connection) -> Result<i64, Error> {
+    let rows = try!(conn.query("SELECT COUNT(DISTINCT crate_id) FROM releases \
+                                WHERE rustdoc_status = 1",
+                               &[]));
+    let crates: i64 = rows.get(0).get(0);
+    Ok((crates + SITEMAP_MAX_URLS - 1) / SITEMAP_MAX_URLS)
+}
+
+
+/// Returns entries of a sitemap. Pages are starting from 1.
+pub fn get_sitemap_entries(conn: &Connection, page: i64) -> Result<Vec<SitemapEntry>, Error> {
+    let rows = try!(conn.query("SELECT DISTINCT ON (crates.name) \
+                                       crates.name, \
+                                       releases.version, \
+                                       releases.target_name, \
+                                       releases.release_time \
+                                FROM crates \
+                                INNER JOIN releases ON releases.crate_id = crates.id \
+                                WHERE releases.rustdoc_status = 1 \
+                                ORDER BY crates.name, releases.release_time DESC \
+                                LIMIT $1 OFFSET $2",
+                               &[&SITEMAP_MAX_URLS, &((page - 1) * SITEMAP_MAX_URLS)]));
+
+    Ok(rows.iter().map(|row| {
+        SitemapEntry {
+            name: row.get(0),
+            version: row.get(1),
+            target_name: row.get(2),
+            release_time: row.get(3),
+        }
+    }).collect())
+}
+
+
+/// Renders a sitemap index of given sitemap URLs
+pub fn sitemap_index(sitemap_urls: &[String]) -> String {
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str("<sitemapindex xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n");
+    for url in sitemap_urls {
+        xml.push_str(&format!("<sitemap><loc>{}</loc></sitemap>\n", url));
+    }
+    xml.push_str("</sitemapindex>\n");
+    xml
+}
+
+
+/// Renders a sitemap. base_url must not end with a slash.
+pub fn sitemap(entries: &[SitemapEntry], base_url: &str) -> String {
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml.push_str("<urlset xmlns=\"http://www.sitemaps.org/schemas/sitemap/0.9\">\n");
+    for entry in entries {
+        let url = match entry.target_name {
+            Some(ref target_name) => {
+                format!("{}/{}/{}/{}/", base_url, entry.name, entry.version, target_name)
+            }
+            None => format!("{}/{}/{}/", base_url, entry.name, entry.version),
+        };
+        xml.push_str(&format!("<url><loc>{}</loc>", url));
+        if let Some(release_time) = entry.release_time {
+            xml.push_str(&format!("<lastmod>{}</lastmod>",
+                                  time::at_utc(release_time).strftime("%Y-%m-%d").unwrap()));
+        }
+        xml.push_str("</url>\n");
+    }
+    xml.push_str("</urlset>\n");
+    xml
+}
+
+
+fn xml_response(xml: String) -> Response {
+    let mut resp = Response::with((status::Ok, xml));
+    resp.headers.set(ContentType("application/xml".parse().unwrap()));
+    resp
+}
+
+
+pub fn sitemap_index_handler(req: &mut Request) -> IronResult<Response> {
+    let count = {
+        let conn = req.extensions.get::<DbConnection>().unwrap();
+        match sitemap_count(conn) {
+            Ok(count) => count,
+            Err(e) => return Err(IronError::new(e, status::InternalServerError)),
+        }
+    };
+
+    let base_url = base_url(req);
+    let urls: Vec<String> = (1..count + 1)
+        .map(|page| format!("{}/sitemap/{}", base_url, page))
+        .collect();
+    Ok(xml_response(sitemap_index(&urls)))
+}
+
+
+pub fn sitemap_handler(req: &mut Request) -> IronResult<Response> {
+    let page = match req.extensions.get::<Router>().unwrap().find("page")
+        .and_then(|p| p.parse::<i64>().ok()) {
+        Some(page) if page >= 1 => page,
+        _ => return Ok(Response::with(status::NotFound)),
+    };
+
+    let entries = {
+        let conn = req.extensions.get::<DbConnection>().unwrap();
+        match get_sitemap_entries(conn, page) {
+            Ok(entries) => entries,
+            Err(e) => return Err(IronError::new(e, status::InternalServerError)),
+        }
+    };
+
+    if entries.is_empty() {
+        return Ok(Response::with(status::NotFound));
+    }
+
+    Ok(xml_response(sitemap(&entries, &base_url(req))))
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use time;
+
+    #[test]
+    fn test_sitemap() {
+        let entries = vec![SitemapEntry {
+                               name: "rand".to_string(),
+                               version: "0.3.14".to_string(),
+                               target_name: Some("rand".to_string()),
+                               release_time: Some(time::Timespec::new(1462000000, 0)),
+                           }];
+        let xml = sitemap(&entries, "https://example.com");
+        assert!(xml.contains("<url><loc>https://example.com/rand/0.3.14/rand/</loc>\
+                              <lastmod>2016-04-30</lastmod></url>"));
+    }
+
+
+    #[test]
+    fn test_sitemap_index() {
+        let xml = sitemap_index(&["https://example.com/sitemap/1".to_string()]);
+        assert!(xml.contains("<sitemap><loc>https://example.com/sitemap/1</loc></sitemap>"));
+    }
+}
@@ -0,0 +1,261 @@
+//! Source code browser
+//!
+//! `/crate/:name/:version/source/` lists files of a release from
+//! `sources_path/<CRATE>/<VERSION>`, directories are listed first. Files are
+//! shown with line numbers and Rust sources are syntax highlighted. Binary
+//! and large files are not shown.
+
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::collections::BTreeMap;
+
+use iron::prelude::*;
+use iron::{Handler, status};
+use iron::modifiers::Redirect;
+use iron::Url;
+use handlebars_iron::Template;
+use rustc_serialize::json::{Json, ToJson};
+use super::{Page, base_url};
+use super::highlight::highlight_rust;
+
+
+/// Files bigger than this are not shown
+pub const MAX_FILE_SIZE: u64 = 512 * 1024;
+
+
+/// Handler serving `/crate/:name/:version/source/...` paths
+pub struct SourceHandler {
+    root: PathBuf,
+}
+
+
+/// An entry of a directory listing
+struct Entry {
+    name: String,
+    is_dir: bool,
+}
+
+
+impl SourceHandler {
+    pub fn new(root: PathBuf) -> SourceHandler {
+        SourceHandler {
+            root: root,
+        }
+    }
+
+
+    /// Returns path of requested file in sources of release, None if it's
+    /// not in sources of release
+    fn resolve(&self, name: &str, version: &str, path: &Path) -> io::Result<Option<PathBuf>> {
+        let release_root = self.root.join(name).join(version);
+        if !release_root.exists() {
+            return Ok(None);
+        }
+        let release_root = try!(fs::canonicalize(release_root));
+        let full_path = release_root.join(path);
+        if !full_path.exists() {
+            return Ok(None);
+        }
+        // symbolic links in sources may point out of sources
+        let full_path = try!(fs::canonicalize(full_path));
+        Ok(if full_path.starts_with(&release_root) {
+            Some(full_path)
+        } else {
+            None
+        })
+    }
+}
+
+
+/// Returns relative path of source file from URL path components after
+/// `source`, None if path is trying to escape from sources
+fn source_path(components: &[String]) -> Option<PathBuf> {
+    let mut path = PathBuf::new();
+    for component in components.iter().filter(|c| !c.is_empty()) {
+        if component == ".." || component == "." || component.contains('\\') {
+            return None;
+        }
+        path.push(component);
+    }
+    Some(path)
+}
+
+
+/// Returns a link with a name and URL
+fn link_json(name: String, url: String) -> Json {
+    let mut tree = BTreeMap::new();
+    tree.insert("name".to_string(), name.to_json());
+    tree.insert("url".to_string(), url.to_json());
+    Json::Object(tree)
+}
+
+
+/// Returns links to every parent directory of path
+fn breadcrumbs_json(base: &str, path: &Path) -> Json {
+    let mut links = Vec::new();
+    let mut url = base.to_string();
+    for component in path.iter() {
+        let component = component.to_string_lossy();
+        url.push('/');
+        url.push_str(&component);
+        links.push(link_json(component.into_owned(), url.clone()));
+    }
+    links.to_json()
+}
+
+
+fn read_entries(dir: &Path) -> io::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    for entry in try!(fs::read_dir(dir)) {
+        let entry = try!(entry);
+        entries.push(Entry {
+            name: entry.file_name().to_string_lossy().into_owned(),
+            is_dir: try!(entry.file_type()).is_dir(),
+        });
+    }
+    // directories first
+    entries.sort_by(|a, b| (!a.is_dir, &a.name).cmp(&(!b.is_dir, &b.name)));
+    Ok(entries)
+}
+
+
+/// Returns directory listing, url is URL of directory without trailing slash
+fn listing_json(url: &str, entries: &[Entry]) -> Json {
+    entries.iter()
+        .map(|entry| if entry.is_dir {
+            link_json(format!("{}/", entry.name), format!("{}/{}/", url, entry.name))
+        } else {
+            link_json(entry.name.clone(), format!("{}/{}", url, entry.name))
+        })
+        .collect::<Vec<Json>>()
+        .to_json()
+}
+
+
+/// Returns line numbers and content of a text file, Rust sources are
+/// highlighted into code_html
+fn file_json(path: &Path, content: &str) -> Json {
+    let lines: Vec<usize> = (1..content.lines().count() + 1).collect();
+    let mut tree = BTreeMap::new();
+    tree.insert("lines".to_string(), lines.to_json());
+    if path.extension().map_or(false, |e| e == "rs") {
+        tree.insert("code_html".to_string(), highlight_rust(content).to_json());
+    } else {
+        tree.insert("code".to_string(), content.to_json());
+    }
+    Json::Object(tree)
+}
+
+
+fn read_file(path: &Path) -> io::Result<Option<String>> {
+    if try!(fs::metadata(path)).len() > MAX_FILE_SIZE {
+        return Ok(None);
+    }
+    let mut content = Vec::new();
+    try!(try!(fs::File::open(path)).read_to_end(&mut content));
+    // binary files are not shown
+    Ok(String::from_utf8(content).ok())
+}
+
+
+impl Handler for SourceHandler {
+    fn handle(&self, req: &mut Request) -> IronResult<Response> {
+        // path is: crate/:name/:version/source/...
+        if req.url.path.len() < 4 || req.url.path.iter().any(|c| c == "..") {
+            return Ok(Response::with(status::NotFound));
+        }
+        let name = req.url.path[1].clone();
+        let version = req.url.path[2].clone();
+        let path = match source_path(&req.url.path[4..]) {
+            Some(path) => path,
+            None => return Ok(Response::with(status::NotFound)),
+        };
+
+        let full_path = match self.resolve(&name, &version, &path) {
+            Ok(Some(full_path)) => full_path,
+            Ok(None) => return Ok(Response::with(status::NotFound)),
+            Err(e) => return Err(IronError::new(e, status::InternalServerError)),
+        };
+
+        let base = format!("/crate/{}/{}/source", name, version);
+        let mut url = base.clone();
+        for component in path.iter() {
+            url.push('/');
+            url.push_str(&component.to_string_lossy());
+        }
+
+        let mut content = BTreeMap::new();
+        content.insert("base".to_string(), base.to_json());
+        content.insert("breadcrumbs".to_string(), breadcrumbs_json(&base, &path));
+        if full_path.is_dir() {
+            // relative links of listing needs a trailing slash
+            if !req.url.path.last().map_or(false, |c| c.is_empty()) {
+                let url = format!("{}/{}/", base_url(req), req.url.path.join("/"));
+                return Ok(Response::with((status::Found,
+                                          Redirect(Url::parse(&url).unwrap()))));
+            }
+            match read_entries(&full_path) {
+                Ok(entries) => {
+                    content.insert("entries".to_string(), listing_json(&url, &entries));
+                }
+                Err(e) => return Err(IronError::new(e, status::InternalServerError)),
+            }
+        } else {
+            match read_file(&full_path) {
+                Ok(Some(file)) => {
+                    content.insert("file".to_string(), file_json(&path, &file));
+                }
+                Ok(None) => {
+                    content.insert("not_shown".to_string(), true.to_json());
+                }
+                Err(e) => return Err(IronError::new(e, status::InternalServerError)),
+            }
+        }
+
+        let page = Page::new(&format!("Source of {}-{}", name, version), content);
+        let mut resp = Response::new();
+        resp.set_mut(Template::new("source", page)).set_mut(status::Ok);
+        Ok(resp)
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use std::path::{Path, PathBuf};
+    use super::{source_path, file_json, listing_json, Entry};
+
+    #[test]
+    fn test_source_path() {
+        let components = |c: &[&str]| c.iter().map(|c| c.to_string()).collect::<Vec<_>>();
+        assert_eq!(source_path(&components(&["src", "lib.rs"])),
+                   Some(PathBuf::from("src/lib.rs")));
+        assert_eq!(source_path(&components(&["src", ""])), Some(PathBuf::from("src")));
+        assert_eq!(source_path(&components(&[])), Some(PathBuf::new()));
+        assert_eq!(source_path(&components(&["src", "..", ".."])), None);
+    }
+
+
+    #[test]
+    fn test_source_json() {
+        let entries = vec![Entry { name: "src".to_string(), is_dir: true },
+                           Entry { name: "Cargo.toml".to_string(), is_dir: false }];
+        let listing = listing_json("/crate/rand/0.3.14/source", &entries);
+        assert_eq!(listing[0].find("name").and_then(|n| n.as_string()), Some("src/"));
+        assert_eq!(listing[0].find("url").and_then(|u| u.as_string()),
+                   Some("/crate/rand/0.3.14/source/src/"));
+        assert_eq!(listing[1].find("url").and_then(|u| u.as_string()),
+                   Some("/crate/rand/0.3.14/source/Cargo.toml"));
+
+        let file = file_json(Path::new("src/lib.rs"), "fn main() {\n}\n");
+        assert_eq!(file["lines"].as_array().map(|l| l.len()), Some(2));
+        assert!(file["code_html"].as_string().unwrap()
+                    .contains("<span class=\"kw\">fn</span> main()"));
+        // other files are escaped by template
+        let file = file_json(Path::new("README"), "<b>");
+        assert_eq!(file.find("code").and_then(|c| c.as_string()), Some("<b>"));
+        assert!(file.find("code_html").is_none());
+    }
+}